@@ -0,0 +1,77 @@
+/* the C API of minecraft-player, built with `cargo build --release --features capi`.
+ * link against the cdylib (libminecraft_player.so, .dylib or .dll) */
+#ifndef MINECRAFT_PLAYER_H
+#define MINECRAFT_PLAYER_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef enum McpStatus {
+    MCP_OK = 0,
+    /* a null pointer, a length of 0 or an out of range value was passed */
+    MCP_INVALID_ARGUMENT = 1,
+    /* the solve (or the device) failed, or the library panicked */
+    MCP_FAILED = 2,
+} McpStatus;
+
+/* what sounds and input are matched in, like `--features` */
+typedef enum McpFeatures {
+    MCP_FEATURES_WAVEFORM = 0,
+    MCP_FEATURES_MEL_WEIGHTED = 1,
+    MCP_FEATURES_MEL_FILTERBANK = 2,
+    MCP_FEATURES_LOG_SPECTRUM = 3,
+    MCP_FEATURES_MFCC = 4,
+    MCP_FEATURES_CHROMA = 5,
+} McpFeatures;
+
+typedef struct McpBasis McpBasis;
+typedef struct McpSchedule McpSchedule;
+
+typedef struct McpSolveOptions {
+    size_t iters;
+    /* picked from the basis when not above 0 */
+    float step;
+    /* whether the input loops back to its start */
+    bool looping;
+    /* commands per tick, the loudest sounds of every tick are kept */
+    size_t budget;
+} McpSolveOptions;
+
+/* the error of the last call on this thread that didn't return MCP_OK, or
+ * NULL. valid until another call on this thread fails */
+const char *mcp_last_error(void);
+
+/* builds a basis out of `count` mono sounds at `sample_rate`, the i-th of
+ * `lengths[i]` samples at `sounds[i]`. pitched variants are separate sounds.
+ * `features` is one of MCP_FEATURES_*. atoms span `atom_ticks` ticks.
+ * nothing passed is kept */
+McpStatus mcp_basis_new(const float *const *sounds, const size_t *lengths, size_t count,
+                        uint32_t sample_rate, size_t atom_ticks, uint32_t features,
+                        McpBasis **out);
+void mcp_basis_free(McpBasis *basis);
+
+/* the cli's defaults, a step picked from the basis */
+McpSolveOptions mcp_solve_options_default(void);
+
+/* solves `length` mono samples, at the basis' sample rate, for the amplitudes
+ * of its sounds at every tick. the loudest is 1.0, and only `budget` sounds are
+ * kept every tick. `options` may be NULL for the defaults */
+McpStatus mcp_solve(const McpBasis *basis, const float *samples, size_t length,
+                    const McpSolveOptions *options, McpSchedule **out);
+
+size_t mcp_schedule_sounds(const McpSchedule *schedule);
+size_t mcp_schedule_ticks(const McpSchedule *schedule);
+/* sounds by ticks, row-major. valid until the schedule is freed */
+const float *mcp_schedule_amplitudes(const McpSchedule *schedule);
+void mcp_schedule_free(McpSchedule *schedule);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
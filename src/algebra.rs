@@ -1,12 +1,84 @@
-use std::time::Instant;
+use std::{collections::HashMap, str::FromStr, sync::{LazyLock, RwLock}, time::Instant};
 
 use anyhow::Error;
-use ndarray::{Array2, ArrayView2};
-use ocl::{Buffer, ProQue};
+use ndarray::{Array2, ArrayBase, ArrayView2, ArrayViewMut2, Axis, CowArray, Data, DataMut, Ix2};
+use ocl::{enums::{DeviceInfo, DeviceInfoResult, Status}, Buffer, Device, Kernel, ProQue};
+use rayon::iter::{IndexedParallelIterator, IntoParallelIterator, IntoParallelRefIterator, IntoParallelRefMutIterator, ParallelIterator};
 use tracing::{event, span, Level};
 
+use crate::{cancel, convergence, progress, tuning::Tiles};
+
 static KERNEL: &str = include_str!("pgd.ocl");
 
+/// ticks per block of the CPU solve, small enough that each block's
+/// products stay in cache and there are blocks for every core
+static CPU_BLOCK: usize = 128;
+
+/// work items per group of a reduction on the device, a power of two
+static REDUCE_LOCAL: usize = 64;
+
+/// groups a reduction on the device is first summed up in
+static REDUCE_GROUPS: usize = 256;
+
+#[derive(thiserror::Error, Debug)]
+pub enum SolverError {
+    #[error("OpenCL error: {0}")]
+    Ocl(#[from] ocl::Error),
+    #[error("no OpenCL devices found")]
+    NoDevices,
+    #[error("no OpenCL device {index} (found {found})")]
+    NoSuchDevice { index: usize, found: usize },
+    #[error("uploaded {uploaded} values for a {m}x{r} basis")]
+    BasisSize { uploaded: usize, m: usize, r: usize },
+    #[error("needs {} MiB of device memory, {} MiB are available", required >> 20, available >> 20)]
+    OutOfMemory { required: u64, available: u64 },
+    #[error("matrix has a bad shape: {0}")]
+    Shape(#[from] ndarray::ShapeError)
+}
+
+/// (global memory, largest single buffer) of a device, in bytes
+fn device_memory(device: Device) -> Result<(u64, u64), SolverError> {
+    let global = match device.info(DeviceInfo::GlobalMemSize)? {
+        DeviceInfoResult::GlobalMemSize(bytes) => bytes,
+        _ => u64::MAX,
+    };
+    let max_alloc = match device.info(DeviceInfo::MaxMemAllocSize)? {
+        DeviceInfoResult::MaxMemAllocSize(bytes) => bytes,
+        _ => u64::MAX,
+    };
+
+    Ok((global, max_alloc))
+}
+
+/// `buffers` (in floats each) fit on the device next to `reserved` bytes
+fn check_fits(device: Device, buffers: &[usize], reserved: u64) -> Result<(), SolverError> {
+    let (global, max_alloc) = device_memory(device)?;
+    let bytes = buffers.iter().map(|len| (len * size_of::<f32>()) as u64).collect::<Vec<u64>>();
+
+    if let Some(largest) = bytes.iter().copied().max().filter(|largest| *largest > max_alloc) {
+        return Err(SolverError::OutOfMemory { required: largest, available: max_alloc });
+    }
+
+    let required = bytes.iter().sum::<u64>();
+    let available = global.saturating_sub(reserved);
+    if required > available {
+        return Err(SolverError::OutOfMemory { required, available });
+    }
+
+    Ok(())
+}
+
+/// drivers that overcommit only fail once a buffer is built or first used
+fn allocation_error(error: ocl::Error, required: u64, device: Device) -> SolverError {
+    match error.api_status() {
+        Some(Status::CL_MEM_OBJECT_ALLOCATION_FAILURE | Status::CL_OUT_OF_RESOURCES | Status::CL_INVALID_BUFFER_SIZE) => {
+            let available = device_memory(device).map(|(global, _)| global).unwrap_or(0);
+            SolverError::OutOfMemory { required, available }
+        },
+        _ => SolverError::Ocl(error),
+    }
+}
+
 pub fn interpolated_range(a: f32, b: f32, r: usize) -> Vec<f32> {
     assert!(r >= 2);
 
@@ -14,49 +86,385 @@ pub fn interpolated_range(a: f32, b: f32, r: usize) -> Vec<f32> {
     (0..r).map(|i| a + i as f32 * step).collect()
 }
 
-pub fn normalize_to_minus_plus(array: &mut Array2<f32>) {
-    let min_val = array.iter().cloned().fold(f32::INFINITY, f32::min);
-    let max_val = array.iter().cloned().fold(f32::NEG_INFINITY, f32::max);
+/// the smallest and largest value. these and the normalizations go through
+/// the values in memory order, which for mapped chunks is the file's
+pub fn bounds<S: Data<Elem = f32>>(array: &ArrayBase<S, Ix2>) -> (f32, f32) {
+    let min_val = array.fold(f32::INFINITY, |a, b| a.min(*b));
+    let max_val = array.fold(f32::NEG_INFINITY, |a, b| a.max(*b));
+    (min_val, max_val)
+}
+
+pub fn normalize_to_minus_plus<S: DataMut<Elem = f32>>(array: &mut ArrayBase<S, Ix2>) {
+    let (min_val, max_val) = bounds(array);
+    scale_to_minus_plus(array, min_val, max_val);
+}
 
+/// `normalize_to_minus_plus` with bounds found elsewhere, e.g. over every
+/// chapter of the input
+pub fn scale_to_minus_plus<S: DataMut<Elem = f32>>(array: &mut ArrayBase<S, Ix2>, min_val: f32, max_val: f32) {
     let range = max_val - min_val;
 
     if range > 0.0 {
-        for val in array.iter_mut() {
-            *val = 2.0 * (*val - min_val) / range - 1.0;
-        }
+        array.mapv_inplace(|val| 2.0 * (val - min_val) / range - 1.0);
     } else {
-        for val in array.iter_mut() {
+        array.fill(0.0);
+    }
+}
+
+/// divides by the largest magnitude, keeping zero at zero
+pub fn normalize_to_global<S: DataMut<Elem = f32>>(array: &mut ArrayBase<S, Ix2>) {
+    let (min_val, max_val) = bounds(array);
+    scale_to_global(array, min_val.abs().max(max_val.abs()));
+}
+
+/// `normalize_to_global` with a peak found elsewhere
+pub fn scale_to_global<S: DataMut<Elem = f32>>(array: &mut ArrayBase<S, Ix2>, peak: f32) {
+    if peak > 0.0 {
+        array.mapv_inplace(|val| val / peak);
+    }
+}
+
+/// `normalize_to_global` for each column on its own. returns the peak of every
+/// column so the solution can be scaled back with `scale_columns`
+pub fn normalize_columns<S: DataMut<Elem = f32>>(array: &mut ArrayBase<S, Ix2>) -> Vec<f32> {
+    let mut peaks = Vec::with_capacity(array.ncols());
+
+    for mut column in array.columns_mut() {
+        let peak = column.iter().cloned().fold(0.0, |a: f32, b| a.max(b.abs()));
+        if peak > 0.0 {
+            column.mapv_inplace(|val| val / peak);
+        }
+        peaks.push(peak);
+    }
+
+    peaks
+}
+
+/// multiplies every column by its scale, undoing `normalize_columns`
+pub fn scale_columns(array: &mut Array2<f32>, scales: &[f32]) {
+    assert_eq!(array.ncols(), scales.len());
+
+    for (mut column, scale) in array.columns_mut().into_iter().zip(scales) {
+        column.mapv_inplace(|val| val * scale);
+    }
+}
+
+/// adds `weight * from` into the given `rows` of `into`, after undoing
+/// `normalize_columns` with `column_scales` and then `normalize_to_global`.
+/// same as doing those on a copy of `from`, without the copy. only the
+/// `budget` largest values of every column are added
+pub fn accumulate_rows(
+    into: &mut Array2<f32>,
+    from: ArrayView2<f32>,
+    rows: &[usize],
+    column_scales: Option<&[f32]>,
+    weight: f32,
+    budget: usize
+) {
+    let peak = scaled_peak(from, column_scales);
+    let weight = if peak > 0.0 { weight / peak } else { weight };
+    accumulate_rows_scaled(into, from, rows, column_scales, weight, budget);
+}
+
+/// the largest value of `from` after undoing `normalize_columns`
+pub fn scaled_peak(from: ArrayView2<f32>, column_scales: Option<&[f32]>) -> f32 {
+    let scale = |column: usize| column_scales.map(|scales| scales[column]).unwrap_or(1.0);
+
+    return from.indexed_iter()
+        .map(|((_, column), val)| val * scale(column))
+        .fold(f32::NEG_INFINITY, f32::max);
+}
+
+/// `accumulate_rows` without normalizing `from` by its own peak, for when
+/// `weight` already accounts for a peak found elsewhere
+pub fn accumulate_rows_scaled(
+    into: &mut Array2<f32>,
+    from: ArrayView2<f32>,
+    rows: &[usize],
+    column_scales: Option<&[f32]>,
+    weight: f32,
+    budget: usize
+) {
+    assert_eq!(from.nrows(), rows.len());
+    assert_eq!(from.ncols(), into.ncols());
+
+    let scale = |column: usize| column_scales.map(|scales| scales[column]).unwrap_or(1.0);
+
+    // only the values above zero can be kept, and NaN isn't, so a NaN
+    // can't become the threshold. the buffer is reused between columns
+    let mut playing = Vec::new();
+    for (column, values) in from.columns().into_iter().enumerate() {
+        playing.clear();
+        playing.extend(values.iter().copied().filter(|val| *val > 0.0));
+        let threshold = match budget {
+            0 => continue,
+            budget if budget < playing.len() => *playing.select_nth_unstable_by(budget - 1, |a, b| b.total_cmp(a)).1,
+            _ => f32::NEG_INFINITY
+        };
+
+        // zeros add nothing, and would take the place of the values after
+        // them when fewer than `budget` aren't zero
+        let mut kept = 0;
+        for (val, row) in values.iter().zip(rows) {
+            if *val > 0.0 && *val >= threshold && kept < budget {
+                into[[*row, column]] += weight * val * scale(column);
+                kept += 1;
+            }
+        }
+    }
+}
+
+/// zeroes the values of `h` that are NaN or infinite, from a step size too
+/// large for the basis or a broken driver, and returns the columns they
+/// were in. a solution isn't exported, or even sorted, with them in it
+pub fn sanitize(h: &mut Array2<f32>) -> Vec<usize> {
+    let mut columns = Vec::new();
+
+    for (index, mut column) in h.columns_mut().into_iter().enumerate() {
+        if column.iter().all(|val| val.is_finite()) {
+            continue;
+        }
+        column.mapv_inplace(|val| if val.is_finite() { val } else { 0.0 });
+        columns.push(index);
+    }
+
+    return columns;
+}
+
+/// how much of h every group of rows carries, summed over every tick.
+/// `groups` is the group of every row, e.g. the sound event of every pitch
+pub fn group_weights(h: ArrayView2<f32>, groups: &[usize], weights: &mut [f32]) {
+    assert_eq!(h.nrows(), groups.len());
+
+    for (row, group) in h.rows().into_iter().zip(groups) {
+        weights[*group] += row.sum();
+    }
+}
+
+/// one round of narrowing the groups in use down to `max`: the heaviest
+/// half of them are kept, but never fewer than `max`. going a half at a
+/// time lets the solve move what the dropped groups carried onto the kept
+/// ones between rounds, instead of keeping whichever were heaviest at first.
+/// `None` when no more than `max` are in use
+pub fn prune_groups(weights: &[f32], max: usize) -> Option<Vec<bool>> {
+    let mut used = weights.iter().enumerate().filter(|(_, weight)| **weight > 0.0).collect::<Vec<_>>();
+    if used.len() <= max {
+        return None;
+    }
+
+    used.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+    let mut keep = vec![false; weights.len()];
+    for (group, _) in used.iter().take(used.len().div_ceil(2).max(max)) {
+        keep[*group] = true;
+    }
+
+    return Some(keep);
+}
+
+/// zeroes everything below `epsilon`, which the exporter then skips
+pub fn apply_epsilon(array: &mut Array2<f32>, epsilon: f32) {
+    for val in array.iter_mut() {
+        if *val < epsilon {
             *val = 0.0;
         }
     }
 }
 
-pub fn normalize_to_global(array: &mut Array2<f32>) {
-    let max_val = array.iter().cloned().fold(f32::NEG_INFINITY, f32::max);
+/// blends every tick of every atom with its neighbours by `strength` (0 to
+/// 1), so an atom's volume doesn't jump from one tick to the next. the
+/// first and last ticks only have one neighbour to blend with
+pub fn smooth_ticks(array: &mut Array2<f32>, strength: f32) {
+    if strength <= 0.0 {
+        return;
+    }
 
-    if max_val > 0.0 {
-        for val in array.iter_mut() {
-            *val /= max_val;
+    for mut row in array.rows_mut() {
+        let original = row.to_vec();
+        for (t, value) in row.iter_mut().enumerate() {
+            let neighbours = [t.checked_sub(1), Some(t + 1)].into_iter()
+                .flatten()
+                .filter_map(|i| original.get(i))
+                .collect::<Vec<&f32>>();
+            if neighbours.is_empty() {
+                continue;
+            }
+
+            let mean = neighbours.iter().copied().sum::<f32>() / neighbours.len() as f32;
+            *value = (1.0 - strength) * original[t] + strength * mean;
         }
     }
 }
 
+/// raises every amplitude to `gamma`. below 1 brings quiet sounds up,
+/// above 1 pushes them further down. amplitudes are expected in [0, 1]
 pub fn dynamic_range(array: &mut Array2<f32>, gamma: f32) {
     for x in array.iter_mut() {
         *x = x.powf(gamma);
     }
 }
 
-pub fn matrix_from_vecs(matrix_vec: Vec<Vec<f32>>) -> Result<Array2<f32>, Error> {
-    let flat_vec: Vec<f32> = matrix_vec.clone().into_iter().flatten().collect();
+/// rounds every amplitude to a multiple of `step`, or leaves it with 0
+pub fn round_to(array: &mut Array2<f32>, step: f32) {
+    if step <= 0.0 {
+        return;
+    }
+
+    for x in array.iter_mut() {
+        *x = (*x / step).round() * step;
+    }
+}
+
+/// fades the first `fade_in` and the last `fade_out` ticks of a schedule
+/// `n_ticks` long in and out, linearly. `array` holds its ticks from
+/// `first_tick` on, so chapters fade where they are in the whole song
+pub fn fade(array: &mut Array2<f32>, first_tick: usize, n_ticks: usize, fade_in: usize, fade_out: usize) {
+    for (offset, mut column) in array.columns_mut().into_iter().enumerate() {
+        let tick = first_tick + offset;
+        let gain_in = (tick + 1) as f32 / (fade_in + 1) as f32;
+        let gain_out = n_ticks.saturating_sub(tick) as f32 / (fade_out + 1) as f32;
+
+        let gain = gain_in.min(gain_out).min(1.0);
+        if gain < 1.0 {
+            column.mapv_inplace(|x| x * gain);
+        }
+    }
+}
+
+/// what happens to a finished solve (normalized to [0, 1]) before it's
+/// exported, in the order of the fields
+#[derive(Clone, Copy, Debug)]
+pub struct PostProcess {
+    /// see `smooth_ticks`
+    pub smoothing: f32,
+    /// see `dynamic_range`
+    pub gamma: f32,
+    /// scales the loudest amplitude back up to 1
+    pub renormalize: bool,
+    /// scales every amplitude after renormalizing, capped at 1, see
+    /// `loudness::gain`
+    pub gain: f32,
+    /// ticks faded in at the start, see `fade`
+    pub fade_in: usize,
+    /// ticks faded out at the end
+    pub fade_out: usize,
+    /// see `apply_epsilon`
+    pub epsilon: f32,
+    /// see `round_to`
+    pub round: f32
+}
 
+impl Default for PostProcess {
+    fn default() -> Self {
+        Self { smoothing: 0.0, gamma: 1.0, renormalize: false, gain: 1.0, fade_in: 0, fade_out: 0, epsilon: 1e-5, round: 0.0 }
+    }
+}
+
+impl PostProcess {
+    /// smoothing and gamma, which change how loud amplitudes are relative
+    /// to each other
+    pub fn shape(&self, array: &mut Array2<f32>) {
+        smooth_ticks(array, self.smoothing);
+        if self.gamma != 1.0 {
+            dynamic_range(array, self.gamma);
+        }
+    }
+
+    /// renormalization by `peak`, the loudest shaped amplitude of the whole
+    /// schedule, then the gain, fading, epsilon and rounding. `array` is the ticks
+    /// from `first_tick` on of a schedule `n_ticks` long
+    pub fn finish(&self, array: &mut Array2<f32>, peak: f32, first_tick: usize, n_ticks: usize) {
+        if self.renormalize && peak > 0.0 {
+            array.mapv_inplace(|x| x / peak);
+        }
+        if self.gain != 1.0 {
+            array.mapv_inplace(|x| (x * self.gain).min(1.0));
+        }
+        fade(array, first_tick, n_ticks, self.fade_in, self.fade_out);
+        apply_epsilon(array, self.epsilon);
+        round_to(array, self.round);
+    }
+
+    /// the whole pipeline, on a schedule solved at once
+    pub fn apply(&self, array: &mut Array2<f32>) {
+        self.shape(array);
+        let (_, peak) = bounds(array);
+        let n_ticks = array.ncols();
+        self.finish(array, peak, 0, n_ticks);
+    }
+}
+
+/// how the input chunks and the basis are scaled before solving
+#[derive(clap::ValueEnum, Clone, Copy, Default, Debug)]
+pub enum Normalization {
+    /// shift and scale into [-1, 1]
+    #[default]
+    MinusPlus,
+    /// divide by the peak magnitude, keeping silence at zero
+    Global,
+    /// divide every tick by its own peak magnitude, so quiet sections are
+    /// solved as precisely as loud ones. the volumes are scaled back after
+    PerTick,
+}
+
+/// what's done with a solution that has values that aren't finite, see
+/// `sanitize`
+#[derive(clap::ValueEnum, Clone, Copy, Default, Debug)]
+pub enum NonFinite {
+    /// zero them out and carry on, warning about the ticks they were in
+    #[default]
+    Zero,
+    /// stop before anything is exported
+    Abort,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum StepSize {
+    Fixed(f32),
+    /// 1/L, see `DeviceBasis::lipschitz`
+    Auto
+}
+
+impl FromStr for StepSize {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "auto" {
+            return Ok(StepSize::Auto);
+        }
+
+        match s.parse::<f32>() {
+            Ok(step) if step.is_finite() && step > 0.0 => Ok(StepSize::Fixed(step)),
+            _ => Err(format!("`{}` is not a positive number or `auto`", s)),
+        }
+    }
+}
+
+/// `DeviceBasis::lipschitz` for a basis on the host
+pub fn cpu_lipschitz(basis: ArrayView2<f32>, iters: usize) -> f32 {
+    let (_, r) = basis.dim();
+    let mut x = ndarray::Array1::<f32>::from_elem(r, 1.0 / (r as f32).sqrt());
+    let mut estimate = 0.0;
+
+    for _ in 0..iters {
+        let y = basis.t().dot(&basis.dot(&x));
+        estimate = y.dot(&y).sqrt();
+        if estimate == 0.0 {
+            break;
+        }
+        x = y / estimate;
+    }
+
+    estimate
+}
+
+pub fn matrix_from_vecs(matrix_vec: Vec<Vec<f32>>) -> Result<Array2<f32>, Error> {
     let rows = matrix_vec.len();
     let cols = if rows > 0 { matrix_vec[0].len() } else { 0 };
     let shape = (rows, cols);
 
-    return Ok(ndarray::ArrayView2::from_shape(shape, &flat_vec)?
-        .to_owned());
+    let flat_vec: Vec<f32> = matrix_vec.into_iter().flatten().collect();
 
+    return Ok(Array2::from_shape_vec(shape, flat_vec)?);
 }
 
 /// data is V, dimensioned (m, n)
@@ -104,34 +512,265 @@ pub fn cpu_pgd_nnls(
         let grad = wt.dot(&(wh - data));
         h = &h - &(grad * step);
         h.mapv_inplace(|x| x.max(0.0));
-        println!("iter {}, elapsed: {}s", i, start.elapsed().as_secs());
+        event!(Level::TRACE, "iter {}, {}ms", i, start.elapsed().as_millis());
     }
 
     h
 }
 
+/// sums the stacked ticks of every atom into the tick they're heard in.
+/// `stacked` is (span * m, n), block d holding the d-th tick of the atoms
+/// started in each column. `circular` atoms started near the end ring on
+/// into the first ticks, as in a loop
+fn fold(stacked: ArrayView2<f32>, span: usize, circular: bool) -> Array2<f32> {
+    let (rows, n) = stacked.dim();
+    let m = rows / span;
+    let mut folded = Array2::<f32>::zeros((m, n));
+
+    for d in 0..span.min(n) {
+        let block = stacked.slice(ndarray::s![d * m..(d + 1) * m, ..n - d]);
+        let mut target = folded.slice_mut(ndarray::s![.., d..]);
+        target += &block;
+
+        if circular {
+            let wrapped = stacked.slice(ndarray::s![d * m..(d + 1) * m, n - d..]);
+            let mut target = folded.slice_mut(ndarray::s![.., ..d]);
+            target += &wrapped;
+        }
+    }
+
+    folded
+}
+
+/// the adjoint of `fold`: block d of column s is `error` at s + d, or at
+/// s + d - n when `circular`
+fn unfold(error: ArrayView2<f32>, span: usize, circular: bool) -> Array2<f32> {
+    let (m, n) = error.dim();
+    let mut unfolded = Array2::<f32>::zeros((span * m, n));
+
+    for d in 0..span.min(n) {
+        unfolded.slice_mut(ndarray::s![d * m..(d + 1) * m, ..n - d]).assign(&error.slice(ndarray::s![.., d..]));
+
+        if circular {
+            unfolded.slice_mut(ndarray::s![d * m..(d + 1) * m, n - d..]).assign(&error.slice(ndarray::s![.., ..d]));
+        }
+    }
+
+    unfolded
+}
+
+/// `cpu_pgd_nnls` for atoms that span `span` ticks. every column of the
+/// basis is the features of each of an atom's ticks stacked, so it has
+/// `span` times the rows of the data. an atom started at tick t is heard in
+/// ticks t..t + span, which is a convolution over ticks:
+///
+/// V[t] ~ sum over d of W_d h[t - d]
+///
+/// the gradient is the same W^T(Wh-V) with the error shifted back to the
+/// tick each part of the atom was started in
+pub fn cpu_conv_pgd_nnls(
+    data: ArrayView2<f32>,
+    basis: ArrayView2<f32>,
+    span: usize,
+    iters: usize,
+    step: f32,
+) -> Array2<f32> {
+    let (_, n) = data.dim();
+    let (_, r) = basis.dim();
+
+    cpu_conv_pgd_nnls_from(data, basis, span, Array2::zeros((r, n)), iters, step, false).0
+}
+
+/// ‖W h - V‖ of atoms spanning `span` ticks, folded like the solve folds
+/// them, summed up in f64 a tick at a time from only the sounds h plays.
+/// that takes no more memory than W and is quick for the sparse solutions
+/// NNLS gives, as a check on the f32 of the solve
+pub fn residual_norm_f64(data: ArrayView2<f32>, basis: ArrayView2<f32>, h: ArrayView2<f32>, span: usize, circular: bool) -> f64 {
+    return tick_errors_f64(data, basis, h, span, circular).iter().sum::<f64>().sqrt();
+}
+
+/// ‖W h - V‖² of every tick on its own, see `residual_norm_f64`
+pub fn tick_errors_f64(data: ArrayView2<f32>, basis: ArrayView2<f32>, h: ArrayView2<f32>, span: usize, circular: bool) -> Vec<f64> {
+    let (m, n) = data.dim();
+    assert_eq!(basis.nrows(), span * m);
+    assert_eq!(h.dim(), (basis.ncols(), n));
+
+    return (0..n).into_par_iter()
+        .map(|t| {
+            let mut error = data.column(t).iter().map(|v| -(*v as f64)).collect::<Vec<f64>>();
+            for d in 0..span.min(n) {
+                // the atoms started d ticks before, from the end of a loop
+                let started = match (d <= t, circular) {
+                    (true, _) => t - d,
+                    (false, true) => t + n - d,
+                    (false, false) => continue,
+                };
+
+                for (sound, amplitude) in h.column(started).iter().enumerate().filter(|(_, amplitude)| **amplitude != 0.0) {
+                    let atom = basis.slice(ndarray::s![d * m..(d + 1) * m, sound]);
+                    for (error, value) in error.iter_mut().zip(atom) {
+                        *error += *value as f64 * *amplitude as f64;
+                    }
+                }
+            }
+            error.iter().map(|error| error * error).sum::<f64>()
+        })
+        .collect();
+}
+
+/// whether the solve precomputes the gram matrix Q = W^T W and p = W^T V,
+/// so every iteration is Q h - p (r x r by r x n) instead of W h - V and
+/// W^T of that (m x r by r x n, twice). that's less work when r < 2m, e.g.
+/// a basis of fewer sounds than twice its features. atoms spanning ticks
+/// are shifted against each other in every tick, so they never use it
+pub fn use_gram(m: usize, r: usize, span: usize) -> bool {
+    span == 1 && r < 2 * m
+}
+
+/// `cpu_conv_pgd_nnls` from `initial`, stopping early when cancellation is
+/// requested like `pgd_nnls_from`. `circular` solves the ticks as a loop,
+/// the atoms of the last ticks ringing on into the first, see `fold`
+pub fn cpu_conv_pgd_nnls_from(
+    data: ArrayView2<f32>,
+    basis: ArrayView2<f32>,
+    span: usize,
+    initial: Array2<f32>,
+    iters: usize,
+    step: f32,
+    circular: bool,
+) -> (Array2<f32>, usize) {
+    let (m, n) = data.dim();
+    let (rows, r) = basis.dim();
+
+    assert_eq!(rows, span * m);
+    assert_eq!(initial.dim(), (r, n));
+
+    let mut h = initial;
+    let mut completed = 0;
+
+    // columns of h only depend on the same column of V when atoms are one
+    // tick long, so blocks of ticks are solved side by side on every core.
+    // longer atoms tie neighbouring ticks together and are solved whole
+    let block = match span {
+        1 => CPU_BLOCK,
+        _ => n.max(1),
+    };
+    let mut h_blocks = h.axis_chunks_iter_mut(Axis(1), block).collect::<Vec<ArrayViewMut2<f32>>>();
+
+    // with the gram matrix every block is solved against W^T V instead of V
+    let gram = use_gram(m, r, span).then(|| basis.t().dot(&basis));
+    let targets = data.axis_chunks_iter(Axis(1), block)
+        .map(|data| match gram {
+            Some(_) => CowArray::from(basis.t().dot(&data)),
+            None => CowArray::from(data),
+        })
+        .collect::<Vec<CowArray<f32, Ix2>>>();
+
+    // the objective is only worked out when it's recorded. with the gram
+    // matrix it's ½h^T(Qh - 2p), plus the ½‖V‖² that leaves out
+    let recorder = convergence::recorder();
+    let constant = match (&gram, &recorder) {
+        (Some(_), Some(_)) => 0.5 * data.iter().map(|x| x * x).sum::<f32>(),
+        _ => 0.0,
+    };
+
+    for _ in 0..iters {
+        if cancel::requested() {
+            event!(Level::WARN, "solve cancelled after {} iterations", completed);
+            break;
+        }
+
+        let objective = h_blocks.par_iter_mut().zip(targets.par_iter()).map(|(h, target)| {
+            let (grad, objective) = match &gram {
+                Some(gram) => {
+                    let grad = gram.dot(h) - target;
+                    let objective = match recorder {
+                        Some(_) => 0.5 * h.iter().zip(&grad).zip(target.iter()).map(|((h, g), p)| h * (g - p)).sum::<f32>(),
+                        None => 0.0,
+                    };
+                    (grad, objective)
+                },
+                None => {
+                    let error = fold(basis.dot(h).view(), span, circular) - target;
+                    let objective = match recorder {
+                        Some(_) => 0.5 * error.iter().map(|e| e * e).sum::<f32>(),
+                        None => 0.0,
+                    };
+                    (basis.t().dot(&unfold(error.view(), span, circular)), objective)
+                },
+            };
+            h.scaled_add(-step, &grad);
+            h.mapv_inplace(|x| x.max(0.0));
+            objective
+        }).sum::<f32>();
+
+        if let Some(recorder) = &recorder {
+            recorder.add(completed, constant + objective);
+        }
+        completed += 1;
+        event!(Level::INFO, progress = true, iteration = completed, iterations = iters, "solving");
+        progress::report(completed, iters);
+    }
+
+    drop(h_blocks);
+    (h, completed)
+}
+
 pub fn pgd_nnls(
     data: Array2<f32>,
     basis: Array2<f32>,
     iters: usize,
     step: f32,
 ) -> Array2<f32> {
-    let _span = span!(Level::TRACE, "pgd_nnls", "gpu");
+    let (_, n) = data.dim();
+    let (_, r) = basis.dim();
 
-    let (m1, n) = data.dim();
-    let (m2, r) = basis.dim();
+    pgd_nnls_from(data, basis, Array2::zeros((r, n)), iters, step).0
+}
 
-    assert_eq!(m1, m2);
+/// tiles picked for each device by name, see `tuning`. devices that
+/// haven't been tuned use the default
+static TILES: LazyLock<RwLock<HashMap<String, Tiles>>> = LazyLock::new(Default::default);
+
+pub fn use_tiles(device: Device, tiles: Tiles) -> Result<(), SolverError> {
+    TILES.write().unwrap().insert(device.name()?, tiles);
+    Ok(())
+}
+
+fn tiles_for(device: Device) -> Result<Tiles, SolverError> {
+    Ok(TILES.read().unwrap().get(&device.name()?).copied().unwrap_or_default())
+}
+
+/// columns of the basis, or ticks of V, uploaded per block, so neither is
+/// ever copied whole on the host
+static UPLOAD_BLOCK: usize = 4096;
+
+/// picks devices by their index on the first platform, or just the first
+/// device when `indices` is empty
+pub fn select_devices(indices: &[usize]) -> Result<Vec<Device>, SolverError> {
+    let platform = ocl::Platform::first()?;
+    let devices = Device::list_all(platform)?;
+
+    if indices.is_empty() {
+        return match devices.first() {
+            Some(device) => Ok(vec![*device]),
+            None => Err(SolverError::NoDevices),
+        };
+    }
 
-    let ts_row = 2;
-    let ts_col = 64;
+    return indices.iter()
+        .map(|index| devices.get(*index).copied()
+            .ok_or(SolverError::NoSuchDevice { index: *index, found: devices.len() }))
+        .collect();
+}
 
+pub(crate) fn build_pro_que(device: Device, tiles: Tiles) -> Result<ProQue, ocl::Error> {
     let kernel = KERNEL.lines()
         .map(|line| {
             if line.contains("/// REPLACE_WITH_COL") {
-                format!("#define TS_COL {}", ts_col)
+                format!("#define TS_COL {}", tiles.col)
             } else if line.contains("/// REPLACE_WITH_ROW") {
-                format!("#define TS_ROW {}", ts_row)
+                format!("#define TS_ROW {}", tiles.row)
             } else {
                 line.to_string()
             }
@@ -139,56 +778,794 @@ pub fn pgd_nnls(
         .map(|line| line + "\n")
         .collect::<String>();
 
-    let pq = ProQue::builder()
+    // ProQue panics instead of erroring when there is no platform at all
+    ocl::Platform::first()?;
+
+    ProQue::builder()
         .src(kernel)
-        .dims((r.max(m1), n))
+        .device(device)
+        .dims(1)
         .build()
-        .unwrap();
+}
 
-    let basis: Vec<f32> = basis.into_iter().collect();
+/// one device's copy of the basis
+struct Replica {
+    pq: ProQue,
+    tiles: Tiles,
+    w: Buffer<f32>,
+    w_t: Buffer<f32>
+}
 
-    event!(Level::DEBUG, "copying W");
-    let buffer_w = Buffer::<f32>::builder()
-        .queue(pq.queue().clone())
-        .flags(ocl::flags::MEM_READ_ONLY)
-        .len(basis.len())
-        .copy_host_slice(&basis)
-        .build()
-        .unwrap();
+/// the basis W resident on one or more devices. it's uploaded as W^T (r x m),
+/// which is just every sound's features back to back, and W (m x r) is
+/// derived from it on the device before solving
+pub struct DeviceBasis {
+    replicas: Vec<Replica>,
+    m: usize,
+    r: usize
+}
+
+impl DeviceBasis {
+    /// uploads `r` columns (sounds), a block at a time, straight into the
+    /// device buffers. only the block currently being uploaded is held on
+    /// the host, so the full basis never has to exist in host memory. every
+    /// device gets the whole basis, see `pgd_nnls_device`
+    pub fn upload<I: IntoIterator<Item = Vec<Vec<f32>>>>(blocks: I, r: usize, devices: &[Device]) -> Result<Self, SolverError> {
+        let _span = span!(Level::DEBUG, "upload_basis", tag = "gpu").entered();
+
+        let mut blocks = blocks.into_iter().peekable();
+        let m = blocks.peek().and_then(|block| block.first()).map(|column| column.len()).unwrap_or(0);
+
+        let mut replicas = Vec::with_capacity(devices.len());
+        for device in devices {
+            event!(Level::DEBUG, "using device `{}`", device.name()?);
+            check_fits(*device, &[r * m, r * m], 0)?;
+
+            let tiles = tiles_for(*device)?;
+            let pq = build_pro_que(*device, tiles)?;
+            let required = (2 * r * m * size_of::<f32>()) as u64;
+
+            let w_t = Buffer::<f32>::builder()
+                .queue(pq.queue().clone())
+                .len(r * m)
+                .build()
+                .map_err(|e| allocation_error(e, required, *device))?;
 
-    event!(Level::DEBUG, "generating W^T");
-    let mut w_t = vec![0.0f32; r * m1];
-    for i in 0..r {
-        for j in 0..m1 {
-            w_t[j * r + i] = basis[i * m1 + j];
+            let w = Buffer::<f32>::builder()
+                .queue(pq.queue().clone())
+                .len(r * m)
+                .build()
+                .map_err(|e| allocation_error(e, required, *device))?;
+
+            replicas.push(Replica { pq, tiles, w, w_t });
+        }
+
+        let mut offset = 0;
+        for block in blocks {
+            let flat = block.into_iter().flatten().collect::<Vec<f32>>();
+            event!(Level::TRACE, "uploading {} columns at {}", flat.len() / m.max(1), offset / m.max(1));
+            for replica in &replicas {
+                replica.w_t.write(&flat).offset(offset).enq()
+                    .map_err(|e| allocation_error(e, (2 * r * m * size_of::<f32>()) as u64, replica.pq.device()))?;
+            }
+            offset += flat.len();
         }
+
+        if offset != r * m {
+            return Err(SolverError::BasisSize { uploaded: offset, m, r });
+        }
+
+        Ok(Self { replicas, m, r })
     }
 
-    event!(Level::DEBUG, "copying W^T");
+    pub fn from_array(basis: ArrayView2<f32>, devices: &[Device]) -> Result<Self, SolverError> {
+        let (_, r) = basis.dim();
+        let blocks = basis
+            .axis_chunks_iter(Axis(1), UPLOAD_BLOCK)
+            .map(|block| block.columns().into_iter().map(|column| column.to_vec()).collect::<Vec<Vec<f32>>>());
 
-    let buffer_w_t = Buffer::<f32>::builder()
-        .queue(pq.queue().clone())
-        .flags(ocl::flags::MEM_READ_ONLY)
-        .len(w_t.len())
-        .copy_host_slice(&w_t)
-        .build()
-        .unwrap();
-    drop(w_t);
-    drop(basis);
+        Self::upload(blocks, r, devices)
+    }
+
+    /// (m, r), same as the host array it represents
+    pub fn dim(&self) -> (usize, usize) {
+        (self.m, self.r)
+    }
+
+    /// how many devices the basis lives on
+    pub fn devices(&self) -> usize {
+        self.replicas.len()
+    }
+
+    /// `normalize_to_minus_plus`, on the device, given the basis' min and max
+    pub fn normalize_to_minus_plus(&self, min_val: f32, max_val: f32) -> Result<(), SolverError> {
+        let range = max_val - min_val;
+        if range > 0.0 {
+            self.affine(2.0 / range, -2.0 * min_val / range - 1.0)
+        } else {
+            self.affine(0.0, 0.0)
+        }
+    }
+
+    /// `normalize_to_global`, on the device, given the basis' min and max
+    pub fn normalize_to_global(&self, min_val: f32, max_val: f32) -> Result<(), SolverError> {
+        let peak = min_val.abs().max(max_val.abs());
+        if peak > 0.0 {
+            self.affine(1.0 / peak, 0.0)
+        } else {
+            Ok(())
+        }
+    }
 
-    let data: Vec<f32> = data.into_iter().collect();
+    /// x <- x * scale + offset for every value of the basis
+    pub fn affine(&self, scale: f32, offset: f32) -> Result<(), SolverError> {
+        let len = self.r * self.m;
+        for replica in &self.replicas {
+            let k_affine = replica.pq.kernel_builder("affine")
+                .global_work_size(len)
+                .arg(&replica.w_t)
+                .arg(scale)
+                .arg(offset)
+                .arg(len as u32)
+                .build()?;
+
+            unsafe { k_affine.enq()?; }
+            replica.pq.finish()?;
+        }
+
+        Ok(())
+    }
+
+    /// zeroes the given columns (sounds), so the solve can't use them. their
+    /// gradient is 0, so their rows of h stay where they start
+    pub fn zero_columns(&self, columns: &[usize]) -> Result<(), SolverError> {
+        let zeros = vec![0.0; self.m];
+        for replica in &self.replicas {
+            for column in columns {
+                replica.w_t.write(&zeros).offset(column * self.m).enq()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// largest eigenvalue of W^T W by power iteration, which is the
+    /// lipschitz constant L of the NNLS gradient. steps up to 1/L are
+    /// guaranteed not to diverge. runs W x and W^T (W x) through the
+    /// solver's own kernels with a single column
+    pub fn lipschitz(&self, iters: usize) -> Result<f32, SolverError> {
+        let _span = span!(Level::DEBUG, "lipschitz", tag = "gpu").entered();
+
+        self.sync_w()?;
+        let (m, r) = (self.m, self.r);
+        let replica = &self.replicas[0];
+        let pq = &replica.pq;
+        let tiles = replica.tiles;
+
+        let mut x = vec![1.0 / (r as f32).sqrt(); r];
+
+        let buffer_x = Buffer::<f32>::builder()
+            .queue(pq.queue().clone())
+            .len(r)
+            .copy_host_slice(&x)
+            .build()?;
+
+        let buffer_zero = Buffer::<f32>::builder()
+            .queue(pq.queue().clone())
+            .len(m)
+            .fill_val(0.0)
+            .build()?;
+
+        let buffer_wx = Buffer::<f32>::builder()
+            .queue(pq.queue().clone())
+            .len(m)
+            .build()?;
+
+        let buffer_y = Buffer::<f32>::builder()
+            .queue(pq.queue().clone())
+            .len(r)
+            .build()?;
+
+        let k_w = pq.kernel_builder("gemm_whv")
+            .global_work_size((m.div_ceil(tiles.row) * tiles.row, tiles.col))
+            .local_work_size((tiles.row, tiles.col))
+            .arg(&replica.w)
+            .arg(&buffer_x)
+            .arg(&buffer_zero)
+            .arg(&buffer_wx)
+            .arg(m as u32)
+            .arg(1u32)
+            .arg(r as u32)
+            .build()?;
+
+        let k_w_t = pq.kernel_builder("gemm_grad")
+            .global_work_size((r.div_ceil(tiles.row) * tiles.row, tiles.col))
+            .local_work_size((tiles.row, tiles.col))
+            .arg(&replica.w_t)
+            .arg(&buffer_wx)
+            .arg(&buffer_y)
+            .arg(r as u32)
+            .arg(1u32)
+            .arg(m as u32)
+            .build()?;
+
+        let mut estimate = 0.0;
+        for _ in 0..iters {
+            unsafe {
+                k_w.enq()?;
+                k_w_t.enq()?;
+            }
+            buffer_y.read(&mut x).enq()?;
+
+            // x had unit norm, so |W^T W x| converges to the eigenvalue
+            estimate = x.iter().map(|v| v * v).sum::<f32>().sqrt();
+            if estimate == 0.0 {
+                break;
+            }
+
+            x.iter_mut().for_each(|v| *v /= estimate);
+            buffer_x.write(&x).enq()?;
+        }
+
+        Ok(estimate)
+    }
+
+    /// W (m x r) read back from the first device
+    pub fn to_host(&self) -> Result<Array2<f32>, SolverError> {
+        self.sync_w()?;
+
+        let mut w = vec![0.0; self.m * self.r];
+        self.replicas[0].w.read(&mut w).enq()?;
+
+        // read back from a buffer of exactly m * r
+        Ok(Array2::from_shape_vec((self.m, self.r), w).unwrap())
+    }
+
+    /// ‖W h - V‖ for atoms spanning `span` ticks, folded like the solve
+    /// folds them, worked out on the first device. only the norm itself
+    /// is read back
+    pub fn residual_norm(&self, data: ArrayView2<f32>, h: ArrayView2<f32>, span: usize, circular: bool) -> Result<f32, SolverError> {
+        let (m, n) = data.dim();
+        assert_eq!(m * span, self.m);
+        assert_eq!(h.dim(), (self.r, n));
+
+        self.sync_w()?;
+        let replica = &self.replicas[0];
+        let pq = &replica.pq;
+        let (ts_row, ts_col) = (replica.tiles.row, replica.tiles.col);
+
+        // V only in the first block of the stacked rows, as in the solve
+        let mut v = data.iter().cloned().collect::<Vec<f32>>();
+        v.resize(self.m * n, 0.0);
+        let upload = |values: &[f32]| Buffer::<f32>::builder()
+            .queue(pq.queue().clone())
+            .len(values.len())
+            .copy_host_slice(values)
+            .build();
+        let buffer_v = upload(&v)?;
+        let buffer_h = upload(&h.iter().cloned().collect::<Vec<f32>>())?;
+        drop(v);
+
+        let buffer_whv = Buffer::<f32>::builder()
+            .queue(pq.queue().clone())
+            .len(self.m * n)
+            .build()?;
+
+        let k_whv = pq.kernel_builder("gemm_whv")
+            .global_work_size((self.m.div_ceil(ts_row) * ts_row, n.div_ceil(ts_col) * ts_col))
+            .local_work_size((ts_row, ts_col))
+            .arg(&replica.w)
+            .arg(&buffer_h)
+            .arg(&buffer_v)
+            .arg(&buffer_whv)
+            .arg(self.m as u32)
+            .arg(n as u32)
+            .arg(self.r as u32)
+            .build()?;
+        unsafe { k_whv.enq()?; }
+
+        let error = match span {
+            1 => buffer_whv,
+            _ => {
+                let buffer_folded = Buffer::<f32>::builder()
+                    .queue(pq.queue().clone())
+                    .len(m * n)
+                    .build()?;
+
+                let k_fold = pq.kernel_builder("fold")
+                    .global_work_size((m, n))
+                    .arg(&buffer_whv)
+                    .arg(&buffer_folded)
+                    .arg(m as u32)
+                    .arg(n as u32)
+                    .arg(span as u32)
+                    .arg(circular as u32)
+                    .build()?;
+                unsafe { k_fold.enq()?; }
+                buffer_folded
+            },
+        };
+
+        let reduction = Reduction::new(pq)?;
+        let squared = reduction.run(&reduction.dot(pq, &error, &error, m * n)?)?;
+        Ok(squared.max(0.0).sqrt())
+    }
+
+    /// the bytes both copies of the basis take up on a device
+    fn bytes(&self) -> u64 {
+        (2 * self.m * self.r * size_of::<f32>()) as u64
+    }
+
+    fn sync_w(&self) -> Result<(), SolverError> {
+        for replica in &self.replicas {
+            let k_transpose = replica.pq.kernel_builder("transpose")
+                .global_work_size((self.r, self.m))
+                .arg(&replica.w_t)
+                .arg(&replica.w)
+                .arg(self.r as u32)
+                .arg(self.m as u32)
+                .build()?;
+
+            unsafe { k_transpose.enq()?; }
+            replica.pq.finish()?;
+        }
+
+        Ok(())
+    }
+}
+
+/// sums dot products of device buffers on the device, in two passes of
+/// `REDUCE_GROUPS` work groups and then one, so only the sum is read back
+struct Reduction {
+    groups: Buffer<f32>,
+    out: Buffer<f32>,
+    k_sum: Kernel
+}
+
+impl Reduction {
+    fn new(pq: &ProQue) -> Result<Self, ocl::Error> {
+        let groups = Buffer::<f32>::builder()
+            .queue(pq.queue().clone())
+            .len(REDUCE_GROUPS)
+            .build()?;
+
+        let out = Buffer::<f32>::builder()
+            .queue(pq.queue().clone())
+            .len(1)
+            .build()?;
+
+        let k_sum = Self::sum(pq, &groups, &out, 1.0, false)?;
+
+        Ok(Self { groups, out, k_sum })
+    }
+
+    fn sum(pq: &ProQue, groups: &Buffer<f32>, out: &Buffer<f32>, weight: f32, accumulate: bool) -> Result<Kernel, ocl::Error> {
+        pq.kernel_builder("reduce_sum")
+            .global_work_size(REDUCE_LOCAL)
+            .local_work_size(REDUCE_LOCAL)
+            .arg(groups)
+            .arg_local::<f32>(REDUCE_LOCAL)
+            .arg(out)
+            .arg(REDUCE_GROUPS as u32)
+            .arg(weight)
+            .arg(accumulate as u32)
+            .build()
+    }
+
+    /// the passes of `weight` times a . b, added to the terms before it
+    /// unless it's the `first`, for `run_terms`
+    fn term(&self, pq: &ProQue, a: &Buffer<f32>, b: &Buffer<f32>, len: usize, weight: f32, first: bool) -> Result<(Kernel, Kernel), ocl::Error> {
+        Ok((self.dot(pq, a, b, len)?, Self::sum(pq, &self.groups, &self.out, weight, !first)?))
+    }
+
+    /// the first pass of a . b, over their first `len` values, for `run`
+    fn dot(&self, pq: &ProQue, a: &Buffer<f32>, b: &Buffer<f32>, len: usize) -> Result<Kernel, ocl::Error> {
+        pq.kernel_builder("reduce_dot")
+            .global_work_size(REDUCE_LOCAL * REDUCE_GROUPS)
+            .local_work_size(REDUCE_LOCAL)
+            .arg(a)
+            .arg(b)
+            .arg_local::<f32>(REDUCE_LOCAL)
+            .arg(&self.groups)
+            .arg(len as u32)
+            .build()
+    }
+
+    /// runs both passes of a `dot` and reads back its single value
+    fn run(&self, dot: &Kernel) -> Result<f32, SolverError> {
+        unsafe {
+            dot.enq()?;
+            self.k_sum.enq()?;
+        }
+
+        self.read()
+    }
+
+    /// runs the passes of every `term` and reads back their weighted sum,
+    /// with a single read however many there are
+    fn run_terms(&self, terms: &[(Kernel, Kernel)]) -> Result<f32, SolverError> {
+        for (dot, sum) in terms {
+            unsafe {
+                dot.enq()?;
+                sum.enq()?;
+            }
+        }
+
+        self.read()
+    }
+
+    fn read(&self) -> Result<f32, SolverError> {
+        let mut sum = vec![0.0; 1];
+        self.out.read(&mut sum).enq()?;
+        Ok(sum[0])
+    }
+}
+
+/// a basis on the devices, or on the host when it didn't fit on them
+pub enum Basis {
+    Device(DeviceBasis),
+    Host(Array2<f32>)
+}
+
+impl Basis {
+    /// `DeviceBasis::upload`, falling back to keeping the basis on the host
+    /// when the devices are out of memory. the blocks are made again for
+    /// that, so only one copy of the basis is ever held
+    pub fn upload<I, F>(mut blocks: F, r: usize, devices: &[Device]) -> Result<Self, SolverError>
+    where
+        I: IntoIterator<Item = Vec<Vec<f32>>>,
+        F: FnMut() -> I,
+    {
+        match DeviceBasis::upload(blocks(), r, devices) {
+            Err(SolverError::OutOfMemory { required, available }) => {
+                event!(Level::WARN, "the basis needs {} MiB of device memory but {} MiB are available, keeping it on the host", required >> 20, available >> 20);
+                Ok(Basis::host(blocks(), r))
+            },
+            basis => Ok(Basis::Device(basis?)),
+        }
+    }
+
+    /// the basis of `upload`, kept on the host
+    pub fn host<I: IntoIterator<Item = Vec<Vec<f32>>>>(blocks: I, r: usize) -> Self {
+        let columns = blocks.into_iter().flatten().collect::<Vec<Vec<f32>>>();
+        let m = columns.first().map(|column| column.len()).unwrap_or(0);
+
+        // every column has the features of one sound, all the same length
+        let basis = Array2::from_shape_vec((r, m), columns.into_iter().flatten().collect()).unwrap();
+        return Basis::Host(basis.reversed_axes());
+    }
+
+    /// `DeviceBasis::from_array`, keeping `basis` on the host when the
+    /// devices are out of memory
+    pub fn from_array(basis: Array2<f32>, devices: &[Device]) -> Result<Self, SolverError> {
+        match DeviceBasis::from_array(basis.view(), devices) {
+            Err(SolverError::OutOfMemory { required, available }) => {
+                event!(Level::WARN, "the basis needs {} MiB of device memory but {} MiB are available, keeping it on the host", required >> 20, available >> 20);
+                Ok(Basis::Host(basis))
+            },
+            uploaded => Ok(Basis::Device(uploaded?)),
+        }
+    }
+
+    pub fn to_host(&self) -> Result<Array2<f32>, SolverError> {
+        match self {
+            Basis::Device(basis) => basis.to_host(),
+            Basis::Host(basis) => Ok(basis.clone()),
+        }
+    }
+
+    pub fn dim(&self) -> (usize, usize) {
+        match self {
+            Basis::Device(basis) => basis.dim(),
+            Basis::Host(basis) => basis.dim(),
+        }
+    }
+
+    pub fn normalize_to_minus_plus(&mut self, min_val: f32, max_val: f32) -> Result<(), SolverError> {
+        let range = max_val - min_val;
+        match range > 0.0 {
+            true => self.affine(2.0 / range, -2.0 * min_val / range - 1.0),
+            false => self.affine(0.0, 0.0),
+        }
+    }
+
+    pub fn normalize_to_global(&mut self, min_val: f32, max_val: f32) -> Result<(), SolverError> {
+        let peak = min_val.abs().max(max_val.abs());
+        match peak > 0.0 {
+            true => self.affine(1.0 / peak, 0.0),
+            false => Ok(()),
+        }
+    }
+
+    pub fn affine(&mut self, scale: f32, offset: f32) -> Result<(), SolverError> {
+        match self {
+            Basis::Device(basis) => basis.affine(scale, offset),
+            Basis::Host(basis) => {
+                basis.mapv_inplace(|x| x * scale + offset);
+                Ok(())
+            },
+        }
+    }
+
+    pub fn zero_columns(&mut self, columns: &[usize]) -> Result<(), SolverError> {
+        match self {
+            Basis::Device(basis) => basis.zero_columns(columns),
+            Basis::Host(basis) => {
+                columns.iter().for_each(|column| basis.column_mut(*column).fill(0.0));
+                Ok(())
+            },
+        }
+    }
+
+    pub fn lipschitz(&self, iters: usize) -> Result<f32, SolverError> {
+        match self {
+            Basis::Device(basis) => basis.lipschitz(iters),
+            Basis::Host(basis) => Ok(cpu_lipschitz(basis.view(), iters)),
+        }
+    }
+
+    /// 1/L of the basis folded over `span` frames, the largest step the
+    /// solve surely converges with. `None` when the basis is all zeros
+    pub fn auto_step(&self, span: usize) -> Result<Option<f32>, SolverError> {
+        // folding the frames of an atom together can grow the operator's
+        // norm by up to the number of frames
+        let lipschitz = self.lipschitz(64)? * span as f32;
+        return Ok((lipschitz > 0.0).then(|| 1.0 / lipschitz));
+    }
+}
+
+/// `conv_pgd_nnls_device`, or its CPU counterpart for a basis on the host.
+/// a solve that runs out of device memory moves the basis to the host.
+/// `data` can be mapped (see `mapped`): the CPU reads it a block of ticks
+/// at a time and a device a batch at a time, so it's never all in memory
+pub fn conv_pgd_nnls(
+    data: ArrayView2<f32>,
+    basis: &Basis,
+    span: usize,
+    initial: Array2<f32>,
+    iters: usize,
+    step: f32,
+    circular: bool,
+) -> Result<(Array2<f32>, usize), SolverError> {
+    let basis = match basis {
+        Basis::Host(basis) => return Ok(cpu_conv_pgd_nnls_from(data, basis.view(), span, initial, iters, step, circular)),
+        Basis::Device(basis) => basis,
+    };
+
+    match conv_pgd_nnls_device(data, basis, span, initial.view(), iters, step, circular) {
+        Err(SolverError::OutOfMemory { required, available }) => {
+            event!(Level::WARN, "the solve needs {} MiB of device memory but {} MiB are available, solving on the CPU", required >> 20, available >> 20);
+            Ok(cpu_conv_pgd_nnls_from(data, basis.to_host()?.view(), span, initial, iters, step, circular))
+        },
+        solved => solved,
+    }
+}
+
+/// same as `pgd_nnls`, but starts from `initial` instead of zeros and stops
+/// early at an iteration boundary when cancellation is requested. also
+/// returns how many iterations actually ran
+pub fn pgd_nnls_from(
+    data: Array2<f32>,
+    basis: Array2<f32>,
+    initial: Array2<f32>,
+    iters: usize,
+    step: f32,
+) -> (Array2<f32>, usize) {
+    let basis = DeviceBasis::from_array(basis.view(), &select_devices(&[]).unwrap()).unwrap();
+    pgd_nnls_device(data.view(), &basis, initial.view(), iters, step).unwrap()
+}
+
+/// every column of h only depends on the same column of V, so with more
+/// than one device the ticks are split between them and solved side by
+/// side, each against its own copy of the basis. nothing has to be
+/// exchanged between devices until the pieces are joined at the end
+pub fn pgd_nnls_device(
+    data: ArrayView2<f32>,
+    basis: &DeviceBasis,
+    initial: ArrayView2<f32>,
+    iters: usize,
+    step: f32,
+) -> Result<(Array2<f32>, usize), SolverError> {
+    let (m1, n) = data.dim();
+    let (m2, r) = basis.dim();
+
+    assert_eq!(m1, m2);
+    assert_eq!(initial.dim(), (r, n));
+
+    event!(Level::DEBUG, "generating W from W^T");
+    basis.sync_w()?;
+
+    let share = n.div_ceil(basis.replicas.len()).max(1);
+    let batches = basis.replicas.iter()
+        .map(|replica| ticks_that_fit(basis, replica, 1))
+        .collect::<Result<Vec<usize>, SolverError>>()?;
+
+    if batches.iter().any(|batch| *batch < share) {
+        event!(Level::WARN, "the ticks don't fit on the device at once, solving them in batches");
+    }
+
+    if basis.replicas.len() == 1 {
+        return pgd_nnls_batched(data, &basis.replicas[0], initial.view(), iters, step, batches[0]);
+    }
+
+    // every device adds its ticks' share of the objective into the same iterations
+    let recorder = convergence::recorder();
+    let pieces = std::thread::scope(|scope| {
+        let handles = basis.replicas.iter()
+            .zip(batches)
+            .zip(data.axis_chunks_iter(Axis(1), share).zip(initial.axis_chunks_iter(Axis(1), share)))
+            .map(|((replica, batch), (data, initial))| {
+                let recorder = recorder.clone();
+                scope.spawn(move || match recorder {
+                    Some(recorder) => recorder.within(|| pgd_nnls_batched(data, replica, initial, iters, step, batch)),
+                    None => pgd_nnls_batched(data, replica, initial, iters, step, batch),
+                })
+            })
+            .collect::<Vec<_>>();
+
+        // a panicking solve thread is a bug, not a device error, so it's passed on
+        handles.into_iter().map(|handle| handle.join().unwrap()).collect::<Result<Vec<_>, SolverError>>()
+    })?;
+
+    // cancellation is seen by every device at about the same time, but the
+    // checkpoint can only claim the iterations all of them finished
+    let completed = pieces.iter().map(|(_, completed)| *completed).min().unwrap_or(0);
+    let pieces = pieces.iter().map(|(h, _)| h.view()).collect::<Vec<_>>();
+
+    Ok((ndarray::concatenate(Axis(1), &pieces)?, completed))
+}
+
+/// `cpu_conv_pgd_nnls` on a device, where `basis` holds atoms that span
+/// `span` ticks. neighbouring ticks depend on each other, so unlike
+/// `pgd_nnls_device` it can't be split across devices and uses the first
+pub fn conv_pgd_nnls_device(
+    data: ArrayView2<f32>,
+    basis: &DeviceBasis,
+    span: usize,
+    initial: ArrayView2<f32>,
+    iters: usize,
+    step: f32,
+    circular: bool,
+) -> Result<(Array2<f32>, usize), SolverError> {
+    if span == 1 {
+        return pgd_nnls_device(data, basis, initial, iters, step);
+    }
+
+    let (m1, n) = data.dim();
+    let (m2, r) = basis.dim();
+
+    assert_eq!(m1 * span, m2);
+    assert_eq!(initial.dim(), (r, n));
+
+    if basis.replicas.len() > 1 {
+        event!(Level::WARN, "atoms spanning ticks can't be split across devices, solving on the first one");
+    }
+
+    // every tick depends on its neighbours, so there are no batches
+    let fit = ticks_that_fit(basis, &basis.replicas[0], span)?;
+    if fit < n {
+        let (global, _) = device_memory(basis.replicas[0].pq.device())?;
+        return Err(SolverError::OutOfMemory { required: basis.bytes() + tick_bytes(basis, span) * n as u64, available: global });
+    }
+
+    event!(Level::DEBUG, "generating W from W^T");
+    basis.sync_w()?;
+
+    return pgd_nnls_replica(data, &basis.replicas[0], initial.view(), iters, step, span, circular);
+}
+
+/// device memory the solve's buffers take up per tick. V and W h - V are
+/// stacked, h and its gradient have r rows, and the folded error is only
+/// there for atoms spanning ticks
+fn tick_bytes(basis: &DeviceBasis, span: usize) -> u64 {
+    let (stacked_m, r) = basis.dim();
+    if use_gram(stacked_m, r, span) {
+        // V until W^T V is made, then W^T V in place of the error
+        return ((stacked_m + 3 * r) * size_of::<f32>()) as u64;
+    }
+
+    let folded = if span > 1 { stacked_m / span } else { 0 };
+    ((2 * stacked_m + 2 * r + folded) * size_of::<f32>()) as u64
+}
+
+/// device memory the solve takes up besides the basis and its ticks, the
+/// gram matrix when there is one
+fn fixed_bytes(basis: &DeviceBasis, span: usize) -> u64 {
+    let (stacked_m, r) = basis.dim();
+    match use_gram(stacked_m, r, span) {
+        true => (r * r * size_of::<f32>()) as u64,
+        false => 0,
+    }
+}
+
+/// how many ticks the solve's buffers have room for next to the basis
+fn ticks_that_fit(basis: &DeviceBasis, replica: &Replica, span: usize) -> Result<usize, SolverError> {
+    let (stacked_m, r) = basis.dim();
+    let (global, max_alloc) = device_memory(replica.pq.device())?;
+
+    let per_tick = tick_bytes(basis, span);
+    let widest = (stacked_m.max(r) * size_of::<f32>()) as u64;
+
+    let reserved = basis.bytes() + fixed_bytes(basis, span);
+    let fit = (global.saturating_sub(reserved) / per_tick.max(1)).min(max_alloc / widest.max(1));
+    if fit == 0 {
+        return Err(SolverError::OutOfMemory { required: reserved + per_tick, available: global });
+    }
+
+    Ok(fit as usize)
+}
+
+/// `pgd_nnls_replica` on `batch` ticks at a time, for when they don't all
+/// fit on the device. only possible because ticks are independent
+fn pgd_nnls_batched(
+    data: ArrayView2<f32>,
+    replica: &Replica,
+    initial: ArrayView2<f32>,
+    iters: usize,
+    step: f32,
+    batch: usize,
+) -> Result<(Array2<f32>, usize), SolverError> {
+    if data.dim().1 <= batch {
+        return pgd_nnls_replica(data, replica, initial, iters, step, 1, false);
+    }
+
+    let pieces = data.axis_chunks_iter(Axis(1), batch)
+        .zip(initial.axis_chunks_iter(Axis(1), batch))
+        .map(|(data, initial)| pgd_nnls_replica(data, replica, initial, iters, step, 1, false))
+        .collect::<Result<Vec<_>, SolverError>>()?;
+
+    // a cancelled batch leaves the ones after it at 0 iterations
+    let completed = pieces.iter().map(|(_, completed)| *completed).min().unwrap_or(0);
+    let pieces = pieces.iter().map(|(h, _)| h.view()).collect::<Vec<_>>();
+
+    Ok((ndarray::concatenate(Axis(1), &pieces)?, completed))
+}
+
+fn pgd_nnls_replica(
+    data: ArrayView2<f32>,
+    replica: &Replica,
+    initial: ArrayView2<f32>,
+    iters: usize,
+    step: f32,
+    span: usize,
+    circular: bool,
+) -> Result<(Array2<f32>, usize), SolverError> {
+    let _span = span!(Level::TRACE, "pgd_nnls", "gpu");
+
+    let (m1, n) = data.dim();
+    let (r, _) = initial.dim();
+
+    let (ts_row, ts_col) = (replica.tiles.row, replica.tiles.col);
+    let pq = &replica.pq;
+
+    let buffer_w = &replica.w;
+    let buffer_w_t = &replica.w_t;
+
+    // atoms spanning ticks are solved against the stacked (span * m) rows,
+    // with V only in the first block so `fold` subtracts it once
+    let stacked_m = span * m1;
 
     event!(Level::DEBUG, "copying V");
     let buffer_v = Buffer::<f32>::builder()
         .queue(pq.queue().clone())
         .flags(ocl::flags::MEM_READ_ONLY)
-        .len(data.len())
-        .copy_host_slice(&data)
+        .len(stacked_m * n)
+        .fill_val(0.0)
         .build()
-        .unwrap();
-    drop(data);
+        ?;
 
-    let mut h: Vec<f32> = vec![0.0; r * n];
+    // V is row major on the device, so a block of ticks is a rectangle of
+    // it. mapped chunks are read from the file a block at a time this way
+    let row_pitch = n * size_of::<f32>();
+    for (index, block) in data.axis_chunks_iter(Axis(1), UPLOAD_BLOCK).enumerate() {
+        let cols = block.ncols();
+        let flat = block.iter().cloned().collect::<Vec<f32>>();
+        buffer_v.write(&flat)
+            .rect([index * UPLOAD_BLOCK, 0, 0], [0, 0, 0], [cols, m1, 1], row_pitch, row_pitch * stacked_m, cols * size_of::<f32>(), cols * m1 * size_of::<f32>())
+            .enq()?;
+    }
+
+    let mut h: Vec<f32> = initial.iter().cloned().collect();
 
     event!(Level::DEBUG, "copying h");
     let buffer_h = Buffer::<f32>::builder()
@@ -196,57 +1573,162 @@ pub fn pgd_nnls(
         .len(h.len())
         .copy_host_slice(&h)
         .build()
-        .unwrap();
-
-    let buffer_whv = Buffer::<f32>::builder()
-        .queue(pq.queue().clone())
-        .len(m1 * n)
-        .build()
-        .unwrap();
+        ?;
 
     let buffer_grad = Buffer::<f32>::builder()
         .queue(pq.queue().clone())
         .len(r * n)
         .build()
-        .unwrap();
+        ?;
 
-    let whv_global = (
-        ((m1 + ts_row - 1) / ts_row) * ts_row,
-        ((n + ts_col - 1) / ts_col) * ts_col
+    let grad_global = |cols: usize| (
+        r.div_ceil(ts_row) * ts_row,
+        cols.div_ceil(ts_col) * ts_col
     );
 
-    let k_whv = pq.kernel_builder("gemm_whv")
-        //.global_work_size((m1, n))
-        .global_work_size(whv_global)
-        //.local_work_size((ts, ts))
+    // W^T times an m x `cols` buffer, into an r x `cols` one
+    let gemm_w_t = |x: &Buffer<f32>, out: &Buffer<f32>, cols: usize| pq.kernel_builder("gemm_grad")
+        .global_work_size(grad_global(cols))
+        //.global_work_size((r, n))
         .local_work_size((ts_row, ts_col))
-        .arg(&buffer_w)
-        .arg(&buffer_h)
-        .arg(&buffer_v)
-        .arg(&buffer_whv)
-        .arg(m1 as u32)
-        .arg(n as u32)
+        .arg(buffer_w_t)
+        .arg(x)
+        .arg(out)
         .arg(r as u32)
-        .build()
-        .unwrap();
+        .arg(cols as u32)
+        .arg(stacked_m as u32)
+        .build();
 
-    let grad_global = (
-        ((r + ts_row - 1) / ts_row) * ts_row,
-        ((n + ts_col - 1) / ts_col) * ts_col
-    );
+    // the kernels every iteration runs, in order, to get the gradient.
+    // their buffers have to outlive the loop
+    let mut kernels = Vec::new();
+    let mut buffers = Vec::new();
 
-    let k_grad = pq.kernel_builder("gemm_grad")
-        .global_work_size(grad_global)
-        //.global_work_size((r, n))
-        .local_work_size((ts_row, ts_col))
-        .arg(&buffer_w_t)
-        .arg(&buffer_whv)
-        .arg(&buffer_grad)
-        .arg(r as u32)
-        .arg(n as u32)
-        .arg(m1 as u32)
-        .build()
-        .unwrap();
+    // when it's recorded, the objective is a weighted sum of dot products
+    // on the device plus a constant
+    let recorder = convergence::recorder();
+    let mut terms = Vec::new();
+    let mut constant = 0.0;
+    let reduction = Reduction::new(pq)?;
+
+    if use_gram(m1, r, span) {
+        event!(Level::DEBUG, "precomputing W^T W and W^T V");
+        let buffer_q = Buffer::<f32>::builder()
+            .queue(pq.queue().clone())
+            .len(r * r)
+            .build()
+            ?;
+
+        let buffer_p = Buffer::<f32>::builder()
+            .queue(pq.queue().clone())
+            .len(r * n)
+            .build()
+            ?;
+
+        unsafe {
+            gemm_w_t(buffer_w, &buffer_q, r)?.enq()?;
+            gemm_w_t(&buffer_v, &buffer_p, n)?.enq()?;
+        }
+        pq.finish()?;
+
+        // ½h^T(Qh - 2p), plus the ½‖V‖² that leaves out while there's still V
+        if recorder.is_some() {
+            constant = 0.5 * reduction.run(&reduction.dot(pq, &buffer_v, &buffer_v, m1 * n)?)?;
+            terms.extend([
+                reduction.term(pq, &buffer_h, &buffer_grad, r * n, 0.5, true)?,
+                reduction.term(pq, &buffer_p, &buffer_h, r * n, -0.5, false)?
+            ]);
+        }
+        drop(buffer_v);
+
+        // Q h - p is the gradient, which is what `gemm_whv` does with Q for W
+        let k_gram = pq.kernel_builder("gemm_whv")
+            .global_work_size(grad_global(n))
+            .local_work_size((ts_row, ts_col))
+            .arg(&buffer_q)
+            .arg(&buffer_h)
+            .arg(&buffer_p)
+            .arg(&buffer_grad)
+            .arg(r as u32)
+            .arg(n as u32)
+            .arg(r as u32)
+            .build()
+            ?;
+
+        kernels.push(("gram", k_gram));
+        buffers.extend([buffer_q, buffer_p]);
+    } else {
+        let buffer_whv = Buffer::<f32>::builder()
+            .queue(pq.queue().clone())
+            .len(stacked_m * n)
+            .build()
+            ?;
+
+        let whv_global = (
+            stacked_m.div_ceil(ts_row) * ts_row,
+            n.div_ceil(ts_col) * ts_col
+        );
+
+        let k_whv = pq.kernel_builder("gemm_whv")
+            //.global_work_size((m1, n))
+            .global_work_size(whv_global)
+            //.local_work_size((ts, ts))
+            .local_work_size((ts_row, ts_col))
+            .arg(buffer_w)
+            .arg(&buffer_h)
+            .arg(&buffer_v)
+            .arg(&buffer_whv)
+            .arg(stacked_m as u32)
+            .arg(n as u32)
+            .arg(r as u32)
+            .build()
+            ?;
+        kernels.push(("whv", k_whv));
+
+        // the folded error is unfolded back into `buffer_whv`, which the
+        // gradient reads either way
+        if span > 1 {
+            let buffer_folded = Buffer::<f32>::builder()
+                .queue(pq.queue().clone())
+                .len(m1 * n)
+                .build()
+                ?;
+
+            let k_fold = pq.kernel_builder("fold")
+                .global_work_size((m1, n))
+                .arg(&buffer_whv)
+                .arg(&buffer_folded)
+                .arg(m1 as u32)
+                .arg(n as u32)
+                .arg(span as u32)
+                .arg(circular as u32)
+                .build()
+                ?;
+
+            let k_unfold = pq.kernel_builder("unfold")
+                .global_work_size((stacked_m, n))
+                .arg(&buffer_folded)
+                .arg(&buffer_whv)
+                .arg(m1 as u32)
+                .arg(n as u32)
+                .arg(span as u32)
+                .arg(circular as u32)
+                .build()
+                ?;
+
+            kernels.extend([("fold", k_fold), ("unfold", k_unfold)]);
+            if recorder.is_some() {
+                terms.push(reduction.term(pq, &buffer_folded, &buffer_folded, m1 * n, 0.5, true)?);
+            }
+            buffers.push(buffer_folded);
+        }
+
+        kernels.push(("grad", gemm_w_t(&buffer_whv, &buffer_grad, n)?));
+        if recorder.is_some() && span == 1 {
+            terms.push(reduction.term(pq, &buffer_whv, &buffer_whv, m1 * n, 0.5, true)?);
+        }
+        buffers.extend([buffer_v, buffer_whv]);
+    }
 
     let k_update = pq.kernel_builder("update_h")
         .global_work_size((r, n))
@@ -256,28 +1738,47 @@ pub fn pgd_nnls(
         .arg(r as u32)
         .arg(n as u32)
         .build()
-        .unwrap();
+        ?;
 
+    let mut completed = 0;
     for i in 0..iters {
+        if cancel::requested() {
+            event!(Level::WARN, "solve cancelled after {} iterations", completed);
+            break;
+        }
+
+        let iteration = Instant::now();
+        for (name, kernel) in &kernels {
+            let start = Instant::now();
+            unsafe { kernel.enq()?; }
+            pq.finish()?;
+            event!(Level::TRACE, "{} done: {}ms", name, start.elapsed().as_millis());
+        }
+
+        // the objective is of h before this iteration's update
+        if let Some(recorder) = &recorder {
+            recorder.add(i, constant + reduction.run_terms(&terms)?);
+        }
+
         let start = Instant::now();
-        unsafe { k_whv.enq().unwrap(); }
-        pq.finish().unwrap();
-        event!(Level::TRACE, "whv done: {}ms", start.elapsed().as_millis());
-        let start = Instant::now();
-        unsafe { k_grad.enq().unwrap(); }
-        pq.finish().unwrap();
-        event!(Level::TRACE, "grad: {}ms", start.elapsed().as_millis());
-        let start = Instant::now();
-        unsafe { k_update.enq().unwrap(); }
-        pq.finish().unwrap();
-        event!(Level::TRACE, "update: {}ms", start.elapsed().as_millis());
-        event!(Level::TRACE, "iter {}, {}ms", i, start.elapsed().as_millis());
+        unsafe { k_update.enq()?; }
+        pq.finish()?;
+        event!(Level::TRACE, "update done: {}ms", start.elapsed().as_millis());
+        event!(Level::TRACE, "iter {}, {}ms", i, iteration.elapsed().as_millis());
+        completed += 1;
+        event!(Level::INFO, progress = true, iteration = completed, iterations = iters, "solving");
+        progress::report(completed, iters);
     }
 
+    drop(kernels);
+    drop(terms);
+    drop(buffers);
+
     event!(Level::TRACE, "reading...");
-    buffer_h.read(&mut h).enq().unwrap();
+    buffer_h.read(&mut h).enq()?;
 
     event!(Level::TRACE, "read! cpu");
-    Array2::from_shape_vec((r, n), h).unwrap()
+    // h was read back from a buffer of exactly r * n
+    Ok((Array2::from_shape_vec((r, n), h).unwrap(), completed))
 }
 
@@ -1,45 +1,432 @@
-use std::{collections::HashMap, io::Cursor, path::{Path, PathBuf}, sync::{atomic::{AtomicUsize, Ordering}, Arc}};
+use std::{collections::{HashMap, HashSet}, fmt::Display, io::Cursor, path::{Component, Path, PathBuf}, sync::{atomic::{AtomicUsize, Ordering}, Arc}, time::Duration};
 
-use anyhow::{anyhow, Error};
 use bytes::Bytes;
-use clap::Parser;
 use futures::stream::{self};
 use lewton::inside_ogg::OggStreamReader;
 use futures::StreamExt;
 use rayon::iter::{IntoParallelIterator, ParallelIterator};
 use serde::{Deserialize, Serialize};
-use tokio::fs;
-use tracing::{event, instrument, span, Level};
+use sha1_smol::Sha1;
+use tokio::{fs, io::{AsyncReadExt, BufReader}, sync::Semaphore};
+use tracing::{event, span, Level};
+
+use crate::{audio::Sound, mojang::{self, AssetIndex, MojangError, Object, Version, VersionManifest}, pack::Pack, timing::{Stage, Timing}};
+
+#[derive(thiserror::Error, Debug)]
+pub enum AssetsError {
+    #[error(transparent)]
+    Mojang(#[from] MojangError),
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("invalid json: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("sound definitions are not utf-8: {0}")]
+    Utf8(#[from] std::str::Utf8Error),
+    #[error("`sounds.json` is not in the asset index")]
+    MissingSoundsJson,
+    #[error("cache-only mode without cached sound definitions (`{0}`)")]
+    MissingSoundDefinitions(PathBuf),
+    #[error("failed to decode `{path}`: {message}")]
+    Decode { path: AssetKey, message: String },
+    #[error("there is no sound event `{0}`")]
+    UnknownEvent(String),
+    #[error("`{0}` plays one of several sounds at random, so it's never in the basis")]
+    UnpredictableEvent(String)
+}
+
+/// where an asset is in the asset index, e.g. `minecraft/sounds/note/harp.ogg`.
+/// always separated by `/`, so keys from the index, from sound definitions
+/// and from walking the cache on any platform compare equal
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct AssetKey(String);
+
+impl AssetKey {
+    pub fn new(key: &str) -> Self {
+        Self(key.replace('\\', "/"))
+    }
+
+    /// the key of a file at `path` relative to a cache directory
+    pub fn from_relative(path: &Path) -> Self {
+        let segments = path.components()
+            .filter_map(|component| match component {
+                Component::Normal(segment) => Some(segment.to_string_lossy()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
 
-use crate::{audio::Sound, mojang::{self, AssetIndex, Object, Version}};
+        Self(segments.join("/"))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// where the asset is cached under `cache_path`
+    pub fn to_path(&self, cache_path: &Path) -> PathBuf {
+        let mut path = cache_path.to_path_buf();
+        path.extend(self.0.split('/'));
+        path
+    }
+}
 
-#[derive(Parser, Debug)]
+impl Display for AssetKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
 pub enum FetchBehavior {
     CacheOnly,
     Refetch,
+    #[default]
     FetchIfMissing
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+/// how many things happen at once while fetching
+#[derive(Clone, Copy, Debug)]
+pub struct Concurrency {
+    /// requests to mojang
+    pub downloads: usize,
+    /// files open, reading the cache and writing downloads into it alike
+    pub files: usize
+}
+
+impl Default for Concurrency {
+    fn default() -> Self {
+        Self { downloads: 64, files: 32 }
+    }
+}
+
+/// everything about how assets are fetched
+#[derive(Clone, Debug)]
+pub struct FetchOptions {
+    pub behavior: FetchBehavior,
+    pub concurrency: Concurrency,
+    /// checks cached sounds against their recorded hashes, refetching the
+    /// ones that don't match. downloads are always checked
+    pub verify: bool,
+    /// never makes a request, whatever `behavior` says. what isn't cached
+    /// is missing
+    pub offline: bool
+}
+
+impl Default for FetchOptions {
+    fn default() -> Self {
+        Self { behavior: FetchBehavior::default(), concurrency: Concurrency::default(), verify: true, offline: false }
+    }
+}
+
+impl FetchOptions {
+    /// `behavior`, unless being offline rules it out
+    pub fn behavior(&self) -> FetchBehavior {
+        match self.offline {
+            true => FetchBehavior::CacheOnly,
+            false => self.behavior,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct ResourceLocation {
     pub name: PathBuf,
     pub volume: Option<f32>,
     pub pitch: Option<f32>,
     pub weight: Option<usize>,
+    /// `file` (the default) or `event`, when `name` is another sound event
+    /// whose sounds it plays
+    #[serde(rename = "type", alias = "resource_type")]
     pub resource_type: Option<String>
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Clone, Debug)]
 #[serde(untagged)]
 pub enum AudioResourceLocation {
     Partial(String),
     Full(ResourceLocation)
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Clone, Debug)]
 pub struct SoundDefinition {
+    /// missing from definitions that only set a subtitle or `replace`
+    #[serde(default)]
     pub sounds: Vec<AudioResourceLocation>,
-    pub subtitle: Option<String>
+    pub subtitle: Option<String>,
+    /// a resource pack's definition replaces the one before it instead of
+    /// adding its sounds to it
+    #[serde(default)]
+    pub replace: bool
+}
+
+/// the sound definitions of a `sounds.json`. a definition that doesn't
+/// parse is skipped with a warning instead of failing the rest, so a
+/// version or pack with an odd one still has all of its others
+pub fn parse_sound_definitions(json: &str) -> Result<HashMap<String, SoundDefinition>, serde_json::Error> {
+    let values = serde_json::from_str::<HashMap<String, serde_json::Value>>(json.trim_start_matches('\u{feff}'))?;
+
+    return Ok(values.into_iter()
+        .filter_map(|(name, value)| match SoundDefinition::deserialize(value) {
+            Ok(definition) => Some((name, definition)),
+            Err(e) => {
+                event!(Level::WARN, "skipping the sound definition of `{}`: '{}'", name, e);
+                None
+            },
+        })
+        .collect());
+}
+
+/// a sound event with a single sound, so it always plays the same file at
+/// the same pitch and volume
+#[derive(Clone, Debug)]
+pub struct PredictableSound {
+    pub event: String,
+    /// asset index key of the `.ogg`
+    pub path: AssetKey,
+    pub pitch: f32,
+    pub volume: f32,
+    /// the events it plays (`type: event`) on the way to its file, in order
+    pub via: Vec<String>
+}
+
+impl PredictableSound {
+    /// `sound`, its file, the way it's played: at its pitch and volume
+    pub fn play<'a>(&self, sound: &'a mut Sound) -> &'a mut Sound {
+        return sound.adjust_pitch(self.pitch).adjust_volume(self.volume);
+    }
+}
+
+/// the file, pitch and volume of the single sound of the last event of
+/// `chain`. a reference to another event plays that event's sound, with
+/// both pitches and volumes multiplied like the game does, and the events
+/// it went through are left in `chain`. `None` for a cycle, which is warned
+/// about unless one of its events is in `cycles` already
+fn resolve_sound(definitions: &HashMap<String, SoundDefinition>, chain: &mut Vec<String>, cycles: &mut HashSet<String>) -> Option<(PathBuf, f32, f32)> {
+    let [sound] = definitions.get(chain.last()?)?.sounds.as_slice() else {
+        return None;
+    };
+
+    let resource_location = match sound {
+        AudioResourceLocation::Partial(s) => return Some((PathBuf::from(s), 1.0, 1.0)),
+        AudioResourceLocation::Full(resource_location) => resource_location,
+    };
+
+    let (pitch, volume) = (resource_location.pitch.unwrap_or(1.0), resource_location.volume.unwrap_or(1.0));
+    match resource_location.resource_type.as_deref() {
+        None | Some("file") | Some("sound") => Some((resource_location.name.clone(), pitch, volume)),
+        Some("event") => {
+            let name = resource_location.name.to_string_lossy();
+            let referenced = name.strip_prefix("minecraft:").unwrap_or(&name).to_string();
+            if let Some(start) = chain.iter().position(|event| *event == referenced) {
+                let cycle = &chain[start..];
+                if !cycle.iter().any(|event| cycles.contains(event)) {
+                    event!(Level::WARN, "sound events play each other in a cycle, `{} -> {}`", cycle.join(" -> "), referenced);
+                    cycles.extend(cycle.iter().cloned());
+                }
+                return None;
+            }
+
+            chain.push(referenced);
+            let (path, referenced_pitch, referenced_volume) = resolve_sound(definitions, chain, cycles)?;
+            Some((path, pitch * referenced_pitch, volume * referenced_volume))
+        },
+        _ => None,
+    }
+}
+
+/// the predictable sounds of `definitions`, by event name so the basis is
+/// in the same order whatever order the definitions were hashed in
+pub fn predictable_sounds(definitions: &HashMap<String, SoundDefinition>) -> Vec<PredictableSound> {
+    let mut events = definitions.keys().collect::<Vec<_>>();
+    events.sort();
+
+    let mut cycles = HashSet::new();
+    return events.into_iter()
+        .filter_map(|event| {
+            let mut chain = vec![event.clone()];
+            let (name, pitch, volume) = resolve_sound(definitions, &mut chain, &mut cycles)?;
+            let path = sound_key(&name.to_string_lossy());
+            Some(PredictableSound { event: chain.remove(0), path, pitch, volume, via: chain })
+        })
+        .collect();
+}
+
+/// the predictable sound of `event`, with or without its `minecraft:`
+pub fn find_predictable_sound(definitions: &HashMap<String, SoundDefinition>, event: &str) -> Result<PredictableSound, AssetsError> {
+    let event = event.strip_prefix("minecraft:").unwrap_or(event);
+    if !definitions.contains_key(event) {
+        return Err(AssetsError::UnknownEvent(event.to_string()));
+    }
+
+    let mut chain = vec![event.to_string()];
+    let Some((name, pitch, volume)) = resolve_sound(definitions, &mut chain, &mut HashSet::new()) else {
+        return Err(AssetsError::UnpredictableEvent(event.to_string()));
+    };
+
+    return Ok(PredictableSound { event: chain.remove(0), path: sound_key(&name.to_string_lossy()), pitch, volume, via: chain });
+}
+
+/// `predictable` with one event for every file and pitch, the one that plays
+/// it through the fewest others. the rest would be the same basis column at
+/// another volume, splitting its amplitude and the tick's commands
+pub fn distinct_sounds(predictable: Vec<PredictableSound>) -> Vec<PredictableSound> {
+    let mut kept = HashMap::new();
+    for (index, sound) in predictable.iter().enumerate() {
+        kept.entry((sound.path.clone(), sound.pitch.to_bits()))
+            .and_modify(|kept: &mut usize| if sound.via.len() < predictable[*kept].via.len() { *kept = index })
+            .or_insert(index);
+    }
+
+    let kept = kept.into_values().collect::<HashSet<usize>>();
+    return predictable.into_iter()
+        .enumerate()
+        .filter(|(index, _)| kept.contains(index))
+        .map(|(_, sound)| sound)
+        .collect();
+}
+
+/// events with how long they play for
+pub type Durations = Vec<(String, Duration)>;
+
+/// sounds (by event) of `sounds` that play for longer than `longest`, like
+/// music discs and ambient loops, left out of it. the basis only has their
+/// first tick, which is often silence or an attack that isn't like the rest
+/// of them. they're returned with how long they are, longest first, and
+/// sounds of unknown length are kept
+pub fn exclude_long<T>(sounds: Vec<(String, T)>, durations: &HashMap<String, Duration>, longest: Duration) -> (Vec<(String, T)>, Durations) {
+    let mut excluded = Vec::new();
+    let kept = sounds.into_iter()
+        .filter(|(event, _)| match durations.get(event) {
+            Some(duration) if *duration > longest => {
+                excluded.push((event.clone(), *duration));
+                false
+            },
+            _ => true,
+        })
+        .collect();
+
+    excluded.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    return (kept, excluded);
+}
+
+/// the asset key of a sound named in a definition, `namespace:path` or just
+/// the path of a `minecraft` one
+pub fn sound_key(name: &str) -> AssetKey {
+    let (namespace, path) = name.split_once(':').unwrap_or(("minecraft", name));
+    AssetKey::new(&format!("{}/sounds/{}.ogg", namespace, path))
+}
+
+/// what every predictable event plays, to tell whether two versions' events
+/// sound the same: the asset hash of its sound (the path, without an asset
+/// index), its pitch and volume
+pub fn event_sounds(definitions: &HashMap<String, SoundDefinition>, asset_index: Option<&AssetIndex>) -> HashMap<String, String> {
+    return predictable_sounds(definitions)
+        .into_iter()
+        .map(|predictable| {
+            let path = predictable.path.to_string();
+            let file = match asset_index {
+                Some(index) => index.objects.get(&path).map(|object| object.hash.clone()).unwrap_or(path),
+                None => path,
+            };
+            (predictable.event, format!("{} {} {}", file, predictable.pitch, predictable.volume))
+        })
+        .collect();
+}
+
+static MANIFEST_FILE: &str = "manifest.json";
+
+/// sha1 of every cached asset, keyed by its asset index path. lets us check
+/// the cache without the asset index (i.e. in cache-only mode)
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct CacheManifest {
+    pub hashes: HashMap<String, String>
+}
+
+impl CacheManifest {
+    pub async fn load(cache_path: &Path) -> Self {
+        let Ok(contents) = fs::read_to_string(cache_path.join(MANIFEST_FILE)).await else {
+            return Self::default();
+        };
+
+        serde_json::from_str(&contents).unwrap_or_else(|e| {
+            event!(Level::WARN, "cache manifest is unreadable, ignoring it: '{}'", e);
+            Self::default()
+        })
+    }
+
+    pub async fn save(&self, cache_path: &Path) -> Result<(), AssetsError> {
+        fs::create_dir_all(cache_path).await?;
+        Ok(write_atomic(&cache_path.join(MANIFEST_FILE), serde_json::to_string(self)?.as_bytes()).await?)
+    }
+}
+
+fn sha1_hex(bytes: &[u8]) -> String {
+    Sha1::from(bytes).digest().to_string()
+}
+
+/// writes next to the destination first and renames over it, so a crash
+/// mid-write never leaves a truncated file at `path`
+pub async fn write_atomic(path: &Path, bytes: &[u8]) -> std::io::Result<()> {
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(".part");
+    let temp_path = PathBuf::from(temp_path);
+
+    fs::write(&temp_path, bytes).await?;
+    fs::rename(&temp_path, path).await?;
+
+    Ok(())
+}
+
+static VERSION_MANIFEST_FILE: &str = "version_manifest.json";
+
+/// how long a cached version manifest is used before asking mojang again
+pub static VERSION_MANIFEST_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// the cached version manifest and how long ago it was written
+async fn read_cached_version_manifest(path: &Path) -> Option<(VersionManifest, Duration)> {
+    let contents = fs::read_to_string(path).await.ok()?;
+    let age = fs::metadata(path).await.ok()?
+        .modified().ok()?
+        .elapsed()
+        .unwrap_or_default();
+
+    match serde_json::from_str(&contents) {
+        Ok(manifest) => Some((manifest, age)),
+        Err(e) => {
+            event!(Level::WARN, "cached version manifest is unreadable, ignoring it: '{}'", e);
+            None
+        }
+    }
+}
+
+/// the version manifest from `url`, cached under `assets`. a cached copy is
+/// used while it is fresh (or always, in cache-only mode), and when fetching
+/// fails no matter how old it is
+pub async fn fetch_version_manifest(assets: &Path, options: &FetchOptions, url: &str) -> Result<VersionManifest, AssetsError> {
+    let _span = span!(Level::INFO, "fetch_version_manifest", tag = "assets").entered();
+
+    let cache_path = assets.join(VERSION_MANIFEST_FILE);
+    let cached = read_cached_version_manifest(&cache_path).await;
+
+    match (options.behavior(), &cached) {
+        (FetchBehavior::CacheOnly, Some((manifest, _))) => return Ok(manifest.clone()),
+        (FetchBehavior::FetchIfMissing, Some((manifest, age))) if *age < VERSION_MANIFEST_TTL => return Ok(manifest.clone()),
+        _ => {}
+    }
+
+    match mojang::fetch_version_manifest(url).await {
+        Ok(manifest) => {
+            fs::create_dir_all(assets).await?;
+            write_atomic(&cache_path, &serde_json::to_vec(&manifest)?).await?;
+            return Ok(manifest);
+        },
+        Err(e) => match cached {
+            Some((manifest, age)) => {
+                event!(Level::WARN, "failed to fetch the version manifest, using the one cached {} minutes ago: '{}'", age.as_secs() / 60, e);
+                return Ok(manifest);
+            },
+            None => return Err(e.into()),
+        },
+    }
 }
 
 fn visit_dirs(dir: &Path) -> std::io::Result<Vec<PathBuf>> {
@@ -60,47 +447,123 @@ fn visit_dirs(dir: &Path) -> std::io::Result<Vec<PathBuf>> {
     Ok(files)
 }
 
-pub async fn fetch_sound_definitions(assets: &PathBuf, version: &Version, behavior: &FetchBehavior, asset_index: &AssetIndex) -> Result<HashMap<String, SoundDefinition>, Error> {
+/// every asset of `version`, or none when only the cache is used
+pub async fn fetch_asset_index(version: &Version, options: &FetchOptions) -> Result<AssetIndex, AssetsError> {
+    match options.behavior() {
+        FetchBehavior::FetchIfMissing | FetchBehavior::Refetch => Ok(mojang::fetch_asset_index(version).await?),
+        FetchBehavior::CacheOnly => Ok(AssetIndex { objects: HashMap::new() }),
+    }
+}
+
+pub async fn fetch_sound_definitions(assets: &Path, version: &Version, options: &FetchOptions, asset_index: &AssetIndex) -> Result<HashMap<String, SoundDefinition>, AssetsError> {
     let _span = span!(Level::INFO, "fetch_sound_definitions", tag = "assets").entered();
 
     let assets_path = assets.join(PathBuf::from(version.id.clone()));
     let sound_definitions_path = &assets_path.join("sound_definitons.json");
 
-    match behavior {
+    match options.behavior() {
         FetchBehavior::CacheOnly => {
             if fs::try_exists(sound_definitions_path).await? {
-                return Ok(serde_json::from_str(&fs::read_to_string(sound_definitions_path).await?)?)
+                return Ok(parse_sound_definitions(&fs::read_to_string(sound_definitions_path).await?)?)
             } else {
-                event!(Level::ERROR, "cache-only mode specified without a sound definitions (`sound_definitions.json`) file");
-                event!(Level::ERROR, help = true, "run with refetch or normal fetch behavior");
-                return Err(anyhow!("missing sound_definitions"))
+                return Err(AssetsError::MissingSoundDefinitions(sound_definitions_path.to_path_buf()))
             }
         }
         FetchBehavior::FetchIfMissing => {
             if fs::try_exists(sound_definitions_path).await? {
-                return Ok(serde_json::from_str(&fs::read_to_string(sound_definitions_path).await?)?)
+                return Ok(parse_sound_definitions(&fs::read_to_string(sound_definitions_path).await?)?)
             }
         },
         FetchBehavior::Refetch => {}
     };
 
-    let sound_definition_asset = asset_index.objects.iter().find(|(k, _)| k.ends_with("sounds.json")).expect("could not find `sounds.json` in asset index");
+    let sound_definition_asset = asset_index.objects.iter().find(|(k, _)| k.ends_with("sounds.json")).ok_or(AssetsError::MissingSoundsJson)?;
     let defs_bytes = mojang::fetch_asset(&sound_definition_asset.1.hash).await?;
     let defs_json = str::from_utf8(&defs_bytes)?;
-    let defs = serde_json::from_str(&defs_json)?;
-    tokio::fs::create_dir_all(assets_path).await.expect("failed to create version directory");
-    tokio::fs::write(sound_definitions_path, defs_json).await.expect("failed to write to file");
+    let defs = parse_sound_definitions(defs_json)?;
+    tokio::fs::create_dir_all(assets_path).await?;
+    write_atomic(sound_definitions_path, defs_json.as_bytes()).await?;
     return Ok(defs);
 }
 
-/// converts all stereo sounds to mono
-pub async fn fetch_sounds(assets: &PathBuf, version: &Version, behavior: &FetchBehavior, asset_index: &AssetIndex) -> Result<HashMap<PathBuf, Sound>, Error> {
+/// buffer size of reads from the cache
+static READ_BUFFER: usize = 64 * 1024;
+
+async fn read_buffered(path: &Path, files: &Semaphore) -> Result<Vec<u8>, std::io::Error> {
+    let _permit = files.acquire().await.expect("the file semaphore is never closed");
+
+    let mut reader = BufReader::with_capacity(READ_BUFFER, fs::File::open(path).await?);
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes).await?;
+    Ok(bytes)
+}
+
+/// reads every cached `.ogg`, dropping files whose hash doesn't match what
+/// we expect so that they get refetched instead of poisoning decode. at
+/// most `files` permits worth of files are open at once, and the largest
+/// are read first so a few big ones don't hold up the end
+async fn read_local_sounds(cache_path: &Path, local_paths: &[PathBuf], expected_hashes: &HashMap<AssetKey, String>, files: &Semaphore) -> HashMap<AssetKey, Bytes> {
+    event!(Level::INFO, "reading local sound assets");
+
+    let mut sized_paths = Vec::with_capacity(local_paths.len());
+    for path in local_paths {
+        let size = fs::metadata(path).await.map(|metadata| metadata.len()).unwrap_or(0);
+        sized_paths.push((size, path));
+    }
+    sized_paths.sort_by(|(a, _), (b, _)| b.cmp(a));
+
+    let byte_results = stream::iter(sized_paths)
+        .map(|(_, path)| async move {
+            (path, read_buffered(path, files).await)
+        })
+        .buffer_unordered(files.available_permits().max(1))
+        .collect::<HashMap<&PathBuf, Result<Vec<u8>, std::io::Error>>>()
+        .await;
+
+    let mut sound_assets_bytes = HashMap::new();
+    let mut corrupt = 0;
+
+    for (sound_path, bytes_res) in byte_results {
+        let key = AssetKey::from_relative(sound_path.strip_prefix(cache_path).unwrap());
+        match bytes_res {
+            Ok(bytes) => {
+                if expected_hashes.get(&key).is_some_and(|hash| *hash != sha1_hex(&bytes)) {
+                    event!(Level::DEBUG, "`{}` does not match its recorded hash", key);
+                    corrupt += 1;
+                    continue;
+                }
+
+                sound_assets_bytes.insert(key, bytes.into());
+            },
+            Err(e) => {
+                event!(Level::WARN, "failed to read `{}`, '{}'", key, e);
+            },
+        }
+    }
+
+    if corrupt > 0 {
+        event!(Level::WARN, "{} cached assets failed their integrity check and were ignored", corrupt);
+    }
+
+    sound_assets_bytes
+}
+
+async fn write_cached(path: &Path, bytes: &[u8], files: &Semaphore) -> Result<(), std::io::Error> {
+    let _permit = files.acquire().await.expect("the file semaphore is never closed");
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).await?;
+    }
+    write_atomic(path, bytes).await
+}
+
+/// fetches (or reads from cache) the raw `.ogg` bytes of every sound
+pub async fn fetch_sounds(assets: &Path, version: &Version, options: &FetchOptions, asset_index: &AssetIndex) -> Result<HashMap<AssetKey, Bytes>, AssetsError> {
     let _span = span!(Level::INFO, "fetch_sounds", tag = "assets").entered();
+    let files = Semaphore::new(options.concurrency.files.max(1));
 
     event!(Level::INFO, "eggs in the morning with toast");
 
-    let mut sound_assets_bytes: HashMap<PathBuf, Bytes> = HashMap::new();
-
     let cache_path = assets.join(PathBuf::from(version.id.clone()));
     let local_paths: Vec<PathBuf> = visit_dirs(&cache_path)
         .unwrap_or(vec![])
@@ -108,36 +571,36 @@ pub async fn fetch_sounds(assets: &PathBuf, version: &Version, behavior: &FetchB
         .filter(|path| path.extension().is_some_and(|ext| ext == "ogg"))
         .collect();
 
-    let remote_objects = match behavior {
+    let mut manifest = CacheManifest::load(&cache_path).await;
+    let recorded_hashes = |manifest: &CacheManifest| manifest.hashes
+        .iter()
+        .map(|(key, hash)| (AssetKey::new(key), hash.clone()))
+        .collect::<HashMap<AssetKey, String>>();
+
+    let (mut sound_assets_bytes, remote_objects) = match options.behavior() {
         FetchBehavior::Refetch => {
-            asset_index.objects
+            let sound_objects = asset_index.objects
                 .iter()
                 .filter(|(key, _)| key.ends_with(".ogg"))
-                .map(|(key, val)| (PathBuf::from(key), val))
-                .collect::<HashMap<PathBuf, &Object>>()
+                .map(|(key, val)| (AssetKey::new(key), val))
+                .collect::<HashMap<AssetKey, &Object>>();
+
+            (HashMap::new(), sound_objects)
         },
         FetchBehavior::FetchIfMissing => {
-            event!(Level::INFO, "reading local sound assets");
-            let byte_results = stream::iter(&local_paths)
-                .map(|path| async move {
-                    (path, fs::read(path).await)
-                })
-                .buffer_unordered(512)
-                .collect::<HashMap<&PathBuf, Result<Vec<u8>, std::io::Error>>>()
-                .await;
-
-            for (sound_path, bytes_res) in byte_results {
-                let sound_path = sound_path.strip_prefix(&cache_path).unwrap();
-                match bytes_res {
-                    Ok(bytes) => {
-                        sound_assets_bytes.insert(sound_path.to_path_buf(), bytes.into());
-                    },
-                    Err(e) => {
-                        event!(Level::WARN, "failed to read `{:?}`, '{}'", sound_path, e);
-                    },
-                }
+            // the asset index is authoritative, the manifest only covers
+            // anything the index doesn't know about
+            let mut expected_hashes = HashMap::new();
+            if options.verify {
+                expected_hashes.extend(recorded_hashes(&manifest));
+                expected_hashes.extend(asset_index.objects
+                    .iter()
+                    .filter(|(key, _)| key.ends_with(".ogg"))
+                    .map(|(key, val)| (AssetKey::new(key), val.hash.clone())));
             }
 
+            let sound_assets_bytes = read_local_sounds(&cache_path, &local_paths, &expected_hashes, &files).await;
+
             let mut remote_total = 0;
             let sound_objects = asset_index.objects
                 .iter()
@@ -147,37 +610,17 @@ pub async fn fetch_sounds(assets: &PathBuf, version: &Version, behavior: &FetchB
                     }
                     key.ends_with(".ogg")
                 })
-                .map(|(key, val)| (PathBuf::from(key), val))
-                .filter(|(key, _)| !local_paths.contains(&cache_path.join(key)))
-                .collect::<HashMap<PathBuf, &Object>>();
+                .map(|(key, val)| (AssetKey::new(key), val))
+                .filter(|(key, _)| !sound_assets_bytes.contains_key(key))
+                .collect::<HashMap<AssetKey, &Object>>();
 
-            event!(Level::INFO, "found remote {} assets and {} local assets. fetching {} assets", remote_total, local_paths.len(), sound_objects.len());
+            event!(Level::INFO, "found remote {} assets and {} local assets. fetching {} assets", remote_total, sound_assets_bytes.len(), sound_objects.len());
 
-            sound_objects
+            (sound_assets_bytes, sound_objects)
         },
         FetchBehavior::CacheOnly => {
-            event!(Level::INFO, "reading local sound assets");
-            let byte_results = stream::iter(&local_paths)
-                .map(|path| async move {
-                    (path, fs::read(path).await)
-                })
-                .buffer_unordered(512)
-                .collect::<HashMap<&PathBuf, Result<Vec<u8>, std::io::Error>>>()
-                .await;
-
-            for (sound_path, bytes_res) in byte_results {
-                let sound_path = sound_path.strip_prefix(&cache_path).unwrap();
-                match bytes_res {
-                    Ok(bytes) => {
-                        sound_assets_bytes.insert(sound_path.to_path_buf(), bytes.into());
-                    },
-                    Err(e) => {
-                        event!(Level::WARN, "failed to read `{:?}`, '{}'", sound_path, e);
-                    },
-                }
-            }
-
-            HashMap::new()
+            let expected_hashes = if options.verify { recorded_hashes(&manifest) } else { HashMap::new() };
+            (read_local_sounds(&cache_path, &local_paths, &expected_hashes, &files).await, HashMap::new())
         },
     };
     
@@ -187,17 +630,24 @@ pub async fn fetch_sounds(assets: &PathBuf, version: &Version, behavior: &FetchB
         let total_requests = Arc::new(AtomicUsize::new(0));
         let errored_requests = Arc::new(AtomicUsize::new(0));
 
-        let request_results: HashMap<PathBuf, Result<Bytes, Error>> = stream::iter(remote_objects)
+        // downloads are written as they arrive, sharing the cap on open files
+        let (cache_path, files) = (&cache_path, &files);
+        let request_results: Vec<_> = stream::iter(remote_objects)
             .map(|(key, val)| {
                 let total_requests = total_requests.clone();
                 let errored_requests = errored_requests.clone();
                 async move {
-                    let res = (key, mojang::fetch_asset(&val.hash).await);
+                    let bytes = mojang::fetch_asset(&val.hash).await;
+                    let written = match &bytes {
+                        Ok(bytes) => write_cached(&key.to_path(cache_path), bytes, files).await,
+                        Err(_) => Ok(()),
+                    };
+                    let res = (key, val.hash.clone(), bytes);
 
                     let total = total_requests.load(Ordering::Relaxed);
                     total_requests.store(total+1, Ordering::Relaxed); 
                     let errored = errored_requests.load(Ordering::Relaxed);
-                    if res.1.is_err() { 
+                    if res.2.is_err() { 
                         errored_requests.store(errored+1, Ordering::Relaxed);
                     }
 
@@ -205,39 +655,90 @@ pub async fn fetch_sounds(assets: &PathBuf, version: &Version, behavior: &FetchB
 
                     event!(Level::DEBUG, "total: {}, errored: {}\r", total, errored);
 
-                    res
+                    written.map(|_| res)
                 }
             })
-            .buffer_unordered(512)
+            .buffer_unordered(options.concurrency.downloads.max(1))
             .collect()
             .await;
 
-        print!("\n");
+        eprintln!();
 
-        for (sound_path, bytes_res) in request_results {
+        for request_result in request_results {
+            let (sound_path, hash, bytes_res) = request_result?;
             match bytes_res {
                 Ok(bytes) => {
-                    sound_assets_bytes.insert((*sound_path).to_path_buf(), bytes.clone());
-                    let sound_path = cache_path.join(sound_path);
-                    fs::create_dir_all(sound_path.parent().unwrap()).await.expect("failed to create parent sound directory");
-                    fs::write(sound_path, bytes).await.expect("failed to write to file");
+                    manifest.hashes.insert(sound_path.to_string(), hash);
+                    sound_assets_bytes.insert(sound_path, bytes);
                 },
                 Err(e) => {
-                    event!(Level::WARN, "failed to fetch `{:?}`, '{:?}'", sound_path, e);
+                    event!(Level::WARN, "failed to fetch `{}`, '{:?}'", sound_path, e);
                 },
             }
         }
+
+        manifest.save(cache_path).await?;
     }
 
+    return Ok(sound_assets_bytes);
+}
+
+/// what an `.ogg` is before it gets truncated and mixed down to mono
+#[derive(Clone, Copy, Debug)]
+pub struct SoundInfo {
+    pub duration: Duration,
+    pub sample_rate: usize,
+    pub channels: u8
+}
+
+/// reads the vorbis identification header and the last page's granule
+/// position, which for vorbis is the number of samples. nothing is decoded
+pub fn sound_info(path: &AssetKey, bytes: &[u8]) -> Result<SoundInfo, AssetsError> {
+    let malformed = |message: &str| AssetsError::Decode { path: path.clone(), message: message.to_string() };
+
+    if !bytes.starts_with(b"OggS") || bytes.len() < 27 {
+        return Err(malformed("not an ogg stream"));
+    }
+
+    // the first page holds only the identification header
+    let header = 27 + bytes[26] as usize;
+    let ident = bytes.get(header..header + 16).ok_or_else(|| malformed("truncated identification header"))?;
+    if &ident[..7] != b"\x01vorbis" {
+        return Err(malformed("not a vorbis stream"));
+    }
+    let channels = ident[11];
+    let sample_rate = u32::from_le_bytes([ident[12], ident[13], ident[14], ident[15]]) as usize;
+
+    let last_page = bytes.windows(4).rposition(|w| w == b"OggS").unwrap_or(0);
+    let granule = bytes.get(last_page + 6..last_page + 14)
+        .map(|g| i64::from_le_bytes([g[0], g[1], g[2], g[3], g[4], g[5], g[6], g[7]]))
+        .ok_or_else(|| malformed("truncated last page"))?;
+
+    if sample_rate == 0 {
+        return Err(malformed("sample rate of 0"));
+    }
+
+    return Ok(SoundInfo {
+        // -1 marks a page without a finished packet, i.e. nothing to play
+        duration: Duration::from_secs_f64(granule.max(0) as f64 / sample_rate as f64),
+        sample_rate,
+        channels
+    });
+}
+
+/// decodes fetched `.ogg`s, converting all stereo sounds to mono
+pub fn decode_sounds(sound_assets_bytes: HashMap<AssetKey, Bytes>) -> Result<HashMap<AssetKey, Sound>, AssetsError> {
+    let _span = span!(Level::INFO, "decode_sounds", tag = "assets").entered();
+
     return Ok(sound_assets_bytes
         .into_par_iter()
-        .map(|(path, bytes)| -> Result<Option<(PathBuf, Sound)>, Error> {
+        .map(|(path, bytes)| -> Result<Option<(AssetKey, Sound)>, AssetsError> {
             let cursor = Cursor::new(bytes);
 
             let mut ogg_reader = OggStreamReader::new(cursor)
-                .map_err(|e| anyhow!("failed to decode {}, {}", path.to_string_lossy(), e))?;
+                .map_err(|e| AssetsError::Decode { path: path.clone(), message: e.to_string() })?;
 
-            let sample_rate: usize = ogg_reader.ident_hdr.audio_sample_rate.try_into().unwrap();
+            let sample_rate = ogg_reader.ident_hdr.audio_sample_rate as usize;
 
             let samples_per_tick = (sample_rate * 50) / 1000;
             let mut samples = Vec::new();
@@ -245,7 +746,7 @@ pub async fn fetch_sounds(assets: &PathBuf, version: &Version, behavior: &FetchB
             let stereo = ogg_reader.ident_hdr.audio_channels == 2;
             
             while let Some(channels) = ogg_reader.read_dec_packet_generic::<Vec<Vec<f32>>>()
-                .map_err(|e| anyhow!("failed to read packet for {}, {}", path.to_string_lossy(), e))? {
+                .map_err(|e| AssetsError::Decode { path: path.clone(), message: format!("failed to read packet, {}", e) })? {
                     
                 if samples.len() >= (samples_per_tick * 5) { // max pitch is 2, and pitch is only
                                                              // ever applied twice, so only ever
@@ -269,15 +770,95 @@ pub async fn fetch_sounds(assets: &PathBuf, version: &Version, behavior: &FetchB
                 }
             }
 
-            return Ok(Some((path.to_path_buf(), Sound {
+            return Ok(Some((path, Sound {
                 samples: samples.to_vec(),
                 sample_rate
             })));
         })
-        .collect::<Result<Vec<Option<(PathBuf, Sound)>>, Error>>()?
+        .collect::<Result<Vec<Option<(AssetKey, Sound)>>, AssetsError>>()?
         .iter()
-        .filter(|t| t.is_some())
-        .map(|t| t.clone().unwrap())
-        .collect::<HashMap<PathBuf, Sound>>()
+        .filter_map(|t| t.clone())
+        .collect::<HashMap<AssetKey, Sound>>()
     );
 }
+
+/// a version's asset index and sound definitions, fetched once for
+/// everything a run looks up about the version
+pub struct VersionAssets {
+    pub asset_index: AssetIndex,
+    pub definitions: HashMap<String, SoundDefinition>
+}
+
+pub async fn fetch_version_assets(version: &Version, assets: &Path, options: &FetchOptions) -> Result<VersionAssets, AssetsError> {
+    event!(Level::INFO, "fetching asset index");
+    let asset_index = fetch_asset_index(version, options).await?;
+
+    event!(Level::INFO, "fetching sound definitions");
+    let definitions = fetch_sound_definitions(assets, version, options, &asset_index).await?;
+
+    return Ok(VersionAssets { asset_index, definitions });
+}
+
+/// sound definitions and the raw `.ogg` bytes of every sound of `version`
+pub async fn fetch_assets(
+    version: &Version,
+    assets: &Path,
+    options: &FetchOptions
+) -> Result<(HashMap<String, SoundDefinition>, HashMap<AssetKey, Bytes>), AssetsError> {
+    let version_assets = fetch_version_assets(version, assets, options).await?;
+
+    event!(Level::INFO, "fetching sounds");
+    let sounds = fetch_sounds(assets, version, options, &version_assets.asset_index).await?;
+
+    return Ok((version_assets.definitions, sounds));
+}
+
+/// the sounds of `version_assets`, `version`'s. `pack` goes over the
+/// version's sounds, see `Pack::apply`
+pub async fn fetch_predictable_sounds(
+    version: Version,
+    version_assets: VersionAssets,
+    pack: Option<&Pack>,
+    assets: &Path,
+    options: &FetchOptions,
+    analysis_rate: usize,
+    timing: &mut Timing
+) -> Result<(Vec<(String, Sound)>, HashMap<String, Duration>), AssetsError> {
+    event!(Level::INFO, "fetching sounds");
+    let mut sounds = fetch_sounds(assets, &version, options, &version_assets.asset_index).await?;
+    let mut definitions = version_assets.definitions;
+    if let Some(pack) = pack {
+        pack.apply(&mut definitions, &mut sounds);
+    }
+
+    // decoding stops after a few ticks, so the full length is read beforehand
+    let lengths = sounds.iter()
+        .filter_map(|(path, bytes)| Some((path.clone(), sound_info(path, bytes).ok()?.duration)))
+        .collect::<HashMap<AssetKey, Duration>>();
+
+    timing.start(Stage::Decode);
+    let sounds = decode_sounds(sounds)?;
+
+    let predictable = distinct_sounds(predictable_sounds(&definitions));
+    let referencing = predictable.iter().filter(|predictable| !predictable.via.is_empty()).count();
+    if referencing > 0 {
+        event!(Level::DEBUG, "{} predictable events play other events", referencing);
+    }
+
+    let mut durations = HashMap::new();
+    let result = predictable
+        .into_iter()
+        .filter_map(|predictable| {
+            let mut sound = sounds.get(&predictable.path)?.clone();
+            if let Some(length) = lengths.get(&predictable.path) {
+                durations.insert(predictable.event.clone(), length.div_f32(predictable.pitch));
+            }
+            predictable.play(&mut sound).resample(analysis_rate);
+            Some((predictable.event, sound))
+        })
+        .collect::<Vec<(String, Sound)>>();
+
+    timing.finish();
+
+    Ok((result, durations))
+}
@@ -0,0 +1,40 @@
+use crate::preview::Model;
+
+/// whether a listener would hear a `playsound` at all, so commands nobody
+/// hears aren't exported. it's the game's model: gain is capped at 1.0 and
+/// falls off linearly to silence at the edge of the sound's range, see
+/// `Model::InGame`
+#[derive(Clone, Copy, Debug)]
+pub struct Audibility {
+    /// the quietest gain that's still heard
+    pub floor: f32,
+    /// blocks between the listener and where the sounds are played
+    pub distance: f32,
+    /// the song category's slider times the master slider, as a fraction
+    pub slider: f32,
+    /// `playsound`'s `minVolume`: listeners out of range still hear the
+    /// sound at this volume, instead of not at all. the exported commands
+    /// leave it at 0
+    pub min_volume: f32,
+}
+
+impl Audibility {
+    /// `floor` in decibels, e.g. -60.0
+    pub fn new(floor: f32, distance: f32, slider: f32) -> Self {
+        return Audibility { floor: 10.0_f32.powf(floor / 20.0), distance, slider, min_volume: 0.0 };
+    }
+
+    /// the gain a `playsound` of `volume` is heard at
+    pub fn heard(&self, volume: f32) -> f32 {
+        let gain = match Model::InGame.gain(volume, self.distance) {
+            0.0 => self.min_volume.min(1.0),
+            gain => gain,
+        };
+
+        return gain * self.slider;
+    }
+
+    pub fn is_audible(&self, volume: f32) -> bool {
+        return self.heard(volume) >= self.floor;
+    }
+}
@@ -4,33 +4,129 @@ macro_rules! time_as_samples {
         ($sample_rate * $time) / 1000
     };
 }
-use std::{cmp::min, collections::HashMap, sync::Arc};
+use std::{collections::{HashMap, HashSet}, hash::{DefaultHasher, Hash, Hasher}, ops::Range, sync::{Arc, RwLock}};
 
-use ndarray::Array2;
-use num_traits::Pow;
+use ocl::{Buffer, ProQue};
 use rayon::iter::{IntoParallelIterator, ParallelIterator};
-use rustfft::{num_complex::{Complex, Complex32, Complex64}, Fft, FftPlanner};
+use rustfft::{num_complex::{Complex, Complex32}, Fft, FftPlanner};
 pub use time_as_samples;
-use tracing::{event, instrument, span, Level};
+use tracing::{event, span, Level};
 
 use crate::algebra;
 
+pub static ANALYSIS_RATE: usize = 48000;
+
 fn lerp(start: f32, end: f32, t: f32) -> f32 {
     start * (1.0 - t) + end * t
 }
 
-pub fn permute_with_pitch(samples: Vec<(String, Sound)>, resolution: usize) -> Vec<((String, f32), Sound)> {
+/// windowed-sinc low-pass. `cutoff` is relative to the sample rate, so 0.5
+/// is nyquist
+fn low_pass(samples: &[f32], cutoff: f32) -> Vec<f32> {
+    let taps = 63;
+    let half = (taps / 2) as isize;
+
+    let window = apodize::hamming_iter(taps).collect::<Vec<f64>>();
+    let mut kernel = (0..taps)
+        .map(|i| {
+            let x = (i as isize - half) as f32;
+            let sinc = if x == 0.0 {
+                2.0 * cutoff
+            } else {
+                (2.0 * std::f32::consts::PI * cutoff * x).sin() / (std::f32::consts::PI * x)
+            };
+            sinc * window[i] as f32
+        })
+        .collect::<Vec<f32>>();
+
+    let sum: f32 = kernel.iter().sum();
+    for k in kernel.iter_mut() {
+        *k /= sum;
+    }
+
+    (0..samples.len() as isize)
+        .map(|i| {
+            kernel.iter()
+                .enumerate()
+                .map(|(k, weight)| {
+                    let index = i + k as isize - half;
+                    if index < 0 || index >= samples.len() as isize {
+                        0.0
+                    } else {
+                        samples[index as usize] * weight
+                    }
+                })
+                .sum()
+        })
+        .collect()
+}
+
+/// drops sounds whose audio is the same as another's, which happens when
+/// several events play the same file. they'd split their amplitude between
+/// them arbitrarily. samples are compared at 16-bit, so float noise from
+/// resampling doesn't keep copies apart. of every group of copies the
+/// shortest event name is kept (alphabetically first on a tie)
+/// seconds of a time like `1m23s`, `45s`, `1m` or `83`
+pub fn parse_time(s: &str) -> Result<f32, String> {
+    let error = || format!("`{}` is not a time like `1m23s`, `45s` or `1m`", s);
+    if s.is_empty() {
+        return Err(error());
+    }
+
+    let (minutes, rest) = match s.split_once('m') {
+        Some((minutes, rest)) => (minutes.parse::<f32>().map_err(|_| error())?, rest),
+        None => (0.0, s),
+    };
+    let seconds = match rest {
+        "" => 0.0,
+        rest => rest.strip_suffix('s').unwrap_or(rest).parse::<f32>().map_err(|_| error())?,
+    };
+
+    // `1m-5s` isn't 55 seconds
+    let value = minutes * 60.0 + seconds;
+    match minutes >= 0.0 && seconds >= 0.0 && value.is_finite() {
+        true => Ok(value),
+        false => Err(error()),
+    }
+}
+
+pub fn dedup(mut sounds: Vec<(String, Sound)>) -> Vec<(String, Sound)> {
+    sounds.sort_by(|(a, _), (b, _)| a.len().cmp(&b.len()).then_with(|| a.cmp(b)));
+
+    let mut seen = HashSet::new();
+    sounds.retain(|(_, sound)| {
+        let mut hasher = DefaultHasher::new();
+        sound.sample_rate.hash(&mut hasher);
+        for sample in &sound.samples {
+            ((sample.clamp(-1.0, 1.0) * i16::MAX as f32).round() as i16).hash(&mut hasher);
+        }
+        seen.insert(hasher.finish())
+    });
+
+    return sounds;
+}
+
+/// every sound at `resolution` pitches, cut to its first `ticks` ticks
+pub fn permute_with_pitch(samples: Vec<(String, Sound)>, resolution: usize, ticks: usize) -> Vec<((String, f32), Sound)> {
     let pitches = algebra::interpolated_range(0.5, 2.0, resolution);
-    let zipped = samples.into_iter().flat_map(|(st, s)| {
+    let pitches = vec![pitches; samples.len()];
+    return permute_with(samples, pitches, ticks);
+}
+
+/// like `permute_with_pitch`, but every sound gets its own set of pitches
+pub fn permute_with(samples: Vec<(String, Sound)>, pitches: Vec<Vec<f32>>, ticks: usize) -> Vec<((String, f32), Sound)> {
+    assert_eq!(samples.len(), pitches.len());
+
+    let zipped = samples.into_iter().zip(pitches).flat_map(|((st, s), pitches)| {
         pitches
-            .iter()
-            .map(|p| ((st.clone(), *p), s.clone()))
+            .into_iter()
+            .map(|p| ((st.clone(), p), s.clone()))
             .collect::<Vec<((String, f32), Sound)>>()
     }).collect::<Vec<((String, f32), Sound)>>();
 
     return zipped
         .into_par_iter()
-        .map(|((id, pitch), mut sound)| ((id, pitch), sound.adjust_pitch(pitch).first_tick().clone()))
+        .map(|((id, pitch), mut sound)| ((id, pitch), sound.adjust_pitch(pitch).ticks(ticks).clone()))
         .collect::<Vec<((String, f32), Sound)>>();
 }
 
@@ -43,19 +139,32 @@ pub struct Sound {
 impl Sound {
     /// pads silence with zeroes
     pub fn first_tick(&mut self) -> &mut Self {
+        return self.ticks(1);
+    }
+
+    /// the first `ticks` ticks, padding silence with zeroes
+    pub fn ticks(&mut self, ticks: usize) -> &mut Self {
         let samples_per_tick = f32::ceil((self.sample_rate as f32 * 50.0) / 1000.0) as usize;
 
-        if self.samples.len() < samples_per_tick {
-            self.samples.resize(samples_per_tick, 0.0);
-        } else {
-            self.samples = (self.samples[0..samples_per_tick]).to_vec();
-        }
+        self.samples.resize(samples_per_tick * ticks, 0.0);
+
+        return self;
+    }
+
+    /// the samples from `start` seconds in, `duration` seconds of them or
+    /// the rest. past the end is empty
+    pub fn section(&mut self, start: f32, duration: Option<f32>) -> &mut Self {
+        let at = |seconds: f32| ((seconds * self.sample_rate as f32) as usize).min(self.samples.len());
+        let (first, last) = (at(start), duration.map(|duration| at(start + duration)).unwrap_or(self.samples.len()));
+
+        self.samples.truncate(last);
+        self.samples.drain(..first);
 
         return self;
     }
 
     /// handles up and downsampling
-    /// linear interpolation
+    /// linear interpolation, low-passed first when downsampling to avoid aliasing
     pub fn resample(&mut self, new_rate: usize) -> &mut Self {
         let input_len = self.samples.len();
         let output_len = (input_len * new_rate) / self.sample_rate;
@@ -65,9 +174,14 @@ impl Sound {
         }
 
         if input_len == output_len {
+            self.sample_rate = new_rate;
             return self;
         }
 
+        if new_rate < self.sample_rate {
+            self.samples = low_pass(&self.samples, 0.5 * new_rate as f32 / self.sample_rate as f32);
+        }
+
         let mut resampled = Vec::with_capacity(output_len);
         let step = (input_len - 1) as f32 / (output_len - 1) as f32;
 
@@ -121,34 +235,30 @@ impl Sound {
         return self;
     }
 
-    pub fn adjust_volume(&mut self, volume: f32) -> &mut Self {
-        if volume == 1.0 {
+    /// drops the silence before the sound starts, the samples more than
+    /// `threshold` dB below its peak, but at most `most` of them. a silent
+    /// sound is left as it is
+    pub fn trim_leading_silence(&mut self, threshold: f32, most: usize) -> &mut Self {
+        let peak = self.samples.iter().fold(0.0f32, |peak, sample| peak.max(sample.abs()));
+        if peak == 0.0 {
             return self;
         }
 
-        for sample in &mut self.samples {
-            *sample = *sample * volume;
-        }
+        let level = peak * 10.0_f32.powf(threshold / 20.0);
+        let start = self.samples.iter().position(|sample| sample.abs() >= level).unwrap_or(0);
+        self.samples.drain(..start.min(most));
 
         return self;
     }
 
-    /// applies filtering to boost mids and decrease lows
-    /// this makes it so important parts (voice, etc) are prioritized in
-    /// reconstruction rather than bass (drums, etc) which our ears are
-    /// more sensitive to
-    pub fn mel(&mut self, processor: &Processor) -> &mut Self {
-        let _span = span!(Level::DEBUG, "mel").entered();
-
-        let mut spectrum = processor.fft(self.clone());
-
-        for bin in spectrum.iter_mut() {
-            let mel_freq = (2595.0 * (1.0 + (bin.freq as f32 / 700.0)).log10()) / 24000.0;
-            let high_pass = (bin.freq as f32) / ((bin.freq as f32).pow(2.0) + (100 as f32).pow(2.0)) + 0.4;
-            bin.complex *= (mel_freq * 2.0) * (high_pass.min(1.0));
+    pub fn adjust_volume(&mut self, volume: f32) -> &mut Self {
+        if volume == 1.0 {
+            return self;
         }
 
-        self.samples = processor.ifft(spectrum);
+        for sample in &mut self.samples {
+            *sample *= volume;
+        }
 
         return self;
     }
@@ -170,28 +280,132 @@ impl FftBin {
     }
 }
 
+static FILTER_KERNEL: &str = include_str!("filter.ocl");
+
+type FftCache = RwLock<HashMap<usize, Arc<dyn Fft<f32>>>>;
+
+/// fft plans are cached by length and planned the first time a length is
+/// seen, so every rayon worker shares one plan per size
 pub struct Processor {
-    fft_cache: HashMap<usize, Arc<dyn Fft<f32>>>,
-    ifft_cache: HashMap<usize, Arc<dyn Fft<f32>>>
+    fft_cache: FftCache,
+    ifft_cache: FftCache,
+    gpu: Option<ProQue>
+}
+
+impl Default for Processor {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Processor {
     pub fn new() -> Self {
-        let mut fft_planner = FftPlanner::new();
-        let mut fft_cache = HashMap::new();
-        let mut ifft_cache = HashMap::new();
-        fft_cache.insert(time_as_samples!(44100, 50), fft_planner.plan_fft_forward(time_as_samples!(44100, 50))); // # samples for 1 tick at 44.1kHz
-        fft_cache.insert(time_as_samples!(48000, 50), fft_planner.plan_fft_forward(time_as_samples!(48000, 50))); // # samples for 1 tick at 48kHz
-
-        ifft_cache.insert(time_as_samples!(44100, 50), fft_planner.plan_fft_inverse(time_as_samples!(44100, 50))); // # samples for 1 tick at 44.1kHz
-        ifft_cache.insert(time_as_samples!(48000, 50), fft_planner.plan_fft_inverse(time_as_samples!(48000, 50))); // # samples for 1 tick at 48kHz
-
         Self {
-            fft_cache,
-            ifft_cache
+            fft_cache: RwLock::new(HashMap::new()),
+            ifft_cache: RwLock::new(HashMap::new()),
+            gpu: None
         } 
     }
 
+    pub(crate) fn plan(&self, length: usize, inverse: bool) -> Arc<dyn Fft<f32>> {
+        let cache = if inverse { &self.ifft_cache } else { &self.fft_cache };
+
+        if let Some(plan) = cache.read().unwrap().get(&length) {
+            return plan.clone();
+        }
+
+        // checked again under the write lock, so racing workers plan once
+        cache.write()
+            .unwrap()
+            .entry(length)
+            .or_insert_with(|| {
+                event!(Level::DEBUG, "planning {} of {} samples", if inverse { "ifft" } else { "fft" }, length);
+                let mut planner = FftPlanner::new();
+                if inverse {
+                    planner.plan_fft_inverse(length)
+                } else {
+                    planner.plan_fft_forward(length)
+                }
+            })
+            .clone()
+    }
+
+    /// lengths that currently have a forward plan
+    pub fn planned_lengths(&self) -> Vec<usize> {
+        self.fft_cache.read().unwrap().keys().copied().collect()
+    }
+
+    /// sets up an OpenCL queue for batched preprocessing. if there is no
+    /// usable device everything stays on the CPU
+    pub fn with_gpu(mut self) -> Self {
+        let _span = span!(Level::DEBUG, "with_gpu", tag = "gpu").entered();
+
+        let pro_que = ocl::Platform::first()
+            .and_then(|platform| ProQue::builder()
+                .platform(platform)
+                .device(ocl::Device::first(platform)?)
+                .src(FILTER_KERNEL)
+                .dims(1)
+                .build());
+
+        match pro_que {
+            Ok(pro_que) => self.gpu = Some(pro_que),
+            Err(e) => event!(Level::WARN, "no OpenCL device for preprocessing, using the CPU: '{}'", e),
+        }
+
+        self
+    }
+
+    pub fn has_gpu(&self) -> bool {
+        self.gpu.is_some()
+    }
+
+    /// applies `operator` to every `operator.n` samples of `batch` on the GPU
+    pub fn apply_operator_gpu(&self, batch: &[f32], operator: &FilterOperator) -> Result<Vec<f32>, ocl::Error> {
+        let pq = self.gpu.as_ref().ok_or_else(|| ocl::Error::from("no OpenCL device"))?;
+        let n = operator.n;
+        let rows = batch.len() / n;
+
+        let input = |values: &[f32]| Buffer::<f32>::builder()
+            .queue(pq.queue().clone())
+            .flags(ocl::flags::MEM_READ_ONLY)
+            .len(values.len())
+            .copy_host_slice(values)
+            .build();
+
+        let buffer_x = input(batch)?;
+        let buffer_circulant = input(&operator.circulant)?;
+        let buffer_window = input(&operator.window)?;
+        let buffer_norm = input(&operator.norm)?;
+
+        let buffer_y = Buffer::<f32>::builder()
+            .queue(pq.queue().clone())
+            .len(batch.len())
+            .build()?;
+
+        let hop = (n / 4).max(1);
+        let kernel = pq.kernel_builder("apply_filter")
+            .global_work_size((rows, n))
+            .arg(&buffer_x)
+            .arg(&buffer_circulant)
+            .arg(&buffer_window)
+            .arg(&buffer_norm)
+            .arg(&buffer_y)
+            .arg(rows as u32)
+            .arg(n as u32)
+            .arg(hop as u32)
+            .build()?;
+
+        unsafe { kernel.enq()?; }
+
+        let mut y = vec![0.0; batch.len()];
+        buffer_y.read(&mut y).enq()?;
+
+        Ok(y)
+    }
+
+    /// hamming windowed and unscaled, so bins grow with the length.
+    /// `ifft` undoes the scaling
     pub fn fft(&self, sound: Sound) -> Vec<FftBin> {
         let _span = span!(Level::DEBUG, "fft", tag = "audio").entered();
 
@@ -210,13 +424,7 @@ impl Processor {
             buffer.push(Complex { re: sample, im: 0.0 });
         }
 
-        let fft = match self.fft_cache.get(&length) {
-            Some(fft) => fft,
-            None => {
-                event!(Level::DEBUG, "cache miss, {} sample size, {} sample rate", length, sound.sample_rate);
-                &FftPlanner::new().plan_fft_forward(length)
-            },
-        };
+        let fft = self.plan(length, false);
 
         fft.process(&mut buffer);
 
@@ -232,21 +440,170 @@ impl Processor {
         bins
     }
 
+    /// the inverse of `fft`, scaled by 1/n. the window is left on, see
+    /// `filter` for taking a spectrum back to samples
     pub fn ifft(&self, spectrum: Vec<FftBin>) -> Vec<f32> {
         let _span = span!(Level::DEBUG, "ifft", tag = "audio").entered();
 
         let mut buffer = spectrum.iter().map(|f| f.complex).collect::<Vec<Complex32>>();
         let length = buffer.len();
 
-        let ifft = match self.ifft_cache.get(&length) {
-            Some(ifft) => ifft,
-            None => {
-                event!(Level::DEBUG, "cache miss, {} sample size", length);
-                &FftPlanner::new().plan_fft_inverse(length)
-            },
-        };
+        let ifft = self.plan(length, true);
 
         ifft.process(&mut buffer);
-        buffer.iter().map(|c| c.re).collect::<Vec<f32>>()
+
+        // rustfft doesn't scale the inverse
+        return buffer.iter()
+            .map(|c| c.re / length as f32)
+            .collect::<Vec<f32>>();
     }
+
+    /// `sound` with every bin of its spectrum scaled by `weight` of the
+    /// bin's frequency. frames of the sound's length, a quarter of it
+    /// apart, are windowed, weighted and windowed again, then overlap-added
+    /// and divided by their summed squared windows. unlike dividing a
+    /// single frame by its window, nothing is blown up at the edges
+    pub fn filter<W: Fn(f32) -> f32>(&self, sound: &Sound, weight: W) -> Vec<f32> {
+        let _span = span!(Level::DEBUG, "filter", tag = "audio").entered();
+
+        let n = sound.samples.len();
+        if n == 0 {
+            return Vec::new();
+        }
+
+        let window = apodize::hamming_iter(n).map(|w| w as f32).collect::<Vec<f32>>();
+        let weights = (0..n).map(|i| weight(i as f32 * sound.sample_rate as f32 / n as f32)).collect::<Vec<f32>>();
+        let (fft, ifft) = (self.plan(n, false), self.plan(n, true));
+
+        let mut samples = vec![0.0; n];
+        let mut norm = vec![0.0; n];
+        for start in filter_frames(n) {
+            let inside = |i: usize| {
+                let t = start + i as isize;
+                (0..n as isize).contains(&t).then_some(t as usize)
+            };
+
+            let mut buffer = (0..n)
+                .map(|i| Complex { re: inside(i).map(|t| sound.samples[t] * window[i]).unwrap_or(0.0), im: 0.0 })
+                .collect::<Vec<Complex32>>();
+            fft.process(&mut buffer);
+            for (bin, weight) in buffer.iter_mut().zip(&weights) {
+                *bin *= weight;
+            }
+            ifft.process(&mut buffer);
+
+            for (i, bin) in buffer.iter().enumerate() {
+                if let Some(t) = inside(i) {
+                    samples[t] += bin.re / n as f32 * window[i];
+                    norm[t] += window[i] * window[i];
+                }
+            }
+        }
+
+        // the hamming window never reaches zero, every sample has some norm
+        for (sample, norm) in samples.iter_mut().zip(norm) {
+            *sample /= norm;
+        }
+        return samples;
+    }
+}
+
+/// `Processor::filter` with fixed weights as a linear operator on sounds of
+/// `n` samples. within a frame, output j only depends on input k through
+/// window[j] window[k] sum_f w[f] cos(2 pi f (j - k) / n), so it's a
+/// circulant between the windows of the frames, summed over them and then
+/// divided by the summed squared windows at j. kept as those three vectors
+/// rather than as the n x n matrix they make
+pub struct FilterOperator {
+    pub n: usize,
+    /// the circulant's first column, divided by `n` like the ifft is
+    circulant: Vec<f32>,
+    window: Vec<f32>,
+    /// one over the summed squared windows of every sample
+    norm: Vec<f32>
+}
+
+impl FilterOperator {
+    pub fn new<W: Fn(f32) -> f32 + Sync>(n: usize, sample_rate: usize, weight: W) -> Self {
+        let window = apodize::hamming_iter(n).collect::<Vec<f64>>();
+        let weights = (0..n)
+            .map(|f| weight(f as f32 * sample_rate as f32 / n as f32) as f64)
+            .collect::<Vec<f64>>();
+
+        let circulant = (0..n)
+            .into_par_iter()
+            .map(|d| {
+                let sum = weights.iter()
+                    .enumerate()
+                    .map(|(f, w)| w * (2.0 * std::f64::consts::PI * ((f * d) % n) as f64 / n as f64).cos())
+                    .sum::<f64>();
+                (sum / n as f64) as f32
+            })
+            .collect::<Vec<f32>>();
+
+        let mut norm = vec![0.0f64; n];
+        for start in filter_frames(n) {
+            for (j, norm) in norm.iter_mut().enumerate() {
+                if let Some(window) = usize::try_from(j as isize - start).ok().and_then(|i| window.get(i)) {
+                    *norm += window * window;
+                }
+            }
+        }
+
+        return FilterOperator {
+            n,
+            circulant,
+            window: window.into_iter().map(|w| w as f32).collect(),
+            norm: norm.into_iter().map(|norm| (1.0 / norm) as f32).collect()
+        };
+    }
+
+    /// the operator applied to `samples` on the CPU, the way the GPU does
+    pub fn apply(&self, samples: &[f32]) -> Vec<f32> {
+        let n = self.n as isize;
+        return (0..n)
+            .map(|j| {
+                let sum = filter_frames(self.n)
+                    .filter(|start| (0..n).contains(&(j - start)))
+                    .map(|start| {
+                        let inside = start.max(0)..(start + n).min(n);
+                        let filtered = inside
+                            .map(|k| self.circulant[(j - k).rem_euclid(n) as usize] * self.window[(k - start) as usize] * samples[k as usize])
+                            .sum::<f32>();
+                        self.window[(j - start) as usize] * filtered
+                    })
+                    .sum::<f32>();
+                sum * self.norm[j as usize]
+            })
+            .collect();
+    }
+}
+
+/// where the frames `Processor::filter` splits `n` samples into start, a
+/// quarter of `n` apart. the first ends a hop into the samples, so the
+/// edges are covered by as many frames as the middle
+pub fn filter_frames(n: usize) -> impl Iterator<Item = isize> {
+    let hop = (n / 4).max(1);
+    return (hop as isize - n as isize..n as isize).step_by(hop);
+}
+
+/// the last chunk is zero padded so the end of the song isn't dropped. the
+/// padding is silence, so the solve naturally gives it quieter commands
+pub fn chunk_ticks(audio: &Sound, samples_per_tick: usize, n_ticks: usize) -> Vec<Sound> {
+    return chunk_range(audio, samples_per_tick, 0..n_ticks);
+}
+
+/// `chunk_ticks` for only the given ticks
+pub fn chunk_range(audio: &Sound, samples_per_tick: usize, ticks: Range<usize>) -> Vec<Sound> {
+    let start = (ticks.start * samples_per_tick).min(audio.samples.len());
+    let end = (ticks.end * samples_per_tick).min(audio.samples.len());
+    let mut samples = audio.samples[start..end].to_vec();
+    samples.resize(ticks.len() * samples_per_tick, 0.0);
+
+    return samples.chunks(samples_per_tick)
+        .map(|samples| Sound {
+            samples: samples.to_vec(),
+            sample_rate: audio.sample_rate
+        })
+        .collect::<Vec<Sound>>();
 }
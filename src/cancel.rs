@@ -0,0 +1,58 @@
+use std::{fs, path::{Path, PathBuf}, sync::{atomic::{AtomicBool, Ordering}, Mutex}};
+
+use tracing::{event, Level};
+
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+static CHECKPOINTABLE: AtomicBool = AtomicBool::new(false);
+static INSTALLED: AtomicBool = AtomicBool::new(false);
+
+/// files that only live as long as the run, removed when ctrl-c exits
+static TEMPORARY: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+/// installs the ctrl-c handler. outside of checkpointable stages ctrl-c
+/// exits right away (cache writes are atomic so nothing is left half
+/// written), inside of them the first ctrl-c asks the stage to wind down
+/// and the second one aborts. installing it again does nothing
+pub fn install() {
+    if INSTALLED.swap(true, Ordering::SeqCst) {
+        return;
+    }
+
+    tokio::spawn(async {
+        while tokio::signal::ctrl_c().await.is_ok() {
+            if !CHECKPOINTABLE.load(Ordering::SeqCst) || REQUESTED.swap(true, Ordering::SeqCst) {
+                event!(Level::WARN, "aborting");
+                remove_temporary();
+                std::process::exit(130);
+            }
+
+            event!(Level::WARN, "stopping after the current iteration, press ctrl-c again to abort without saving");
+        }
+    });
+}
+
+pub fn requested() -> bool {
+    REQUESTED.load(Ordering::SeqCst)
+}
+
+/// marks whether the running stage checks `requested()` and saves progress
+pub fn set_checkpointable(checkpointable: bool) {
+    CHECKPOINTABLE.store(checkpointable, Ordering::SeqCst);
+}
+
+/// removes `path` if ctrl-c exits before `forget_temporary(path)`
+pub fn register_temporary(path: &Path) {
+    TEMPORARY.lock().unwrap().push(path.to_path_buf());
+}
+
+pub fn forget_temporary(path: &Path) {
+    TEMPORARY.lock().unwrap().retain(|temporary| temporary != path);
+}
+
+fn remove_temporary() {
+    for path in TEMPORARY.lock().unwrap().drain(..) {
+        if let Err(e) = fs::remove_file(&path) {
+            event!(Level::WARN, "could not remove `{}`: '{}'", path.to_string_lossy(), e);
+        }
+    }
+}
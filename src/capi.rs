@@ -0,0 +1,246 @@
+use std::{cell::RefCell, ffi::{c_char, CString}, panic::{self, AssertUnwindSafe}, ptr, slice};
+
+use ndarray::Array2;
+
+use crate::{algebra::{self, Basis}, audio::{self, Processor, Sound}, features::{self, FeatureExtractor, FeatureKind}};
+
+/// commands a tick runs by default, the same as the cli's
+static COMMANDS_PER_TICK: usize = 80;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+#[derive(thiserror::Error, Debug)]
+#[error("{0}")]
+struct InvalidArgument(String);
+
+#[repr(C)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum McpStatus {
+    Ok = 0,
+    /// a null pointer, a length of 0 or an out of range value was passed
+    InvalidArgument = 1,
+    /// the solve (or the device) failed, or the library panicked
+    Failed = 2
+}
+
+/// a basis ready to solve with, see `mcp_basis_new`
+pub struct McpBasis {
+    basis: Basis,
+    extractor: Box<dyn FeatureExtractor>,
+    processor: Processor,
+    sample_rate: usize,
+    atom_ticks: usize
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct McpSolveOptions {
+    pub iters: usize,
+    /// picked from the basis when not above 0
+    pub step: f32,
+    /// whether the input loops back to its start
+    pub looping: bool,
+    /// commands per tick, the loudest sounds of every tick are kept
+    pub budget: usize
+}
+
+/// the amplitudes of every sound (in the order they were given to
+/// `mcp_basis_new`) at every tick, see `mcp_solve`
+pub struct McpSchedule {
+    amplitudes: Array2<f32>
+}
+
+fn invalid(message: &str) -> anyhow::Error {
+    return InvalidArgument(message.to_string()).into();
+}
+
+/// runs `f`, keeping its error for `mcp_last_error`. panics don't cross
+/// into the caller
+fn guard<F: FnOnce() -> Result<(), anyhow::Error>>(f: F) -> McpStatus {
+    let (status, message) = match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(())) => (McpStatus::Ok, None),
+        Ok(Err(error)) if error.is::<InvalidArgument>() => (McpStatus::InvalidArgument, Some(error.to_string())),
+        Ok(Err(error)) => (McpStatus::Failed, Some(format!("{:#}", error))),
+        Err(_) => (McpStatus::Failed, Some("the solver panicked".to_string())),
+    };
+
+    // messages can't hold a nul, their text is ours. a call that succeeds
+    // leaves the last error as it was
+    if let Some(message) = message {
+        let message = CString::new(message.replace('\0', " ")).unwrap();
+        LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+    }
+    return status;
+}
+
+/// the `MCP_FEATURES_*` of the header, spelled out so reordering
+/// `FeatureKind` doesn't change what callers get
+fn feature_kind(features: u32) -> Option<FeatureKind> {
+    return match features {
+        0 => Some(FeatureKind::Waveform),
+        1 => Some(FeatureKind::MelWeighted),
+        2 => Some(FeatureKind::MelFilterbank),
+        3 => Some(FeatureKind::LogSpectrum),
+        4 => Some(FeatureKind::Mfcc),
+        5 => Some(FeatureKind::Chroma),
+        _ => None,
+    };
+}
+
+/// the error of the last call on this thread that didn't return
+/// `McpStatus::Ok`, or null. valid until another call on this thread fails
+#[no_mangle]
+pub extern "C" fn mcp_last_error() -> *const c_char {
+    return LAST_ERROR.with(|last| last.borrow().as_ref().map(|message| message.as_ptr()).unwrap_or(ptr::null()));
+}
+
+/// builds a basis out of `count` mono sounds at `sample_rate`, the
+/// `i`th of `lengths[i]` samples at `sounds[i]`. pitched variants are
+/// separate sounds. `features` is one of the header's `MCP_FEATURES_*`.
+/// atoms span `atom_ticks` ticks
+///
+/// # Safety
+/// `sounds` and `lengths` point to `count` values, every sound to as many
+/// samples as its length, and `out` to writable memory. none are kept
+#[no_mangle]
+pub unsafe extern "C" fn mcp_basis_new(
+    sounds: *const *const f32,
+    lengths: *const usize,
+    count: usize,
+    sample_rate: u32,
+    atom_ticks: usize,
+    features: u32,
+    out: *mut *mut McpBasis
+) -> McpStatus {
+    return guard(|| {
+        if sounds.is_null() || lengths.is_null() || out.is_null() || count == 0 {
+            return Err(invalid("sounds, lengths and out can't be null, and count has to be above 0"));
+        }
+        let sample_rate = sample_rate as usize;
+        let samples_per_tick = audio::time_as_samples!(sample_rate, 50);
+        if samples_per_tick == 0 || atom_ticks == 0 {
+            return Err(invalid("sample_rate is too low for a tick, or atom_ticks is 0"));
+        }
+        let Some(kind) = feature_kind(features) else {
+            return Err(invalid("no such features"));
+        };
+
+        let (pointers, lengths) = (slice::from_raw_parts(sounds, count), slice::from_raw_parts(lengths, count));
+        if pointers.iter().zip(lengths).any(|(pointer, length)| pointer.is_null() && *length > 0) {
+            return Err(invalid("a sound with samples can't be null"));
+        }
+        let sounds = pointers.iter().zip(lengths)
+            .map(|(pointer, length)| Sound {
+                samples: if *length > 0 { slice::from_raw_parts(*pointer, *length).to_vec() } else { Vec::new() },
+                sample_rate
+            })
+            .collect::<Vec<Sound>>();
+
+        let extractor = kind.extractor();
+        let processor = Processor::new();
+        let columns = features::atom_features(&sounds, atom_ticks, samples_per_tick, |batch| extractor.extract_batch(batch, &processor));
+        let mut basis = algebra::matrix_from_vecs(columns)?.reversed_axes().as_standard_layout().into_owned();
+        algebra::normalize_to_global(&mut basis);
+
+        let basis = Basis::from_array(basis, &algebra::select_devices(&[])?)?;
+        *out = Box::into_raw(Box::new(McpBasis { basis, extractor, processor, sample_rate, atom_ticks }));
+        Ok(())
+    });
+}
+
+/// # Safety
+/// `basis` is null or from `mcp_basis_new`, and not used again
+#[no_mangle]
+pub unsafe extern "C" fn mcp_basis_free(basis: *mut McpBasis) {
+    if !basis.is_null() {
+        drop(Box::from_raw(basis));
+    }
+}
+
+/// the cli's defaults, a step picked from the basis
+#[no_mangle]
+pub extern "C" fn mcp_solve_options_default() -> McpSolveOptions {
+    return McpSolveOptions { iters: 128, step: 0.0, looping: false, budget: COMMANDS_PER_TICK };
+}
+
+/// solves `length` mono samples, at the basis' sample rate, for the
+/// amplitudes of its sounds at every tick. the loudest is 1.0, and only
+/// `budget` sounds are kept every tick
+///
+/// # Safety
+/// `basis` is from `mcp_basis_new`, `samples` points to `length` samples,
+/// `options` is null or points to options, and `out` to writable memory
+#[no_mangle]
+pub unsafe extern "C" fn mcp_solve(
+    basis: *const McpBasis,
+    samples: *const f32,
+    length: usize,
+    options: *const McpSolveOptions,
+    out: *mut *mut McpSchedule
+) -> McpStatus {
+    return guard(|| {
+        if basis.is_null() || samples.is_null() || out.is_null() || length == 0 {
+            return Err(invalid("basis, samples and out can't be null, and length has to be above 0"));
+        }
+        let (basis, options) = (&*basis, options.as_ref().copied().unwrap_or_else(|| mcp_solve_options_default()));
+
+        let input = Sound { samples: slice::from_raw_parts(samples, length).to_vec(), sample_rate: basis.sample_rate };
+        let samples_per_tick = audio::time_as_samples!(basis.sample_rate, 50);
+        let ticks = audio::chunk_ticks(&input, samples_per_tick, length.div_ceil(samples_per_tick));
+        let mut chunks = algebra::matrix_from_vecs(basis.extractor.extract_batch(&ticks, &basis.processor))?.reversed_axes();
+        algebra::normalize_to_global(&mut chunks);
+
+        if basis.basis.dim().0 != chunks.dim().0 * basis.atom_ticks {
+            return Err(invalid("the input's features don't fit the basis"));
+        }
+
+        let step = match options.step > 0.0 {
+            true => options.step,
+            false => basis.basis.auto_step(basis.atom_ticks)?.ok_or_else(|| anyhow::anyhow!("basis is all zeros, cannot pick a step size"))?,
+        };
+
+        let (n_sounds, n_ticks) = (basis.basis.dim().1, chunks.dim().1);
+        let initial = Array2::zeros((n_sounds, n_ticks));
+        let (h, _) = algebra::conv_pgd_nnls(chunks.view(), &basis.basis, basis.atom_ticks, initial, options.iters, step, options.looping)?;
+
+        let mut amplitudes = Array2::zeros((n_sounds, n_ticks));
+        algebra::accumulate_rows(&mut amplitudes, h.view(), &(0..n_sounds).collect::<Vec<usize>>(), None, 1.0, options.budget);
+        *out = Box::into_raw(Box::new(McpSchedule { amplitudes: amplitudes.as_standard_layout().into_owned() }));
+        Ok(())
+    });
+}
+
+/// # Safety
+/// `schedule` is from `mcp_solve`
+#[no_mangle]
+pub unsafe extern "C" fn mcp_schedule_sounds(schedule: *const McpSchedule) -> usize {
+    return schedule.as_ref().map(|schedule| schedule.amplitudes.dim().0).unwrap_or(0);
+}
+
+/// # Safety
+/// `schedule` is from `mcp_solve`
+#[no_mangle]
+pub unsafe extern "C" fn mcp_schedule_ticks(schedule: *const McpSchedule) -> usize {
+    return schedule.as_ref().map(|schedule| schedule.amplitudes.dim().1).unwrap_or(0);
+}
+
+/// the amplitudes, sounds by ticks in row-major order. valid until the
+/// schedule is freed
+///
+/// # Safety
+/// `schedule` is from `mcp_solve`
+#[no_mangle]
+pub unsafe extern "C" fn mcp_schedule_amplitudes(schedule: *const McpSchedule) -> *const f32 {
+    return schedule.as_ref().map(|schedule| schedule.amplitudes.as_ptr()).unwrap_or(ptr::null());
+}
+
+/// # Safety
+/// `schedule` is null or from `mcp_solve`, and not used again
+#[no_mangle]
+pub unsafe extern "C" fn mcp_schedule_free(schedule: *mut McpSchedule) {
+    if !schedule.is_null() {
+        drop(Box::from_raw(schedule));
+    }
+}
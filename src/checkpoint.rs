@@ -0,0 +1,83 @@
+use std::{fs, io::{BufReader, BufWriter, Read, Write}, path::{Path, PathBuf}};
+
+use anyhow::{anyhow, Error};
+use ndarray::Array2;
+
+static MAGIC: &[u8; 4] = b"MCPH";
+static FORMAT_VERSION: u32 = 1;
+
+/// solver state saved on cancellation. `h` is always a valid (non-negative)
+/// solution, just not a fully converged one
+pub struct Checkpoint {
+    pub iterations: usize,
+    pub h: Array2<f32>
+}
+
+fn read_u64(reader: &mut impl Read) -> Result<u64, Error> {
+    let mut buf = [0u8; 8];
+    reader.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+impl Checkpoint {
+    /// raw little-endian floats after a small header; h is far too big for json
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        let mut temp_path = path.as_os_str().to_owned();
+        temp_path.push(".part");
+        let temp_path = PathBuf::from(temp_path);
+
+        let (rows, cols) = self.h.dim();
+        let mut writer = BufWriter::new(fs::File::create(&temp_path)?);
+        writer.write_all(MAGIC)?;
+        writer.write_all(&FORMAT_VERSION.to_le_bytes())?;
+        writer.write_all(&(self.iterations as u64).to_le_bytes())?;
+        writer.write_all(&(rows as u64).to_le_bytes())?;
+        writer.write_all(&(cols as u64).to_le_bytes())?;
+
+        for value in self.h.iter() {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+
+        writer.flush()?;
+        drop(writer);
+        fs::rename(&temp_path, path)?;
+
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let mut reader = BufReader::new(fs::File::open(path)?);
+
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(anyhow!("`{}` is not a checkpoint", path.to_string_lossy()));
+        }
+
+        let mut version = [0u8; 4];
+        reader.read_exact(&mut version)?;
+        if u32::from_le_bytes(version) != FORMAT_VERSION {
+            return Err(anyhow!("checkpoint was written by an incompatible version"));
+        }
+
+        let iterations = read_u64(&mut reader)? as usize;
+        let rows = read_u64(&mut reader)? as usize;
+        let cols = read_u64(&mut reader)? as usize;
+
+        let mut bytes = Vec::with_capacity(rows * cols * 4);
+        reader.read_to_end(&mut bytes)?;
+        if bytes.len() != rows * cols * 4 {
+            return Err(anyhow!("checkpoint is truncated"));
+        }
+
+        let values = bytes
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect::<Vec<f32>>();
+
+        Ok(Self {
+            iterations,
+            h: Array2::from_shape_vec((rows, cols), values)?
+        })
+    }
+}
@@ -0,0 +1,201 @@
+use std::{cell::RefCell, path::Path, sync::{Arc, Mutex}};
+
+use tracing::{event, Level};
+
+/// what the objective may still fall over the last tenth of the iterations
+/// for the solve to count as stalled, relative to where it is
+static STALLED_BELOW: f32 = 1e-3;
+
+#[derive(thiserror::Error, Debug)]
+pub enum ConvergenceError {
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("png error: {0}")]
+    Png(#[from] png::EncodingError),
+    #[error("nothing was recorded")]
+    Empty
+}
+
+/// the objective, ½‖Wh - V‖², at the start of every iteration of the
+/// solves of one part. solves split into batches or across devices add up
+/// into the same iterations
+pub struct Series {
+    pub name: String,
+    objectives: Mutex<Vec<f32>>
+}
+
+impl Series {
+    pub fn new(name: &str) -> Arc<Series> {
+        return Arc::new(Series { name: name.to_string(), objectives: Mutex::new(Vec::new()) });
+    }
+
+    pub fn objectives(&self) -> Vec<f32> {
+        return self.objectives.lock().unwrap().clone();
+    }
+
+    /// how much the objective fell over the last tenth of the iterations,
+    /// relative to where it ended up
+    pub fn recent_fall(&self) -> Option<f32> {
+        let objectives = self.objectives();
+        let last = *objectives.last()?;
+        let back = (objectives.len() / 10).max(1).min(objectives.len() - 1);
+        let earlier = objectives[objectives.len() - 1 - back];
+        return Some(match last > 0.0 {
+            true => (earlier - last) / last,
+            false => 0.0,
+        });
+    }
+}
+
+/// where the solves on a thread record their objective. it's passed on to
+/// the threads they spawn with `within`
+#[derive(Clone)]
+pub struct Recorder {
+    series: Arc<Series>,
+    base: usize
+}
+
+impl Recorder {
+    pub fn add(&self, iteration: usize, objective: f32) {
+        let mut objectives = self.series.objectives.lock().unwrap();
+        let index = self.base + iteration;
+        if objectives.len() <= index {
+            objectives.resize(index + 1, 0.0);
+        }
+        objectives[index] += objective;
+    }
+
+    /// runs `f` recording into the same series, e.g. on another thread
+    pub fn within<T>(&self, f: impl FnOnce() -> T) -> T {
+        let previous = RECORDING.replace(Some(self.clone()));
+        let result = f();
+        RECORDING.set(previous);
+        return result;
+    }
+}
+
+thread_local! {
+    static RECORDING: RefCell<Option<Recorder>> = const { RefCell::new(None) };
+}
+
+/// records the objective of every solve `f` runs on this thread into
+/// `series`, after the iterations it already holds
+pub fn record<T>(series: &Arc<Series>, f: impl FnOnce() -> T) -> T {
+    let base = series.objectives.lock().unwrap().len();
+    return Recorder { series: series.clone(), base }.within(f);
+}
+
+/// the recorder of the solve running on this thread, if it's recorded.
+/// computing the objective costs time, so it's skipped when not
+pub fn recorder() -> Option<Recorder> {
+    return RECORDING.with_borrow(|recording| recording.clone());
+}
+
+/// says for every series whether more iterations would still help
+pub fn summarize(series: &[Arc<Series>]) {
+    for series in series {
+        let Some(fall) = series.recent_fall() else {
+            continue;
+        };
+
+        let iterations = series.objectives().len();
+        match fall < STALLED_BELOW {
+            true => event!(Level::INFO, "the {} objective fell {:.3}% over the last tenth of its {} iterations, it has stalled", series.name, fall * 100.0, iterations),
+            false => event!(Level::INFO, "the {} objective still fell {:.3}% over the last tenth of its {} iterations, more would help", series.name, fall * 100.0, iterations),
+        }
+    }
+}
+
+/// colors of the series, in order, and what they're called in the log
+static COLORS: [([u8; 3], &str); 6] = [
+    ([31, 119, 180], "blue"),
+    ([214, 39, 40], "red"),
+    ([44, 160, 44], "green"),
+    ([255, 127, 14], "orange"),
+    ([148, 103, 189], "purple"),
+    ([140, 86, 75], "brown"),
+];
+
+static WIDTH: usize = 800;
+static HEIGHT: usize = 400;
+static MARGIN: usize = 24;
+
+/// an RGB image of every series over the iterations, with the objective on
+/// a log scale. light lines mark every power of ten of the objective and
+/// every tenth of the iterations. there's no text, the log says which color
+/// is which part
+pub fn render(series: &[Arc<Series>]) -> Result<Vec<u8>, ConvergenceError> {
+    let all = series.iter().map(|series| series.objectives()).collect::<Vec<Vec<f32>>>();
+    let iterations = all.iter().map(|objectives| objectives.len()).max().unwrap_or(0);
+    let positive = all.iter().flatten().filter(|objective| **objective > 0.0).map(|objective| objective.log10());
+    let (mut low, mut high) = positive.fold((f32::INFINITY, f32::NEG_INFINITY), |(low, high), log| (low.min(log), high.max(log)));
+    if iterations == 0 || !low.is_finite() {
+        return Err(ConvergenceError::Empty);
+    }
+    if high - low < 1.0 {
+        (low, high) = ((low + high) / 2.0 - 0.5, (low + high) / 2.0 + 0.5);
+    }
+
+    let mut pixels = vec![255; WIDTH * HEIGHT * 3];
+    let mut set = |x: usize, y: usize, color: [u8; 3]| {
+        if x < WIDTH && y < HEIGHT {
+            pixels[(y * WIDTH + x) * 3..][..3].copy_from_slice(&color);
+        }
+    };
+
+    let (plot_width, plot_height) = ((WIDTH - 2 * MARGIN) as f32, (HEIGHT - 2 * MARGIN) as f32);
+    let x_of = |iteration: f32| MARGIN as f32 + plot_width * iteration / (iterations - 1).max(1) as f32;
+    let y_of = |objective: f32| MARGIN as f32 + plot_height * (high - objective.max(f32::MIN_POSITIVE).log10().max(low)) / (high - low);
+
+    let grid = [225, 225, 225];
+    for decade in low.ceil() as i32..=high.floor() as i32 {
+        let y = y_of(10f32.powi(decade)).round() as usize;
+        (MARGIN..WIDTH - MARGIN).for_each(|x| set(x, y, grid));
+    }
+    for tenth in 0..=10 {
+        let x = (MARGIN as f32 + plot_width * tenth as f32 / 10.0).round() as usize;
+        (MARGIN..HEIGHT - MARGIN).for_each(|y| set(x, y, grid));
+    }
+
+    let axis = [96, 96, 96];
+    (MARGIN..=HEIGHT - MARGIN).for_each(|y| set(MARGIN, y, axis));
+    (MARGIN..=WIDTH - MARGIN).for_each(|x| set(x, HEIGHT - MARGIN, axis));
+
+    for (objectives, (color, _)) in all.iter().zip(COLORS.iter().cycle()) {
+        let points = objectives.iter().enumerate().map(|(iteration, objective)| (x_of(iteration as f32), y_of(*objective)));
+        for ((x0, y0), (x1, y1)) in points.clone().zip(points.skip(1)) {
+            // enough steps to leave no gaps, two pixels thick
+            let steps = (x1 - x0).abs().max((y1 - y0).abs()).ceil().max(1.0) as usize;
+            for step in 0..=steps {
+                let t = step as f32 / steps as f32;
+                let (x, y) = ((x0 + (x1 - x0) * t).round() as usize, (y0 + (y1 - y0) * t).round() as usize);
+                set(x, y, *color);
+                set(x, y + 1, *color);
+            }
+        }
+    }
+
+    return Ok(pixels);
+}
+
+/// writes `render` as a `.png` and says which color is which part
+pub fn plot(path: &Path, series: &[Arc<Series>]) -> Result<(), ConvergenceError> {
+    let pixels = render(series)?;
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut encoder = png::Encoder::new(std::io::BufWriter::new(std::fs::File::create(path)?), WIDTH as u32, HEIGHT as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.write_header()?.write_image_data(&pixels)?;
+
+    if series.len() > 1 {
+        for (series, (_, color)) in series.iter().zip(COLORS.iter().cycle()) {
+            event!(Level::INFO, "the {} objective is plotted in {}", series.name, color);
+        }
+    }
+    event!(Level::INFO, "plotted the objective to `{}`", path.to_string_lossy());
+
+    return Ok(());
+}
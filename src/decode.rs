@@ -0,0 +1,172 @@
+use std::io::{Cursor, Read};
+
+use anyhow::{anyhow, Error};
+use clap::ValueEnum;
+use hound::{SampleFormat, WavReader};
+use tracing::{event, Level};
+
+use crate::audio::Sound;
+
+/// reads every sample of a wav as f32 in [-1, 1], whatever the bit depth
+/// or sample format it was exported with
+pub fn read_wav<R: Read>(mut reader: WavReader<R>) -> Result<Sound, Error> {
+    let spec = reader.spec();
+    event!(Level::DEBUG, "input is {}-bit {:?} at {}Hz", spec.bits_per_sample, spec.sample_format, spec.sample_rate);
+
+    let samples = match (spec.sample_format, spec.bits_per_sample) {
+        (SampleFormat::Float, 32) => reader.samples::<f32>()
+            .collect::<Result<Vec<f32>, hound::Error>>()?,
+        (SampleFormat::Int, bits @ 8..=32) => {
+            let scale = (1u64 << (bits - 1)) as f32;
+            reader.samples::<i32>()
+                .map(|sample| sample.map(|s| s as f32 / scale))
+                .collect::<Result<Vec<f32>, hound::Error>>()?
+        },
+        (format, bits) => return Err(anyhow!("unsupported wav sample format: {}-bit {:?}", bits, format)),
+    };
+
+    Ok(Sound {
+        samples,
+        sample_rate: spec.sample_rate.try_into()?
+    })
+}
+
+/// the channels of interleaved samples, e.g. a stereo `read_wav`. a
+/// trailing partial frame is dropped
+pub fn split_channels(sound: &Sound, channels: usize) -> Vec<Sound> {
+    return (0..channels)
+        .map(|channel| Sound {
+            samples: sound.samples.chunks_exact(channels).map(|frame| frame[channel]).collect(),
+            sample_rate: sound.sample_rate
+        })
+        .collect();
+}
+
+/// reads a whole wav from a stream that can't be seeked, like stdin.
+/// programs writing one into a pipe can't go back to fill in its sizes,
+/// so a data chunk of an unknown (or too large) size runs to the end
+pub fn buffer_wav<R: Read>(mut reader: R) -> Result<WavReader<Cursor<Vec<u8>>>, Error> {
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+    if bytes.len() < 12 || &bytes[..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+        return Err(anyhow!("not a wav stream"));
+    }
+
+    let mut block_align = 1;
+    let mut offset = 12;
+    while offset + 8 <= bytes.len() {
+        let length = u32::from_le_bytes(bytes[offset + 4..offset + 8].try_into()?) as usize;
+        let body = offset + 8;
+
+        if &bytes[offset..offset + 4] == b"fmt " && body + 14 <= bytes.len() {
+            block_align = u16::from_le_bytes([bytes[body + 12], bytes[body + 13]]).max(1) as usize;
+        } else if &bytes[offset..offset + 4] == b"data" {
+            let available = bytes.len() - body;
+            if length == 0 || length > available {
+                // whole frames only, and a wav can't say more than 4GiB
+                let length = available.min(u32::MAX as usize);
+                let length = (length - length % block_align) as u32;
+                bytes[offset + 4..offset + 8].copy_from_slice(&length.to_le_bytes());
+            }
+            break;
+        }
+
+        // chunks are padded to an even length
+        offset = body + length + length % 2;
+    }
+
+    Ok(WavReader::new(Cursor::new(bytes))?)
+}
+
+/// sample formats `--raw` reads, all little-endian and interleaved
+#[derive(ValueEnum, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RawFormat {
+    S16le,
+    S24le,
+    S32le,
+    F32le
+}
+
+impl RawFormat {
+    fn bytes(&self) -> usize {
+        match self {
+            RawFormat::S16le => 2,
+            RawFormat::S24le => 3,
+            RawFormat::S32le | RawFormat::F32le => 4,
+        }
+    }
+}
+
+/// reads headerless mono samples of `format` at `sample_rate` until the
+/// end, as piped out of another program. a trailing partial sample is
+/// dropped
+pub fn read_raw<R: Read>(mut reader: R, format: RawFormat, sample_rate: usize) -> Result<Sound, Error> {
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+
+    let samples = bytes.chunks_exact(format.bytes())
+        .map(|b| match format {
+            RawFormat::S16le => i16::from_le_bytes([b[0], b[1]]) as f32 / 32768.0,
+            // shifted up so the sign bit lands where an i32's is
+            RawFormat::S24le => (i32::from_le_bytes([0, b[0], b[1], b[2]]) >> 8) as f32 / 8388608.0,
+            RawFormat::S32le => i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f32 / 2147483648.0,
+            RawFormat::F32le => f32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+        })
+        .collect::<Vec<f32>>();
+    event!(Level::DEBUG, "read {} raw {:?} samples at {}Hz", samples.len(), format, sample_rate);
+
+    Ok(Sound { samples, sample_rate })
+}
+
+/// opus always decodes at this rate
+#[cfg(feature = "opus")]
+static OPUS_RATE: usize = 48000;
+
+/// the most samples a packet decodes to, 120ms at 48kHz
+#[cfg(feature = "opus")]
+static MAX_OPUS_FRAME: usize = 5760;
+
+/// reads an ogg opus file (`.opus`), as discord and most voice recorders
+/// write them, decoded to `channels` interleaved channels: 1 mixes it down
+/// to mono, 2 keeps the stereo image. also says how many channels the
+/// stream has, the decoder makes up the second of a mono one
+#[cfg(feature = "opus")]
+pub fn read_opus<R: Read + std::io::Seek>(reader: R, channels: usize) -> Result<(Sound, usize), Error> {
+    use audiopus::{coder::Decoder, Channels, SampleRate};
+
+    let mut packets = ogg::PacketReader::new(reader);
+    let head = packets.read_packet()?.ok_or_else(|| anyhow!("the opus file is empty"))?;
+    if head.data.len() < 19 || &head.data[..8] != b"OpusHead" {
+        return Err(anyhow!("not an opus stream"));
+    }
+    // decoded samples the encoder put in front of the audio
+    let pre_skip = u16::from_le_bytes([head.data[10], head.data[11]]) as usize * channels;
+    event!(Level::DEBUG, "input is {}-channel opus, originally at {}Hz", head.data[9], u32::from_le_bytes([head.data[12], head.data[13], head.data[14], head.data[15]]));
+
+    // the comment header comes next, it has no audio
+    packets.read_packet()?;
+
+    let mut decoder = match channels {
+        1 => Decoder::new(SampleRate::Hz48000, Channels::Mono)?,
+        2 => Decoder::new(SampleRate::Hz48000, Channels::Stereo)?,
+        _ => return Err(anyhow!("opus only decodes to mono or stereo, not {} channels", channels)),
+    };
+    let mut samples = Vec::new();
+    let mut frame = vec![0.0f32; MAX_OPUS_FRAME * channels];
+    let mut end = None;
+    while let Some(packet) = packets.read_packet()? {
+        let decoded = decoder.decode_float(Some(packet.data.as_slice().try_into()?), frame.as_mut_slice().try_into()?, false)?;
+        samples.extend_from_slice(&frame[..decoded * channels]);
+        if packet.last_in_stream() {
+            end = Some(packet.absgp_page() as usize * channels);
+        }
+    }
+
+    // the last page's granule position is where the audio really ends
+    if let Some(end) = end {
+        samples.truncate(end.max(pre_skip));
+    }
+    samples.drain(..pre_skip.min(samples.len()));
+
+    Ok((Sound { samples, sample_rate: OPUS_RATE }, head.data[9] as usize))
+}
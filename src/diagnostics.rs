@@ -0,0 +1,91 @@
+use std::{fmt::Display, fs, io::Write, path::Path, sync::Mutex};
+
+use ocl::{enums::DeviceInfo, Device, Platform};
+use zip::{write::SimpleFileOptions, ZipWriter};
+
+use crate::logging;
+
+#[derive(thiserror::Error, Debug)]
+pub enum DiagnosticsError {
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("zip error: {0}")]
+    Zip(#[from] zip::result::ZipError)
+}
+
+/// what the run noted down about itself so far, see `note`
+static NOTES: Mutex<Vec<(String, String)>> = Mutex::new(Vec::new());
+
+/// notes down something about the run for a bundle, like the versions it
+/// resolved or the shape of a matrix. later notes of the same name win
+pub fn note(name: &str, value: impl Display) {
+    let mut notes = NOTES.lock().unwrap();
+    notes.retain(|(existing, _)| existing != name);
+    notes.push((name.to_string(), value.to_string()));
+}
+
+/// every OpenCL platform and its devices, or why there are none
+pub fn devices() -> String {
+    let platforms = match ocl::core::get_platform_ids() {
+        Ok(platforms) => platforms.into_iter().map(Platform::new).collect::<Vec<Platform>>(),
+        Err(error) => return format!("no OpenCL platforms: {}\n", error),
+    };
+
+    let mut output = String::new();
+    for (index, platform) in platforms.iter().enumerate() {
+        let name = platform.name().unwrap_or_else(|error| error.to_string());
+        let version = platform.version().unwrap_or_else(|error| error.to_string());
+        output.push_str(&format!("platform {}: {} ({})\n", index, name, version));
+
+        match Device::list_all(platform) {
+            Ok(devices) => {
+                for (index, device) in devices.iter().enumerate() {
+                    let info = |info: DeviceInfo| device.info(info).map(|info| info.to_string()).unwrap_or_else(|error| error.to_string());
+                    output.push_str(&format!(
+                        "  device {}: {} by {}, {}, driver {}, {} bytes of memory\n",
+                        index, info(DeviceInfo::Name), info(DeviceInfo::Vendor), info(DeviceInfo::Version),
+                        info(DeviceInfo::DriverVersion), info(DeviceInfo::GlobalMemSize)
+                    ));
+                }
+            },
+            Err(error) => output.push_str(&format!("  no devices: {}\n", error)),
+        }
+    }
+
+    return output;
+}
+
+/// a zip of everything useful in a bug report: the error, the settings, the
+/// environment and OpenCL devices, the notes and the last log lines. it's
+/// only written locally, attaching it to an issue is up to the user
+pub fn write_bundle(path: &Path, error: &str, settings: &str) -> Result<(), DiagnosticsError> {
+    let environment = format!(
+        "minecraft-player {}\n{} {}\n",
+        env!("CARGO_PKG_VERSION"), std::env::consts::OS, std::env::consts::ARCH
+    );
+    let notes = NOTES.lock().unwrap().iter()
+        .map(|(name, value)| format!("{}: {}\n", name, value))
+        .collect::<String>();
+    let log = logging::recent_lines().join("\n");
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let mut zip = ZipWriter::new(fs::File::create(path)?);
+    let options = SimpleFileOptions::default();
+    for (name, contents) in [
+        ("error.txt", error),
+        ("settings.txt", settings),
+        ("environment.txt", &environment),
+        ("devices.txt", &devices()),
+        ("notes.txt", &notes),
+        ("log.txt", &log),
+    ] {
+        zip.start_file(name, options)?;
+        zip.write_all(contents.as_bytes())?;
+    }
+    zip.finish()?;
+
+    Ok(())
+}
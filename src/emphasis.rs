@@ -0,0 +1,127 @@
+use std::{path::PathBuf, str::FromStr};
+
+/// ticks quieter than this (relative to the loudest second) still get a
+/// share of the budget, intros shouldn't go silent
+static LOUDNESS_FLOOR: f32 = 0.25;
+
+/// ticks the loudness is averaged over, about a second
+static LOUDNESS_WINDOW: usize = 20;
+
+#[derive(thiserror::Error, Debug)]
+pub enum EmphasisError {
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("line {line}: {message}")]
+    Parse { line: usize, message: String }
+}
+
+/// where the weights of ticks come from, given on the command line as a
+/// path or `auto`
+#[derive(Clone, Debug)]
+pub enum Emphasis {
+    File(PathBuf),
+    Auto
+}
+
+impl FromStr for Emphasis {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "" => Err("missing the emphasis file".to_string()),
+            "auto" => Ok(Emphasis::Auto),
+            path => Ok(Emphasis::File(PathBuf::from(path))),
+        }
+    }
+}
+
+/// weights from lines of `start end weight`, in seconds. `#` starts a
+/// comment. ticks no line covers weigh 1, later lines override earlier ones
+pub fn parse(contents: &str, n_ticks: usize) -> Result<Vec<f32>, EmphasisError> {
+    let mut weights = vec![1.0; n_ticks];
+
+    for (index, line) in contents.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or_default().trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let error = |message: &str| EmphasisError::Parse { line: index + 1, message: message.to_string() };
+        let fields = line.split_whitespace()
+            .map(|field| field.parse::<f32>().map_err(|_| error(&format!("`{}` is not a number", field))))
+            .collect::<Result<Vec<f32>, EmphasisError>>()?;
+
+        let [start, end, weight] = fields[..] else {
+            return Err(error("expected `start end weight`"));
+        };
+        if !(0.0..=end).contains(&start) {
+            return Err(error("start has to be between 0 and the end"));
+        }
+        if !weight.is_finite() || weight < 0.0 {
+            return Err(error("weight has to be a non-negative number"));
+        }
+
+        // a tick lasts 50ms
+        let first = ((start * 20.0) as usize).min(n_ticks);
+        let last = ((end * 20.0).ceil() as usize).min(n_ticks);
+        weights[first..last].fill(weight);
+    }
+
+    Ok(weights)
+}
+
+/// weights from how loud the input is around every tick, as a stand-in for
+/// where the important parts are. choruses tend to be louder than intros
+pub fn from_loudness(samples: &[f32], samples_per_tick: usize, n_ticks: usize) -> Vec<f32> {
+    let loudness = (0..n_ticks)
+        .map(|tick| {
+            let tick = samples.iter().skip(tick * samples_per_tick).take(samples_per_tick);
+            (tick.map(|sample| sample * sample).sum::<f32>() / samples_per_tick.max(1) as f32).sqrt()
+        })
+        .collect::<Vec<f32>>();
+
+    let half = LOUDNESS_WINDOW / 2;
+    let smoothed = (0..n_ticks)
+        .map(|tick| {
+            let window = &loudness[tick.saturating_sub(half)..(tick + half + 1).min(n_ticks)];
+            window.iter().sum::<f32>() / window.len() as f32
+        })
+        .collect::<Vec<f32>>();
+
+    let loudest = smoothed.iter().cloned().fold(0.0, f32::max);
+    return smoothed.iter()
+        .map(|loudness| if loudest > 0.0 { (loudness / loudest).max(LOUDNESS_FLOOR) } else { 1.0 })
+        .collect();
+}
+
+/// the commands every tick may use. the heaviest ticks get all of `budget`
+/// and the rest their share of it, but at least one
+pub fn budgets(weights: &[f32], budget: usize) -> Vec<usize> {
+    let heaviest = weights.iter().cloned().fold(0.0, f32::max);
+
+    return weights.iter()
+        .map(|weight| match heaviest > 0.0 {
+            true => ((budget as f32 * weight / heaviest).round() as usize).clamp(1, budget),
+            false => budget,
+        })
+        .collect();
+}
+
+/// the first tick of the `length` ticks that weigh the most together, e.g.
+/// the loudest stretch with weights `from_loudness`
+pub fn heaviest_window(weights: &[f32], length: usize) -> usize {
+    if length >= weights.len() {
+        return 0;
+    }
+
+    let mut sum = weights[..length].iter().sum::<f32>();
+    let (mut best, mut best_sum) = (0, sum);
+    for start in 1..=weights.len() - length {
+        sum += weights[start + length - 1] - weights[start - 1];
+        if sum > best_sum {
+            (best, best_sum) = (start, sum);
+        }
+    }
+
+    return best;
+}
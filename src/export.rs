@@ -0,0 +1,919 @@
+use std::{collections::{BTreeMap, HashMap, VecDeque}, io::{self, Seek, Write}, ops::Range, path::{Path, PathBuf}, str::FromStr};
+
+use ndarray::ArrayView1;
+use serde_json::json;
+use tokio::fs;
+use tracing::{event, span, Level};
+use zip::{write::SimpleFileOptions, ZipWriter};
+
+use crate::{exporter::{Exporter, Song}, surround, versions::Availability};
+
+#[derive(thiserror::Error, Debug)]
+pub enum ExportError {
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("invalid json: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("zip error: {0}")]
+    Zip(#[from] zip::result::ZipError),
+    #[error("output `{0}` exists and is not a directory")]
+    NotADirectory(PathBuf),
+    #[error("output directory `{0}` is not empty")]
+    NotEmpty(PathBuf),
+    #[error("output `{0}` already exists")]
+    Exists(PathBuf),
+    #[error("`{alias}`, the alias of `{event}`, is not a sound event")]
+    InvalidAlias { event: String, alias: String },
+    #[error("`{0}` is aliased twice, with and without `minecraft:`")]
+    DuplicateAlias(String)
+}
+
+/// what the playback is written as
+#[derive(clap::ValueEnum, Clone, Copy, Default, Debug, PartialEq)]
+pub enum Format {
+    /// a datapack of tick functions that schedule each other
+    #[default]
+    Datapack,
+    /// a structure (`.nbt`) of command blocks, for servers without datapacks
+    #[cfg(feature = "structure")]
+    Structure,
+}
+
+/// the sound categories of the game, each with its own volume slider
+#[derive(clap::ValueEnum, Clone, Copy, Default, Debug, PartialEq)]
+pub enum Category {
+    Master,
+    Music,
+    /// jukeboxes and note blocks
+    #[default]
+    Record,
+    Weather,
+    Block,
+    Hostile,
+    Neutral,
+    Player,
+    Ambient,
+    Voice,
+}
+
+impl Category {
+    /// the name `playsound` and `stopsound` take
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Category::Master => "master",
+            Category::Music => "music",
+            Category::Record => "record",
+            Category::Weather => "weather",
+            Category::Block => "block",
+            Category::Hostile => "hostile",
+            Category::Neutral => "neutral",
+            Category::Player => "player",
+            Category::Ambient => "ambient",
+            Category::Voice => "voice",
+        }
+    }
+}
+
+/// where the sounds are played from, given on the command line as `player`,
+/// `world <x> <y> <z>` or `entity <selector>`
+#[derive(Clone, Debug, PartialEq)]
+pub enum Anchor {
+    /// at every player, so everyone hears it as loud wherever they stand
+    Player,
+    /// at a fixed position, heard quieter (or not at all) further away
+    World([f64; 3]),
+    /// at every entity the selector picks, e.g. a speaker armor stand
+    Entity(String),
+}
+
+impl Default for Anchor {
+    fn default() -> Self {
+        Anchor::World([0.0, -60.0, 0.0])
+    }
+}
+
+impl Anchor {
+    /// the `playsound` of `sound` in `category` from here
+    pub fn playsound(&self, sound: &PlaySound, category: Category) -> String {
+        let play = |targets: &str, position: &str| format!("playsound {} {} {} {} {:.5} {:.5} ", sound.name, category.as_str(), targets, position, sound.volume, sound.pitch);
+        match self {
+            Anchor::Player => format!("execute as @a at @s run {}", play("@s", "~ ~ ~")),
+            Anchor::World([x, y, z]) => play("@a", &format!("{} {} {}", x, y, z)),
+            Anchor::Entity(selector) => format!("execute at {} run {}", selector, play("@a", "~ ~ ~")),
+        }
+    }
+}
+
+impl FromStr for Anchor {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().split_once(' ').unwrap_or((s.trim(), "")) {
+            ("player", "") => Ok(Anchor::Player),
+            ("world", position) => {
+                let coordinates = position.split_whitespace()
+                    .map(|coordinate| coordinate.parse::<f64>().ok().filter(|coordinate| coordinate.is_finite()))
+                    .collect::<Option<Vec<f64>>>();
+                match coordinates.as_deref() {
+                    Some(&[x, y, z]) => Ok(Anchor::World([x, y, z])),
+                    _ => Err(format!("`{}` is not a position, give it as `world <x> <y> <z>`", position)),
+                }
+            },
+            ("entity", selector) if selector.trim().starts_with('@') => Ok(Anchor::Entity(selector.trim().to_string())),
+            ("entity", selector) => Err(format!("`{}` is not an entity selector, e.g. `@e[tag=speaker]`", selector)),
+            _ => Err(format!("`{}` is not one of `player`, `world <x> <y> <z>` or `entity <selector>`", s)),
+        }
+    }
+}
+
+pub static NAMESPACE: &str = "audio";
+/// the format of 1.21, the python module's default
+pub static PACK_FORMAT: u32 = 48;
+/// 24w21a's, which renamed the `functions` directories to `function`
+static SINGULAR_FORMAT: u32 = 45;
+/// from 25w31a's on, `pack.mcmeta` says which formats it's for as a range
+static RANGED_FORMAT: u32 = 82;
+/// the top of the range of an open `pack.mcmeta`, see `mcmeta`
+static OPEN_FORMAT: u32 = i32::MAX as u32;
+
+/// identical ticks in a row it takes to share one function between them.
+/// two would take just as many files
+pub static MIN_REPEATS: usize = 3;
+
+/// sounds `--annotate` names in the comment of a tick, the loudest first
+pub static ANNOTATED_SOUNDS: usize = 3;
+
+/// `function`, or `functions` in the datapacks of versions before 24w21a
+fn functions(pack_format: u32) -> &'static str {
+    match pack_format >= SINGULAR_FORMAT {
+        true => "function",
+        false => "functions",
+    }
+}
+
+/// `<output>/data/audio/function`, where the functions of `audio:` are
+fn function_directory(output: &Path, pack_format: u32) -> PathBuf {
+    output.join("data").join(NAMESPACE).join(functions(pack_format))
+}
+
+/// `<output>/data/audio/function/_`, where every tick function lives so
+/// they can schedule each other as `audio:_/{index}`
+pub fn tick_directory(output: &Path, pack_format: u32) -> PathBuf {
+    function_directory(output, pack_format).join("_")
+}
+
+/// `audio:_/play`, in the tick directory, has the sounds of ticks that
+/// repeat, see `repeat_function`
+pub fn play_directory(tick_directory: &Path) -> PathBuf {
+    tick_directory.join("play")
+}
+
+/// `<output>/data/audio/function/chapter`, where `audio:chapter/{index}`
+/// start playback at the beginning of a chapter
+pub fn chapter_directory(output: &Path, pack_format: u32) -> PathBuf {
+    function_directory(output, pack_format).join("chapter")
+}
+
+/// `audio:index`, which lists the chapters
+pub fn index_path(output: &Path, pack_format: u32) -> PathBuf {
+    function_directory(output, pack_format).join("index.mcfunction")
+}
+
+/// `audio:finish`, which runs after the last tick
+pub fn finish_path(output: &Path, pack_format: u32) -> PathBuf {
+    function_directory(output, pack_format).join("finish.mcfunction")
+}
+
+/// `audio:stop`, which ends a song that loops
+pub fn stop_path(output: &Path, pack_format: u32) -> PathBuf {
+    function_directory(output, pack_format).join("stop.mcfunction")
+}
+
+/// `audio:start`, which ducks other sounds before starting the song
+pub fn start_path(output: &Path, pack_format: u32) -> PathBuf {
+    function_directory(output, pack_format).join("start.mcfunction")
+}
+
+/// `audio:load`, which the game runs on load to make the `audio` score
+/// grouped tick functions keep their place in
+pub fn load_path(output: &Path, pack_format: u32) -> PathBuf {
+    function_directory(output, pack_format).join("load.mcfunction")
+}
+
+/// `#minecraft:load`, the tag that has the game run `audio:load`
+pub fn load_tag_path(output: &Path, pack_format: u32) -> PathBuf {
+    output.join("data").join("minecraft").join("tags").join(functions(pack_format)).join("load.json")
+}
+
+/// `<output>/sound_versions.json`, the versions the sound events only some
+/// of the basis versions have are in
+pub fn availability_path(output: &Path) -> PathBuf {
+    output.join("sound_versions.json")
+}
+
+/// `<output>/manifest.json`, what `--annotate` says about every tick
+pub fn manifest_path(output: &Path) -> PathBuf {
+    output.join("manifest.json")
+}
+
+/// one `playsound` of a tick. `sound` indexes the basis
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlaySound<'a> {
+    pub sound: usize,
+    pub name: &'a str,
+    pub volume: f32,
+    pub pitch: f32
+}
+
+/// whether `name` is a resource location, `path` or `namespace:path`, like
+/// the sound events that can be played and the functions that can be run
+pub fn is_resource_location(name: &str) -> bool {
+    let (namespace, path) = name.split_once(':').unwrap_or(("minecraft", name));
+    let valid = |part: &str, extra: &[char]| !part.is_empty()
+        && part.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || "_-.".contains(c) || extra.contains(&c));
+
+    return valid(namespace, &[]) && valid(path, &['/']);
+}
+
+/// the events to play instead of basis events, from a json object of
+/// `"event": "alias"`. an alias can be in another namespace, like a
+/// resource pack's own sounds. events are keyed like the basis names them,
+/// without `minecraft:`
+pub fn parse_aliases(contents: &str) -> Result<HashMap<String, String>, ExportError> {
+    let parsed = serde_json::from_str::<HashMap<String, String>>(contents)?;
+
+    let mut aliases = HashMap::with_capacity(parsed.len());
+    for (event, alias) in parsed {
+        if !is_resource_location(&alias) {
+            return Err(ExportError::InvalidAlias { event, alias });
+        }
+
+        let event = event.strip_prefix("minecraft:").map(str::to_string).unwrap_or(event);
+        if aliases.insert(event.clone(), alias).is_some() {
+            return Err(ExportError::DuplicateAlias(event));
+        }
+    }
+    Ok(aliases)
+}
+
+/// the `budget` loudest sounds of a tick, loudest first. silent ones are
+/// left out, so a tick can have fewer commands than the budget
+pub fn select<'a>(amplitudes: ArrayView1<f32>, sound_ids: &'a [(String, f32)], budget: usize) -> Vec<PlaySound<'a>> {
+    return Selector::default().select(amplitudes, sound_ids, budget);
+}
+
+/// `select` for tick after tick, keeping its buffer of the sounds playing
+/// in a tick between them. only the loudest `budget` of those are sorted
+#[derive(Default)]
+pub struct Selector {
+    playing: Vec<(usize, f32)>
+}
+
+impl Selector {
+    pub fn select<'a>(&mut self, amplitudes: ArrayView1<f32>, sound_ids: &'a [(String, f32)], budget: usize) -> Vec<PlaySound<'a>> {
+        assert_eq!(amplitudes.len(), sound_ids.len());
+
+        // NaN isn't above 0 either
+        self.playing.clear();
+        self.playing.extend(amplitudes.iter().copied().enumerate().filter(|(_, volume)| *volume > 0.0));
+
+        // equally loud sounds go by their index, like a stable sort of them all
+        let louder = |a: &(usize, f32), b: &(usize, f32)| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0));
+        if budget < self.playing.len() {
+            if budget > 0 {
+                self.playing.select_nth_unstable_by(budget - 1, louder);
+            }
+            self.playing.truncate(budget);
+        }
+        self.playing.sort_unstable_by(louder);
+
+        return self.playing.iter()
+            .map(|(sound, volume)| {
+                let (name, pitch) = &sound_ids[*sound];
+                PlaySound { sound: *sound, name, volume: *volume, pitch: *pitch }
+            })
+            .collect();
+    }
+}
+
+/// stops the sounds of `category` for everyone who hasn't opted out of the
+/// music with the `nomusic` tag
+pub fn stop_sounds(category: Category) -> String {
+    format!("stopsound @a[tag=!nomusic] {}\n", category.as_str())
+}
+
+/// what a tick plays in `category`. it stops the previous tick's sounds
+/// (unless they're meant to ring into this one) and plays its own from
+/// `anchor`
+pub fn tick_sounds(sounds: &[PlaySound], stop_previous: bool, category: Category, anchor: &Anchor) -> String {
+    let mut output = String::new();
+    if stop_previous {
+        output.push_str(&stop_sounds(category));
+    }
+
+    for sound in sounds {
+        output.push_str(&anchor.playsound(sound, category));
+        output.push('\n');
+    }
+
+    return output;
+}
+
+/// stops sound `name` of `category`, however many times it plays, for
+/// everyone `stop_sounds` would stop it for
+pub fn stop_sound(category: Category, name: &str) -> String {
+    format!("stopsound @a[tag=!nomusic] {} {}\n", category.as_str(), name)
+}
+
+/// with atoms of more than a tick, the sounds to stop `atom_ticks` after
+/// they were played, since the solve only modeled that much of them. a
+/// sound played again since is left alone, `stopsound` would stop that
+/// one too
+#[derive(Default)]
+pub struct Stops {
+    /// the sounds of the last `atom_ticks` ticks, the oldest first
+    played: VecDeque<Vec<String>>
+}
+
+impl Stops {
+    /// the sounds to stop before tick `sounds` plays
+    pub fn next(&mut self, atom_ticks: usize, sounds: &[PlaySound]) -> Vec<String> {
+        let mut stopped = Vec::new();
+        if self.played.len() == atom_ticks {
+            if let Some(oldest) = self.played.pop_front() {
+                for name in oldest {
+                    if !stopped.contains(&name) && !self.played.iter().any(|played| played.contains(&name)) {
+                        stopped.push(name);
+                    }
+                }
+            }
+        }
+        self.played.push_back(sounds.iter().map(|sound| sound.name.to_string()).collect());
+
+        return stopped;
+    }
+}
+
+/// `tick_sounds` of tick `index` of `song`, from `Song::anchor` or, with
+/// `Song::directions`, around every player from the tick's direction. with
+/// atoms of more than a tick, the sounds `stops` is done with are stopped
+/// one by one instead of all of the previous tick's
+pub fn song_tick_sounds(song: &Song, index: usize, sounds: &[PlaySound], stops: &mut Stops) -> String {
+    let stop_previous = song.atom_ticks == 1;
+    let mut output = String::new();
+    if !stop_previous {
+        for name in stops.next(song.atom_ticks, sounds) {
+            output.push_str(&stop_sound(song.category, &name));
+        }
+    }
+
+    output.push_str(&match &song.directions {
+        Some(directions) => surround::tick_sounds(sounds, stop_previous, song.category, directions.get(index).copied().unwrap_or(0.0)),
+        None => tick_sounds(sounds, stop_previous, song.category, &song.anchor),
+    });
+    return output;
+}
+
+/// schedules tick function `next` in `ticks` ticks, nothing after the last
+pub fn schedule_next(next: Option<usize>, ticks: usize) -> String {
+    match next {
+        Some(next) => format!("schedule function {}:_/{} {}t append\n", NAMESPACE, next, ticks),
+        None => String::new(),
+    }
+}
+
+/// when tick `tick` plays, as `m:ss.cc`
+pub fn tick_time(tick: usize) -> String {
+    let centiseconds = tick * 5;
+    format!("{}:{:02}.{:02}", centiseconds / 6000, centiseconds / 100 % 60, centiseconds % 100)
+}
+
+/// the comment `--annotate` puts in front of what tick `tick` plays: when it
+/// plays, how far off the solution was there (if it's known) and its loudest
+/// sounds, which `select` put first
+pub fn tick_comment(tick: usize, sounds: &[PlaySound], residual: Option<f32>) -> String {
+    let mut output = format!("# tick {} at {}", tick, tick_time(tick));
+    if let Some(residual) = residual {
+        output.push_str(&format!(", residual {:.3}", residual));
+    }
+
+    let loudest = sounds.iter()
+        .take(ANNOTATED_SOUNDS)
+        .map(|sound| format!("{} at {:.2}, pitch {:.2}", sound.name, sound.volume, sound.pitch))
+        .collect::<Vec<String>>();
+    match loudest.is_empty() {
+        true => output.push_str("\n# silent\n"),
+        false => output.push_str(&format!("\n# loudest: {}\n", loudest.join("; "))),
+    }
+
+    return output;
+}
+
+/// the body of tick function `audio:_/{index}`: its sounds, then it
+/// schedules `next`, which is `None` for the last tick so it doesn't point at
+/// a function that doesn't exist
+pub fn tick_function(sounds: &[PlaySound], next: Option<usize>, stop_previous: bool, category: Category, anchor: &Anchor) -> String {
+    return tick_sounds(sounds, stop_previous, category, anchor) + &schedule_next(next, 1);
+}
+
+/// schedules `audio:finish` in `ticks` ticks, from the last tick
+pub fn schedule_finish(ticks: usize) -> String {
+    format!("schedule function {}:finish {}t append\n", NAMESPACE, ticks)
+}
+
+/// the body of `audio:finish`: stops whatever still rings and runs
+/// `on_finish`, e.g. the next song or a credits screen
+pub fn finish_function(on_finish: Option<&str>, category: Category) -> String {
+    let mut output = stop_sounds(category);
+    if let Some(function) = on_finish {
+        output.push_str(&format!("function {}\n", function));
+    }
+
+    return output;
+}
+
+/// the body of `audio:stop`: a song that loops never gets to `audio:finish`,
+/// so this clears whichever of the tick functions (`ticks`) and the repeated
+/// ones (`repeated`, see `repeat_function`) is scheduled and stops what rings
+pub fn stop_function(ticks: &[usize], repeated: &[usize], category: Category) -> String {
+    let mut output = String::new();
+    for tick in ticks {
+        output.push_str(&format!("schedule clear {}:_/{}\n", NAMESPACE, tick));
+    }
+    for first in repeated {
+        output.push_str(&format!("schedule clear {}:_/play/{}\n", NAMESPACE, first));
+    }
+
+    return output + &stop_sounds(category);
+}
+
+/// the body of `audio:start`: stops what plays in the `duck` categories, like
+/// the game's own music, so it doesn't play over the song, then starts it.
+/// nothing can turn their sliders down, the game's music comes back on its
+/// own minutes later
+pub fn start_function(duck: &[Category]) -> String {
+    let mut output = String::new();
+    for category in duck {
+        output.push_str(&stop_sounds(*category));
+    }
+
+    return output + &format!("function {}:_/0\n", NAMESPACE);
+}
+
+/// the body of tick function `audio:_/{first}` when it and the `repeats - 1`
+/// ticks after it play the same (`tick_sounds`, in `audio:_/play/{first}`).
+/// those are scheduled from here instead of getting functions of their own,
+/// and `next` is the tick after them
+pub fn repeat_function(first: usize, repeats: usize, next: Option<usize>) -> String {
+    let mut output = format!("function {}:_/play/{}\n", NAMESPACE, first);
+    for delay in 1..repeats {
+        output.push_str(&format!("schedule function {}:_/play/{} {}t append\n", NAMESPACE, first, delay));
+    }
+
+    return output + &schedule_next(next, repeats);
+}
+
+/// the body of `audio:load`, the score grouped tick functions count in
+pub fn load_function() -> String {
+    format!("scoreboard objectives add {} dummy\n", NAMESPACE)
+}
+
+/// the body of tick function `audio:_/{first}` when it plays the ticks
+/// after it too, `ticks` being what each plays (`tick_sounds`). it runs
+/// again every tick, playing the lines of the tick its `#tick` score is at.
+/// it only goes on from the score when it scheduled itself, which it notes
+/// in `#next`, so it starts over from `first` whenever it's started from
+/// outside, whatever the score was left at. once the score is at the last
+/// tick it runs `then`, the same as a tick function would. comments in
+/// `ticks` are kept as they are
+pub fn block_function(first: usize, ticks: &[String], then: &str) -> String {
+    let last = first + ticks.len() - 1;
+    let score = format!("#tick {}", NAMESPACE);
+    let next = format!("#next {}", NAMESPACE);
+
+    let mut output = format!("execute unless score {} matches {} run scoreboard players set {} {}\n", next, first, score, first);
+    output.push_str(&format!("scoreboard players reset {}\n", next));
+    for (offset, sounds) in ticks.iter().enumerate() {
+        for line in sounds.lines() {
+            if line.starts_with('#') {
+                output.push_str(&format!("{}\n", line));
+                continue;
+            }
+            output.push_str(&format!("execute if score {} matches {} run {}\n", score, first + offset, line));
+        }
+    }
+    if last > first {
+        output.push_str(&format!("execute if score {} matches {}..{} run scoreboard players set {} {}\n", score, first, last - 1, next, first));
+        output.push_str(&format!("execute if score {} matches {}..{} run schedule function {}:_/{} 1t append\n", score, first, last - 1, NAMESPACE, first));
+    }
+    for line in then.lines() {
+        output.push_str(&format!("execute if score {} matches {} run {}\n", score, last, line));
+    }
+
+    return output + &format!("scoreboard players add {} 1\n", score);
+}
+
+/// the body of `audio:chapter/{index}`, which jumps into the tick functions
+/// at the chapter's first tick. they play on into the following chapters
+pub fn chapter_function(first_tick: usize) -> String {
+    format!("function {}:_/{}\n", NAMESPACE, first_tick)
+}
+
+/// the body of `audio:index`: a line per chapter, by the time it starts at,
+/// that starts it when clicked. `chapters` are their first ticks
+pub fn index_function(chapters: &[usize]) -> String {
+    let mut output = String::new();
+
+    for (index, first_tick) in chapters.iter().enumerate() {
+        let seconds = first_tick / 20;
+        let line = json!({
+            "text": format!("chapter {} ({}:{:02}:{:02})", index + 1, seconds / 3600, seconds / 60 % 60, seconds % 60),
+            "clickEvent": {
+                "action": "run_command",
+                "value": format!("/function {}:chapter/{}", NAMESPACE, index)
+            }
+        });
+        output.push_str(&format!("tellraw @s {}\n", line));
+    }
+
+    return output;
+}
+
+/// ticks in a row that play the same, written as one once the run ends
+struct Run {
+    first: usize,
+    length: usize,
+    sounds: String,
+    /// the `tick_comment` of each, empty unless `Song::annotate`
+    comments: Vec<String>,
+    silent: bool
+}
+
+/// ticks in a row played from one function, see `block_function`
+struct Block {
+    first: usize,
+    /// the `tick_sounds` of each, after its `tick_comment`
+    ticks: Vec<String>,
+    silent: bool
+}
+
+/// writes every tick as a function that schedules the next, see
+/// `tick_function`, into a datapack `prepare_output` made. with
+/// `Song::ticks_per_function` above 1, ticks are grouped into functions
+/// of that many instead, see `block_function`. with `Song::annotate`, the
+/// functions say what they play and `manifest.json` where every tick is
+pub struct Datapack {
+    song: Song,
+    tick_directory: PathBuf,
+    run: Option<Run>,
+    block: Option<Block>,
+    /// the tick functions and repeated ones written, for `audio:stop`
+    functions: Vec<usize>,
+    repeated: Vec<usize>,
+    /// the `manifest.json` entry of every tick written, by tick
+    manifest: BTreeMap<usize, serde_json::Value>,
+    stops: Stops
+}
+
+impl Datapack {
+    /// writes the `pack.mcmeta` of `Song::pack_format`, a datapack
+    /// `prepare_output` cleared the way for
+    pub fn new(song: Song) -> Result<Self, ExportError> {
+        let tick_directory = tick_directory(&song.output, song.pack_format);
+        std::fs::create_dir_all(&tick_directory)?;
+        std::fs::write(song.output.join("pack.mcmeta"), serde_json::to_string_pretty(&mcmeta(song.pack_format, song.open_format))?)?;
+
+        Ok(Datapack {
+            tick_directory,
+            song,
+            run: None,
+            block: None,
+            functions: Vec::new(),
+            repeated: Vec::new(),
+            manifest: BTreeMap::new(),
+            stops: Stops::default()
+        })
+    }
+
+    /// tick function `tick`, `delay` ticks later, or after the last one
+    /// `audio:finish` once its atoms are done ringing. a loop starts over
+    /// instead, the first ticks were solved with those atoms ringing on
+    fn then(&self, tick: usize, delay: usize) -> String {
+        match tick < self.song.n_ticks {
+            true => schedule_next(Some(tick), delay),
+            false if self.song.looping => schedule_next(Some(0), delay),
+            false => schedule_finish(delay + self.song.atom_ticks - 1),
+        }
+    }
+
+    /// the comment of a function that plays for the `length` ticks from
+    /// `first`, which only has the first tick's `tick_comment`
+    fn same_through(&self, first: usize, length: usize) -> String {
+        match self.song.annotate && length > 1 {
+            true => format!("# the same through tick {}\n", first + length - 1),
+            false => String::new(),
+        }
+    }
+
+    /// points the manifest entries of `ticks` at function `name` in
+    /// `directory`, the one that plays them
+    fn note_function(&mut self, ticks: Range<usize>, directory: &Path, name: usize) {
+        let path = function_path(directory, name);
+        let relative = path.strip_prefix(&self.song.output).unwrap_or(&path);
+        let function_directory = function_directory(&self.song.output, self.song.pack_format);
+        let id = directory.strip_prefix(&function_directory).unwrap_or(directory).join(name.to_string());
+        let id = format!("{}:{}", NAMESPACE, id.to_string_lossy().replace('\\', "/"));
+
+        for tick in ticks {
+            if let Some(entry) = self.manifest.get_mut(&tick) {
+                entry["function"] = json!(id);
+                entry["path"] = json!(relative.to_string_lossy().replace('\\', "/"));
+            }
+        }
+    }
+
+    /// writes the tick functions of the run that just ended. silence only
+    /// needs its first tick, and sounds repeated often enough are played
+    /// from a shared function, see `repeat_function`
+    fn end_run(&mut self) -> Result<(), ExportError> {
+        let Some(run) = self.run.take() else {
+            return Ok(());
+        };
+
+        let end = run.first + run.length;
+        let tick_directory = self.tick_directory.clone();
+        let comment = run.comments.first().cloned().unwrap_or_default() + &self.same_through(run.first, run.length);
+        if run.silent {
+            let output = comment + &run.sounds + &self.then(end, run.length);
+            std::fs::write(function_path(&tick_directory, run.first), output)?;
+            self.functions.push(run.first);
+            self.note_function(run.first..end, &tick_directory, run.first);
+        } else if run.length >= MIN_REPEATS {
+            let play_directory = play_directory(&tick_directory);
+            std::fs::create_dir_all(&play_directory)?;
+            std::fs::write(function_path(&play_directory, run.first), comment.clone() + &run.sounds)?;
+            let output = comment + &repeat_function(run.first, run.length, None) + &self.then(end, run.length);
+            std::fs::write(function_path(&tick_directory, run.first), output)?;
+            self.functions.push(run.first);
+            self.repeated.push(run.first);
+            self.note_function(run.first..end, &play_directory, run.first);
+        } else {
+            for tick in run.first..end {
+                let comment = run.comments.get(tick - run.first).map(String::as_str).unwrap_or_default();
+                let output = comment.to_string() + &run.sounds + &self.then(tick + 1, 1);
+                std::fs::write(function_path(&tick_directory, tick), output)?;
+                self.functions.push(tick);
+                self.note_function(tick..tick + 1, &tick_directory, tick);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// writes the function of the block of ticks that just ended. a silent
+    /// one only needs its first tick, like a silent run
+    fn end_block(&mut self) -> Result<(), ExportError> {
+        let Some(block) = self.block.take() else {
+            return Ok(());
+        };
+
+        let (length, end) = (block.ticks.len(), block.first + block.ticks.len());
+        let output = match block.silent {
+            true => block.ticks[0].clone() + &self.same_through(block.first, length) + &self.then(end, length),
+            false => block_function(block.first, &block.ticks, &self.then(end, 1)),
+        };
+        let tick_directory = self.tick_directory.clone();
+        std::fs::write(function_path(&tick_directory, block.first), output)?;
+        self.functions.push(block.first);
+        self.note_function(block.first..end, &tick_directory, block.first);
+
+        Ok(())
+    }
+}
+
+fn function_path(directory: &Path, tick: usize) -> PathBuf {
+    directory.join(tick.to_string()).with_extension("mcfunction")
+}
+
+impl Exporter for Datapack {
+    fn write_tick(&mut self, index: usize, sounds: &[PlaySound]) -> Result<(), anyhow::Error> {
+        let comment = match self.song.annotate {
+            true => {
+                let residual = self.song.residuals.as_ref().and_then(|residuals| residuals.get(index).copied());
+                let loudest = sounds.iter()
+                    .take(ANNOTATED_SOUNDS)
+                    .map(|sound| json!({ "sound": sound.name, "volume": sound.volume, "pitch": sound.pitch }))
+                    .collect::<Vec<serde_json::Value>>();
+                self.manifest.insert(index, json!({
+                    "tick": index,
+                    "time": tick_time(index),
+                    "sounds": sounds.len(),
+                    "residual": residual,
+                    "loudest": loudest
+                }));
+                tick_comment(index, sounds, residual)
+            },
+            false => String::new(),
+        };
+
+        let played = song_tick_sounds(&self.song, index, sounds, &mut self.stops);
+        // a tick that only stops sounds still has to be written
+        let (silent, sounds) = (sounds.is_empty() && (self.song.atom_ticks == 1 || played.is_empty()), played);
+        if self.song.ticks_per_function > 1 {
+            match &mut self.block {
+                Some(block) if block.ticks.len() < self.song.ticks_per_function => {
+                    block.ticks.push(comment + &sounds);
+                    block.silent &= silent;
+                },
+                _ => {
+                    self.end_block()?;
+                    self.block = Some(Block { first: index, ticks: vec![comment + &sounds], silent });
+                },
+            }
+            return Ok(());
+        }
+
+        match &mut self.run {
+            Some(run) if run.sounds == sounds => {
+                run.length += 1;
+                run.comments.push(comment);
+            },
+            _ => {
+                self.end_run()?;
+                self.run = Some(Run { first: index, length: 1, sounds, comments: vec![comment], silent });
+            },
+        }
+
+        Ok(())
+    }
+
+    /// every chapter gets a function of its own to jump to
+    fn split(&mut self) -> Result<(), anyhow::Error> {
+        self.end_run()?;
+        Ok(self.end_block()?)
+    }
+
+    /// writes `audio:finish`, or `audio:stop` for a loop, and `audio:start`
+    /// when other categories are ducked. grouped ticks need `audio:load`,
+    /// and an annotated datapack gets its `manifest.json`
+    fn finish(mut self: Box<Self>) -> Result<(), anyhow::Error> {
+        self.end_run()?;
+        self.end_block()?;
+
+        let song = &self.song;
+        if song.annotate {
+            let manifest = self.manifest.values().collect::<Vec<&serde_json::Value>>();
+            std::fs::write(manifest_path(&song.output), serde_json::to_string_pretty(&manifest)?)?;
+        }
+        if !song.duck.is_empty() {
+            std::fs::write(start_path(&song.output, song.pack_format), start_function(&song.duck))?;
+        }
+
+        let grouped = song.ticks_per_function > 1;
+        if grouped {
+            std::fs::write(load_path(&song.output, song.pack_format), load_function())?;
+            std::fs::create_dir_all(load_tag_path(&song.output, song.pack_format).parent().unwrap())?;
+            let tag = json!({ "values": [format!("{}:load", NAMESPACE)] });
+            std::fs::write(load_tag_path(&song.output, song.pack_format), serde_json::to_string_pretty(&tag)?)?;
+        }
+
+        match song.looping {
+            true => {
+                let mut stop = stop_function(&self.functions, &self.repeated, song.category);
+                // so the next start doesn't pick up where this one stopped
+                if grouped {
+                    stop.push_str(&format!("scoreboard players reset #tick {}\n", NAMESPACE));
+                    stop.push_str(&format!("scoreboard players reset #next {}\n", NAMESPACE));
+                }
+                std::fs::write(stop_path(&song.output, song.pack_format), stop)?;
+                event!(Level::INFO, "the song loops until `function {}:stop`", NAMESPACE);
+            },
+            false => std::fs::write(finish_path(&song.output, song.pack_format), finish_function(song.on_finish.as_deref(), song.category))?,
+        }
+
+        Ok(())
+    }
+}
+
+fn is_tick_function(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "mcfunction")
+        && path.file_stem().and_then(|stem| stem.to_str()).is_some_and(|stem| stem.parse::<usize>().is_ok())
+}
+
+/// makes sure `output` is a directory a datapack can be written into,
+/// creating it if needed. refuses to touch a non-empty directory unless
+/// `force` is set, in which case tick functions left over from a previous
+/// (possibly longer) song are removed so they can't be scheduled, in either
+/// layout. the datapack itself is only laid out once the version is known
+pub async fn prepare_output(output: &Path, force: bool) -> Result<(), ExportError> {
+    let _span = span!(Level::INFO, "prepare_output", tag = "export").entered();
+
+    if fs::try_exists(output).await? {
+        if !fs::metadata(output).await?.is_dir() {
+            return Err(ExportError::NotADirectory(output.to_path_buf()));
+        }
+
+        let non_empty = fs::read_dir(output).await?.next_entry().await?.is_some();
+        if non_empty && !force {
+            return Err(ExportError::NotEmpty(output.to_path_buf()));
+        }
+    }
+
+    // chapter functions are numbered like tick functions, and go stale with them
+    let mut removed = 0;
+    for pack_format in [SINGULAR_FORMAT - 1, SINGULAR_FORMAT] {
+        let tick_directory = tick_directory(output, pack_format);
+        for directory in [&tick_directory, &play_directory(&tick_directory), &chapter_directory(output, pack_format)] {
+            if !fs::try_exists(directory).await? {
+                continue;
+            }
+
+            let mut entries = fs::read_dir(directory).await?;
+            while let Some(entry) = entries.next_entry().await? {
+                if is_tick_function(&entry.path()) {
+                    fs::remove_file(entry.path()).await?;
+                    removed += 1;
+                }
+            }
+        }
+
+        let functions = [index_path, finish_path, stop_path, start_path, load_path, load_tag_path].map(|path| path(output, pack_format));
+        for stale in functions.into_iter().chain([availability_path(output), manifest_path(output)]) {
+            if fs::try_exists(&stale).await? {
+                fs::remove_file(&stale).await?;
+            }
+        }
+    }
+
+    if removed > 0 {
+        event!(Level::INFO, "removed {} tick functions from a previous run", removed);
+    }
+
+    fs::create_dir_all(output).await?;
+    Ok(())
+}
+
+/// the `pack.mcmeta` of a datapack of `pack_format`. an `open` one is for
+/// a version newer than `versions` knows the format of, and says it's for
+/// every format from `pack_format` on, so that version still loads it
+pub fn mcmeta(pack_format: u32, open: bool) -> serde_json::Value {
+    let max_format = match open {
+        true => OPEN_FORMAT,
+        false => pack_format,
+    };
+    let mut pack = json!({
+        "pack_format": pack_format,
+        "description": "generated by minecraft-player"
+    });
+    if open {
+        pack["supported_formats"] = json!({ "min_inclusive": pack_format, "max_inclusive": max_format });
+    }
+    if open || pack_format >= RANGED_FORMAT {
+        pack["min_format"] = json!(pack_format);
+        pack["max_format"] = json!(max_format);
+    }
+
+    return json!({ "pack": pack });
+}
+
+/// notes down the events of `availability` that are missing from some
+/// version, a server on one of those just doesn't play them
+pub async fn write_availability(output: &Path, availability: &Availability, versions: usize) -> Result<(), ExportError> {
+    let partial = availability.iter()
+        .filter(|(_, ids)| ids.len() < versions)
+        .collect::<BTreeMap<&String, &Vec<String>>>();
+
+    fs::write(availability_path(output), serde_json::to_string_pretty(&partial)?).await?;
+    Ok(())
+}
+
+/// zips every file under `directory`, by its path relative to it, into
+/// `writer`, which is handed back
+pub fn zip_directory<W: Write + Seek>(directory: &Path, writer: W) -> Result<W, ExportError> {
+    let mut zip = ZipWriter::new(writer);
+    let options = SimpleFileOptions::default();
+
+    let mut pending = vec![directory.to_path_buf()];
+    while let Some(current) = pending.pop() {
+        let mut entries = std::fs::read_dir(&current)?.collect::<Result<Vec<_>, _>>()?;
+        entries.sort_by_key(|entry| entry.file_name());
+
+        for entry in entries {
+            let path = entry.path();
+            if entry.file_type()?.is_dir() {
+                pending.push(path);
+                continue;
+            }
+
+            // zips use `/` whatever the platform
+            let name = path.strip_prefix(directory).unwrap().components()
+                .map(|component| component.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            zip.start_file(name, options)?;
+            io::copy(&mut std::fs::File::open(&path)?, &mut zip)?;
+        }
+    }
+
+    Ok(zip.finish()?)
+}
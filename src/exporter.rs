@@ -0,0 +1,63 @@
+use std::path::PathBuf;
+
+use anyhow::Error;
+
+use crate::export::{Anchor, Category, Datapack, Format, PlaySound};
+#[cfg(feature = "structure")]
+use crate::structure::Structure;
+
+/// what every exporter is told about the song before its first tick
+#[derive(Clone, Debug)]
+pub struct Song {
+    pub output: PathBuf,
+    pub n_ticks: usize,
+    /// ticks every sound rings for, the ones after the last tick included
+    pub atom_ticks: usize,
+    pub category: Category,
+    pub anchor: Anchor,
+    /// with `--surround`, the direction every tick comes from, see
+    /// `surround::directions`. it takes the place of `anchor`
+    pub directions: Option<Vec<f32>>,
+    pub looping: bool,
+    pub on_finish: Option<String>,
+    pub duck: Vec<Category>,
+    /// ticks a datapack plays from each of its tick functions
+    pub ticks_per_function: usize,
+    /// the data pack format of the version it's played on, which decides
+    /// how a datapack is laid out
+    pub pack_format: u32,
+    /// whether that version is newer than `versions` knows the format of,
+    /// see `export::mcmeta`
+    pub open_format: bool,
+    /// whether a datapack comments what its functions play and writes a
+    /// `manifest.json`, see `--annotate`
+    pub annotate: bool,
+    /// how far off the solution of every tick is, relative to the input,
+    /// when it was solved for just now
+    pub residuals: Option<Vec<f32>>
+}
+
+/// an output target. it's given what every tick plays, in order, with the
+/// sounds already picked and aliased, and writes it out however it likes
+pub trait Exporter {
+    fn write_tick(&mut self, index: usize, sounds: &[PlaySound]) -> Result<(), Error>;
+
+    /// playback can be started at the next tick, e.g. a chapter, so it
+    /// can't be merged with the ones before it
+    fn split(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// after the last tick, or the last one written before cancellation
+    fn finish(self: Box<Self>) -> Result<(), Error>;
+}
+
+/// the exporter of every `Format`. a new target is a module with an
+/// `Exporter`, a `Format` and a line here
+pub fn create(format: Format, song: &Song) -> Result<Box<dyn Exporter>, Error> {
+    Ok(match format {
+        Format::Datapack => Box::new(Datapack::new(song.clone())?),
+        #[cfg(feature = "structure")]
+        Format::Structure => Box::new(Structure::new(song.clone())),
+    })
+}
@@ -0,0 +1,309 @@
+use num_traits::Pow;
+use rayon::iter::{IntoParallelRefIterator, ParallelExtend, ParallelIterator};
+use tracing::{event, span, Level};
+
+use crate::audio::{self, FftBin, FilterOperator, Processor, Sound};
+
+/// turns one tick of audio into the vector the solver matches against.
+/// the same extractor has to be applied to both the basis and the target
+pub trait FeatureExtractor: Sync + Send {
+    fn extract(&self, sound: &Sound, processor: &Processor) -> Vec<f32>;
+
+    /// extracts a whole batch at once. extractors that can do better than
+    /// one sound at a time (e.g. on the GPU) override this
+    fn extract_batch(&self, sounds: &[Sound], processor: &Processor) -> Vec<Vec<f32>> {
+        sounds.par_iter().map(|sound| self.extract(sound, processor)).collect()
+    }
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Default, Debug)]
+pub enum FeatureKind {
+    Waveform,
+    #[default]
+    MelWeighted,
+    MelFilterbank,
+    LogSpectrum,
+    Mfcc,
+    Chroma,
+}
+
+impl FeatureKind {
+    pub fn extractor(&self) -> Box<dyn FeatureExtractor> {
+        match self {
+            FeatureKind::Waveform => Box::new(Waveform),
+            FeatureKind::MelWeighted => Box::new(MelWeighted),
+            FeatureKind::MelFilterbank => Box::new(MelFilterbank { bands: 64 }),
+            FeatureKind::LogSpectrum => Box::new(LogSpectrum),
+            FeatureKind::Mfcc => Box::new(Mfcc { bands: 64, coefficients: 20 }),
+            FeatureKind::Chroma => Box::new(Chroma),
+        }
+    }
+}
+
+/// sounds uploaded per GPU preprocessing batch
+static GPU_BATCH: usize = 4096;
+
+/// how much `--focus` boosts its band, about 12dB
+static FOCUS_BOOST: f32 = 4.0;
+
+fn hz_to_mel(hz: f32) -> f32 {
+    2595.0 * (1.0 + hz / 700.0).log10()
+}
+
+fn mel_to_hz(mel: f32) -> f32 {
+    700.0 * (10f32.powf(mel / 2595.0) - 1.0)
+}
+
+/// bins up to and including nyquist, the rest mirror them
+fn positive_bins(mut bins: Vec<FftBin>) -> Vec<FftBin> {
+    bins.truncate(bins.len() / 2 + 1);
+    bins
+}
+
+/// a band of frequencies the solve prioritizes
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Focus {
+    /// 300-3400Hz, where speech and most melodies are
+    Voice,
+    /// 20-250Hz, kick drums and bass lines
+    Bass,
+    /// 4-16kHz, hi-hats and air
+    Treble,
+}
+
+impl Focus {
+    fn band(&self) -> (f32, f32) {
+        match self {
+            Focus::Voice => (300.0, 3400.0),
+            Focus::Bass => (20.0, 250.0),
+            Focus::Treble => (4000.0, 16000.0),
+        }
+    }
+
+    /// the weight of a frequency: `FOCUS_BOOST` inside the band, 1 from an
+    /// octave away from it and a raised cosine (over octaves) between
+    pub fn weight(&self, freq: f32) -> f32 {
+        let (low, high) = self.band();
+        let octaves = match freq {
+            freq if freq < low => (low / freq.max(f32::EPSILON)).log2(),
+            freq if freq > high => (freq / high).log2(),
+            _ => 0.0,
+        };
+        let ramp = 0.5 + 0.5 * (std::f32::consts::PI * octaves.min(1.0)).cos();
+        return 1.0 + (FOCUS_BOOST - 1.0) * ramp;
+    }
+
+    /// `weight` of every frequency
+    pub fn weights(&self, frequencies: &[f32]) -> Vec<f32> {
+        return frequencies.iter().map(|freq| self.weight(*freq)).collect();
+    }
+}
+
+/// `inner` on sounds whose spectrum is weighted by `focus` first, so the
+/// rows of the input and the basis are weighted alike whatever they are
+pub struct Focused {
+    pub inner: Box<dyn FeatureExtractor>,
+    pub focus: Focus
+}
+
+impl Focused {
+    fn filter(&self, sound: &Sound, processor: &Processor) -> Sound {
+        // bins past nyquist mirror the ones below it and are weighted the same
+        let nyquist = sound.sample_rate as f32 / 2.0;
+        let samples = processor.filter(sound, |freq| self.focus.weight(nyquist - (nyquist - freq).abs()));
+
+        Sound { samples, sample_rate: sound.sample_rate }
+    }
+}
+
+impl FeatureExtractor for Focused {
+    fn extract(&self, sound: &Sound, processor: &Processor) -> Vec<f32> {
+        self.inner.extract(&self.filter(sound, processor), processor)
+    }
+
+    fn extract_batch(&self, sounds: &[Sound], processor: &Processor) -> Vec<Vec<f32>> {
+        let filtered = sounds.par_iter().map(|sound| self.filter(sound, processor)).collect::<Vec<Sound>>();
+        self.inner.extract_batch(&filtered, processor)
+    }
+}
+
+/// the raw samples, matched as is
+pub struct Waveform;
+
+impl FeatureExtractor for Waveform {
+    fn extract(&self, sound: &Sound, _: &Processor) -> Vec<f32> {
+        sound.samples.clone()
+    }
+}
+
+/// applies filtering to boost mids and decrease lows
+/// this makes it so important parts (voice, etc) are prioritized in
+/// reconstruction rather than bass (drums, etc) which our ears are
+/// more sensitive to
+pub struct MelWeighted;
+
+impl MelWeighted {
+    fn weight(freq: f32) -> f32 {
+        let mel_freq = hz_to_mel(freq) / 24000.0;
+        let high_pass = freq / (freq.pow(2.0) + 100_f32.pow(2.0)) + 0.4;
+        (mel_freq * 2.0) * (high_pass.min(1.0))
+    }
+
+    /// `Processor::filter` with these weights, as an operator on sounds of
+    /// `n` samples
+    pub fn operator(n: usize, sample_rate: usize) -> FilterOperator {
+        return FilterOperator::new(n, sample_rate, Self::weight);
+    }
+}
+
+impl FeatureExtractor for MelWeighted {
+    fn extract(&self, sound: &Sound, processor: &Processor) -> Vec<f32> {
+        let _span = span!(Level::DEBUG, "mel").entered();
+
+        processor.filter(sound, Self::weight)
+    }
+
+    fn extract_batch(&self, sounds: &[Sound], processor: &Processor) -> Vec<Vec<f32>> {
+        let Some(first) = sounds.first() else {
+            return Vec::new();
+        };
+
+        let (n, sample_rate) = (first.samples.len(), first.sample_rate);
+        let uniform = sounds.iter().all(|s| s.samples.len() == n && s.sample_rate == sample_rate);
+
+        if !processor.has_gpu() || !uniform {
+            return sounds.par_iter().map(|sound| self.extract(sound, processor)).collect();
+        }
+
+        let _span = span!(Level::DEBUG, "mel_batch", tag = "gpu").entered();
+        let operator = Self::operator(n, sample_rate);
+        let mut features = Vec::with_capacity(sounds.len());
+
+        for batch in sounds.chunks(GPU_BATCH) {
+            let flat = batch.iter().flat_map(|s| s.samples.iter().copied()).collect::<Vec<f32>>();
+
+            match processor.apply_operator_gpu(&flat, &operator) {
+                Ok(filtered) => features.extend(filtered.chunks_exact(n).map(|c| c.to_vec())),
+                Err(e) => {
+                    event!(Level::WARN, "GPU preprocessing failed, falling back to the CPU: '{}'", e);
+                    features.par_extend(batch.par_iter().map(|sound| self.extract(sound, processor)));
+                }
+            }
+        }
+
+        features
+    }
+}
+
+/// power in triangular bands spaced evenly on the mel scale
+pub struct MelFilterbank {
+    pub bands: usize
+}
+
+impl MelFilterbank {
+    fn energies(&self, sound: &Sound, processor: &Processor) -> Vec<f32> {
+        let bins = positive_bins(processor.fft(sound.clone()));
+        let max_mel = hz_to_mel(sound.sample_rate as f32 / 2.0);
+        let edges = (0..self.bands + 2)
+            .map(|i| mel_to_hz(max_mel * i as f32 / (self.bands + 1) as f32))
+            .collect::<Vec<f32>>();
+
+        (0..self.bands)
+            .map(|band| {
+                let (low, center, high) = (edges[band], edges[band + 1], edges[band + 2]);
+                bins.iter()
+                    .filter(|bin| bin.freq > low && bin.freq < high)
+                    .map(|bin| {
+                        let weight = if bin.freq <= center {
+                            (bin.freq - low) / (center - low)
+                        } else {
+                            (high - bin.freq) / (high - center)
+                        };
+                        weight * bin.complex.norm_sqr()
+                    })
+                    .sum()
+            })
+            .collect()
+    }
+}
+
+impl FeatureExtractor for MelFilterbank {
+    fn extract(&self, sound: &Sound, processor: &Processor) -> Vec<f32> {
+        self.energies(sound, processor)
+    }
+}
+
+/// log magnitude of every bin up to nyquist
+pub struct LogSpectrum;
+
+impl FeatureExtractor for LogSpectrum {
+    fn extract(&self, sound: &Sound, processor: &Processor) -> Vec<f32> {
+        positive_bins(processor.fft(sound.clone()))
+            .iter()
+            .map(|bin| bin.complex.norm().ln_1p())
+            .collect()
+    }
+}
+
+/// DCT-II of the log mel energies, keeping the first `coefficients`
+pub struct Mfcc {
+    pub bands: usize,
+    pub coefficients: usize
+}
+
+impl FeatureExtractor for Mfcc {
+    fn extract(&self, sound: &Sound, processor: &Processor) -> Vec<f32> {
+        let log_energies = MelFilterbank { bands: self.bands }
+            .energies(sound, processor)
+            .iter()
+            .map(|e| (e + 1e-10).ln())
+            .collect::<Vec<f32>>();
+
+        let n = log_energies.len() as f32;
+        (0..self.coefficients)
+            .map(|k| {
+                log_energies.iter()
+                    .enumerate()
+                    .map(|(i, e)| e * (std::f32::consts::PI / n * (i as f32 + 0.5) * k as f32).cos())
+                    .sum()
+            })
+            .collect()
+    }
+}
+
+/// magnitude folded into the 12 pitch classes, starting at C
+pub struct Chroma;
+
+impl FeatureExtractor for Chroma {
+    fn extract(&self, sound: &Sound, processor: &Processor) -> Vec<f32> {
+        let mut chroma = vec![0.0; 12];
+
+        for bin in positive_bins(processor.fft(sound.clone())) {
+            if bin.freq < 27.5 || bin.freq > 5000.0 {
+                continue;
+            }
+
+            let midi = 69.0 + 12.0 * (bin.freq / 440.0).log2();
+            chroma[(midi.round() as usize) % 12] += bin.complex.norm();
+        }
+
+        chroma
+    }
+}
+
+/// features of every tick of the sounds, stacked per sound so a column of
+/// the basis is an atom spanning `ticks` ticks
+pub fn atom_features<E: Fn(&[Sound]) -> Vec<Vec<f32>>>(sounds: &[Sound], ticks: usize, samples_per_tick: usize, extract: E) -> Vec<Vec<f32>> {
+    if ticks == 1 {
+        return extract(sounds);
+    }
+
+    let split = sounds.iter()
+        .flat_map(|sound| audio::chunk_ticks(sound, samples_per_tick, ticks))
+        .collect::<Vec<Sound>>();
+
+    return extract(&split)
+        .chunks(ticks)
+        .map(|tick_features| tick_features.concat())
+        .collect();
+}
@@ -1,7 +1,45 @@
 pub mod mojang;
 pub mod assets;
+pub mod pack;
 pub mod audio;
 pub mod algebra;
+pub mod convergence;
+pub mod subtick;
 pub mod logging;
+pub mod timing;
+pub mod cancel;
+pub mod checkpoint;
+pub mod matrices;
+pub mod mapped;
+pub mod export;
+pub mod decode;
+pub mod diagnostics;
+pub mod features;
+pub mod preview;
+pub mod audibility;
+pub mod pitch;
+pub mod separate;
+pub mod stems;
+pub mod verify;
+pub mod versions;
+pub mod tuning;
+pub mod emphasis;
+pub mod schedule;
+#[cfg(feature = "structure")]
+pub mod structure;
+#[cfg(feature = "npy")]
+pub mod npy;
+#[cfg(feature = "python")]
+pub mod python;
+#[cfg(feature = "capi")]
+pub mod capi;
+#[cfg(feature = "serve")]
+pub mod serve;
+pub mod tempo;
+pub mod surround;
+pub mod loudness;
+pub mod webhook;
+pub mod progress;
+pub mod exporter;
 #[cfg(test)]
 pub mod tests;
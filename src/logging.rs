@@ -1,23 +1,40 @@
-use std::fmt::{Debug, Write};
+/// a help line at a level only known at runtime, e.g. the level of the line
+/// it explains. `event!` needs its level to be a constant
+#[macro_export]
+macro_rules! help_at {
+    ($level:expr, $($message:tt)+) => {
+        match $level {
+            tracing::Level::ERROR => tracing::event!(tracing::Level::ERROR, help = true, $($message)+),
+            tracing::Level::WARN => tracing::event!(tracing::Level::WARN, help = true, $($message)+),
+            tracing::Level::INFO => tracing::event!(tracing::Level::INFO, help = true, $($message)+),
+            tracing::Level::DEBUG => tracing::event!(tracing::Level::DEBUG, help = true, $($message)+),
+            tracing::Level::TRACE => tracing::event!(tracing::Level::TRACE, help = true, $($message)+),
+        }
+    };
+}
+use std::{collections::VecDeque, ffi::OsString, fmt::Debug, fs, io, path::{Path, PathBuf}, sync::Mutex};
 
-use tracing::{field::Visit, level_filters::LevelFilter, span::Id, Event, Level, Subscriber};
-use tracing_subscriber::{field::{MakeExt, RecordFields}, filter, fmt::{self, format::{self, Writer}, FmtContext, FormatEvent, FormatFields, FormattedFields}, layer::SubscriberExt, registry::LookupSpan, util::SubscriberInitExt, Layer};
+use serde_json::{Map, Value};
+use tracing::{field::{Field, Visit}, level_filters::LevelFilter, Event, Level, Metadata, Subscriber};
+use tracing_subscriber::{filter::{self, FilterExt}, fmt::{self, format, writer::BoxMakeWriter, FmtContext, FormatEvent, FormatFields, MakeWriter}, layer::SubscriberExt, registry::LookupSpan, util::SubscriberInitExt, Layer};
 use colored::*;
 use anyhow::Error;
+pub use help_at;
+
+/// previous log files kept next to the current one, as `run.log.1` (the
+/// newest) up to `run.log.5`
+static KEPT_LOGS: usize = 5;
 
-#[derive(PartialEq, PartialOrd, Ord, Eq, Clone, Debug)]
+/// log lines kept in memory for a diagnostics bundle
+static RECENT_LINES: usize = 200;
+
+static RECENT: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+
+#[derive(PartialEq, PartialOrd, Ord, Eq, Clone, Debug, Default)]
 struct FieldData {
     tag: Option<String>
 }
 
-impl Default for FieldData {
-    fn default() -> Self {
-        Self {
-            tag: None
-        }
-    }
-}
-
 #[derive(Default)]
 struct TagExtractor {
     data: FieldData
@@ -46,7 +63,39 @@ impl Visit for MessageExtractor {
     }
 }
 
-struct CustomLayer;
+/// every field of an event, for the json lines
+#[derive(Default)]
+struct JsonExtractor {
+    fields: Map<String, Value>
+}
+
+impl Visit for JsonExtractor {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.fields.insert(field.name().to_string(), Value::from(value));
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.fields.insert(field.name().to_string(), Value::from(value));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.fields.insert(field.name().to_string(), Value::from(value));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.fields.insert(field.name().to_string(), Value::from(value));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.fields.insert(field.name().to_string(), Value::from(value));
+    }
+
+    fn record_debug(&mut self, field: &Field, debug: &dyn std::fmt::Debug) {
+        self.fields.insert(field.name().to_string(), Value::from(format!("{:?}", debug)));
+    }
+}
+
+pub(crate) struct CustomLayer;
 
 impl<S> Layer<S> for CustomLayer
 where
@@ -86,11 +135,39 @@ fn color_tag(tag: String) -> ColoredString {
         "gpu" => tag.cyan(),
         "assets" => tag.bright_blue(),
         "audio" => tag.bright_green(),
+        "export" => tag.magenta(),
         _ => tag.bright_black()
     }
 }
 
-struct TaggedFormatter;
+/// the tag of the innermost span that has one
+fn span_tag<S, N>(ctx: &FmtContext<'_, S, N>) -> String
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    let data: FieldData = if let Some(leaf_span) = ctx.parent_span() {
+        let mut data = None;
+        for span in leaf_span.scope().from_root() {
+            let ext = span.extensions();
+            let field_data = ext.get::<FieldData>().expect("no fielddata");
+            if field_data != &FieldData::default() {
+                data = Some(field_data.clone());
+            }
+        }
+
+        data
+    } else {
+        None
+    }.unwrap_or(FieldData::default());
+
+    return data.tag.unwrap_or(String::from("other"));
+}
+
+/// colors are left out of log files
+struct TaggedFormatter {
+    colors: bool
+}
 
 impl<S, N> FormatEvent<S, N> for TaggedFormatter
 where
@@ -108,34 +185,21 @@ where
         let level = *metadata.level();
 
 
-        let data: FieldData = if let Some(leaf_span) = ctx.parent_span() {
-            let mut data = None;
-            for span in leaf_span.scope().from_root() {
-                let ext = span.extensions();
-                let field_data = ext.get::<FieldData>().expect("no fielddata");
-                if field_data != &FieldData::default() {
-                    data = Some(field_data.clone());
-                }
-            }
-
-            data
-        } else {
-            None
-        }.unwrap_or(FieldData::default());
-
-        let tag = color_tag(data.tag.unwrap_or(String::from("other")));
+        let paint = |string: ColoredString| if self.colors { string } else { string.clear() };
+        let tag = paint(color_tag(span_tag(ctx)));
+        let level_name = paint(color_level(level));
 
         let mut visitor = MessageExtractor::default();
         event.record(&mut visitor);
 
         if event.fields().find(|f| f.name() == "help").is_some(){
-            write!(writer, "{:<6}{}  ", tag, color_level(level))?;
-            write!(writer, "{}", format!("help: {}", visitor.message).yellow())?;
+            write!(writer, "{:<6}{}  ", tag, level_name)?;
+            write!(writer, "{}", paint(format!("help: {}", visitor.message).yellow()))?;
         } else if level == Level::ERROR {
-            write!(writer, "{:<6}{}  ", tag, color_level(level))?;
+            write!(writer, "{:<6}{}  ", tag, level_name)?;
             write!(writer, "{}", visitor.message)?;
         } else {
-            write!(writer, "{} {:<6}{:>8}  ", time.bright_black(), tag, color_level(level))?;
+            write!(writer, "{} {:<6}{:>8}  ", paint(time.bright_black()), tag, level_name)?;
             write!(writer, "{}", visitor.message)?;
         }
 
@@ -143,6 +207,57 @@ where
     }
 }
 
+/// one json object per line: time, level, tag (the stage it happened in),
+/// message and the event's fields, e.g. `iteration` and `iterations` of the
+/// solve. `help` is true for help lines
+struct JsonFormatter;
+
+impl<S, N> FormatEvent<S, N> for JsonFormatter
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: format::Writer<'_>,
+        event: &Event<'_>,
+    ) -> std::fmt::Result {
+        let mut visitor = JsonExtractor::default();
+        event.record(&mut visitor);
+
+        let mut line = Map::new();
+        line.insert("time".to_string(), Value::from(chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true)));
+        line.insert("level".to_string(), Value::from(event.metadata().level().as_str().to_lowercase()));
+        line.insert("tag".to_string(), Value::from(span_tag(ctx)));
+        line.extend(visitor.fields);
+
+        writeln!(writer, "{}", Value::Object(line))
+    }
+}
+
+/// json lines written to `writer`
+pub(crate) fn json_layer<S, W>(writer: W) -> impl Layer<S>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    fmt::layer()
+        .event_format(JsonFormatter)
+        .with_writer(writer)
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Default, Debug, PartialEq)]
+pub enum LogFormat {
+    /// colored lines
+    #[default]
+    Human,
+    /// json lines
+    Json,
+    /// colored lines on stderr, json lines on stdout
+    Both,
+}
+
 #[derive(clap::ValueEnum, Clone, Default, Debug)]
 pub enum Verbosity {
     ProblemsOnly,
@@ -153,9 +268,9 @@ pub enum Verbosity {
 }
 
 // there are easier ways, but i feel like such a rustacean today, so traits it is
-impl Into<Level> for Verbosity {
-    fn into(self) -> Level {
-        match self {
+impl From<Verbosity> for Level {
+    fn from(val: Verbosity) -> Self {
+        match val {
             Verbosity::Normal => Level::INFO,
             Verbosity::ProblemsOnly => Level::WARN,
             Verbosity::Debug => Level::DEBUG,
@@ -164,22 +279,123 @@ impl Into<Level> for Verbosity {
     }
 }
 
-pub fn setup<I: Into<Level>>(max_level: I) -> Result<(), Error> {
+/// writes lines into `RECENT`, dropping the oldest beyond `RECENT_LINES`
+struct RecentWriter;
+
+impl io::Write for RecentWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut recent = RECENT.lock().unwrap();
+        for line in String::from_utf8_lossy(buf).lines() {
+            recent.push_back(line.to_string());
+        }
+        while recent.len() > RECENT_LINES {
+            recent.pop_front();
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// the last log lines, down to debug, if `setup` was asked to keep them
+pub fn recent_lines() -> Vec<String> {
+    return RECENT.lock().unwrap().iter().cloned().collect();
+}
+
+/// moves `path` to `path.1`, `path.1` to `path.2` and so on, dropping the
+/// oldest beyond `KEPT_LOGS`
+pub(crate) fn rotate(path: &Path) -> Result<(), io::Error> {
+    let numbered = |n: usize| {
+        let mut numbered = OsString::from(path.as_os_str());
+        numbered.push(format!(".{}", n));
+        PathBuf::from(numbered)
+    };
+
+    for n in (1..KEPT_LOGS).rev() {
+        if numbered(n).exists() {
+            fs::rename(numbered(n), numbered(n + 1))?;
+        }
+    }
+    if path.exists() {
+        fs::rename(path, numbered(1))?;
+    }
+
+    Ok(())
+}
+
+/// events with a `progress` field are only for programs following along,
+/// the human format leaves them out. a `log_file` gets everything down to
+/// trace, whatever the console's `max_level`, after the previous one is
+/// rotated out of the way. with `keep_recent`, the last lines down to debug
+/// are kept for `recent_lines`. with `stdout_taken`, both colored and json
+/// lines go to stderr whatever the format
+pub fn setup<I: Into<Level>>(max_level: I, format: LogFormat, log_file: Option<&Path>, keep_recent: bool, stdout_taken: bool) -> Result<(), Error> {
     let max_level: Level = max_level.into();
     let enable_log = max_level >= Level::TRACE;
+    let from_current = move |metadata: &Metadata<'_>| {
+        metadata.target().starts_with(env!("CARGO_CRATE_NAME")) || enable_log
+    };
+    let human_only = move |metadata: &Metadata<'_>| {
+        from_current(metadata) && metadata.fields().field("progress").is_none()
+    };
+
+    let human = (format != LogFormat::Json).then(|| {
+        let writer = match format {
+            _ if stdout_taken => BoxMakeWriter::new(std::io::stderr),
+            LogFormat::Both => BoxMakeWriter::new(std::io::stderr),
+            _ => BoxMakeWriter::new(std::io::stdout),
+        };
+
+        fmt::layer()
+            .event_format(TaggedFormatter { colors: true })
+            .with_writer(writer)
+            //.map_fmt_fields(|f| f.debug_alt())
+            .with_filter(LevelFilter::from_level(max_level).and(filter::filter_fn(human_only)))
+    });
+
+    let json = (format != LogFormat::Human).then(|| {
+        let writer = match stdout_taken {
+            true => BoxMakeWriter::new(std::io::stderr),
+            false => BoxMakeWriter::new(std::io::stdout),
+        };
+        json_layer(writer).with_filter(LevelFilter::from_level(max_level).and(filter::filter_fn(from_current)))
+    });
+
+    let file = match log_file {
+        Some(path) => {
+            rotate(path)?;
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+
+            let only_current = |metadata: &Metadata<'_>| {
+                metadata.target().starts_with(env!("CARGO_CRATE_NAME")) && metadata.fields().field("progress").is_none()
+            };
+            Some(fmt::layer()
+                .event_format(TaggedFormatter { colors: false })
+                .with_writer(Mutex::new(fs::File::create(path)?))
+                .with_filter(LevelFilter::TRACE.and(filter::filter_fn(only_current))))
+        },
+        None => None
+    };
+
+    let recent = keep_recent.then(|| {
+        fmt::layer()
+            .event_format(TaggedFormatter { colors: false })
+            .with_writer(|| RecentWriter)
+            .with_filter(LevelFilter::DEBUG.and(filter::filter_fn(human_only)))
+    });
+
     tracing_subscriber::registry()
         .with(CustomLayer)
-        .with(LevelFilter::from_level(max_level))
-        .with(
-            fmt::layer()
-                .event_format(TaggedFormatter)
-                //.map_fmt_fields(|f| f.debug_alt())
-                .with_filter(filter::filter_fn(move |metadata| { 
-                    let from_current = metadata.target().starts_with(env!("CARGO_CRATE_NAME"));
-                    from_current || (!from_current && enable_log)
-                }))
-        )
+        .with(human)
+        .with(json)
+        .with(file)
+        .with(recent)
         .init();
-    
+
     Ok(())
 }
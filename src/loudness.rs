@@ -0,0 +1,53 @@
+use ndarray::ArrayView2;
+
+/// ticks quieter than this (in dB) aren't part of a song's loudness, like
+/// the absolute gate of EBU R 128
+static ABSOLUTE_GATE: f32 = -70.0;
+
+/// and neither are ticks this much quieter than the rest, like its
+/// relative gate, so quiet intros and outros don't drag the song down
+static RELATIVE_GATE: f32 = -10.0;
+
+/// the level every sound plays at when its volume is 1, the RMS of its
+/// waveform
+pub fn sound_levels<'a>(waveforms: impl IntoIterator<Item = &'a [f32]>) -> Vec<f32> {
+    return waveforms.into_iter()
+        .map(|samples| match samples.is_empty() {
+            true => 0.0,
+            false => (samples.iter().map(|sample| sample * sample).sum::<f32>() / samples.len() as f32).sqrt(),
+        })
+        .collect();
+}
+
+/// the power every tick of `amplitudes` (sounds by ticks) plays at, every
+/// sound at its level (`sound_levels`) times its volume. sounds are added
+/// up by power, as if they had nothing to do with each other
+pub fn tick_powers(amplitudes: ArrayView2<f32>, levels: &[f32]) -> Vec<f32> {
+    assert_eq!(amplitudes.nrows(), levels.len());
+
+    return amplitudes.columns().into_iter()
+        .map(|volumes| volumes.iter().zip(levels).map(|(volume, level)| (volume * level).powi(2)).sum())
+        .collect();
+}
+
+fn mean_above(powers: &[f32], gate: f32) -> Option<f32> {
+    let gated = powers.iter().filter(|power| **power > gate).collect::<Vec<&f32>>();
+    match gated.is_empty() {
+        true => None,
+        false => Some(gated.iter().copied().sum::<f32>() / gated.len() as f32),
+    }
+}
+
+/// the loudness of ticks of `powers`, in dB relative to a full-scale sound,
+/// gated like EBU R 128 (without its weighting). `None` if it's all silence
+pub fn loudness(powers: &[f32]) -> Option<f32> {
+    let mean = mean_above(powers, 10.0_f32.powf(ABSOLUTE_GATE / 10.0))?;
+    let mean = mean_above(powers, mean * 10.0_f32.powf(RELATIVE_GATE / 10.0))?;
+
+    return Some(10.0 * mean.log10());
+}
+
+/// the volume gain that brings a song of `loudness` to `target`, in dB
+pub fn gain(loudness: f32, target: f32) -> f32 {
+    return 10.0_f32.powf((target - loudness) / 20.0);
+}
@@ -1,293 +1,3233 @@
 extern crate ocl;
-use std::{collections::HashMap, ffi::OsStr, path::PathBuf, time::Instant};
+use std::{cell::Cell, collections::HashMap, hash::{DefaultHasher, Hash, Hasher}, io::Write, ops::Range, path::{Path, PathBuf}, sync::{atomic::{AtomicBool, Ordering}, Arc, Mutex}, time::Duration};
 
 use anyhow::{Error, anyhow};
 use clap::Parser;
-use inquire::Select;
-use minecraft_player::{algebra::{self}, assets::{self, AudioResourceLocation, FetchBehavior}, audio::{self, Sound}, logging::{self, Verbosity}, mojang::{self, AssetIndex, Version}};
-use ndarray::Axis;
-use rayon::iter::{IntoParallelIterator, ParallelIterator};
-use tracing::{event, info, instrument, level_filters::LevelFilter, span, Level};
-use colored::*;
-
-#[derive(clap::Args, Debug)]
+use inquire::{Confirm, CustomType, Select};
+use minecraft_player::{algebra::{self, NonFinite, Normalization, StepSize}, assets::{self, Concurrency, FetchBehavior, FetchOptions}, audio::{self, Sound}, cancel, checkpoint::Checkpoint, mapped::{Chunks, MappedChunks}, matrices::{Matrices, PartMatrices}, convergence::{self, Series}, decode::{self, RawFormat}, diagnostics, emphasis::{self, Emphasis}, export::{self, Anchor, Category, Format, PlaySound, Selector}, exporter::{self, Exporter, Song}, features::{self, FeatureExtractor, FeatureKind, Focus, Focused}, logging::{self, LogFormat, Verbosity}, loudness, mojang::{self, Version}, pack::{self, Pack}, pitch, progress, audibility::Audibility, preview::{Clipping, Model, Preview, Reconstruction}, schedule::{self, Schedule, Stages}, separate, stems::{SoundFilter, Stem}, subtick, surround, tempo, verify, versions::{self, Availability, VersionError}, timing::{Stage, Timing}, tuning::{self, TuningCache}, webhook};
+#[cfg(feature = "structure")]
+use minecraft_player::structure;
+#[cfg(feature = "npy")]
+use minecraft_player::npy;
+#[cfg(feature = "serve")]
+use minecraft_player::serve;
+use ndarray::{s, Array2, ArrayView1, Axis};
+use rayon::iter::{IntoParallelIterator, IntoParallelRefIterator, ParallelIterator};
+use tracing::{event, info, span, Level};
+
+/// sounds whose features are extracted and uploaded to the device at once
+static BASIS_BLOCK: usize = 4096;
+static COMMANDS_PER_TICK: usize = 80;
+static CHECKPOINT_FILE: &str = "checkpoint.bin";
+
+/// where `--tune` writes its tries, in the assets directory
+static TUNE_DIRECTORY: &str = "tune";
+/// where `--output-zip` writes the datapack before zipping it, in the
+/// assets directory
+static ZIP_DIRECTORY: &str = "zip";
+/// where `serve` keeps its jobs, under the assets
+#[cfg(feature = "serve")]
+static SERVE_DIRECTORY: &str = "serve";
+
+/// ticks the end of a `--loop` preview is crossfaded into its start over
+static SEAM_TICKS: usize = 4;
+
+#[derive(clap::Args, Clone, Debug)]
 #[group(required = false, multiple = false)]
 struct BehaviorGroup {
-    #[arg(short, long, help = "refetch all assets and replace all local files")]
+    #[arg(short, long, help = "refetch all assets and replace all local files", global = true)]
     refetch: bool,
     
-    #[arg(short, long, help = "do not check against asset index and force use of local files")]
+    #[arg(short, long, help = "do not check against asset index and force use of local files", global = true)]
     local: bool
 }
 
-#[derive(Parser, Debug)]
-#[command(version, about)]
+impl BehaviorGroup {
+    fn behavior(&self) -> FetchBehavior {
+        match (self.refetch, self.local) {
+            (true, false) => FetchBehavior::Refetch,
+            (false, true) => FetchBehavior::CacheOnly,
+            (false, false) => FetchBehavior::FetchIfMissing,
+            _ => unimplemented!("impossible")
+        }
+    }
+}
+
+#[derive(clap::Args, Clone, Debug)]
+#[group(required = false, multiple = false)]
+struct CharacterGroup {
+    #[arg(long, help = "only use sounds with a clear pitch, for melodic fidelity")]
+    tonal_only: bool,
+
+    #[arg(long, help = "only use noisy sounds without a clear pitch, for rhythmic fidelity")]
+    percussive_only: bool
+}
+
+#[derive(clap::Subcommand, Clone, Debug)]
+enum Command {
+    /// solve a few small problems on the CPU and the OpenCL devices and compare
+    /// them, to catch broken drivers before a long solve
+    Verify,
+    /// list the minecraft versions there are assets for
+    Versions {
+        /// only versions whose id contains this
+        filter: Option<String>,
+
+        #[arg(long, help = "leave out snapshots and old alphas and betas")]
+        releases: bool
+    },
+    /// list the predictable sound events of a version, to pick filters before
+    /// committing to a long run
+    Sounds {
+        #[arg(long, help = "version to list the sounds of, or `latest-release` / `latest-snapshot`")]
+        version: Option<String>
+    },
+    /// write one sound of the basis to a `.wav`, as the solve hears it, to
+    /// audition it before filtering for it
+    ExtractSound {
+        #[arg(long, help = "version to take the sound from, or `latest-release` / `latest-snapshot`")]
+        version: Option<String>,
+
+        #[arg(long, help = "sound event, e.g. `entity.villager.ambient`")]
+        id: String,
+
+        #[arg(long, help = "pitch it's played at, from 0.5 to 2.0", default_value = "1.0")]
+        pitch: f32,
+
+        #[arg(long, help = "ticks it's cut to, like `--atom-ticks` (default: as many as are decoded)")]
+        ticks: Option<u8>,
+
+        /// the `.wav` to write
+        output: PathBuf
+    },
+    /// host a shared conversion server: an http api to submit audio, poll
+    /// its job and download the datapack. jobs are solved one at a time
+    /// with the settings the server was started with
+    #[cfg(feature = "serve")]
+    Serve {
+        #[arg(long, help = "address to listen on", default_value = "127.0.0.1:8080")]
+        listen: std::net::SocketAddr,
+
+        #[arg(long, help = "jobs that may wait, more are turned away", default_value = "16")]
+        max_queued: usize,
+
+        #[arg(long, help = "largest input accepted, in MiB", default_value = "64")]
+        max_upload_mib: usize,
+
+        #[arg(long, help = "minutes a finished job's datapack can be downloaded for", default_value = "60")]
+        keep_minutes: u64
+    }
+}
+
+#[derive(Parser, Clone, Debug)]
+#[command(version, about, subcommand_negates_reqs = true)]
 struct Args {
-    #[arg(short, long, help = "version from which to fetch assets from")]
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[arg(short, long, help = "version from which to fetch assets from, or `latest-release` / `latest-snapshot`")]
     target_version: Option<String>,
 
+    #[arg(long, value_delimiter = ',', help = "more versions whose sounds join the basis, for events the target version doesn't have")]
+    extra_versions: Vec<String>,
+
+    #[arg(long, help = "version of the server the output is played on, when it isn't the target version. events it renamed are remapped and ones it lacks left out")]
+    output_version: Option<String>,
+
+    #[arg(long, help = "also leave out events that sound different on the output version", requires = "output_version")]
+    exclude_changed: bool,
+
+    #[arg(long, help = "data pack format of the datapack, e.g. for a snapshot that changed it (default: the one of the version the output is played on)", value_parser = clap::value_parser!(u32).range(4..))]
+    pack_format: Option<u32>,
+
+    /// set along with `pack_format` when the version the datapack is played
+    /// on is newer than the formats known, see `export::mcmeta`
+    #[arg(skip)]
+    open_pack_format: bool,
+
+    #[arg(long, help = "resource pack the server sends players (`resource-pack` in server.properties), whose sounds go over the version's")]
+    server_pack_url: Option<String>,
+
+    #[arg(long, help = "SHA-1 the server pack is checked against (`resource-pack-sha1`). only a checked pack is cached", requires = "server_pack_url", value_parser = sha1)]
+    server_pack_sha1: Option<String>,
+
+    #[arg(long, help = "never prompt: no version means the latest release, and an ambiguous one is an error", global = true)]
+    non_interactive: bool,
+
     #[clap(flatten)]
     behavior: BehaviorGroup,
 
-    #[arg(short, long, help = "assets directory (default: ./data)", default_value = "./data")]
+    #[arg(short, long, help = "assets directory (default: ./data)", default_value = "./data", global = true)]
     assets: PathBuf,
 
-    #[arg(short, long, help = "input audio file")]
-    input: PathBuf,
+    #[arg(long, help = "version manifest to fetch versions from, for mirrors and proxies", default_value = mojang::VERSION_MANIFEST_URL, global = true)]
+    manifest_url: String,
 
-    #[arg(short, long, help = "output datapack directory")]
-    output: PathBuf,
+    #[arg(long, help = "maximum concurrent asset downloads", default_value = "64", value_parser = clap::value_parser!(u16).range(1..), global = true)]
+    download_jobs: u16,
+
+    #[arg(long, help = "maximum files open at once, reading the asset cache and writing downloads into it", default_value = "32", value_parser = clap::value_parser!(u16).range(1..), global = true)]
+    read_jobs: u16,
+
+    #[arg(long, help = "proxy for every request, e.g. `http://proxy:3128` (default: the HTTPS_PROXY/HTTP_PROXY/ALL_PROXY variables)", global = true)]
+    proxy: Option<String>,
+
+    #[arg(long, help = "post to this (discord) webhook whenever a stage finishes, and a summary at the end, to follow long solves on a remote machine", global = true)]
+    webhook_url: Option<String>,
+
+    #[arg(long = "ca-cert", help = "extra root certificate to trust, PEM or DER. can be repeated", global = true)]
+    ca_certificates: Vec<PathBuf>,
+
+    #[arg(short, long, help = "input audio file, a mono `.wav` (stereo with `--surround`) or an `.opus`. `-` reads a `.wav` (or `--raw` samples) from stdin", required_unless_present_any = ["stems", "import_matrices"])]
+    input: Option<PathBuf>,
+
+    #[arg(long, help = "read the input as headerless mono samples of this format, e.g. streamed from another program", requires = "raw_rate", conflicts_with = "stems")]
+    raw: Option<RawFormat>,
+
+    #[arg(long, help = "sample rate of `--raw` input", requires = "raw", value_parser = clap::value_parser!(u32).range(1..))]
+    raw_rate: Option<u32>,
+
+    #[arg(long = "stem", help = "solve pre-separated stems instead of one input, as `path[,budget=N][,sounds=all|tonal|percussive]`", conflicts_with_all = ["input", "hpss"])]
+    stems: Vec<Stem>,
+
+    #[arg(short, long, help = "output datapack directory (or structure file with `--format structure`)", required_unless_present_any = ["export_matrices", "output_zip"])]
+    output: Option<PathBuf>,
+
+    #[arg(long, help = "write the datapack as a single zip to this file instead, or to stdout with `-`", conflicts_with_all = ["output", "export_matrices", "tune"])]
+    output_zip: Option<PathBuf>,
+
+    #[arg(long, help = "what the playback is written as", default_value = "datapack")]
+    format: Format,
+
+    #[arg(long, help = "overwrite a non-empty output directory or an existing structure")]
+    force: bool,
 
     #[arg(long, help = "output reconstruction as `.wav`")]
     reconstruction: Option<PathBuf>,
 
-    #[arg(long, help = "verbosity of logging", default_value = "normal")]
-    verbosity: Verbosity
-}
+    #[arg(long, help = "write a stereo `.wav` with the input on the left and the reconstruction on the right, to compare them on headphones")]
+    ab_preview: Option<PathBuf>,
 
-async fn find_version(target_version: &Option<String>) -> Result<Version, Error> {
-    event!(Level::INFO, "fetching version manifest");
-    let manifest = mojang::fetch_version_manifest().await?;
+    #[arg(long, help = "how the reconstruction handles ticks louder than ±1.0", default_value = "limit")]
+    preview_clipping: Clipping,
 
-    match target_version {
-        Some(version_str) => {
-            let possible_versions = manifest.versions.iter().filter(|v| v.id.contains(version_str)).collect::<Vec<&Version>>();
-            let exact_version = manifest.versions.iter().find(|v| v.id == *version_str);
+    #[arg(long, help = "how the reconstruction turns volumes into gain", default_value = "naive")]
+    preview_model: Model,
 
-            if let Some(exact_version) = exact_version {
-                return Ok(exact_version.clone())
-            }
+    #[arg(long, help = "blocks between the listener and the sounds for `--preview-model in-game` and `--audible-floor`", default_value = "0", value_parser = non_negative)]
+    preview_distance: f32,
 
-            if possible_versions.is_empty() {
-                event!(Level::INFO, "could not find a matching version to `{}`", version_str);
-            } else if possible_versions.len() > 1 {
-                println!("multiple matching versions to `{}`", version_str);
-                return Ok(Select::new("what version will you use?", possible_versions).prompt().unwrap().clone())
-            } else {
-                return Ok(possible_versions[0].clone())
-            }
-        },
-        None => {},
-    };
+    #[arg(long, help = "write the reconstruction as dithered 16-bit instead of 32-bit float")]
+    preview_dither: bool,
 
-    return Ok(Select::new("what version will you use?", manifest.versions).prompt().unwrap())
-}
+    #[arg(long, help = "write where every tick is in the reconstruction to a `.txt` next to it, as labels to import into audacity", requires = "reconstruction")]
+    preview_markers: bool,
 
-async fn fetch_predictable_sounds(
-    version: &Option<String>,
-    assets: &PathBuf,
-    behavior: &FetchBehavior
-) -> Result<Vec<(String, Sound)>, Error> {
-    let version = find_version(version).await?;
-    
-    let asset_index = match behavior {
-        FetchBehavior::FetchIfMissing | FetchBehavior::Refetch => {
-            event!(Level::INFO, "fetching asset index");
-            mojang::fetch_asset_index(&version).await?
-        },
-        FetchBehavior::CacheOnly => AssetIndex {
-            objects: HashMap::new()
-        },
-    };
+    #[arg(long, help = "seed of everything random: the preview's dither and `verify`'s problems. the solve itself has no randomness", default_value = "0", global = true)]
+    seed: u64,
+
+    #[arg(long, help = "pitch sounds onto the semitones of the 12-TET scale instead of a uniform grid")]
+    musical: bool,
 
-    event!(Level::INFO, "fetching sound definitions");
-    let definitions = assets::fetch_sound_definitions(&assets, &version, &behavior, &asset_index).await?;
+    #[clap(flatten)]
+    character: CharacterGroup,
 
-    event!(Level::INFO, "fetching sounds");
-    let sounds = assets::fetch_sounds(&assets, &version, &behavior, &asset_index).await?;
+    #[arg(long, help = "only use sounds that play out within this many ticks at their pitch", value_parser = clap::value_parser!(u32).range(1..))]
+    max_sound_ticks: Option<u32>,
 
-    let mut result = HashMap::new();
+    #[arg(long, help = "trim the silence basis sounds start with, up to a tick of the samples this many dB below their peak (default: -60)", num_args = 0..=1, default_missing_value = "-60", allow_negative_numbers = true, value_parser = decibels)]
+    trim_silence: Option<f32>,
 
-    let sound_path = PathBuf::from("minecraft/sounds");
+    #[arg(long, help = "leave out sounds longer than this (like `10s`), such as music discs and ambient loops, unless `--allow-long`", default_value = "10s", value_parser = seconds)]
+    long_sounds: f32,
 
-    for (identifier, def) in definitions {
-        if def.sounds.len() == 1 {
-            if let Some(sound) = def.sounds.get(0) {
-                let resource = match sound {
-                    AudioResourceLocation::Partial(s) => Some((PathBuf::from(s), 1.0, 1.0)),
-                    AudioResourceLocation::Full(resource_location) => {
-                        match &resource_location.resource_type {
-                            Some(resource_type) if resource_type != "sound" => None,
-                            _ => Some((
-                                    resource_location.name.clone(),
-                                    resource_location.pitch.unwrap_or(1.0),
-                                    resource_location.volume.unwrap_or(1.0)
-                            )),
-                        }
-                    },
-                };
+    #[arg(long, help = "keep sounds longer than `--long-sounds` in the basis")]
+    allow_long: bool,
 
-                if let Some((sound_name, pitch, volume)) = resource {
-                    let sound_path = sound_path.join(&sound_name).with_extension("ogg");
-                    let sound = sounds.iter().find(|(path, _)| *path == &sound_path);
-                    if let Some(sound) = sound {
-                        let mut sound = sound.1.clone();
-                        result.insert(identifier, sound.adjust_pitch(pitch).adjust_volume(volume).resample(48000).clone());
-                    }
-                }
-            }
-        }
+    #[arg(long, help = "ticks every basis sound spans, so longer sounds can carry across ticks", default_value = "1", value_parser = clap::value_parser!(u8).range(1..=4))]
+    atom_ticks: u8,
+
+    #[arg(long, help = "use at most this many different sound events across the whole song, narrowed down over rounds of the solve", value_parser = clap::value_parser!(u32).range(1..), conflicts_with = "chapter_minutes")]
+    max_unique_sounds: Option<u32>,
+
+    #[arg(long, help = "file of `start end weight` lines, or `auto` to weigh ticks by loudness, that shares the commands of ticks by importance")]
+    emphasis: Option<Emphasis>,
+
+    #[arg(long, help = "json object of `\"event\": \"alias\"` pairs, to play other sound events than the solved ones (e.g. a resource pack's)")]
+    alias_map: Option<PathBuf>,
+
+    #[arg(long, help = "sound category the song plays in, whose volume slider players turn it up and down with", default_value = "record")]
+    category: Category,
+
+    #[arg(long, help = "where the sounds play from: `player` (at everyone, as loud wherever they stand), `world <x> <y> <z>` or `entity <selector>`", default_value = "world 0 -60 0", allow_hyphen_values = true)]
+    anchor: Anchor,
+
+    #[arg(long, help = "play around every player from emitters in front, behind and to either side, following the stereo image of a stereo input", requires = "input", conflicts_with_all = ["anchor", "raw"])]
+    surround: bool,
+
+    #[arg(long, value_delimiter = ',', help = "sound categories to stop when the song is started with `audio:start`, e.g. `music` for the game's own")]
+    duck: Vec<Category>,
+
+    #[arg(long, help = "function to run once the song is over, e.g. the next song", value_parser = function)]
+    on_finish: Option<String>,
+
+    #[arg(long = "loop", help = "play the song over and over, solved so its end runs seamlessly into its start", conflicts_with_all = ["on_finish", "chapter_minutes"])]
+    looping: bool,
+
+    #[arg(long, help = "ticks each datapack function plays, for about that many times fewer files. every tick checks a score for each command of the function's ticks", default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..=20))]
+    ticks_per_function: u32,
+
+    #[arg(long, help = "comment every function with its tick, time, residual and loudest sounds, and write `manifest.json` of where every tick is played")]
+    annotate: bool,
+
+    #[arg(long, help = "split the input into harmonic and percussive parts and solve them with tonal and percussive sounds")]
+    hpss: bool,
+
+    #[arg(long, help = "skip this much of the input, e.g. `1m23s`", value_parser = audio::parse_time)]
+    start: Option<f32>,
+
+    #[arg(long, help = "only convert this much of the input (from `--start`), e.g. `45s`", value_parser = seconds)]
+    duration: Option<f32>,
+
+    #[arg(long, help = "estimate the tempo and stretch the input slightly so every 1/N of a beat lasts whole ticks", value_parser = clap::value_parser!(u32).range(1..=16))]
+    align_beats: Option<u32>,
+
+    #[arg(long, help = "only solve this long a stretch of the input, its loudest, e.g. `15s` or `1m`", value_parser = seconds)]
+    excerpt: Option<f32>,
+
+    #[arg(long, help = "adjust the settings on `--excerpt` with previews in between, then solve the whole song with them", requires = "excerpt", conflicts_with_all = ["non_interactive", "resume", "chapter_minutes"])]
+    tune: bool,
+
+    #[arg(long, help = "sample rate that basis and input are analyzed at", default_value_t = audio::ANALYSIS_RATE)]
+    analysis_rate: usize,
+
+    #[arg(long, help = "frames per second the input is solved in before they're merged into ticks, e.g. `100` or `200` to catch transients between ticks", default_value = "20", value_parser = frame_rate, conflicts_with = "chapter_minutes")]
+    frame_rate: usize,
+
+    #[arg(long, help = "representation that basis and input are matched in", default_value = "mel-weighted")]
+    features: FeatureKind,
+
+    #[arg(long, help = "band of frequencies the solve prioritizes, weighting both input and basis")]
+    focus: Option<Focus>,
+
+    #[arg(long, help = "run feature extraction on the GPU where the representation allows it")]
+    gpu_preprocess: bool,
+
+    #[arg(long, help = "OpenCL devices to split the solve across, by index (default: the first one)", value_delimiter = ',', global = true)]
+    devices: Vec<usize>,
+
+    #[arg(long, help = "probe the kernels' work-group sizes again instead of using the ones cached for the device", global = true)]
+    retune: bool,
+
+    #[arg(long, help = "projected gradient descent iterations", default_value = "128", value_parser = clap::value_parser!(u32).range(1..))]
+    iters: u32,
+
+    #[arg(long, help = "gradient descent step size, or `auto` for 1/L of the basis", default_value = "1e-6")]
+    step: StepSize,
+
+    #[arg(long, help = "amplitudes below this are not exported", default_value = "1e-5", value_parser = non_negative)]
+    epsilon: f32,
+
+    #[arg(long, help = "leave out commands a listener would hear quieter than this many decibels, e.g. `-60`", allow_negative_numbers = true, value_parser = decibels)]
+    audible_floor: Option<f32>,
+
+    #[arg(long, help = "the `--category` slider (times master) listeners are assumed to have for `--audible-floor`, from 0 to 1", default_value = "1", value_parser = fraction)]
+    category_volume: f32,
+
+    #[arg(long, help = "how much every tick's volumes are blended with the neighbouring ticks' (0 to 1), against clicks", default_value = "0", value_parser = fraction)]
+    smoothing: f32,
+
+    #[arg(long, help = "raise every volume to this power before exporting: below 1 brings quiet sounds up, above 1 pushes them down", default_value = "1", value_parser = positive)]
+    gamma: f32,
+
+    #[arg(long, help = "scale the loudest volume back up to 1 after smoothing and gamma")]
+    renormalize: bool,
+
+    #[arg(long, help = "scale the volumes so every song plays at this loudness, in dB relative to a sound at full volume, e.g. `-20`", allow_negative_numbers = true, value_parser = decibels, conflicts_with_all = ["renormalize", "import_matrices"])]
+    target_loudness: Option<f32>,
+
+    #[arg(long, help = "round volumes to multiples of this (0 leaves them as they are), for smaller datapacks", default_value = "0", value_parser = non_negative)]
+    round: f32,
+
+    #[arg(long, help = "fade the volumes in over this long at the start, e.g. `2s`", value_parser = seconds)]
+    fade_in: Option<f32>,
+
+    #[arg(long, help = "fade the volumes out over this long at the end, e.g. `4s`", value_parser = seconds)]
+    fade_out: Option<f32>,
+
+    #[arg(long, help = "how basis and input are scaled before solving", default_value = "minus-plus")]
+    normalization: Normalization,
+
+    #[arg(long, help = "what to do when the solution has values that aren't finite (NaN or infinite), from a step size too large or a broken driver", default_value = "zero")]
+    non_finite: NonFinite,
+
+    #[arg(long, help = "verbosity of logging", default_value = "normal", global = true)]
+    verbosity: Verbosity,
+
+    #[arg(long, help = "how log lines are written, `json` for programs following the progress", default_value = "human", global = true)]
+    log_format: LogFormat,
+
+    #[arg(long, help = "also write the whole log, down to trace, to this file. the previous ones are kept as `.1` to `.5`", global = true)]
+    log_file: Option<PathBuf>,
+
+    #[arg(long, help = "on error, write the settings, versions, OpenCL devices, matrix shapes and last log lines to this zip (default: diagnostics.zip), for attaching to an issue. nothing is sent anywhere", num_args = 0..=1, default_missing_value = "diagnostics.zip", global = true)]
+    diagnostics: Option<PathBuf>,
+
+    #[arg(long, help = "record the objective every iteration and plot it to this `.png`, to tell whether more `--iters` would help", conflicts_with = "chapter_minutes")]
+    plot_convergence: Option<PathBuf>,
+
+    #[arg(long, help = "work out the residual of the final solution again in f64 on the CPU and warn when the device's is off, against subtly broken drivers", conflicts_with = "chapter_minutes")]
+    verify_solution: bool,
+
+    #[cfg(feature = "npy")]
+    #[arg(long, help = "write every part's basis, chunks and solution as `.npy`, with a `.csv` of its sounds, to this directory for analysis in numpy", conflicts_with = "chapter_minutes")]
+    dump_npy: Option<PathBuf>,
+
+    #[arg(long, help = "also write the per-stage timing summary as `.json`")]
+    timings: Option<PathBuf>,
+
+    #[arg(long, help = "continue from a checkpoint saved by a cancelled run")]
+    resume: Option<PathBuf>,
+
+    #[arg(long, help = "where a cancelled run saves its checkpoint (default: <assets>/checkpoint.bin)")]
+    checkpoint: Option<PathBuf>,
+
+    #[arg(long, help = "build the basis and the input's chunks and write them to this directory instead of solving, to solve on another machine with `--import-matrices`", conflicts_with_all = ["output", "import_matrices", "resume", "chapter_minutes", "tune"])]
+    export_matrices: Option<PathBuf>,
+
+    #[arg(long, help = "solve and export the matrices `--export-matrices` wrote to this directory, without any assets or input", conflicts_with_all = ["input", "stems", "emphasis", "reconstruction", "ab_preview", "chapter_minutes", "tune"])]
+    import_matrices: Option<PathBuf>,
+
+    #[arg(long, help = "split the output into chapters of this many minutes, solved one at a time to bound memory", value_parser = clap::value_parser!(u32).range(1..), conflicts_with = "resume")]
+    chapter_minutes: Option<u32>,
+
+    #[arg(long, help = "build every part's chunks in a memory-mapped file next to the cached schedule instead of in memory, for inputs too long for them to fit", conflicts_with_all = ["chapter_minutes", "export_matrices", "import_matrices"])]
+    map_chunks: bool
+}
+
+fn non_negative(s: &str) -> Result<f32, String> {
+    match s.parse::<f32>() {
+        Ok(value) if value.is_finite() && value >= 0.0 => Ok(value),
+        _ => Err(format!("`{}` is not a non-negative number", s)),
     }
+}
 
-    Ok(result.into_iter().collect::<Vec<(String, Sound)>>()) 
+fn positive(s: &str) -> Result<f32, String> {
+    match s.parse::<f32>() {
+        Ok(value) if value.is_finite() && value > 0.0 => Ok(value),
+        _ => Err(format!("`{}` is not a positive number", s)),
+    }
 }
 
-#[tokio::main]
-async fn main() -> Result<(), Error> {
-    let args = Args::parse();
-    logging::setup(args.verbosity)?;
+fn fraction(s: &str) -> Result<f32, String> {
+    match s.parse::<f32>() {
+        Ok(value) if (0.0..=1.0).contains(&value) => Ok(value),
+        _ => Err(format!("`{}` is not a number between 0 and 1", s)),
+    }
+}
 
-    let _span = span!(Level::INFO, "main", tag = "main").entered();
+fn sha1(s: &str) -> Result<String, String> {
+    match s.len() == 40 && s.chars().all(|c| c.is_ascii_hexdigit()) {
+        true => Ok(s.to_lowercase()),
+        false => Err(format!("`{}` is not a SHA-1 of 40 hex digits", s)),
+    }
+}
 
-    let behavior = match (args.behavior.refetch, args.behavior.local) {
-        (true, false) => FetchBehavior::Refetch,
-        (false, true) => FetchBehavior::CacheOnly,
-        (false, false) => FetchBehavior::FetchIfMissing,
-        _ => unimplemented!("impossible")
-    };
+fn frame_rate(s: &str) -> Result<usize, String> {
+    match s.parse::<usize>() {
+        Ok(value) if value.is_multiple_of(subtick::TICK_RATE) && (subtick::TICK_RATE..=10 * subtick::TICK_RATE).contains(&value) => Ok(value),
+        _ => Err(format!("`{}` is not a multiple of 20 from 20 to 200", s)),
+    }
+}
 
-    info!("loading predictable sounds");
+fn decibels(s: &str) -> Result<f32, String> {
+    match s.parse::<f32>() {
+        Ok(value) if value.is_finite() && value <= 0.0 => Ok(value),
+        _ => Err(format!("`{}` is not a level in decibels at or below 0", s)),
+    }
+}
 
-    let predictable_sounds = fetch_predictable_sounds(&args.target_version, &args.assets, &behavior).await?;
+fn function(s: &str) -> Result<String, String> {
+    match export::is_resource_location(s) {
+        true => Ok(s.to_string()),
+        false => Err(format!("`{}` is not a function like `namespace:path`", s)),
+    }
+}
 
-    event!(Level::INFO, "found {} predictable sounds", predictable_sounds.len());
+fn seconds(s: &str) -> Result<f32, String> {
+    match audio::parse_time(s) {
+        Ok(value) if value > 0.0 => Ok(value),
+        _ => Err(format!("`{}` is not a length like `15s` or `1m`", s)),
+    }
+}
 
-    let processor = audio::Processor::new();
+/// an input the solve runs on, the basis sounds it may use and how many
+/// commands per tick it gets
+struct Part {
+    name: String,
+    audio: Sound,
+    sounds: Vec<usize>,
+    budget: usize
+}
 
-    let sounds = audio::permute_with_pitch(predictable_sounds, 32)
-        .into_par_iter()
-        .map(|(id, mut sound)| (id, sound.mel(&processor).clone()))
-        .collect::<Vec<((String, f32), Sound)>>();
+fn rms(samples: &[f32]) -> f32 {
+    if samples.is_empty() {
+        return 0.0;
+    }
 
-    let sound_ids = sounds.iter().map(|s| s.0.clone()).collect::<Vec<(String, f32)>>();
+    return (samples.iter().map(|sample| sample * sample).sum::<f32>() / samples.len() as f32).sqrt();
+}
 
-    let sound_bins = sounds.iter().map(|s| s.1.samples.clone()).collect::<Vec<Vec<f32>>>();
+/// reads a `.wav`, an `.opus` or, with `--raw`, headerless samples from a
+/// file, or a `.wav` or samples from stdin (`-`), resampled to `analysis_rate`
+fn read_input(path: &Path, analysis_rate: usize, raw: Option<(RawFormat, u32)>) -> Result<Sound, Error> {
+    let mut audio = match raw {
+        Some((format, rate)) if path == Path::new("-") => {
+            event!(Level::INFO, "reading {} from stdin", value_name(&format));
+            decode::read_raw(std::io::stdin().lock(), format, rate as usize)?
+        },
+        Some((format, rate)) => {
+            event!(Level::INFO, "reading `{}` as {}", path.to_string_lossy(), value_name(&format));
+            decode::read_raw(std::io::BufReader::new(std::fs::File::open(path)?), format, rate as usize)?
+        },
+        None if is_opus(path) => read_opus(path, 1)?.0,
+        None => read_wav(path)?,
+    };
+
+    if audio.sample_rate != analysis_rate {
+        event!(Level::INFO, "resampling input from {}Hz to {}Hz", audio.sample_rate, analysis_rate);
+        audio.resample(analysis_rate);
+    }
+
+    return Ok(audio);
+}
+
+fn is_opus(path: &Path) -> bool {
+    return path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("opus"));
+}
+
+/// see `decode::read_opus`
+#[cfg(feature = "opus")]
+fn read_opus(path: &Path, channels: usize) -> Result<(Sound, usize), Error> {
+    event!(Level::INFO, "reading `{}`", path.to_string_lossy());
+    return decode::read_opus(std::io::BufReader::new(std::fs::File::open(path)?), channels);
+}
 
-    let mut sound_bins = algebra::matrix_from_vecs(sound_bins)?
-        .reversed_axes();
+#[cfg(not(feature = "opus"))]
+fn read_opus(path: &Path, _channels: usize) -> Result<(Sound, usize), Error> {
+    event!(Level::ERROR, "`{}` is opus, which this build can't decode", path.to_string_lossy());
+    event!(Level::ERROR, help = true, "build with `--features opus` (it links libopus), or convert it to a `.wav` first");
+    return Err(anyhow!("opus support isn't built in"));
+}
 
-    drop(sounds);
+fn read_wav(path: &Path) -> Result<Sound, Error> {
+    if path == Path::new("-") {
+        event!(Level::INFO, "reading a wav from stdin");
+        let reader = decode::buffer_wav(std::io::stdin().lock())?;
+        if reader.spec().channels > 1 {
+            event!(Level::ERROR, "stereo audio is not supported! please pipe in mono:");
+            event!(Level::ERROR, help = true, "with ffmpeg, add `-ac 1` before the output");
+            return Err(anyhow!("input was stereo"));
+        }
+        return decode::read_wav(reader);
+    }
 
-    event!(Level::INFO, "reading target file");
-    let mut reader = hound::WavReader::open(&args.input)?;
+    event!(Level::INFO, "reading `{}`", path.to_string_lossy());
+    let reader = hound::WavReader::open(path)?;
 
     if reader.spec().channels > 1 {
         event!(Level::ERROR, "stereo audio is not supported! please convert your input file into mono:");
-        let input_filename: &str = args.input.file_stem().unwrap().to_str().unwrap();
+        let input_filename: &str = path.file_stem().unwrap().to_str().unwrap();
         event!(Level::ERROR, help = true, "if you have ffmpeg installed:");
         event!(Level::ERROR, help = true, "ffmpeg -i {}.wav -ac 1 {}.mono.wav", input_filename, input_filename);
         return Err(anyhow!("input was stereo"));
     }
 
-    let samples = reader.samples::<i16>()
-        .map(|r| r.expect("found empty sample"))
-        .collect::<Vec<i16>>()
-        .iter()
-        .map(|i| *i as f32)
-        .collect::<Vec<f32>>();
-
-    let sample_rate: usize = reader.spec().sample_rate.try_into().unwrap();
+    return decode::read_wav(reader);
+}
 
-    let mut target_audio = Sound {
-        samples,
-        sample_rate
+/// `--surround`: both channels of a stereo `.wav` or `.opus` (or a `.wav`
+/// from stdin), at half volume so their mix is what a mono input would be,
+/// resampled to `analysis_rate`
+fn read_stereo(path: &Path, analysis_rate: usize) -> Result<Vec<Sound>, Error> {
+    let (audio, channels) = match path == Path::new("-") {
+        true => {
+            event!(Level::INFO, "reading a stereo wav from stdin");
+            let reader = decode::buffer_wav(std::io::stdin().lock())?;
+            let channels = reader.spec().channels as usize;
+            (decode::read_wav(reader)?, channels)
+        },
+        false if is_opus(path) => read_opus(path, 2)?,
+        false => {
+            event!(Level::INFO, "reading `{}`", path.to_string_lossy());
+            let reader = hound::WavReader::open(path)?;
+            let channels = reader.spec().channels as usize;
+            (decode::read_wav(reader)?, channels)
+        },
     };
 
-    event!(Level::INFO, "resampling input");
-    target_audio.resample(48000);
+    if channels != 2 {
+        event!(Level::ERROR, "`--surround` follows the stereo image, but the input has {} channels", channels);
+        return Err(anyhow!("input wasn't stereo"));
+    }
 
-    let chunks = target_audio.samples.chunks_exact(2400).collect::<Vec<&[f32]>>()
-        .into_iter()
-        .map(|samples| Sound {
-            samples: samples.to_vec(),
-            sample_rate
+    return Ok(decode::split_channels(&audio, 2).into_iter()
+        .map(|mut channel| {
+            channel.samples.iter_mut().for_each(|sample| *sample /= 2.0);
+            if channel.sample_rate != analysis_rate {
+                channel.resample(analysis_rate);
+            }
+            channel
         })
-        .map(|mut sound| sound.mel(&processor).clone())
-        .map(|sound| sound.samples)
-        .collect::<Vec<Vec<f32>>>();
+        .collect());
+}
 
-    drop(target_audio);
+/// all inputs played together, as long as the longest
+fn mix(inputs: &[Sound]) -> Sound {
+    let length = inputs.iter().map(|input| input.samples.len()).max().unwrap_or(0);
+    let mut samples = vec![0.0; length];
+    for input in inputs {
+        samples.iter_mut().zip(&input.samples).for_each(|(mixed, sample)| *mixed += sample);
+    }
 
-    let sound_bins_clone = match &args.reconstruction {
-        Some(_) => {
-            event!(Level::WARN, "cloning sound_bins for usage in later reconstruction, which will spike memory");
-            event!(Level::WARN, "if this crashes, disable reconstruction");
-            Some(sound_bins.clone())
-        },
-        None => None
+    return Sound { samples, sample_rate: inputs.first().map(|input| input.sample_rate).unwrap_or(audio::ANALYSIS_RATE) };
+}
+
+/// stretches the inputs so every `subdivision`th of a beat lasts whole
+/// ticks, then delays them so the beats start on a tick. stems share the
+/// tempo of their mix. inputs that can't be aligned are left as they are
+fn align_beats(inputs: Vec<Sound>, subdivision: u32) -> Vec<Sound> {
+    let _span = span!(Level::INFO, "align_beats", tag = "audio").entered();
+    let processor = audio::Processor::new();
+
+    let Some(bpm) = tempo::estimate(&mix(&inputs), &processor) else {
+        event!(Level::WARN, "could not estimate a tempo, leaving the input as is");
+        return inputs;
+    };
+    event!(Level::INFO, "estimated tempo: {:.1}bpm", bpm);
+
+    let Some(stretch) = tempo::grid_stretch(bpm, subdivision) else {
+        event!(Level::WARN, "aligning to the ticks would stretch by more than {}%, leaving the input as is", tempo::MAX_STRETCH * 100.0);
+        return inputs;
     };
+    event!(Level::INFO, "stretching the input by {:+.2}%", (stretch - 1.0) * 100.0);
 
-    let start = Instant::now();
-    let mut chunks = algebra::matrix_from_vecs(chunks)?
-        .reversed_axes();
+    let stretched = inputs.iter().map(|input| tempo::time_stretch(input, stretch)).collect::<Vec<Sound>>();
+    let offset = tempo::beat_offset(&mix(&stretched), bpm / stretch, &processor);
+    event!(Level::DEBUG, "delaying the input by {} samples", offset);
 
-    event!(Level::DEBUG, "chunks: {:?}", &chunks.dim());
-    event!(Level::DEBUG, "bins: {:?}", &sound_bins.dim());
+    return stretched.into_iter()
+        .map(|input| {
+            let mut samples = vec![0.0; offset];
+            samples.extend(input.samples);
+            Sound { samples, sample_rate: input.sample_rate }
+        })
+        .collect();
+}
 
-    algebra::normalize_to_minus_plus(&mut chunks);
-    algebra::normalize_to_minus_plus(&mut sound_bins);
+/// the inputs from `start` seconds in, `duration` seconds of them or the
+/// rest. stems are cut at the same time
+fn trim(inputs: Vec<Sound>, start: f32, duration: Option<f32>) -> Result<Vec<Sound>, Error> {
+    let longest = inputs.iter().map(|input| input.samples.len() as f32 / input.sample_rate as f32).fold(0.0, f32::max);
+    if start >= longest {
+        return Err(anyhow!("`--start` is past the end of the input, which is {:.1}s long", longest));
+    }
 
-    event!(Level::INFO, "running NNLS...");
+    return Ok(inputs.into_iter()
+        .map(|mut input| {
+            input.section(start, duration);
+            input
+        })
+        .collect());
+}
 
-    let mut approximation = algebra::pgd_nnls(chunks, sound_bins, 128, 1e-6);
+/// the loudest `seconds` of the inputs, whole ticks of it. stems are cut
+/// where their mix is loudest
+fn excerpt(inputs: Vec<Sound>, seconds: f32, samples_per_tick: usize) -> Vec<Sound> {
+    let mixed = mix(&inputs);
+    let n_ticks = mixed.samples.len().div_ceil(samples_per_tick);
+    let length = (seconds * 20.0).ceil() as usize;
 
-    algebra::normalize_to_global(&mut approximation);
+    let start = emphasis::heaviest_window(&emphasis::from_loudness(&mixed.samples, samples_per_tick, n_ticks), length);
+    event!(Level::INFO, "solving {:.1}s from {:.1}s in", length as f32 / 20.0, start as f32 / 20.0);
 
-    event!(Level::INFO, "done! elapsed: {}ms", start.elapsed().as_millis());
+    return inputs.into_iter()
+        .map(|input| {
+            let first = (start * samples_per_tick).min(input.samples.len());
+            let last = ((start + length) * samples_per_tick).min(input.samples.len());
+            Sound { samples: input.samples[first..last].to_vec(), sample_rate: input.sample_rate }
+        })
+        .collect();
+}
 
-    
+/// uses the tiles cached for each device, probing the ones not seen before
+async fn tune_devices(devices: &[ocl::Device], assets: &Path, retune: bool) -> Result<(), Error> {
+    let mut cache = TuningCache::load(assets).await;
+    let mut probed = false;
 
-    event!(Level::INFO, "saving to datapack...");
+    for device in devices {
+        let name = device.name()?;
+        let tiles = match cache.devices.get(&name) {
+            Some(tiles) if !retune => *tiles,
+            _ => {
+                event!(Level::INFO, "tuning kernels for `{}`", name);
+                let tiles = tuning::probe(*device)?;
+                event!(Level::INFO, "using {}x{} tiles for `{}`", tiles.row, tiles.col, name);
+                cache.devices.insert(name, tiles);
+                probed = true;
+                tiles
+            }
+        };
 
-    let mut writer = match &args.reconstruction {
-        Some(output_path) => Some(hound::WavWriter::create(output_path, hound::WavSpec {
-            channels: 1,
-            sample_rate: 48000,
-            bits_per_sample: 32,
-            sample_format: hound::SampleFormat::Float,
-        }).unwrap()),
-        None => None,
-    };
+        algebra::use_tiles(*device, tiles)?;
+    }
 
-    for (index, amplitudes) in approximation.axis_iter(Axis(1)).enumerate() {
-        let mut amplitudes: Vec<(usize, (&f32, &(String, f32)))> = amplitudes.iter().zip(&sound_ids).enumerate().collect();
-        amplitudes.sort_by(|a, b| b.1.0.partial_cmp(a.1.0).unwrap());
+    if probed {
+        cache.save(assets).await?;
+    }
 
-        let amplitudes = &amplitudes[0..80];
-        let mut output = String::new();
-        output.push_str("stopsound @a[tag=!nomusic] record\n");
-        let mut current_sample = vec![0.0; 2400];
+    return Ok(());
+}
 
-        for (i, (amplitude, (name, pitch))) in amplitudes {
-            output.push_str(&format!("playsound {} record @a 0 -60 0 {:.5} {:.5} \n", name, amplitude, pitch));
+async fn verify_devices(indices: &[usize], assets: &Path, retune: bool, seed: u64) -> Result<(), Error> {
+    let devices = algebra::select_devices(indices)?;
+    tune_devices(&devices, assets, retune).await?;
+    let mut total_failed = 0;
 
-            if writer.is_some() {
-                let mut sound = Sound {
-                    samples: sound_bins_clone.as_ref().unwrap().column(*i).to_vec(),
-                    sample_rate: 48000
-                };
+    for (index, device) in devices.iter().enumerate() {
+        event!(Level::INFO, "checking `{}`", device.name()?);
 
-                sound.adjust_volume(**amplitude);
+        let mut failed = 0;
+        let problems = verify::SHAPES.iter().map(|shape| (*shape, 1)).chain(verify::CONV_SHAPES.iter().cloned());
+        for (problem_index, (shape, span)) in problems.enumerate() {
+            let parity = verify::parity(*device, shape, span, seed.wrapping_add(problem_index as u64))?;
+            let (m, r, n) = parity.shape;
+            let problem = match parity.span {
+                1 => format!("{}x{}x{}", m, r, n),
+                span => format!("{}x{}x{} over {} ticks", m, r, n, span),
+            };
 
-                for (j, sample) in sound.samples.iter().enumerate() {
-                    current_sample[j] += sample;
+            match parity.passed() {
+                true => event!(Level::INFO, "{}: max deviation {:e} (cpu {}ms, device {}ms)", problem, parity.deviation, parity.cpu_millis, parity.device_millis),
+                false => {
+                    event!(Level::ERROR, "{}: max deviation {:e}, over {:e}", problem, parity.deviation, verify::TOLERANCE);
+                    failed += 1;
                 }
             }
         }
 
-        if let Some(writer) = &mut writer {
-            for sample in current_sample {
-                writer.write_sample(sample).expect("failed to write smaple");
+        if failed > 0 {
+            event!(Level::ERROR, help = true, "device {} gives wrong results, try updating its driver or pick another with `--devices`", indices.get(index).copied().unwrap_or(0));
+        }
+        total_failed += failed;
+    }
+
+    if total_failed > 0 {
+        return Err(anyhow!("{} parity checks failed", total_failed));
+    }
+
+    event!(Level::INFO, "all devices match the CPU");
+    return Ok(());
+}
+
+/// the version `target_version` names, or the one picked from its matches.
+/// those are printed, or logged when the zip has stdout (`zips_to_stdout`)
+async fn find_version(target_version: &Option<String>, assets: &Path, options: &FetchOptions, manifest_url: &str, non_interactive: bool, zips_to_stdout: bool) -> Result<Version, Error> {
+    event!(Level::INFO, "fetching version manifest");
+    let manifest = assets::fetch_version_manifest(assets, options, manifest_url).await?;
+
+    let Some(version_str) = target_version else {
+        if non_interactive {
+            return Ok(versions::resolve(&manifest, versions::LATEST_RELEASE)?);
+        }
+        return Ok(Select::new("what version will you use?", manifest.versions).prompt()?);
+    };
+
+    match versions::resolve(&manifest, version_str) {
+        Ok(version) => return Ok(version),
+        Err(VersionError::Ambiguous { candidates, .. }) if !non_interactive => {
+            match zips_to_stdout {
+                true => event!(Level::INFO, "multiple matching versions to `{}`", version_str),
+                false => println!("multiple matching versions to `{}`", version_str),
+            }
+            return Ok(Select::new("what version will you use?", candidates).prompt()?);
+        },
+        Err(VersionError::Ambiguous { query, candidates }) if zips_to_stdout => {
+            let ids = candidates.iter().map(|candidate| candidate.id.as_str()).collect::<Vec<_>>();
+            event!(Level::ERROR, "`{}` matches {}", query, ids.join(", "));
+            return Err(VersionError::Ambiguous { query, candidates }.into());
+        },
+        Err(VersionError::Ambiguous { query, candidates }) => {
+            // one per line on stdout so scripts can pick from them
+            for candidate in &candidates {
+                println!("{}", candidate.id);
             }
+            return Err(VersionError::Ambiguous { query, candidates }.into());
+        },
+        Err(error) if !non_interactive => {
+            event!(Level::INFO, "{}", error);
+            return Ok(Select::new("what version will you use?", manifest.versions).prompt()?);
+        },
+        Err(error) => return Err(error.into()),
+    }
+}
+
+/// the asset indices and sound definitions of the run's versions by id,
+/// each fetched the first time it's needed
+type FetchedVersions = HashMap<String, assets::VersionAssets>;
+
+/// what the predictable sound events of `version` play, without fetching the sounds
+async fn fetch_event_sounds(version: &Version, fetched: &mut FetchedVersions, assets: &Path, options: &FetchOptions) -> Result<HashMap<String, String>, Error> {
+    if !fetched.contains_key(&version.id) {
+        fetched.insert(version.id.clone(), assets::fetch_version_assets(version, assets, options).await?);
+    }
+    let version_assets = &fetched[&version.id];
+
+    // cache-only has no asset index, and then none of the versions have one
+    let asset_index = (!version_assets.asset_index.objects.is_empty()).then_some(&version_assets.asset_index);
+    return Ok(assets::event_sounds(&version_assets.definitions, asset_index));
+}
+
+/// checks the basis against the output version, saying what happens to the
+/// events that don't play the same there
+async fn check_compatibility(
+    versions: &[Version],
+    output_version: &Version,
+    args: &Args,
+    options: &FetchOptions,
+    fetched: &mut FetchedVersions
+) -> Result<HashMap<String, versions::Compatibility>, Error> {
+    use versions::Compatibility;
+
+    let mut sets = Vec::new();
+    for version in versions {
+        sets.push((version.id.clone(), fetch_event_sounds(version, fetched, &args.assets, options).await?.into_iter().collect()));
+    }
+    let (basis, _) = versions::union(sets);
+    let output = fetch_event_sounds(output_version, fetched, &args.assets, options).await?;
+    let compatibility = versions::compatibility(&basis.into_iter().collect(), &output);
+
+    let (mut renamed, mut changed, mut missing) = (0, 0, 0);
+    for (event, compatibility) in &compatibility {
+        match compatibility {
+            Compatibility::Same => continue,
+            Compatibility::Renamed(_) => renamed += 1,
+            Compatibility::Changed => changed += 1,
+            Compatibility::Missing => missing += 1,
         }
+        event!(Level::DEBUG, "{} on {}: {:?}", event, output_version, compatibility);
+    }
 
-        output.push_str(&format!("schedule function audio:_/{} 1t append\n", index + 1));
-        tokio::fs::write(args.output.join(index.to_string()).with_extension("mcfunction"), output).await?;
+    if renamed + missing > 0 {
+        event!(Level::WARN, "{} events are renamed on {} and will use the new name, {} don't exist there and are left out", renamed, output_version, missing);
     }
-    
-    if let Some(writer) = writer {
-        writer.finalize().unwrap();
+    if changed > 0 && args.exclude_changed {
+        event!(Level::WARN, "{} events sound different on {} and are left out", changed, output_version);
+    } else if changed > 0 {
+        event!(Level::WARN, "{} events sound different on {}, the output won't sound like the preview", changed, output_version);
+        event!(Level::WARN, help = true, "pass `--exclude-changed` to leave them out");
+    }
+
+    return Ok(compatibility);
+}
+
+async fn list_versions(assets: &Path, options: &FetchOptions, manifest_url: &str, filter: Option<&str>, releases: bool) -> Result<(), Error> {
+    let manifest = assets::fetch_version_manifest(assets, options, manifest_url).await?;
+
+    for version in manifest.versions {
+        if releases && version.kind != "release" {
+            continue;
+        }
+        if filter.is_some_and(|filter| !version.id.contains(filter)) {
+            continue;
+        }
+
+        let latest = if version.id == manifest.latest.release {
+            " (latest release)"
+        } else if version.id == manifest.latest.snapshot {
+            " (latest snapshot)"
+        } else {
+            ""
+        };
+        println!("{}\t{}{}", version.id, version.kind, latest);
+    }
+
+    return Ok(());
+}
+
+async fn list_sounds(version: Version, assets: &Path, options: &FetchOptions) -> Result<(), Error> {
+    let (definitions, sounds) = assets::fetch_assets(&version, assets, options).await?;
+
+    // events that play other events share their files
+    let predictable = assets::predictable_sounds(&definitions)
+        .into_iter()
+        .filter_map(|predictable| {
+            let bytes = sounds.get(&predictable.path)?.clone();
+            Some((predictable, bytes))
+        })
+        .collect::<Vec<_>>();
+
+    let infos = predictable
+        .into_par_iter()
+        .map(|(predictable, bytes)| (assets::sound_info(&predictable.path, &bytes), predictable))
+        .collect::<Vec<_>>();
+
+    for (info, predictable) in infos {
+        match info {
+            Ok(info) => println!(
+                "{}\t{:.2}s\t{}Hz\t{}{}",
+                predictable.event,
+                info.duration.as_secs_f32(),
+                info.sample_rate,
+                if info.channels == 1 { "mono" } else { "stereo" },
+                predictable.via.iter().map(|event| format!("\t-> {}", event)).collect::<String>()
+            ),
+            Err(e) => event!(Level::WARN, "{}", e),
+        }
+    }
+
+    return Ok(());
+}
+
+/// decodes the sound `event` plays and writes it at `pitch` the way it is in
+/// the basis: at most the few ticks that are decoded, with the pitch and
+/// volume of its definition applied first
+async fn extract_sound(version: Version, assets: &Path, options: &FetchOptions, event: &str, pitch: f32, ticks: Option<u8>, output: &Path) -> Result<(), Error> {
+    if !(0.5..=2.0).contains(&pitch) {
+        return Err(anyhow!("the game only plays sounds at pitches from 0.5 to 2.0, not {}", pitch));
     }
 
+    let (definitions, mut sounds) = assets::fetch_assets(&version, assets, options).await?;
+    let predictable = assets::find_predictable_sound(&definitions, event)?;
+
+    let bytes = sounds.remove(&predictable.path).ok_or_else(|| anyhow!("`{}` isn't in the assets of {}", predictable.path, version.id))?;
+    let mut decoded = assets::decode_sounds(HashMap::from([(predictable.path.clone(), bytes)]))?;
+    let mut sound = decoded.remove(&predictable.path).unwrap();
+    predictable.play(&mut sound).adjust_pitch(pitch);
+    if let Some(ticks) = ticks {
+        sound.ticks(ticks as usize);
+    }
+
+    let mut preview = Preview::new(std::fs::File::create(output)?, sound.sample_rate as u32, Clipping::Limit, false)?;
+    preview.write_tick(sound.samples)?;
+    preview.finalize()?;
+
+    event!(Level::INFO, "wrote {} at pitch {} to {}", predictable.event, pitch, output.display());
     return Ok(());
 }
+
+/// `--output-zip -`, which keeps stdout to the zip
+fn zips_to_stdout(args: &Args) -> bool {
+    return args.output_zip.as_deref() == Some(Path::new("-"));
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    let args = Args::parse();
+    // listings and `--output-zip -` keep stdout to themselves, the log lines
+    // go to stderr
+    let listing = matches!(args.command, Some(Command::Versions { .. }) | Some(Command::Sounds { .. }));
+    let stdout_taken = listing || zips_to_stdout(&args);
+    logging::setup(args.verbosity.clone(), args.log_format, args.log_file.as_deref(), keeps_recent_lines(&args), stdout_taken)?;
+
+    let _span = span!(Level::INFO, "main", tag = "main").entered();
+
+    mojang::configure(&mojang::Network {
+        proxy: args.proxy.clone(),
+        ca_certificates: args.ca_certificates.clone()
+    })?;
+
+    if let Some(url) = &args.webhook_url {
+        webhook::configure(url.clone());
+    }
+
+    // proxy and webhook urls can have credentials in them
+    let diagnostics = args.diagnostics.clone().map(|path| {
+        let redacted = |url: &Option<String>| url.as_ref().map(|_| "(redacted)".to_string());
+        let settings = Args { proxy: redacted(&args.proxy), webhook_url: redacted(&args.webhook_url), ..args.clone() };
+        (path, format!("{:#?}", settings))
+    });
+    let result = match (args.tune, args.output_zip.is_some()) {
+        (true, _) => tune(args).await,
+        (false, true) => run_zipped(args).await,
+        (false, false) => run(args, None).await
+    };
+    if let Err(error) = &result {
+        suggest(error);
+        webhook::send(format!("failed: {:#}", error));
+
+        if let Some((path, settings)) = diagnostics {
+            match diagnostics::write_bundle(&path, &format!("{:?}", error), &settings) {
+                Ok(()) => event!(Level::ERROR, help = true, "wrote diagnostics to {}, attach it to an issue", path.display()),
+                Err(bundle_error) => event!(Level::WARN, "couldn't write diagnostics to {}: {}", path.display(), bundle_error),
+            }
+        }
+    }
+
+    webhook::flush().await;
+    return result;
+}
+
+/// diagnostics bundle the last lines, and `serve` reports a job's progress
+/// with them
+fn keeps_recent_lines(args: &Args) -> bool {
+    #[cfg(feature = "serve")]
+    if let Some(Command::Serve { .. }) = args.command {
+        return true;
+    }
+
+    return args.diagnostics.is_some();
+}
+
+/// points at the flag that gets past a library error, the library itself
+/// doesn't know which flags exist
+fn suggest(error: &Error) {
+    if let Some(error) = error.downcast_ref::<export::ExportError>() {
+        match error {
+            export::ExportError::NotEmpty(_) => event!(Level::ERROR, help = true, "pass `--force` to overwrite it, or choose an empty directory"),
+            export::ExportError::Exists(_) => event!(Level::ERROR, help = true, "pass `--force` to overwrite it, or choose another file"),
+            export::ExportError::NotADirectory(_) => event!(Level::ERROR, help = true, "choose a directory for the output, not a file"),
+            export::ExportError::InvalidAlias { .. } => event!(Level::ERROR, help = true, "sound events are lowercase, like `block.note_block.harp` or `mypack:custom.sound`"),
+            _ => {}
+        }
+    } else if let Some(error) = error.downcast_ref::<assets::AssetsError>() {
+        match error {
+            assets::AssetsError::MissingSoundsJson | assets::AssetsError::MissingSoundDefinitions(_) => {
+                event!(Level::ERROR, help = true, "run with refetch or normal fetch behavior")
+            },
+            assets::AssetsError::Mojang(error) => suggest_network(error),
+            _ => {}
+        }
+    } else if let Some(error) = error.downcast_ref::<mojang::MojangError>() {
+        suggest_network(error);
+    } else if let Some(error) = error.downcast_ref::<pack::PackError>() {
+        match error {
+            pack::PackError::Mojang(error) => suggest_network(error),
+            pack::PackError::HashMismatch { .. } => event!(Level::ERROR, help = true, "copy `resource-pack-sha1` from the server's server.properties, the pack may have been updated"),
+            pack::PackError::NotCached(_) => event!(Level::ERROR, help = true, "run once without `--local` to download the pack"),
+            _ => {}
+        }
+    } else if let Some(emphasis::EmphasisError::Parse { .. }) = error.downcast_ref::<emphasis::EmphasisError>() {
+        event!(Level::ERROR, help = true, "every line of the emphasis file is `start end weight`, in seconds");
+    } else if let Some(error) = error.downcast_ref::<algebra::SolverError>() {
+        match error {
+            algebra::SolverError::NoDevices => event!(Level::ERROR, help = true, "install an OpenCL driver for your GPU (or a CPU runtime like pocl)"),
+            algebra::SolverError::NoSuchDevice { found, .. } => event!(Level::ERROR, help = true, "pick one of the {} devices with `--devices`", found),
+            algebra::SolverError::OutOfMemory { .. } => event!(Level::ERROR, help = true, "use fewer sounds (e.g. `--max-sound-ticks`) or a lower `--analysis-rate`"),
+            _ => {}
+        }
+    }
+}
+
+fn suggest_network(error: &mojang::MojangError) {
+    if error.is_untrusted() {
+        event!(Level::ERROR, help = true, "the server's certificate isn't trusted. behind a proxy that intercepts TLS, pass its root certificate with `--ca-cert`");
+    } else if error.is_offline() {
+        event!(Level::ERROR, help = true, "check your connection (and `--proxy`, if you need one), or pass `--local` to use cached assets");
+    }
+}
+
+fn post_process(args: &Args) -> algebra::PostProcess {
+    return algebra::PostProcess {
+        smoothing: args.smoothing,
+        gamma: args.gamma,
+        renormalize: args.renormalize,
+        gain: 1.0,
+        fade_in: args.fade_in.map(|seconds| (seconds * 20.0).round() as usize).unwrap_or(0),
+        fade_out: args.fade_out.map(|seconds| (seconds * 20.0).round() as usize).unwrap_or(0),
+        epsilon: args.epsilon,
+        round: args.round
+    };
+}
+
+/// frames the solve splits every tick into, see `--frame-rate`
+fn frames_per_tick(args: &Args) -> usize {
+    return args.frame_rate / subtick::TICK_RATE;
+}
+
+fn fetch_options(args: &Args) -> FetchOptions {
+    return FetchOptions {
+        behavior: args.behavior.behavior(),
+        concurrency: Concurrency { downloads: args.download_jobs.into(), files: args.read_jobs.into() },
+        ..FetchOptions::default()
+    };
+}
+
+/// what the solve needs of a part besides its chunks and basis, from its
+/// audio or from `--import-matrices`
+struct PartLayout {
+    name: String,
+    sounds: Vec<usize>,
+    budget: usize,
+    loudness: f32
+}
+
+/// the parts of a solve and how their frames make up the schedule
+struct Layout {
+    parts: Vec<PartLayout>,
+    sound_ids: Vec<(String, f32)>,
+    n_ticks: usize,
+    frames: usize,
+    /// frames per atom
+    span: usize,
+    compensation: Option<Vec<Vec<f32>>>
+}
+
+/// a part's solve, kept for the rounds of `narrow_palette` to solve again,
+/// for `--verify-solution` and for `--dump-npy`
+struct Problem {
+    chunks: Chunks,
+    step: f32,
+    series: Option<Arc<Series>>
+}
+
+/// narrows the sound events the solve uses down to `max` across every part,
+/// like a group sparsity penalty over each event's pitches and ticks: every
+/// round drops the lightest events from the bases and solves again from
+/// where the last round left off, with a quarter of the iterations
+fn narrow_palette(
+    layout: &Layout,
+    bases: &mut [algebra::Basis],
+    problems: &[Option<Problem>],
+    solved: &mut [(Array2<f32>, Option<Vec<f32>>)],
+    max: usize,
+    args: &Args
+) -> Result<(), Error> {
+    let mut events = HashMap::new();
+    let groups = layout.sound_ids.iter()
+        .map(|(id, _)| {
+            let next = events.len();
+            *events.entry(id.as_str()).or_insert(next)
+        })
+        .collect::<Vec<usize>>();
+    let iters = (args.iters as usize / 4).max(1);
+
+    while !cancel::requested() {
+        let mut weights = vec![0.0; events.len()];
+        for (part, (h, _)) in layout.parts.iter().zip(solved.iter()) {
+            let part_groups = part.sounds.iter().map(|sound| groups[*sound]).collect::<Vec<usize>>();
+            algebra::group_weights(h.view(), &part_groups, &mut weights);
+        }
+
+        let Some(keep) = algebra::prune_groups(&weights, max) else {
+            break;
+        };
+        event!(Level::INFO, "narrowing down to {} of {} sound events", keep.iter().filter(|keep| **keep).count(), weights.iter().filter(|weight| **weight > 0.0).count());
+
+        for (((part, basis), problem), (h, _)) in layout.parts.iter().zip(bases.iter_mut()).zip(problems).zip(solved.iter_mut()) {
+            let dropped = part.sounds.iter().enumerate()
+                .filter(|(_, sound)| !keep[groups[**sound]])
+                .map(|(row, _)| row)
+                .collect::<Vec<usize>>();
+            basis.zero_columns(&dropped)?;
+            dropped.iter().for_each(|row| h.row_mut(*row).fill(0.0));
+
+            // problems are kept for this
+            let problem = problem.as_ref().unwrap();
+            let initial = std::mem::take(h);
+            (*h, _) = recorded(problem.series.as_ref(), || algebra::conv_pgd_nnls(problem.chunks.view(), basis, layout.span, initial, iters, problem.step, args.looping))?;
+            check_finite(h, &part.name, layout.frames, 0, args.non_finite)?;
+        }
+    }
+
+    return Ok(());
+}
+
+/// what the residual of a part's final solution on a device may be off by,
+/// relative to the one worked out in f64 on the CPU
+static SOLUTION_TOLERANCE: f64 = 1e-3;
+
+/// `--verify-solution`: the residual of every part's final solution from a
+/// device, worked out again in f64 on the CPU. a subtly broken driver gives
+/// plausible amplitudes whose residual is off from the device's own, or
+/// further from the input than silence
+fn verify_solution(layout: &Layout, bases: &[algebra::Basis], problems: &[Option<Problem>], solved: &[(Array2<f32>, Option<Vec<f32>>)], args: &Args) {
+    let span = layout.span;
+    let mut passed = true;
+
+    for (((part, basis), problem), (h, _)) in layout.parts.iter().zip(bases).zip(problems).zip(solved) {
+        let algebra::Basis::Device(basis) = basis else {
+            event!(Level::DEBUG, "the {} part was solved on the CPU, there's nothing to verify", part.name);
+            continue;
+        };
+
+        // problems are kept for this
+        let chunks = problem.as_ref().unwrap().chunks.view();
+        let residuals = basis.residual_norm(chunks.view(), h.view(), span, args.looping)
+            .and_then(|device| Ok((device as f64, algebra::residual_norm_f64(chunks.view(), basis.to_host()?.view(), h.view(), span, args.looping))));
+        let (device, host) = match residuals {
+            Ok(residuals) => residuals,
+            Err(e) => {
+                event!(Level::WARN, "could not verify the {} solution: '{}'", part.name, e);
+                continue;
+            }
+        };
+
+        let silence = chunks.iter().map(|value| (*value as f64).powi(2)).sum::<f64>().sqrt();
+        let deviation = (device - host).abs() / host.max(f64::MIN_POSITIVE);
+        event!(Level::DEBUG, "{} residual: {} on the device, {} in f64, {} for silence", part.name, device, host, silence);
+
+        if deviation.is_nan() || deviation > SOLUTION_TOLERANCE {
+            event!(Level::WARN, "the device's residual of the {} solution is {:e} off from the CPU's, over {:e}", part.name, deviation, SOLUTION_TOLERANCE);
+            passed = false;
+        }
+        if host > silence * (1.0 + SOLUTION_TOLERANCE) {
+            event!(Level::WARN, "the {} solution is further from the input than silence", part.name);
+            passed = false;
+        }
+    }
+
+    match passed {
+        true => event!(Level::INFO, "the solution checks out in f64"),
+        false => event!(Level::WARN, help = true, "check the device with `minecraft-player verify`, or solve on another one with `--devices`"),
+    }
+}
+
+/// `algebra::sanitize` of a part's solution of `frames` frames per tick,
+/// starting at tick `first_tick`, reporting the ticks that weren't finite.
+/// an error with `--non-finite abort`
+fn check_finite(h: &mut Array2<f32>, part: &str, frames: usize, first_tick: usize, non_finite: NonFinite) -> Result<(), Error> {
+    let columns = algebra::sanitize(h);
+    if columns.is_empty() {
+        return Ok(());
+    }
+
+    let mut ticks = columns.iter().map(|column| first_tick + column / frames).collect::<Vec<usize>>();
+    ticks.dedup();
+    for tick in &ticks {
+        event!(Level::DEBUG, "the {} solution isn't finite in tick {} ({})", part, tick, export::tick_time(*tick));
+    }
+
+    let first_few = ticks.iter()
+        .take(3)
+        .map(|tick| format!("{} ({})", tick, export::tick_time(*tick)))
+        .collect::<Vec<String>>();
+    let more = match ticks.len() > first_few.len() {
+        true => format!(" and {} more", ticks.len() - first_few.len()),
+        false => String::new(),
+    };
+
+    let (level, result) = match non_finite {
+        NonFinite::Zero => {
+            event!(Level::WARN, "the {} solution isn't finite in {} ticks, zeroed those values out: {}{}", part, ticks.len(), first_few.join(", "), more);
+            (Level::WARN, Ok(()))
+        },
+        NonFinite::Abort => {
+            event!(Level::ERROR, "the {} solution isn't finite in {} ticks: {}{}", part, ticks.len(), first_few.join(", "), more);
+            (Level::ERROR, Err(anyhow!("the {} solution isn't finite", part)))
+        },
+    };
+    logging::help_at!(level, "pass a smaller `--step`, or solve on another device with `--devices` and check this one with `minecraft-player verify`");
+    return result;
+}
+
+/// `--annotate`: how far off the solution of every tick is, relative to its
+/// input. the parts solve their own features of the same frames, so their
+/// errors add up. `None` when a basis can't be read back
+fn tick_residuals(layout: &Layout, bases: &[algebra::Basis], problems: &[Option<Problem>], solved: &[(Array2<f32>, Option<Vec<f32>>)], looping: bool) -> Option<Vec<f32>> {
+    let mut errors = vec![0.0f64; layout.n_ticks];
+    let mut norms = vec![0.0f64; layout.n_ticks];
+
+    for (((part, basis), problem), (h, _)) in layout.parts.iter().zip(bases).zip(problems).zip(solved) {
+        // problems are kept for this
+        let chunks = problem.as_ref().unwrap().chunks.view();
+        let basis = match basis.to_host() {
+            Ok(basis) => basis,
+            Err(e) => {
+                event!(Level::WARN, "could not work out the residuals of the {} solution: '{}'", part.name, e);
+                return None;
+            }
+        };
+
+        let frame_errors = algebra::tick_errors_f64(chunks.view(), basis.view(), h.view(), layout.span, looping);
+        for (frame, (error, column)) in frame_errors.iter().zip(chunks.columns()).enumerate() {
+            errors[frame / layout.frames] += error;
+            norms[frame / layout.frames] += column.iter().map(|value| (*value as f64).powi(2)).sum::<f64>();
+        }
+    }
+
+    // a silent tick has nothing to be off from
+    return Some(errors.iter().zip(&norms).map(|(error, norm)| if *norm > 0.0 { (error / norm).sqrt() as f32 } else { 0.0 }).collect());
+}
+
+/// the annotations comment every tick with its residual, and the webhook's
+/// summary then says how far off they are on average
+fn measures_residuals(args: &Args) -> bool {
+    return args.annotate;
+}
+
+/// `--dump-npy`: the final basis, chunks and solution of every part, for
+/// analysis in numpy. a part that can't be written is only warned about
+#[cfg(feature = "npy")]
+fn dump_npy(directory: &Path, layout: &Layout, bases: &[algebra::Basis], problems: &[Option<Problem>], solved: &[(Array2<f32>, Option<Vec<f32>>)]) {
+    for (((part, basis), problem), (h, _)) in layout.parts.iter().zip(bases).zip(problems).zip(solved) {
+        // problems are kept for this
+        let chunks = problem.as_ref().unwrap().chunks.view();
+        let dumped = basis.to_host()
+            .map_err(Error::from)
+            .and_then(|basis| Ok(npy::dump(directory, &part.name, basis.view(), chunks.view(), h.view(), &layout.sound_ids, &part.sounds)?));
+        if let Err(e) = dumped {
+            event!(Level::WARN, "could not write the {} matrices: '{}'", part.name, e);
+        }
+    }
+}
+
+/// runs `solve`, recording its objective after what `series` already holds
+/// for `--plot-convergence`
+fn recorded<T>(series: Option<&Arc<Series>>, solve: impl FnOnce() -> T) -> T {
+    match series {
+        Some(series) => convergence::record(series, solve),
+        None => solve(),
+    }
+}
+
+/// the raw solution of every part and how many iterations it got through,
+/// which is what gets resumed from
+struct Solved {
+    parts: Vec<(Array2<f32>, Option<Vec<f32>>)>,
+    completed: usize,
+    /// see `tick_residuals`, only worked out for `--annotate`
+    residuals: Option<Vec<f32>>
+}
+
+impl Solved {
+    /// the solutions stacked in part order
+    fn checkpoint(&self) -> Result<Checkpoint, Error> {
+        let views = self.parts.iter().map(|(h, _)| h.view()).collect::<Vec<_>>();
+        return Ok(Checkpoint { iterations: self.completed, h: ndarray::concatenate(Axis(0), &views)? });
+    }
+}
+
+/// songs solved one after the other (`serve`'s jobs, `--tune`'s runs),
+/// which keep the bases of the last one on the devices with what they were
+/// built from (`resident_key`). the next song with the same basis only
+/// uploads its own V and h. a single run has no batch, its bases are
+/// dropped once it's solved
+#[derive(Default)]
+struct Batch {
+    resident: Mutex<Option<(String, Vec<algebra::Basis>)>>,
+    ending: AtomicBool
+}
+
+impl Batch {
+    /// the resident bases when they were built from `key`. others are
+    /// dropped, so the devices have room for the ones about to be built
+    fn take(&self, key: &str) -> Option<Vec<algebra::Basis>> {
+        let (resident_key, bases) = self.resident.lock().unwrap().take()?;
+        return (resident_key == key).then_some(bases);
+    }
+
+    fn keep(&self, key: String, bases: Vec<algebra::Basis>) {
+        if !self.ending.load(Ordering::Relaxed) {
+            *self.resident.lock().unwrap() = Some((key, bases));
+        }
+    }
+
+    /// the next song is the last, there's nothing to keep its bases for
+    fn end(&self) {
+        self.ending.store(true, Ordering::Relaxed);
+    }
+}
+
+/// everything the bases of a run are built from: its sounds (see `stages`),
+/// how their features are made and scaled, the devices and which of the
+/// sounds every part has
+fn resident_key(args: &Args, stages: &Stages, parts: &[Part]) -> String {
+    let sounds = parts.iter()
+        .map(|part| {
+            let mut hasher = DefaultHasher::new();
+            part.sounds.hash(&mut hasher);
+            hasher.finish()
+        })
+        .collect::<Vec<u64>>();
+
+    let features = (args.features, args.focus, args.analysis_rate, args.frame_rate, args.normalization, &args.devices);
+    return format!("{} {:?} {:?}", stages.basis, features, sounds);
+}
+
+/// the amplitudes, the raw solution and the bases `solve_parts` is done with
+type SolvedParts = (Array2<f32>, Solved, Vec<algebra::Basis>);
+
+/// solves every part of `layout` with its basis, then merges the solutions
+/// into the amplitude of every sound in every tick. `chunks` makes the
+/// normalized chunks of a part, and their scales with per-tick
+/// normalization, when it's its turn. the bases are handed back for a
+/// `Batch` to keep. `None` when cancelled, with the progress saved as a
+/// checkpoint
+fn solve_parts(
+    args: &Args,
+    layout: &Layout,
+    mut bases: Vec<algebra::Basis>,
+    mut chunks: impl FnMut(usize, &mut Timing) -> Result<(Chunks, Option<Vec<f32>>), Error>,
+    timing: &mut Timing
+) -> Result<Option<SolvedParts>, Error> {
+    let checkpoint_path = checkpoint_path(args);
+    let n_frames = layout.n_ticks * layout.frames;
+    let rows = layout.parts.iter().map(|part| part.sounds.len()).sum::<usize>();
+
+    let iters = args.iters as usize;
+    let initial = match &args.resume {
+        Some(resume_path) => {
+            let checkpoint = Checkpoint::load(resume_path)?;
+            if checkpoint.h.dim() != (rows, n_frames) {
+                event!(Level::ERROR, "checkpoint does not match this input and basis");
+                event!(Level::ERROR, help = true, "resume with the same input, version and asset settings as the cancelled run");
+                return Err(anyhow!("checkpoint shape mismatch"));
+            }
+
+            event!(Level::INFO, "resuming from iteration {}", checkpoint.iterations);
+            checkpoint
+        },
+        None => Checkpoint {
+            iterations: 0,
+            h: Array2::zeros((rows, n_frames))
+        }
+    };
+
+    #[cfg(feature = "npy")]
+    let dump_directory = args.dump_npy.as_deref();
+    #[cfg(not(feature = "npy"))]
+    let dump_directory: Option<&Path> = None;
+
+    let remaining = iters - initial.iterations.min(iters);
+    let mut solved = Vec::with_capacity(layout.parts.len());
+    let mut problems = Vec::with_capacity(layout.parts.len());
+    let mut completed = remaining;
+    let mut recorded_series = Vec::new();
+    let mut offset = 0;
+
+    for (index, (part, basis)) in layout.parts.iter().zip(&bases).enumerate() {
+        let (chunks, tick_scales) = chunks(index, timing)?;
+        timing.start(Stage::Solve);
+
+        event!(Level::DEBUG, "{} chunks: {:?}", part.name, &chunks.view().dim());
+        event!(Level::DEBUG, "{} bins: {:?}", part.name, &basis.dim());
+        diagnostics::note(&format!("{} chunks", part.name), format!("{:?}", chunks.view().dim()));
+        diagnostics::note(&format!("{} basis", part.name), format!("{:?}", basis.dim()));
+
+        let step = step_size(args.step, basis, layout.span)?;
+
+        match layout.parts.len() {
+            1 => event!(Level::INFO, "running NNLS..."),
+            _ => event!(Level::INFO, "running NNLS on the {} part...", part.name)
+        }
+
+        let part_initial = initial.h.slice(s![offset..offset + part.sounds.len(), ..]).to_owned();
+        offset += part.sounds.len();
+
+        let series = args.plot_convergence.as_ref().map(|_| Series::new(&part.name));
+        if let Some(series) = &series {
+            recorded_series.push(series.clone());
+        }
+
+        cancel::set_checkpointable(true);
+        let (mut h, part_completed) = recorded(series.as_ref(), || algebra::conv_pgd_nnls(chunks.view(), basis, layout.span, part_initial, remaining, step, args.looping))?;
+        check_finite(&mut h, &part.name, layout.frames, 0, args.non_finite)?;
+        completed = completed.min(part_completed);
+        solved.push((h, tick_scales));
+
+        // the rounds narrowing the palette solve the same chunks again
+        let kept = (args.max_unique_sounds.is_some() || args.verify_solution || measures_residuals(args) || dump_directory.is_some())
+            .then_some(Problem { chunks, step, series });
+        problems.push(kept);
+    }
+
+    if let Some(max) = args.max_unique_sounds {
+        narrow_palette(layout, &mut bases, &problems, &mut solved, max as usize, args)?;
+    }
+
+    if args.verify_solution && !cancel::requested() {
+        verify_solution(layout, &bases, &problems, &solved, args);
+    }
+    let residuals = match measures_residuals(args) && !cancel::requested() {
+        true => tick_residuals(layout, &bases, &problems, &solved, args.looping),
+        false => None,
+    };
+    #[cfg(feature = "npy")]
+    if let Some(directory) = dump_directory.filter(|_| !cancel::requested()) {
+        dump_npy(directory, layout, &bases, &problems, &solved);
+    }
+    drop(problems);
+
+    let solved = Solved { parts: solved, completed: initial.iterations + completed, residuals };
+
+    if let Some(path) = &args.plot_convergence {
+        convergence::summarize(&recorded_series);
+        if let Err(e) = convergence::plot(path, &recorded_series) {
+            event!(Level::WARN, "could not plot the objective: '{}'", e);
+        }
+    }
+    drop(initial);
+
+    if cancel::requested() {
+        solved.checkpoint()?.save(&checkpoint_path)?;
+        event!(Level::WARN, "saved progress ({}/{} iterations) to `{}`", solved.completed, iters, checkpoint_path.to_string_lossy());
+        event!(Level::WARN, help = true, "rerun with `--resume {}` to continue", checkpoint_path.to_string_lossy());
+        return Ok(None);
+    }
+
+    // parts are merged by how loud they were, a single part is left as is
+    let loudest = layout.parts.iter().map(|part| part.loudness).fold(0.0, f32::max);
+
+    let mut approximation = Array2::<f32>::zeros((layout.sound_ids.len(), layout.n_ticks));
+    for (part, (h, tick_scales)) in layout.parts.iter().zip(&solved.parts) {
+        let weight = if loudest > 0.0 { part.loudness / loudest } else { 1.0 };
+
+        // frames are merged into the ticks they're in, scales and all
+        let quantized = layout.compensation.as_ref().map(|compensation| {
+            let rows = part.sounds.iter().map(|sound| compensation[*sound].as_slice()).collect::<Vec<&[f32]>>();
+            subtick::quantize(h.view(), layout.frames, tick_scales.as_deref(), &rows)
+        });
+        let (h, tick_scales) = match &quantized {
+            Some(quantized) => (quantized.view(), None),
+            None => (h.view(), tick_scales.as_deref()),
+        };
+        algebra::accumulate_rows(&mut approximation, h, &part.sounds, tick_scales, weight, part.budget);
+    }
+
+    algebra::normalize_to_global(&mut approximation);
+
+    timing.finish();
+    event!(Level::INFO, "done! elapsed: {}ms", timing.get(Stage::Solve).unwrap_or_default().as_millis());
+
+    return Ok(Some((approximation, solved, bases)));
+}
+
+/// everything that changes the solve, by the stage it changes, to tell cached
+/// schedules apart. export-only options (post-processing, emphasis, aliases,
+/// the preview) are left out, changing them only exports the schedule again
+fn stages(args: &Args, versions: &[Version], output_version: Option<&Version>, pack: Option<&Pack>) -> Stages {
+    let stems = args.stems.iter().map(|stem| (stem.name(), stem.filter, stem.budget)).collect::<Vec<_>>();
+    let versions = versions.iter().map(|version| &version.id).collect::<Vec<_>>();
+    let output_version = output_version.map(|version| (&version.id, args.exclude_changed));
+    let pack = pack.map(|pack| &pack.sha1);
+    let long_sounds = (!args.allow_long).then_some(args.long_sounds);
+    let basis = (versions, output_version, pack, args.musical, args.character.tonal_only, args.character.percussive_only, args.max_sound_ticks, long_sounds, args.trim_silence, args.atom_ticks);
+    let solve = (stems, args.hpss, args.analysis_rate, args.frame_rate, args.features, args.focus, args.iters, args.step, args.normalization, args.looping, args.max_unique_sounds);
+    return Stages {
+        basis: format!("{} {:?}", env!("CARGO_PKG_VERSION"), basis),
+        solve: format!("{:?}", solve),
+    };
+}
+
+/// `--checkpoint`, or the one in the assets
+fn checkpoint_path(args: &Args) -> PathBuf {
+    return args.checkpoint.clone().unwrap_or_else(|| args.assets.join(CHECKPOINT_FILE));
+}
+
+/// `--pack-format`, or the format of the version the datapack is played on.
+/// true if there's no telling which that is, see `Args::open_pack_format`
+fn pack_format(args: &Args, played_on: Option<&Version>) -> (u32, bool) {
+    if let Some(pack_format) = args.pack_format {
+        return (pack_format, false);
+    }
+
+    let known = played_on.and_then(|version| version.release_time.as_deref()).and_then(versions::pack_format);
+    if known.is_none() && args.format == Format::Datapack {
+        let version = played_on.map(|version| version.id.as_str()).unwrap_or("the output");
+        event!(Level::WARN, "no telling which data pack format {} takes, writing {} and up", version, versions::newest_pack_format());
+        event!(Level::WARN, help = true, "pass `--pack-format` if it doesn't load, or `-r` if the version manifest is old");
+    }
+    return match known {
+        Some(known) => (known, false),
+        None => (versions::newest_pack_format(), true),
+    };
+}
+
+/// the step size of `--step`, working out 1/L of the basis for `auto`
+fn step_size(step: StepSize, basis: &algebra::Basis, atom_ticks: usize) -> Result<f32, Error> {
+    return match step {
+        StepSize::Fixed(step) => Ok(step),
+        StepSize::Auto => {
+            let Some(step) = basis.auto_step(atom_ticks)? else {
+                return Err(anyhow!("basis is all zeros, cannot pick a step size"));
+            };
+
+            event!(Level::INFO, "using step size 1/{:.3}", 1.0 / step);
+            Ok(step)
+        }
+    };
+}
+
+/// a run split into chapters of `length` ticks that are solved one at a
+/// time, so only one chapter's chunks and solution are ever in memory.
+/// solutions are spilled to `spill_directory`, which doubles as a cache:
+/// chapters already there aren't solved again
+struct Chapters {
+    args: Args,
+    parts: Vec<Part>,
+    extractor: Box<dyn FeatureExtractor>,
+    processor: audio::Processor,
+    rows: usize,
+    n_ticks: usize,
+    length: usize,
+    spill_directory: PathBuf,
+    /// see `surround::directions`
+    directions: Option<Vec<f32>>,
+    /// see `loudness::sound_levels`
+    sound_levels: Option<Vec<f32>>,
+    /// the mixed input, for `--ab-preview`
+    original: Option<Vec<f32>>,
+    /// see `ExportDetails::aliases`
+    aliases: HashMap<String, String>
+}
+
+impl Chapters {
+    fn ranges(&self) -> Vec<Range<usize>> {
+        (0..self.n_ticks).step_by(self.length)
+            .map(|start| start..(start + self.length).min(self.n_ticks))
+            .collect()
+    }
+
+    fn spill_path(&self, ticks: &Range<usize>) -> PathBuf {
+        self.spill_directory.join(format!("{}-{}.bin", ticks.start, ticks.end))
+    }
+
+    fn features_path(&self, part: usize, ticks: &Range<usize>) -> PathBuf {
+        self.spill_directory.join(format!("features-{}-{}-{}.bin", part, ticks.start, ticks.end))
+    }
+
+    /// the features of every tick of a part in a chapter, a column each.
+    /// they're extracted once and kept next to the solutions until every
+    /// chapter is solved, since the bounds and the neighbouring chapters
+    /// need them too
+    fn chunks(&self, index: usize, part: &Part, ticks: &Range<usize>) -> Result<Array2<f32>, Error> {
+        let path = self.features_path(index, ticks);
+        if path.exists() {
+            return Ok(Checkpoint::load(&path)?.h);
+        }
+
+        let samples_per_tick = audio::time_as_samples!(self.args.analysis_rate, 50);
+        let chunks = audio::chunk_range(&part.audio, samples_per_tick, ticks.clone());
+        let chunks = self.extractor.extract_batch(&chunks, &self.processor);
+        let chunks = algebra::matrix_from_vecs(chunks)?.reversed_axes();
+        let features = Checkpoint { iterations: 0, h: chunks };
+        features.save(&path)?;
+        return Ok(features.h);
+    }
+
+    /// the ticks a chapter is solved over: its own and `atom_ticks - 1` of
+    /// each neighbour, so atoms starting in the chapter before can explain
+    /// its first ticks and atoms near its end are fit with their whole tails
+    fn overlapped(&self, ticks: &Range<usize>) -> Range<usize> {
+        let overlap = self.args.atom_ticks as usize - 1;
+        return ticks.start.saturating_sub(overlap)..(ticks.end + overlap).min(self.n_ticks);
+    }
+
+    /// the features of a part over `overlapped(ticks)`, from the chapters
+    /// they fall in
+    fn overlapped_chunks(&self, index: usize, part: &Part, ticks: &Range<usize>) -> Result<Array2<f32>, Error> {
+        let solved = self.overlapped(ticks);
+        let mut views = Vec::new();
+        let chunks = self.ranges().into_iter()
+            .filter(|chapter| chapter.start < solved.end && solved.start < chapter.end)
+            .map(|chapter| Ok::<_, Error>((self.chunks(index, part, &chapter)?, chapter)))
+            .collect::<Result<Vec<_>, Error>>()?;
+        for (chunks, chapter) in &chunks {
+            let start = solved.start.max(chapter.start) - chapter.start;
+            let end = solved.end.min(chapter.end) - chapter.start;
+            views.push(chunks.slice(s![.., start..end]));
+        }
+        return Ok(ndarray::concatenate(Axis(1), &views)?);
+    }
+
+    /// solves every chapter that isn't spilled yet. false if cancelled
+    fn solve(&self, bases: &[algebra::Basis], timing: &mut Timing) -> Result<bool, Error> {
+        let ranges = self.ranges();
+        let pending = ranges.iter().filter(|ticks| !self.spill_path(ticks).exists()).collect::<Vec<_>>();
+        if pending.len() < ranges.len() {
+            event!(Level::INFO, "reusing {} of {} chapters solved before", ranges.len() - pending.len(), ranges.len());
+        }
+        if pending.is_empty() {
+            return Ok(true);
+        }
+        std::fs::create_dir_all(&self.spill_directory)?;
+
+        // chunks are scaled by the bounds of the whole input, like they
+        // would be if solved at once. per-tick normalization needs none
+        timing.start(Stage::Features);
+        let bounds = match self.args.normalization {
+            Normalization::PerTick => vec![(0.0, 0.0); self.parts.len()],
+            _ => {
+                event!(Level::INFO, "measuring the input");
+                self.parts.iter().enumerate()
+                    .map(|(index, part)| {
+                        ranges.iter().try_fold((f32::INFINITY, f32::NEG_INFINITY), |(min_val, max_val), ticks| {
+                            let (chapter_min, chapter_max) = algebra::bounds(&self.chunks(index, part, ticks)?);
+                            Ok::<_, Error>((min_val.min(chapter_min), max_val.max(chapter_max)))
+                        })
+                    })
+                    .collect::<Result<Vec<(f32, f32)>, Error>>()?
+            }
+        };
+
+        let atom_ticks = self.args.atom_ticks as usize;
+        let steps = bases.iter()
+            .map(|basis| step_size(self.args.step, basis, atom_ticks))
+            .collect::<Result<Vec<f32>, Error>>()?;
+
+        for (index, ticks) in pending.into_iter().enumerate() {
+            event!(Level::INFO, "running NNLS on ticks {} to {} ({}/{})...", ticks.start, ticks.end, index + 1, ranges.len());
+
+            let mut solved = Vec::with_capacity(self.parts.len());
+            let mut completed = self.args.iters as usize;
+            let solved_ticks = self.overlapped(ticks);
+            let own = ticks.start - solved_ticks.start..ticks.end - solved_ticks.start;
+            for (index, (((part, basis), (min_val, max_val)), step)) in self.parts.iter().zip(bases).zip(&bounds).zip(&steps).enumerate() {
+                timing.start(Stage::Features);
+                let mut chunks = self.overlapped_chunks(index, part, ticks)?;
+
+                timing.start(Stage::Solve);
+                let tick_scales = match self.args.normalization {
+                    Normalization::MinusPlus => {
+                        algebra::scale_to_minus_plus(&mut chunks, *min_val, *max_val);
+                        None
+                    },
+                    Normalization::Global => {
+                        algebra::scale_to_global(&mut chunks, min_val.abs().max(max_val.abs()));
+                        None
+                    },
+                    Normalization::PerTick => Some(algebra::normalize_columns(&mut chunks)),
+                };
+
+                let initial = Array2::zeros((part.sounds.len(), solved_ticks.len()));
+                let (mut h, part_completed) = algebra::conv_pgd_nnls(chunks.view(), basis, atom_ticks, initial, self.args.iters as usize, *step, false)?;
+                check_finite(&mut h, &part.name, 1, solved_ticks.start, self.args.non_finite)?;
+                if let Some(tick_scales) = tick_scales {
+                    algebra::scale_columns(&mut h, &tick_scales);
+                }
+                completed = completed.min(part_completed);
+                // the overlap is only there to be fit, its neighbours keep it
+                solved.push(h.slice(s![.., own.clone()]).to_owned());
+            }
+
+            // a chapter is spilled whole or not at all
+            if cancel::requested() {
+                event!(Level::WARN, "cancelled while solving ticks {} to {}", ticks.start, ticks.end);
+                event!(Level::WARN, help = true, "rerun with the same input and settings to continue from there, solved chapters are kept");
+                return Ok(false);
+            }
+
+            let views = solved.iter().map(|h| h.view()).collect::<Vec<_>>();
+            Checkpoint { iterations: completed, h: ndarray::concatenate(Axis(0), &views)? }.save(&self.spill_path(ticks))?;
+        }
+
+        // the features are only kept for the chapters still to be solved
+        for index in 0..self.parts.len() {
+            for ticks in &ranges {
+                let path = self.features_path(index, ticks);
+                if path.exists() {
+                    std::fs::remove_file(path)?;
+                }
+            }
+        }
+
+        return Ok(true);
+    }
+
+    /// the schedule of a chapter, from its spilled solution. `peaks` are
+    /// the largest value of every part over all chapters
+    fn schedule(&self, ticks: &Range<usize>, peaks: &[f32], weights: &[f32]) -> Result<Array2<f32>, Error> {
+        let h = Checkpoint::load(&self.spill_path(ticks))?.h;
+
+        let mut schedule = Array2::<f32>::zeros((self.rows, ticks.len()));
+        let mut offset = 0;
+        for ((part, peak), weight) in self.parts.iter().zip(peaks).zip(weights) {
+            let part_h = h.slice(s![offset..offset + part.sounds.len(), ..]);
+            let weight = if *peak > 0.0 { weight / peak } else { *weight };
+            algebra::accumulate_rows_scaled(&mut schedule, part_h, &part.sounds, None, weight, part.budget);
+            offset += part.sounds.len();
+        }
+
+        return Ok(schedule);
+    }
+
+    /// exports every chapter in order, scaled like a schedule solved at once,
+    /// then the chapter functions and the index. `None` if cancelled
+    async fn export(&self, sound_ids: &[(String, f32)], sound_waveforms: Option<Vec<Vec<f32>>>, tick_budgets: &[usize], output: &Path) -> Result<Option<Exported>, Error> {
+        let ranges = self.ranges();
+
+        // parts are merged by how loud they were, a single part is left as is
+        let loudness = self.parts.iter().map(|part| rms(&part.audio.samples)).collect::<Vec<f32>>();
+        let loudest = loudness.iter().cloned().fold(0.0, f32::max);
+        let weights = loudness.iter().map(|loudness| if loudest > 0.0 { loudness / loudest } else { 1.0 }).collect::<Vec<f32>>();
+
+        // the volumes are relative to the loudest of everything, which
+        // takes a pass over the parts and one over their merged schedules
+        let mut peaks = vec![f32::NEG_INFINITY; self.parts.len()];
+        for ticks in &ranges {
+            let h = Checkpoint::load(&self.spill_path(ticks))?.h;
+            let mut offset = 0;
+            for (part, peak) in self.parts.iter().zip(&mut peaks) {
+                let part_h = h.slice(s![offset..offset + part.sounds.len(), ..]);
+                *peak = peak.max(algebra::scaled_peak(part_h, None));
+                offset += part.sounds.len();
+            }
+        }
+
+        let mut loudest = f32::NEG_INFINITY;
+        for ticks in &ranges {
+            let (_, max_val) = algebra::bounds(&self.schedule(ticks, &peaks, &weights)?);
+            loudest = loudest.max(max_val);
+        }
+        let loudest = if loudest > 0.0 { loudest } else { 1.0 };
+
+        // renormalizing takes the loudest shaped amplitude of every chapter,
+        // and the loudness every shaped tick
+        let mut post_process = post_process(&self.args);
+        let mut shaped_peak = f32::NEG_INFINITY;
+        let mut powers = Vec::new();
+        let target = self.args.target_loudness.zip(self.sound_levels.as_ref());
+        if post_process.renormalize || target.is_some() {
+            for ticks in &ranges {
+                let mut schedule = self.schedule(ticks, &peaks, &weights)?;
+                schedule.mapv_inplace(|val| val / loudest);
+                post_process.shape(&mut schedule);
+                shaped_peak = shaped_peak.max(algebra::bounds(&schedule).1);
+                if let Some((_, levels)) = target {
+                    powers.extend(loudness::tick_powers(schedule.view(), levels));
+                }
+            }
+        }
+        if let Some((target, _)) = target {
+            post_process.gain = target_gain(target, &powers, shaped_peak);
+        }
+
+        let details = ExportDetails { directions: self.directions.clone(), original: self.original.clone(), aliases: self.aliases.clone(), ..ExportDetails::default() };
+        let mut writer = TickWriter::new(&self.args, self.n_ticks, sound_ids, sound_waveforms, details)?;
+        for ticks in &ranges {
+            let mut schedule = self.schedule(ticks, &peaks, &weights)?;
+            schedule.mapv_inplace(|val| val / loudest);
+            post_process.shape(&mut schedule);
+            post_process.finish(&mut schedule, shaped_peak, ticks.start, self.n_ticks);
+            writer.split()?;
+
+            for (tick, amplitudes) in ticks.clone().zip(schedule.axis_iter(Axis(1))) {
+                if cancel::requested() {
+                    break;
+                }
+                writer.write(amplitudes, sound_ids, tick_budgets[tick])?;
+            }
+        }
+        let exported = writer.finish()?;
+
+        if cancel::requested() {
+            event!(Level::WARN, "export cancelled after {} of {} ticks", exported.ticks, self.n_ticks);
+            event!(Level::WARN, help = true, "rerun with the same input and settings to export again, solved chapters are kept");
+            return Ok(None);
+        }
+
+        let pack_format = self.args.pack_format.expect("the pack format is resolved before the solve");
+        let chapter_directory = export::chapter_directory(output, pack_format);
+        tokio::fs::create_dir_all(&chapter_directory).await?;
+        for (index, ticks) in ranges.iter().enumerate() {
+            tokio::fs::write(chapter_directory.join(index.to_string()).with_extension("mcfunction"), export::chapter_function(ticks.start)).await?;
+        }
+
+        let starts = ranges.iter().map(|ticks| ticks.start).collect::<Vec<usize>>();
+        tokio::fs::write(export::index_path(output, pack_format), export::index_function(&starts)).await?;
+        event!(Level::INFO, "wrote {} chapters, list them with `function {}:index`", ranges.len(), export::NAMESPACE);
+
+        return Ok(Some(exported));
+    }
+}
+
+/// what an export wrote, for the summary `--webhook-url` gets
+struct Exported {
+    ticks: usize,
+    /// commands written, after the inaudible ones were left out
+    commands: usize,
+    culled: usize,
+    /// the mean of the ticks' residuals, when they were worked out
+    residual: Option<f32>
+}
+
+/// what the export is told besides the schedule
+#[derive(Default)]
+struct ExportDetails {
+    /// see `tick_residuals`, when it was solved just now
+    residuals: Option<Vec<f32>>,
+    /// see `surround::directions`
+    directions: Option<Vec<f32>>,
+    /// see `loudness::sound_levels`, for `--target-loudness`
+    sound_levels: Option<Vec<f32>>,
+    /// the mixed input, for `--ab-preview`
+    original: Option<Vec<f32>>,
+    /// the ticks every sound spans, when it's the imported matrices' rather
+    /// than this run's `--atom-ticks`
+    atom_ticks: Option<usize>,
+    /// `--alias-map`, read once before the solve
+    aliases: HashMap<String, String>
+}
+
+/// tells what `--long-sounds` left out of the basis and how long they are,
+/// the longest few at info and every one of them at debug
+fn report_long(excluded: &[(String, Duration)], longest: f32) {
+    if excluded.is_empty() {
+        return;
+    }
+
+    for (event, duration) in excluded {
+        event!(Level::DEBUG, "left out `{}`, it plays for {:.1}s", event, duration.as_secs_f32());
+    }
+
+    let longest_few = excluded.iter()
+        .take(3)
+        .map(|(event, duration)| format!("{} ({:.0}s)", event, duration.as_secs_f32()))
+        .collect::<Vec<String>>();
+    let more = match excluded.len() > longest_few.len() {
+        true => format!(" and {} more", excluded.len() - longest_few.len()),
+        false => String::new(),
+    };
+    event!(
+        Level::INFO,
+        "left out {} sounds longer than {}s, their first tick isn't like the rest of them: {}{} (`--allow-long` keeps them)",
+        excluded.len(), longest, longest_few.join(", "), more
+    );
+}
+
+/// `--target-loudness`: the gain that brings a shaped schedule, whose ticks
+/// play at `powers` and whose loudest volume is `peak`, to `target`
+fn target_gain(target: f32, powers: &[f32], peak: f32) -> f32 {
+    let Some(loudness) = loudness::loudness(powers) else {
+        event!(Level::WARN, "the song is silent, it has no loudness to bring to {}dB", target);
+        return 1.0;
+    };
+
+    let gain = loudness::gain(loudness, target);
+    event!(Level::INFO, "the song plays at {:.1}dB, {:+.1}dB from the target", loudness, target - loudness);
+    if peak * gain > 1.0 {
+        event!(Level::WARN, "the loudest volumes are capped at 1, up to {:.1}dB short of the target", 20.0 * (peak * gain).log10());
+        event!(Level::WARN, help = true, "a lower `--gamma` brings the quiet volumes up instead, or aim for a lower `--target-loudness`");
+    }
+
+    return gain;
+}
+
+/// picks the sounds of every tick and hands them to the exporter of the
+/// format, and to the preview's when given the basis waveforms
+struct TickWriter {
+    n_ticks: usize,
+    exporters: Vec<Box<dyn Exporter>>,
+    selector: Selector,
+    aliases: HashMap<String, String>,
+    audibility: Option<Audibility>,
+    category: Category,
+    commands: usize,
+    culled: usize,
+    exported: usize,
+    /// see `Exported::residual`
+    residual: Option<f32>
+}
+
+impl TickWriter {
+    fn new(args: &Args, n_ticks: usize, sound_ids: &[(String, f32)], sound_waveforms: Option<Vec<Vec<f32>>>, details: ExportDetails) -> Result<Self, Error> {
+        let residual = details.residuals.as_ref()
+            .filter(|residuals| !residuals.is_empty())
+            .map(|residuals| residuals.iter().sum::<f32>() / residuals.len() as f32);
+        let song = Song {
+            output: args.output.clone().unwrap(),
+            n_ticks,
+            atom_ticks: details.atom_ticks.unwrap_or(args.atom_ticks as usize),
+            category: args.category,
+            anchor: args.anchor.clone(),
+            directions: details.directions,
+            looping: args.looping,
+            on_finish: args.on_finish.clone(),
+            duck: args.duck.clone(),
+            ticks_per_function: args.ticks_per_function as usize,
+            pack_format: args.pack_format.expect("the pack format is resolved before the export"),
+            open_format: args.open_pack_format,
+            annotate: args.annotate,
+            residuals: details.residuals
+        };
+        let mut exporters = vec![exporter::create(args.format, &song)?];
+
+        let samples_per_tick = audio::time_as_samples!(args.analysis_rate, 50);
+        // both previews render the same sounds from one copy of the waveforms
+        let waveforms = sound_waveforms.map(Arc::<[Vec<f32>]>::from);
+        if let (Some(output_path), Some(waveforms)) = (&args.reconstruction, waveforms.clone()) {
+            let file = std::io::BufWriter::new(std::fs::File::create(output_path)?);
+            let preview = Preview::new(file, args.analysis_rate as u32, args.preview_clipping, args.preview_dither)?.with_seed(args.seed);
+            let preview = match args.looping {
+                true => preview.with_seam(samples_per_tick * SEAM_TICKS),
+                false => preview,
+            };
+            let preview = match args.preview_markers {
+                true => preview.with_markers(Box::new(std::io::BufWriter::new(std::fs::File::create(output_path.with_extension("txt"))?))),
+                false => preview,
+            };
+            exporters.push(Box::new(Reconstruction::new(preview, waveforms, args.preview_model, args.preview_distance, samples_per_tick, song.atom_ticks)));
+        }
+        // without the loop's seam, which would put the two out of step
+        if let (Some(output_path), Some(waveforms), Some(original)) = (&args.ab_preview, waveforms, details.original) {
+            let file = std::io::BufWriter::new(std::fs::File::create(output_path)?);
+            let preview = Preview::ab(file, args.analysis_rate as u32, args.preview_clipping, args.preview_dither, original)?.with_seed(args.seed);
+            exporters.push(Box::new(Reconstruction::new(preview, waveforms, args.preview_model, args.preview_distance, samples_per_tick, song.atom_ticks)));
+        }
+
+        let aliases = details.aliases;
+        let mut unknown = aliases.keys().filter(|event| !sound_ids.iter().any(|(id, _)| id == *event)).map(String::as_str).collect::<Vec<&str>>();
+        if !unknown.is_empty() {
+            unknown.sort();
+            event!(Level::WARN, "{} aliased events aren't in the basis and never play: {}", unknown.len(), unknown.join(", "));
+            event!(Level::WARN, help = true, "the keys of `--alias-map` are the solved events, like `entity.cow.ambient`");
+        }
+
+        let audibility = args.audible_floor.map(|floor| Audibility::new(floor, args.preview_distance, args.category_volume));
+        return Ok(Self { n_ticks, exporters, selector: Selector::default(), aliases, audibility, category: args.category, commands: 0, culled: 0, exported: 0, residual });
+    }
+
+    /// exports the next tick
+    fn write(&mut self, amplitudes: ArrayView1<'_, f32>, sound_ids: &[(String, f32)], budget: usize) -> Result<(), Error> {
+        // aliases only change what plays in game, the preview goes by the basis index
+        let mut sounds = self.selector.select(amplitudes, sound_ids, budget).into_iter()
+            .map(|play| PlaySound { name: self.aliases.get(play.name).map(String::as_str).unwrap_or(play.name), ..play })
+            .collect::<Vec<PlaySound>>();
+
+        let selected = sounds.len();
+        if let Some(audibility) = &self.audibility {
+            sounds.retain(|play| audibility.is_audible(play.volume));
+        }
+        self.commands += selected;
+        self.culled += selected - sounds.len();
+
+        for exporter in &mut self.exporters {
+            exporter.write_tick(self.exported, &sounds)?;
+        }
+
+        self.exported += 1;
+        event!(Level::INFO, progress = true, tick = self.exported, ticks = self.n_ticks, "exporting");
+        return Ok(());
+    }
+
+    /// chapters call this where they start, so every chapter has a function
+    /// to jump to
+    fn split(&mut self) -> Result<(), Error> {
+        return self.exporters.iter_mut().try_for_each(|exporter| exporter.split());
+    }
+
+    /// finishes every exporter
+    fn finish(self) -> Result<Exported, Error> {
+        for exporter in self.exporters {
+            exporter.finish()?;
+        }
+
+        if let Some(audibility) = self.audibility {
+            event!(
+                Level::INFO, "left out {} of {} commands as inaudible {} blocks away at {:.0}% `{}` volume",
+                self.culled, self.commands, audibility.distance, audibility.slider * 100.0, self.category.as_str()
+            );
+        }
+
+        return Ok(Exported { ticks: self.exported, commands: self.commands - self.culled, culled: self.culled, residual: self.residual });
+    }
+}
+
+async fn save_timings(args: &Args, mut timing: Timing, exported: Option<Exported>) -> Result<(), Error> {
+    timing.finish();
+    timing.log_summary();
+    if webhook::enabled() {
+        webhook::send(summary(args, &timing, exported.as_ref()));
+    }
+
+    if let Some(timings_path) = &args.timings {
+        tokio::fs::write(timings_path, timing.to_json()?).await?;
+    }
+
+    return Ok(());
+}
+
+/// the message `--webhook-url` gets when a run finishes: how long it and
+/// each stage took, how many commands it plays, how far off the input
+/// they are and how large the output is
+fn summary(args: &Args, timing: &Timing, exported: Option<&Exported>) -> String {
+    let stages = timing.stages().map(|(stage, duration)| format!("{} {}", stage, webhook::format_duration(duration))).collect::<Vec<String>>();
+    let mut summary = format!("finished in {} ({})", webhook::format_duration(timing.total()), stages.join(", "));
+    if let Some(exported) = exported {
+        summary.push_str(&format!(", {} ticks playing {:.1} commands a tick", exported.ticks, exported.commands as f64 / exported.ticks.max(1) as f64));
+        if exported.culled > 0 {
+            summary.push_str(&format!(" ({} left out as inaudible)", exported.culled));
+        }
+        if let Some(residual) = exported.residual {
+            summary.push_str(&format!(", {:.1}% residual on average", residual * 100.0));
+        }
+    }
+
+    let output = args.output.as_ref().or(args.export_matrices.as_ref());
+    if let Some((output, size)) = output.and_then(|output| Some((output, webhook::size_on_disk(output).ok()?))) {
+        summary.push_str(&format!(", {:.1} MiB in `{}`", size as f64 / (1 << 20) as f64, output.to_string_lossy()));
+    }
+
+    return summary;
+}
+
+/// exports a finished solve in `--format` (and the preview, given the basis
+/// waveforms). `resumable` is saved if the export gets cancelled
+async fn export_schedule(args: &Args, mut schedule: Schedule, sound_waveforms: Option<Vec<Vec<f32>>>, tick_budgets: &[usize], resumable: Option<Checkpoint>, details: ExportDetails, mut timing: Timing) -> Result<(), Error> {
+    // `PostProcess::apply`, measuring the loudness once it's shaped
+    let mut post_process = post_process(args);
+    post_process.shape(&mut schedule.amplitudes);
+    let (_, peak) = algebra::bounds(&schedule.amplitudes);
+    if let (Some(target), Some(levels)) = (args.target_loudness, &details.sound_levels) {
+        post_process.gain = target_gain(target, &loudness::tick_powers(schedule.amplitudes.view(), levels), peak);
+    }
+    let n_ticks = schedule.amplitudes.dim().1;
+    post_process.finish(&mut schedule.amplitudes, peak, 0, n_ticks);
+
+    timing.start(Stage::Export);
+    event!(Level::INFO, "saving to {}...", value_name(&args.format));
+
+    let mut writer = TickWriter::new(args, n_ticks, &schedule.sound_ids, sound_waveforms, details)?;
+    for (index, amplitudes) in schedule.amplitudes.axis_iter(Axis(1)).enumerate() {
+        if cancel::requested() {
+            break;
+        }
+
+        writer.write(amplitudes, &schedule.sound_ids, tick_budgets[index])?;
+    }
+    let exported = writer.finish()?;
+
+    cancel::set_checkpointable(false);
+
+    if cancel::requested() {
+        // the solve itself finished, so resuming only redoes the export
+        match resumable {
+            Some(checkpoint) => {
+                let checkpoint_path = checkpoint_path(args);
+                checkpoint.save(&checkpoint_path)?;
+                event!(Level::WARN, "export cancelled after {} of {} ticks, saved solution to `{}`", exported.ticks, n_ticks, checkpoint_path.to_string_lossy());
+                event!(Level::WARN, help = true, "rerun with `--resume {}` to skip the solve", checkpoint_path.to_string_lossy());
+            },
+            None => {
+                event!(Level::WARN, "export cancelled after {} of {} ticks", exported.ticks, n_ticks);
+                event!(Level::WARN, help = true, "rerun with the same input and settings to skip the solve, its result is cached");
+            }
+        }
+        return Ok(());
+    }
+
+    return save_timings(args, timing, Some(exported)).await;
+}
+
+/// `--export-matrices`: the chunks and basis of every part, with what the
+/// solve and the export need besides, for `import_matrices` to solve on
+/// another machine
+fn export_matrices(
+    layout: Layout,
+    bases: &[algebra::Basis],
+    mut chunks: impl FnMut(usize, &mut Timing) -> Result<(Chunks, Option<Vec<f32>>), Error>,
+    tick_budgets: Vec<usize>,
+    availability: Option<(Availability, usize)>,
+    timing: &mut Timing
+) -> Result<Matrices, Error> {
+    let mut parts = Vec::with_capacity(layout.parts.len());
+    for (index, (part, basis)) in layout.parts.into_iter().zip(bases).enumerate() {
+        let (chunks, tick_scales) = chunks(index, timing)?;
+        parts.push(PartMatrices {
+            name: part.name,
+            sounds: part.sounds,
+            budget: part.budget,
+            loudness: part.loudness,
+            tick_scales,
+            chunks: chunks.into_owned(),
+            basis: basis.to_host()?
+        });
+    }
+
+    let (availability, versions) = availability.unwrap_or((Availability::new(), 1));
+    return Ok(Matrices {
+        sound_ids: layout.sound_ids,
+        n_ticks: layout.n_ticks,
+        frames: layout.frames,
+        span: layout.span,
+        tick_budgets,
+        compensation: layout.compensation,
+        versions,
+        availability,
+        parts
+    });
+}
+
+/// `--import-matrices`: solves and exports what `--export-matrices` wrote,
+/// likely on another machine. nothing is fetched and no input is read, both
+/// went into the matrices. the solve and export settings are this run's
+async fn import_matrices(args: &Args, directory: &Path, aliases: HashMap<String, String>, mut timing: Timing) -> Result<(), Error> {
+    event!(Level::INFO, "importing the matrices in `{}`", directory.to_string_lossy());
+    let matrices = Matrices::load(directory)?;
+
+    // only `--export-matrices` runs without an output
+    let output = args.output.as_deref().unwrap();
+    if matrices.versions > 1 && args.format == Format::Datapack {
+        export::write_availability(output, &matrices.availability, matrices.versions).await?;
+    }
+
+    let devices = algebra::select_devices(&args.devices)?;
+    if devices.len() > 1 {
+        event!(Level::INFO, "splitting the solve across {} devices", devices.len());
+    }
+    tune_devices(&devices, &args.assets, args.retune).await?;
+
+    timing.start(Stage::Features);
+    let mut parts = Vec::with_capacity(matrices.parts.len());
+    let mut bases = Vec::with_capacity(matrices.parts.len());
+    let mut chunks = Vec::with_capacity(matrices.parts.len());
+    for part in matrices.parts {
+        parts.push(PartLayout { name: part.name, sounds: part.sounds, budget: part.budget, loudness: part.loudness });
+        bases.push(algebra::Basis::from_array(part.basis, &devices)?);
+        chunks.push(Some((Chunks::Memory(part.chunks), part.tick_scales)));
+    }
+
+    let layout = Layout {
+        parts,
+        sound_ids: matrices.sound_ids,
+        n_ticks: matrices.n_ticks,
+        frames: matrices.frames,
+        span: matrices.span,
+        compensation: matrices.compensation
+    };
+    event!(Level::INFO, sounds = layout.sound_ids.len(), "basis has {} sounds", layout.sound_ids.len());
+
+    // every part's chunks are taken once, when it's solved
+    let solve_args = args.clone();
+    let (solved, layout, timing) = progress::solving(move || {
+        let solved = solve_parts(&solve_args, &layout, bases, |index, _| Ok(chunks[index].take().unwrap()), &mut timing);
+        (solved, layout, timing)
+    }).await;
+    let Some((amplitudes, solved, bases)) = solved? else {
+        return Ok(());
+    };
+    drop(bases);
+
+    // there's no input to cache the schedule by, a cancelled export is
+    // resumed from the solution instead
+    let resumable = solved.checkpoint()?;
+    let schedule = Schedule { sound_ids: layout.sound_ids, amplitudes };
+    let details = ExportDetails { residuals: solved.residuals, atom_ticks: Some(layout.span / layout.frames), aliases, ..ExportDetails::default() };
+    return export_schedule(args, schedule, None, &matrices.tick_budgets, Some(resumable), details, timing).await;
+}
+
+/// a single conversion, or one song of `batch`
+async fn run(args: Args, batch: Option<&Batch>) -> Result<(), Error> {
+    match &args.command {
+        Some(Command::Verify) => return verify_devices(&args.devices, &args.assets, args.retune, args.seed).await,
+        Some(Command::Versions { filter, releases }) => {
+            return list_versions(&args.assets, &fetch_options(&args), &args.manifest_url, filter.as_deref(), *releases).await;
+        },
+        Some(Command::Sounds { version }) => {
+            let options = fetch_options(&args);
+            let version = find_version(version, &args.assets, &options, &args.manifest_url, args.non_interactive, zips_to_stdout(&args)).await?;
+            return list_sounds(version, &args.assets, &options).await;
+        },
+        Some(Command::ExtractSound { version, id, pitch, ticks, output }) => {
+            let options = fetch_options(&args);
+            let version = find_version(version, &args.assets, &options, &args.manifest_url, args.non_interactive, zips_to_stdout(&args)).await?;
+            return extract_sound(version, &args.assets, &options, id, *pitch, *ticks, output).await;
+        },
+        #[cfg(feature = "serve")]
+        Some(Command::Serve { listen, max_queued, max_upload_mib, keep_minutes }) => {
+            let options = serve::ServeOptions {
+                listen: *listen,
+                directory: args.assets.join(SERVE_DIRECTORY),
+                max_queued: *max_queued,
+                max_upload: max_upload_mib << 20,
+                keep: Duration::from_secs(keep_minutes * 60)
+            };
+            return serve_jobs(&args, options).await;
+        },
+        None => {}
+    }
+
+    cancel::install();
+
+    // checked up front so a bad output path doesn't waste a whole solve
+    // only missing for subcommands, which have returned by now, and for
+    // `--export-matrices`, which doesn't solve
+    let output = args.output.as_deref();
+    if args.chapter_minutes.is_some() && args.format != Format::Datapack {
+        return Err(anyhow!("chapters are only written to datapacks"));
+    }
+    if args.anchor == Anchor::Player && args.preview_distance > 0.0 {
+        return Err(anyhow!("`--anchor player` plays the sounds where every player is, there's no `--preview-distance` to them"));
+    }
+    if args.annotate && args.format != Format::Datapack {
+        return Err(anyhow!("only datapacks are annotated, command blocks can't hold comments"));
+    }
+    if args.ticks_per_function > 1 && args.format != Format::Datapack {
+        return Err(anyhow!("only datapacks group ticks into functions, a structure already plays a row of command blocks a tick"));
+    }
+    if args.duck.contains(&args.category) {
+        return Err(anyhow!("`--duck` would stop the song itself, it plays in `{}`", args.category.as_str()));
+    }
+    // a sound spanning several columns is solved against all of them at
+    // once, which a scale per column would pull apart
+    let span = args.atom_ticks as usize * frames_per_tick(&args);
+    if matches!(args.normalization, Normalization::PerTick) && span > 1 {
+        event!(Level::ERROR, "per-tick normalization scales every column on its own, but the basis sounds span {} of them", span);
+        event!(Level::ERROR, help = true, "use `--normalization global`, or leave `--atom-ticks` and `--frame-rate` at their defaults");
+        return Err(anyhow!("per-tick normalization needs sounds spanning a single tick"));
+    }
+
+    if let Some(output) = output {
+        match args.format {
+            Format::Datapack => {
+                export::prepare_output(output, args.force).await?;
+            },
+            #[cfg(feature = "structure")]
+            Format::Structure => structure::prepare_output(output, args.force).await?,
+        }
+    }
+
+    let fetch_options = fetch_options(&args);
+
+    let mut timing = Timing::new();
+
+    // read before the solve so a broken file fails fast
+    let aliases = match &args.alias_map {
+        Some(path) => export::parse_aliases(&std::fs::read_to_string(path)?)?,
+        None => HashMap::new()
+    };
+    if !aliases.is_empty() {
+        event!(Level::INFO, "playing {} sound events under an alias", aliases.len());
+    }
+
+    if let Some(directory) = &args.import_matrices {
+        // without a version to go by
+        let (resolved, open_pack_format) = pack_format(&args, None);
+        let args = Args { pack_format: Some(resolved), open_pack_format, ..args.clone() };
+        return import_matrices(&args, directory, aliases, timing).await;
+    }
+
+    info!("loading predictable sounds");
+
+    timing.start(Stage::Fetch);
+    let version = find_version(&args.target_version, &args.assets, &fetch_options, &args.manifest_url, args.non_interactive, zips_to_stdout(&args)).await?;
+    let mut versions = vec![version];
+    for query in &args.extra_versions {
+        let extra = find_version(&Some(query.clone()), &args.assets, &fetch_options, &args.manifest_url, args.non_interactive, zips_to_stdout(&args)).await?;
+        if !versions.iter().any(|version| version.id == extra.id) {
+            versions.push(extra);
+        }
+    }
+    diagnostics::note("versions", versions.iter().map(|version| version.id.as_str()).collect::<Vec<&str>>().join(", "));
+
+    let pack = match &args.server_pack_url {
+        Some(url) => Some(pack::fetch(url, args.server_pack_sha1.as_deref(), &args.assets, &fetch_options).await?),
+        None => None
+    };
+    if let Some(pack) = &pack {
+        diagnostics::note("server pack", &pack.sha1);
+    }
+
+    // known before the solve, a cached schedule is exported without the sounds.
+    // exported matrices take it along to the machine that exports them
+    let mut fetched = FetchedVersions::new();
+    let event_availability = match versions.len() > 1 && (args.format == Format::Datapack || args.export_matrices.is_some()) {
+        true => {
+            let mut events = Vec::new();
+            for version in &versions {
+                events.push((version.id.clone(), fetch_event_sounds(version, &mut fetched, &args.assets, &fetch_options).await?.into_iter().collect()));
+            }
+            Some(versions::union(events).1)
+        },
+        false => None
+    };
+    if let (Some(output), Some(availability)) = (output, &event_availability) {
+        export::write_availability(output, availability, versions.len()).await?;
+    }
+
+    // nothing to check when the basis is the output version's own
+    let output_version = match &args.output_version {
+        Some(query) => Some(find_version(&Some(query.clone()), &args.assets, &fetch_options, &args.manifest_url, args.non_interactive, zips_to_stdout(&args)).await?)
+            .filter(|output_version| versions.len() > 1 || output_version.id != versions[0].id),
+        None => None
+    };
+    if let Some(output_version) = &output_version {
+        diagnostics::note("output version", &output_version.id);
+    }
+    let compatibility = match &output_version {
+        Some(output_version) => Some(check_compatibility(&versions, output_version, &args, &fetch_options, &mut fetched).await?),
+        None => None
+    };
+
+    // the datapack is laid out for the version it's played on
+    let played_on = output_version.as_ref().unwrap_or(&versions[0]);
+    let (resolved, open_pack_format) = pack_format(&args, Some(played_on));
+    let args = Args { pack_format: Some(resolved), open_pack_format, ..args.clone() };
+
+    // inputs are read up front, they identify the solve in the cache
+    let inputs = match &args.input {
+        Some(input) if args.surround => read_stereo(input, args.analysis_rate)?,
+        Some(input) => vec![read_input(input, args.analysis_rate, args.raw.zip(args.raw_rate))?],
+        None => args.stems.iter().map(|stem| read_input(&stem.path, args.analysis_rate, None)).collect::<Result<Vec<Sound>, Error>>()?
+    };
+    let inputs = match (args.start, args.duration) {
+        (None, None) => inputs,
+        (start, duration) => trim(inputs, start.unwrap_or(0.0), duration)?,
+    };
+    let inputs = match args.align_beats {
+        Some(subdivision) => align_beats(inputs, subdivision),
+        None => inputs
+    };
+    let inputs = match args.excerpt {
+        Some(seconds) => excerpt(inputs, seconds, audio::time_as_samples!(args.analysis_rate, 50)),
+        None => inputs
+    };
+    // both channels were cut the same way, only their mix is solved
+    let (inputs, directions) = match args.surround {
+        true => {
+            let directions = surround::directions(&inputs[0], &inputs[1], audio::time_as_samples!(args.analysis_rate, 50));
+            (vec![mix(&inputs)], Some(directions))
+        },
+        false => (inputs, None),
+    };
+
+    let stages = stages(&args, &versions, output_version.as_ref(), pack.as_ref());
+    let schedule_path = schedule::path(&args.assets, &schedule::fingerprint(&inputs, &stages.settings()));
+    let stages_path = schedule::stages_path(&args.assets, &inputs);
+    // chapters are cached one by one instead, exported matrices aren't solved
+    let mut cached = match (&args.resume, args.chapter_minutes, &args.export_matrices) {
+        (None, None, None) => Schedule::load(&schedule_path).ok(),
+        _ => None
+    };
+    if cached.is_none() && args.resume.is_none() && args.export_matrices.is_none() {
+        if let Some(stage) = Stages::load(&stages_path).and_then(|previous| stages.changed_since(&previous)) {
+            event!(Level::INFO, "the {} settings changed since this input was last solved, solving it again", stage);
+        }
+    }
+
+    let samples_per_tick = audio::time_as_samples!(args.analysis_rate, 50);
+    let n_ticks = inputs.iter().map(|input| input.samples.len().div_ceil(samples_per_tick)).max().unwrap_or(0);
+    diagnostics::note("ticks", n_ticks);
+
+    let frames = frames_per_tick(&args);
+    if !samples_per_tick.is_multiple_of(frames) {
+        event!(Level::ERROR, "a tick of {} samples can't be split into {} frames", samples_per_tick, frames);
+        event!(Level::ERROR, help = true, "pick a `--frame-rate` that divides a tick evenly at this `--analysis-rate`, or leave either at its default");
+        return Err(anyhow!("frame rate doesn't divide a tick"));
+    }
+
+    // stems each get their own share, separated parts compete for the same
+    let even_budget = (COMMANDS_PER_TICK / args.stems.len().max(1)).max(1);
+    let tick_budget = match args.stems.is_empty() {
+        true => COMMANDS_PER_TICK,
+        false => args.stems.iter().map(|stem| stem.budget.unwrap_or(even_budget)).sum()
+    };
+    // every sound is played from two emitters with `--surround`
+    let tick_budget = match args.surround {
+        true => (tick_budget / 2).max(1),
+        false => tick_budget
+    };
+
+    let tick_budgets = match &args.emphasis {
+        Some(emphasis) => {
+            let weights = match emphasis {
+                Emphasis::File(path) => emphasis::parse(&std::fs::read_to_string(path)?, n_ticks)?,
+                Emphasis::Auto => emphasis::from_loudness(&mix(&inputs).samples, samples_per_tick, n_ticks),
+            };
+            emphasis::budgets(&weights, tick_budget)
+        },
+        None => vec![tick_budget; n_ticks]
+    };
+
+    // the previews and `--target-loudness` need the basis waveforms, which
+    // only the full pipeline makes
+    if let Some(schedule) = cached.take_if(|_| args.reconstruction.is_none() && args.ab_preview.is_none() && args.target_loudness.is_none()) {
+        event!(Level::INFO, "reusing the schedule solved for this input and these settings, only exporting it again");
+        return export_schedule(&args, schedule, None, &tick_budgets, None, ExportDetails { directions, aliases, ..ExportDetails::default() }, timing).await;
+    }
+
+    let mut sets = Vec::new();
+    let mut durations = HashMap::new();
+    for version in versions {
+        timing.start(Stage::Fetch);
+        let id = version.id.clone();
+        let version_assets = match fetched.remove(&id) {
+            Some(version_assets) => version_assets,
+            None => assets::fetch_version_assets(&version, &args.assets, &fetch_options).await?,
+        };
+        let (sounds, version_durations) = assets::fetch_predictable_sounds(version, version_assets, pack.as_ref(), &args.assets, &fetch_options, args.analysis_rate, &mut timing).await?;
+        for (event, duration) in version_durations {
+            durations.entry(event).or_insert(duration);
+        }
+        sets.push((id, sounds));
+    }
+
+    let n_versions = sets.len();
+    let (mut predictable_sounds, availability) = versions::union(sets);
+    if n_versions > 1 {
+        let partial = availability.values().filter(|ids| ids.len() < n_versions).count();
+        event!(Level::INFO, "merged the sounds of {} versions, {} events are missing from some of them", n_versions, partial);
+    }
+
+    if let Some(compatibility) = &compatibility {
+        for (event, compatibility) in compatibility {
+            if let (versions::Compatibility::Renamed(name), Some(duration)) = (compatibility, durations.get(event).copied()) {
+                durations.entry(name.clone()).or_insert(duration);
+            }
+        }
+        predictable_sounds = versions::adapt(predictable_sounds, compatibility, args.exclude_changed);
+    }
+
+    event!(Level::INFO, sounds = predictable_sounds.len(), "found {} predictable sounds", predictable_sounds.len());
+
+    let total = predictable_sounds.len();
+    let predictable_sounds = audio::dedup(predictable_sounds);
+    if predictable_sounds.len() < total {
+        event!(Level::INFO, "dropped {} sounds that duplicate another", total - predictable_sounds.len());
+    }
+
+    let predictable_sounds = match args.allow_long {
+        true => predictable_sounds,
+        false => {
+            let (kept, excluded) = assets::exclude_long(predictable_sounds, &durations, Duration::from_secs_f32(args.long_sounds));
+            report_long(&excluded, args.long_sounds);
+            kept
+        },
+    };
+
+    let gathered = Gathered {
+        sounds: predictable_sounds,
+        durations,
+        availability: event_availability.map(|availability| (availability, n_versions)),
+        inputs,
+        directions,
+        stages,
+        schedule_path,
+        stages_path,
+        cached,
+        tick_budgets,
+        aliases,
+        devices: None
+    };
+    return convert(args, gathered, timing, batch).await;
+}
+
+/// what `run` gathers before it converts: the sounds the basis is built
+/// from, the input read and cut, and where its solve is cached
+struct Gathered {
+    sounds: Vec<(String, Sound)>,
+    durations: HashMap<String, Duration>,
+    /// see `versions::union`, with the number of versions, when there are several
+    availability: Option<(Availability, usize)>,
+    inputs: Vec<Sound>,
+    /// see `surround::directions`
+    directions: Option<Vec<f32>>,
+    stages: Stages,
+    schedule_path: PathBuf,
+    stages_path: PathBuf,
+    /// the schedule solved for this input and these settings before
+    cached: Option<Schedule>,
+    tick_budgets: Vec<usize>,
+    /// see `ExportDetails::aliases`
+    aliases: HashMap<String, String>,
+    /// picked with `--devices` when it's solved if `None`. without any,
+    /// the basis is kept on the host like for `--export-matrices`
+    devices: Option<Vec<ocl::Device>>
+}
+
+/// the rest of `run`: builds the basis from the gathered sounds, solves the
+/// input with it and exports the schedule
+async fn convert(args: Args, gathered: Gathered, mut timing: Timing, batch: Option<&Batch>) -> Result<(), Error> {
+    let Gathered { sounds: predictable_sounds, durations, availability, inputs, directions, stages, schedule_path, stages_path, cached, tick_budgets, aliases, devices } = gathered;
+    let output = args.output.as_deref();
+    let samples_per_tick = audio::time_as_samples!(args.analysis_rate, 50);
+    let frames = frames_per_tick(&args);
+    let samples_per_frame = samples_per_tick / frames;
+    let even_budget = (COMMANDS_PER_TICK / args.stems.len().max(1)).max(1);
+
+    let processor = match args.gpu_preprocess {
+        true => audio::Processor::new().with_gpu(),
+        false => audio::Processor::new()
+    };
+    let extractor = match args.focus {
+        Some(focus) => Box::new(Focused { inner: args.features.extractor(), focus }),
+        None => args.features.extractor()
+    };
+
+    timing.start(Stage::Permute);
+    let atom_ticks = args.atom_ticks as usize;
+    let atom_frames = atom_ticks * frames;
+    let mut predictable_sounds = predictable_sounds;
+    let (tonal_only, percussive_only) = (args.character.tonal_only, args.character.percussive_only);
+
+    // only analyzed when something needs it, it's an autocorrelation per
+    // sound. what's found is kept with the basis settings, only sounds new
+    // to them are analyzed again
+    let filters_stems = args.stems.iter().any(|stem| stem.filter != SoundFilter::All);
+    let characters = match args.musical || args.hpss || filters_stems || tonal_only || percussive_only {
+        true => {
+            let characters_path = schedule::characters_path(&args.assets, &format!("{} {}", stages.basis, args.analysis_rate));
+            let mut known = schedule::load_characters(&characters_path).unwrap_or_default();
+            let unknown = predictable_sounds.iter().filter(|(name, _)| !known.contains_key(name)).collect::<Vec<_>>();
+            if !unknown.is_empty() {
+                event!(Level::INFO, "estimating fundamentals");
+                let analyzed = unknown.par_iter()
+                    .map(|(name, sound)| (name.clone(), pitch::analyze(sound, &processor)))
+                    .collect::<Vec<(String, pitch::Character)>>();
+                for (name, character) in &analyzed {
+                    event!(Level::DEBUG, "{}: {:?}", name, character);
+                }
+
+                known.extend(analyzed);
+                if let Err(e) = schedule::save_characters(&characters_path, &known) {
+                    event!(Level::DEBUG, "could not keep the characters of the basis sounds: '{}'", e);
+                }
+            }
+            let characters = predictable_sounds.iter().map(|(name, _)| known[name]).collect::<Vec<pitch::Character>>();
+
+            let total = predictable_sounds.len();
+            let (kept_sounds, kept_characters): (Vec<_>, Vec<_>) = predictable_sounds.into_iter()
+                .zip(characters)
+                .filter(|(_, character)| match (tonal_only, percussive_only) {
+                    (true, _) => character.is_tonal(),
+                    (_, true) => !character.is_tonal(),
+                    _ => true
+                })
+                .unzip();
+
+            if tonal_only || percussive_only {
+                event!(Level::INFO, "kept {} of {} sounds", kept_sounds.len(), total);
+            }
+
+            let characters = kept_sounds.iter()
+                .map(|(name, _)| name.clone())
+                .zip(kept_characters)
+                .collect::<HashMap<String, pitch::Character>>();
+
+            predictable_sounds = kept_sounds;
+            Some(characters)
+        },
+        false => None
+    };
+
+    let sounds = match (args.musical, &characters) {
+        (true, Some(characters)) => {
+            event!(Level::INFO, "pitching sounds onto semitones");
+            let pitches = predictable_sounds.iter().map(|(name, _)| pitch::musical_pitches(characters[name].fundamental)).collect();
+            audio::permute_with(predictable_sounds, pitches, atom_ticks)
+        },
+        _ => audio::permute_with_pitch(predictable_sounds, 32, atom_ticks)
+    };
+
+    // playsound plays the whole sound while the basis only has its first
+    // tick, so long sounds can be left out instead of being cut every tick
+    let sounds = match args.max_sound_ticks {
+        Some(max_ticks) => {
+            let longest = Duration::from_millis(50 * max_ticks as u64);
+            let total = sounds.len();
+            let kept = sounds.into_iter()
+                .filter(|((name, pitch), _)| durations.get(name).is_some_and(|duration| duration.div_f32(*pitch) <= longest))
+                .collect::<Vec<_>>();
+            event!(Level::INFO, "kept {} of {} sounds at most {} ticks long", kept.len(), total, max_ticks);
+            kept
+        },
+        None => sounds
+    };
+    event!(Level::INFO, sounds = sounds.len(), "basis has {} sounds", sounds.len());
+
+    let sound_ids = sounds.iter().map(|s| s.0.clone()).collect::<Vec<(String, f32)>>();
+
+    // the loudness is measured by the levels of the sounds, not by their features
+    let sound_levels = args.target_loudness.map(|_| loudness::sound_levels(sounds.iter().map(|s| s.1.samples.as_slice())));
+
+    // the preview is rendered from the raw waveforms, features aren't necessarily audio
+    let sound_waveforms = match args.reconstruction.is_some() || args.ab_preview.is_some() {
+        true => {
+            event!(Level::WARN, "keeping basis waveforms for usage in later reconstruction, which will spike memory");
+            event!(Level::WARN, "if this crashes, disable reconstruction");
+            Some(sounds.iter().map(|s| s.1.samples.clone()).collect::<Vec<Vec<f32>>>())
+        },
+        false => None
+    };
+
+    // only the basis is trimmed, the game still plays the silence, which
+    // the previews and levels above have to hear too. at most a tick, so
+    // a sound still starts in the tick it's played in
+    let sounds = match args.trim_silence {
+        Some(threshold) => {
+            let samples_per_tick = audio::time_as_samples!(args.analysis_rate, 50);
+            let mut trimmed = 0;
+            let sounds = sounds.into_iter()
+                .map(|(id, mut sound)| {
+                    let length = sound.samples.len();
+                    sound.trim_leading_silence(threshold, samples_per_tick);
+                    trimmed += (sound.samples.len() < length) as usize;
+                    (id, sound)
+                })
+                .collect::<Vec<_>>();
+            event!(Level::INFO, "trimmed the silence {} sounds start with", trimmed);
+            sounds
+        },
+        None => sounds,
+    };
+
+    timing.start(Stage::Chunking);
+
+    // `characters` is filled in whenever a filter other than `All` is used
+    let subset = |filter: SoundFilter| (0..sound_ids.len())
+        .filter(|i| filter.allows(characters.as_ref().map(|characters| &characters[&sound_ids[*i].0])))
+        .collect::<Vec<usize>>();
+
+    // stems are heard together, like in the reconstruction
+    let original = args.ab_preview.as_ref().map(|_| mix(&inputs).samples);
+    let mut inputs = inputs.into_iter();
+    let parts = match (&args.input, args.hpss) {
+        (None, _) => {
+            args.stems.iter()
+                .zip(inputs)
+                .map(|(stem, audio)| Part {
+                    name: stem.name(),
+                    audio,
+                    sounds: subset(stem.filter),
+                    budget: stem.budget.unwrap_or(even_budget)
+                })
+                .collect::<Vec<Part>>()
+        },
+        (Some(_), true) => {
+            let target_audio = inputs.next().unwrap();
+
+            event!(Level::INFO, "separating harmonic and percussive parts");
+            let (harmonic, percussive) = separate::hpss(&target_audio, &processor);
+            drop(target_audio);
+
+            vec![
+                Part { name: "harmonic".to_string(), audio: harmonic, sounds: subset(SoundFilter::Tonal), budget: COMMANDS_PER_TICK },
+                Part { name: "percussive".to_string(), audio: percussive, sounds: subset(SoundFilter::Percussive), budget: COMMANDS_PER_TICK }
+            ]
+        },
+        (Some(_), false) => vec![Part {
+            name: "input".to_string(),
+            audio: inputs.next().unwrap(),
+            sounds: subset(SoundFilter::All),
+            budget: COMMANDS_PER_TICK
+        }]
+    };
+
+    let parts = parts.into_iter()
+        .filter(|part| {
+            if part.sounds.is_empty() {
+                event!(Level::WARN, "no sounds to solve the {} part with, skipping it", part.name);
+            }
+            !part.sounds.is_empty()
+        })
+        .collect::<Vec<Part>>();
+
+    if parts.is_empty() {
+        return Err(anyhow!("no sounds left to solve with"));
+    }
+
+    let (schedule, resumable, residuals) = match cached {
+        Some(schedule) if schedule.sound_ids == sound_ids => {
+            event!(Level::INFO, "reusing the schedule solved for this input and these settings");
+            if args.annotate {
+                event!(Level::DEBUG, "the residuals aren't cached, the annotations leave them out");
+            }
+            (schedule, None, None)
+        },
+        _ => {
+            // picked before the expensive part so a wrong `--devices` fails fast
+            // exported matrices are built on the host, for a machine without devices
+            let devices = match (&args.export_matrices, devices) {
+                (Some(_), _) => Vec::new(),
+                (None, Some(devices)) => devices,
+                (None, None) => {
+                    let devices = algebra::select_devices(&args.devices)?;
+                    if devices.len() > 1 {
+                        event!(Level::INFO, "splitting the solve across {} devices", devices.len());
+                    }
+                    tune_devices(&devices, &args.assets, args.retune).await?;
+                    devices
+                }
+            };
+
+            timing.start(Stage::Features);
+            let sounds = sounds.into_iter().map(|(_, sound)| sound).collect::<Vec<Sound>>();
+
+            // what every offset into a tick is worth at its start, to merge frames with
+            let compensation = (frames > 1).then(|| sounds.par_iter()
+                .map(|sound| subtick::decay_compensation(&sound.samples, samples_per_frame, frames, atom_frames))
+                .collect::<Vec<Vec<f32>>>());
+
+            // exported matrices are built on the host, there's nothing to keep resident
+            let resident = batch.filter(|_| args.export_matrices.is_none()).map(|batch| (batch, resident_key(&args, &stages, &parts)));
+            let bases = match resident.as_ref().and_then(|(batch, key)| batch.take(key)) {
+                Some(bases) => {
+                    event!(Level::INFO, "reusing the basis the last song left on the devices");
+                    bases
+                },
+                None => {
+                    // features are extracted a block at a time and uploaded as they're made,
+                    // so the full basis matrix never sits in host memory
+                    // blocks are made again if the basis has to fall back to the host
+                    let bounds = Cell::new((f32::INFINITY, f32::NEG_INFINITY));
+                    let mut bases = Vec::with_capacity(parts.len());
+                    for part in &parts {
+                        let (bounds, sounds, extractor, processor) = (&bounds, &sounds, &extractor, &processor);
+                        let blocks = || part.sounds.chunks(BASIS_BLOCK).map(move |indices| {
+                            let batch = indices.iter().map(|i| sounds[*i].clone()).collect::<Vec<Sound>>();
+                            let features = features::atom_features(&batch, atom_frames, samples_per_frame, |batch| extractor.extract_batch(batch, processor));
+                            let (mut basis_min, mut basis_max) = bounds.get();
+                            for value in features.iter().flatten() {
+                                basis_min = basis_min.min(*value);
+                                basis_max = basis_max.max(*value);
+                            }
+                            bounds.set((basis_min, basis_max));
+                            features
+                        });
+
+                        bases.push(match devices.is_empty() {
+                            true => algebra::Basis::host(blocks(), part.sounds.len()),
+                            false => algebra::Basis::upload(blocks, part.sounds.len(), &devices)?,
+                        });
+                    }
+                    let (basis_min, basis_max) = bounds.get();
+
+                    // every part is scaled the same way, so their solutions stay comparable
+                    for basis in &mut bases {
+                        match args.normalization {
+                            Normalization::MinusPlus => basis.normalize_to_minus_plus(basis_min, basis_max)?,
+                            Normalization::Global | Normalization::PerTick => basis.normalize_to_global(basis_min, basis_max)?,
+                        }
+                    }
+                    bases
+                },
+            };
+            drop(sounds);
+
+            let n_ticks = parts.iter().map(|part| part.audio.samples.len().div_ceil(samples_per_tick)).max().unwrap_or(0);
+            let n_frames = n_ticks * frames;
+            let rows = parts.iter().map(|part| part.sounds.len()).sum::<usize>();
+
+            if let Some(minutes) = args.chapter_minutes {
+                let chapters = Chapters {
+                    args: args.clone(),
+                    parts,
+                    extractor,
+                    processor,
+                    rows,
+                    n_ticks,
+                    length: minutes as usize * 60 * 20,
+                    // next to where the whole schedule would be cached
+                    spill_directory: schedule_path.with_extension(""),
+                    directions,
+                    sound_levels,
+                    original,
+                    aliases
+                };
+
+                cancel::set_checkpointable(true);
+                let (finished, chapters, bases, solve_timing) = progress::solving(move || {
+                    let finished = chapters.solve(&bases, &mut timing);
+                    (finished, chapters, bases, timing)
+                }).await;
+                timing = solve_timing;
+                let finished = finished?;
+                match resident {
+                    Some((batch, key)) => batch.keep(key, bases),
+                    None => drop(bases),
+                }
+                if !finished {
+                    return Ok(());
+                }
+
+                timing.start(Stage::Export);
+                event!(Level::INFO, "saving to datapack...");
+                // chapters can't be exported as matrices, so there's an output
+                let Some(exported) = chapters.export(&sound_ids, sound_waveforms, &tick_budgets, output.unwrap()).await? else {
+                    return Ok(());
+                };
+                cancel::set_checkpointable(false);
+
+                return save_timings(&args, timing, Some(exported)).await;
+            }
+
+            let layout = Layout {
+                parts: parts.iter()
+                    .map(|part| PartLayout { name: part.name.clone(), sounds: part.sounds.clone(), budget: part.budget, loudness: rms(&part.audio.samples) })
+                    .collect(),
+                sound_ids: sound_ids.clone(),
+                n_ticks,
+                frames,
+                span: atom_frames,
+                compensation
+            };
+
+            // a part's chunks are only made when it's its turn to be solved,
+            // on the solve's thread
+            let (solve_args, chunks_path) = (args.clone(), schedule_path.clone());
+            let chunks = move |index: usize, timing: &mut Timing| -> Result<(Chunks, Option<Vec<f32>>), Error> {
+                let (args, schedule_path) = (&solve_args, &chunks_path);
+                let mut chunks = match args.map_chunks {
+                    true => {
+                        // chunked and featured a block of frames at a time
+                        timing.start(Stage::Features);
+                        let path = schedule_path.with_extension(format!("{}.chunks", index));
+                        let mapped = MappedChunks::build(&path, n_frames, |frames| {
+                            extractor.extract_batch(&audio::chunk_range(&parts[index].audio, samples_per_frame, frames), &processor)
+                        })?;
+                        event!(Level::DEBUG, "mapped the {} chunks to `{}`", parts[index].name, path.to_string_lossy());
+                        Chunks::Mapped(mapped)
+                    },
+                    false => {
+                        timing.start(Stage::Chunking);
+                        let chunks = audio::chunk_ticks(&parts[index].audio, samples_per_frame, n_frames);
+
+                        timing.start(Stage::Features);
+                        let chunks = extractor.extract_batch(&chunks, &processor);
+                        Chunks::Memory(algebra::matrix_from_vecs(chunks)?.reversed_axes())
+                    },
+                };
+
+                // only per-tick normalization has to be undone after the solve
+                let mut view = chunks.view_mut();
+                let tick_scales = match args.normalization {
+                    Normalization::MinusPlus => {
+                        algebra::normalize_to_minus_plus(&mut view);
+                        None
+                    },
+                    Normalization::Global => {
+                        algebra::normalize_to_global(&mut view);
+                        None
+                    },
+                    Normalization::PerTick => Some(algebra::normalize_columns(&mut view)),
+                };
+
+                return Ok((chunks, tick_scales));
+            };
+
+            if let Some(directory) = &args.export_matrices {
+                let matrices = export_matrices(layout, &bases, chunks, tick_budgets, availability, &mut timing)?;
+
+                timing.start(Stage::Export);
+                matrices.save(directory)?;
+                event!(Level::INFO, "wrote the matrices to `{}`", directory.to_string_lossy());
+                event!(Level::INFO, help = true, "solve them with `--import-matrices {}`, here or on another machine", directory.to_string_lossy());
+                return save_timings(&args, timing, None).await;
+            }
+
+            let solve_args = args.clone();
+            let (solved, solve_timing) = progress::solving(move || {
+                let solved = solve_parts(&solve_args, &layout, bases, chunks, &mut timing);
+                (solved, timing)
+            }).await;
+            timing = solve_timing;
+            let Some((amplitudes, solved, bases)) = solved? else {
+                return Ok(());
+            };
+            // narrowing the palette zeroed sounds out of the bases
+            match resident.filter(|_| args.max_unique_sounds.is_none()) {
+                Some((batch, key)) => batch.keep(key, bases),
+                None => drop(bases),
+            }
+
+            // the export can be redone from the cached schedule, a checkpoint is
+            // only needed when that couldn't be saved
+            let schedule = Schedule { sound_ids, amplitudes };
+            let resumable = match schedule.save(&schedule_path) {
+                Ok(()) => None,
+                Err(e) => {
+                    event!(Level::WARN, "could not cache the schedule: '{}'", e);
+                    Some(solved.checkpoint()?)
+                }
+            };
+            // only to say what changed on the next run
+            if let Err(e) = stages.save(&stages_path) {
+                event!(Level::DEBUG, "could not record the settings of the stages: '{}'", e);
+            }
+            (schedule, resumable, solved.residuals)
+        }
+    };
+
+    return export_schedule(&args, schedule, sound_waveforms, &tick_budgets, resumable, ExportDetails { residuals, directions, sound_levels, original, atom_ticks: None, aliases }, timing).await;
+}
+
+fn value_name<T: clap::ValueEnum>(value: &T) -> String {
+    value.to_possible_value().map(|value| value.get_name().to_string()).unwrap_or_default()
+}
+
+fn select_value<T: clap::ValueEnum + Clone>(message: &str) -> Result<T, Error> {
+    let names = T::value_variants().iter().map(value_name).collect::<Vec<String>>();
+    let index = Select::new(message, names).raw_prompt()?.index;
+    return Ok(T::value_variants()[index].clone());
+}
+
+/// asks what to change before the next try of `--tune`. false once the
+/// settings are good as they are
+fn adjust(args: &mut Args) -> Result<bool, Error> {
+    loop {
+        let sounds = match (args.character.tonal_only, args.character.percussive_only) {
+            (true, _) => "tonal",
+            (_, true) => "percussive",
+            _ => "all"
+        };
+        let options = vec![
+            "try again".to_string(),
+            format!("sparsity (amplitudes below {:e} are dropped)", args.epsilon),
+            format!("weighting (matched in {})", value_name(&args.features)),
+            format!("normalization ({})", value_name(&args.normalization)),
+            format!("sounds ({}{})", sounds, if args.musical { ", on semitones" } else { "" }),
+            "solve the whole song with these".to_string(),
+        ];
+
+        match Select::new("what next?", options).raw_prompt()?.index {
+            0 => return Ok(true),
+            1 => {
+                let epsilon = CustomType::<f32>::new("drop amplitudes below")
+                    .with_default(args.epsilon)
+                    .with_help_message("higher is sparser: fewer, louder sounds")
+                    .prompt()?;
+                args.epsilon = epsilon.max(0.0);
+            },
+            2 => args.features = select_value("match basis and input in")?,
+            3 => args.normalization = select_value("scale basis and input by")?,
+            4 => {
+                let filter = Select::new("solve with", vec!["all sounds", "tonal sounds", "percussive sounds"]).raw_prompt()?.index;
+                (args.character.tonal_only, args.character.percussive_only) = (filter == 1, filter == 2);
+                args.musical = Confirm::new("pitch sounds onto semitones only?").with_default(args.musical).prompt()?;
+            },
+            _ => return Ok(false),
+        }
+    }
+}
+
+/// `serve`: every job is converted like `-i` would, into a datapack, with
+/// the settings the server was started with. anything a run writes outside
+/// of the assets' caches goes in the job's directory
+#[cfg(feature = "serve")]
+async fn serve_jobs(args: &Args, options: serve::ServeOptions) -> Result<(), Error> {
+    cancel::install();
+
+    // every job is the next song of the server's batch
+    let batch = Batch::default();
+    let batch = &batch;
+    let convert = |files: serve::JobFiles| {
+        // the job this server was stopped in
+        let resume = files.checkpoint.exists().then(|| files.checkpoint.clone());
+
+        // boxed, a job's `run` would otherwise hold this one. every file a
+        // run writes besides the job's own output is left out, the server's
+        // settings would have every job write over the same one
+        Box::pin(run(Args {
+            command: None,
+            input: Some(files.input),
+            raw: None,
+            raw_rate: None,
+            stems: Vec::new(),
+            output: Some(files.output),
+            output_zip: None,
+            format: Format::Datapack,
+            force: true,
+            non_interactive: true,
+            reconstruction: None,
+            ab_preview: None,
+            preview_markers: false,
+            log_file: None,
+            diagnostics: None,
+            plot_convergence: None,
+            #[cfg(feature = "npy")]
+            dump_npy: None,
+            timings: None,
+            resume,
+            checkpoint: Some(files.checkpoint),
+            export_matrices: None,
+            import_matrices: None,
+            chapter_minutes: None,
+            tune: false,
+            ..args.clone()
+        }, Some(batch)))
+    };
+    serve::serve(options, convert, cancel::requested).await?;
+    return Ok(());
+}
+
+/// `--output-zip`: runs with the datapack written to a directory of its own,
+/// then zips it into the file or onto stdout. a cancelled run isn't zipped
+async fn run_zipped(args: Args) -> Result<(), Error> {
+    let zip = args.output_zip.clone().unwrap();
+    if args.format != Format::Datapack {
+        return Err(anyhow!("only datapacks are zipped, `--format structure` is a single file already"));
+    }
+    // checked up front so a bad output path doesn't waste a whole solve
+    let to_stdout = zip == Path::new("-");
+    if !to_stdout && zip.exists() && !args.force {
+        return Err(export::ExportError::Exists(zip).into());
+    }
+
+    let directory = args.assets.join(ZIP_DIRECTORY).join(std::process::id().to_string());
+    let result = run(Args { output: Some(directory.clone()), force: true, ..args }, None).await;
+
+    let result = match result {
+        Ok(()) if cancel::requested() => Ok(()),
+        Ok(()) if to_stdout => {
+            let zip = export::zip_directory(&directory, std::io::Cursor::new(Vec::new()))?.into_inner();
+            let mut stdout = std::io::stdout().lock();
+            stdout.write_all(&zip)?;
+            stdout.flush()?;
+            event!(Level::INFO, "wrote the datapack to stdout as a zip");
+            Ok(())
+        },
+        Ok(()) => {
+            export::zip_directory(&directory, std::fs::File::create(&zip)?)?;
+            event!(Level::INFO, "zipped the datapack to `{}`", zip.to_string_lossy());
+            Ok(())
+        },
+        Err(error) => Err(error),
+    };
+
+    if let Err(error) = std::fs::remove_dir_all(&directory) {
+        event!(Level::WARN, "couldn't remove `{}`: {}", directory.to_string_lossy(), error);
+    }
+    return result;
+}
+
+/// `--tune`: solves the excerpt again and again as the settings are
+/// adjusted, with a preview of every try, then the whole song with the
+/// settings it was left at. tries are cached like any solve, so going back
+/// to earlier settings is quick
+async fn tune(mut args: Args) -> Result<(), Error> {
+    let tune_directory = args.assets.join(TUNE_DIRECTORY);
+    if args.input.as_deref() == Some(Path::new("-")) {
+        return Err(anyhow!("`--tune` reads the input again for every try, it can't come from stdin"));
+    }
+
+    // asked once rather than before every try
+    if args.target_version.is_none() {
+        let version = find_version(&None, &args.assets, &fetch_options(&args), &args.manifest_url, false, zips_to_stdout(&args)).await?;
+        args.target_version = Some(version.id);
+    }
+
+    // every try solves with the same basis, unless its settings change
+    let batch = Batch::default();
+    for attempt in 1.. {
+        let preview = tune_directory.join(format!("excerpt-{}.wav", attempt));
+        tokio::fs::create_dir_all(&tune_directory).await?;
+
+        run(Args {
+            output: Some(tune_directory.join("datapack")),
+            format: Format::Datapack,
+            force: true,
+            reconstruction: Some(preview.clone()),
+            timings: None,
+            tune: false,
+            ..args.clone()
+        }, Some(&batch)).await?;
+        if cancel::requested() {
+            return Ok(());
+        }
+
+        event!(Level::INFO, "listen to the excerpt in `{}`", preview.to_string_lossy());
+        if !adjust(&mut args)? {
+            break;
+        }
+    }
+
+    event!(Level::INFO, "solving the whole song with these settings");
+    batch.end();
+    return run(Args { excerpt: None, tune: false, ..args }, Some(&batch)).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// a song made of the basis' own sounds (a pluck, a lower pluck and a
+    /// noise burst, at every pitch the basis has them at), `notes` being the
+    /// `(tick, sound, volume)` of every one, converted like the cli converts
+    /// a song, with the basis on the host. the ticks are the `budget`
+    /// loudest sounds of the schedule it cached, loudest first
+    fn synthetic_song(features: &str, notes: &[(usize, usize, f32)], n_ticks: usize, budget: usize) -> Vec<Vec<(usize, f32)>> {
+        let directory = std::env::temp_dir().join(format!("minecraft-player-song-{}-{}", std::process::id(), features));
+        let _ = std::fs::remove_dir_all(&directory);
+        let output = directory.join("datapack");
+        let args = Args::parse_from(["minecraft-player", "-i", "song.wav", "-o", output.to_str().unwrap(), "-a", directory.to_str().unwrap(), "--features", features, "--iters", "512", "--step", "auto", "--pack-format", "48"]);
+
+        let (rate, samples_per_tick) = (args.analysis_rate, audio::time_as_samples!(args.analysis_rate, 50));
+        let decay = |index: usize| (-(index as f32) / 400.0).exp();
+        let tone = |hz: f32| Sound {
+            samples: (0..samples_per_tick).map(|index| (2.0 * std::f32::consts::PI * hz * index as f32 / rate as f32).sin() * decay(index)).collect(),
+            sample_rate: rate
+        };
+        let mut seed = 1u32;
+        let noise = Sound {
+            samples: (0..samples_per_tick)
+                .map(|index| {
+                    seed = seed.wrapping_mul(1664525).wrapping_add(1013904223);
+                    ((seed >> 8) as f32 / (1u32 << 23) as f32 - 1.0) * decay(index)
+                })
+                .collect(),
+            sample_rate: rate
+        };
+        let events = vec![("block.note_block.harp".to_string(), tone(440.0)), ("block.note_block.bass".to_string(), tone(110.0)), ("block.note_block.snare".to_string(), noise)];
+
+        // pitched like the basis pitches them
+        let (sound_ids, sounds): (Vec<(String, f32)>, Vec<Sound>) = audio::permute_with_pitch(events.clone(), 32, 1).into_iter().unzip();
+        let mut input = Sound { samples: vec![0.0; n_ticks * samples_per_tick], sample_rate: rate };
+        for (tick, sound, volume) in notes {
+            let start = tick * samples_per_tick;
+            input.samples[start..start + samples_per_tick].iter_mut().zip(&sounds[*sound].samples).for_each(|(mixed, sample)| *mixed += sample * volume);
+        }
+
+        let gathered = Gathered {
+            sounds: events,
+            durations: HashMap::new(),
+            availability: None,
+            inputs: vec![input],
+            directions: None,
+            stages: Stages { basis: String::new(), solve: String::new() },
+            schedule_path: directory.join("schedule.bin"),
+            stages_path: directory.join("stages.json"),
+            cached: None,
+            tick_budgets: vec![budget; n_ticks],
+            aliases: HashMap::new(),
+            devices: Some(Vec::new())
+        };
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            export::prepare_output(&output, true).await.unwrap();
+            convert(args, gathered, Timing::new(), None).await.unwrap();
+        });
+
+        let schedule = Schedule::load(&directory.join("schedule.bin")).unwrap();
+        std::fs::remove_dir_all(&directory).unwrap();
+        assert_eq!(schedule.sound_ids, sound_ids);
+        return schedule.amplitudes.columns().into_iter()
+            .map(|amplitudes| {
+                let mut picked = amplitudes.iter().copied().enumerate().filter(|(_, volume)| *volume > 0.0).collect::<Vec<(usize, f32)>>();
+                picked.sort_by(|a, b| b.1.total_cmp(&a.1));
+                picked.truncate(budget);
+                picked
+            })
+            .collect();
+    }
+
+    #[test]
+    fn test_synthetic_song() {
+        // every sound has 32 pitches, the harp's first, then the bass' and the snare's
+        let notes = [(0, 40, 1.0), (1, 72, 0.5), (2, 40, 0.8), (2, 18, 0.4), (4, 88, 0.6), (5, 50, 1.0), (7, 24, 0.7)];
+        // neighbouring pitches sound alike, so a note's volume is spread
+        // over them
+        let near = |sound: usize, note: usize| sound / 32 == note / 32 && sound.abs_diff(note) <= 2;
+
+        for features in ["mel-weighted", "waveform"] {
+            let ticks = synthetic_song(features, &notes, 8, 4);
+            let volume = |tick: usize, note: usize| ticks[tick].iter().filter(|(sound, _)| near(*sound, note)).map(|(_, volume)| volume).sum::<f32>();
+
+            for (tick, picked) in ticks.iter().enumerate() {
+                let mut expected = notes.iter().filter(|note| note.0 == tick).collect::<Vec<_>>();
+                expected.sort_by(|a, b| b.2.total_cmp(&a.2));
+
+                // every note is played, louder ones louder, and nothing else is
+                for note in &expected {
+                    assert!(picked.iter().any(|(sound, _)| *sound == note.1), "{} tick {} picked {:?}, not {:?}", features, tick, picked, expected);
+                }
+                assert!(expected.windows(2).all(|pair| volume(tick, pair[0].1) > volume(tick, pair[1].1)), "{} tick {} picked {:?}, not {:?}", features, tick, picked, expected);
+                assert!(picked.iter().all(|(sound, _)| expected.iter().any(|note| near(*sound, note.1))), "{} tick {} picked {:?}", features, tick, picked);
+            }
+
+            // a sound played again keeps its volume relative to the first time
+            let (first, again) = (volume(0, 40), volume(2, 40));
+            assert!((again / first - 0.8).abs() < 0.05, "{} played the harp at {} and then {}", features, first, again);
+        }
+    }
+}
@@ -0,0 +1,143 @@
+use std::{fs::{self, OpenOptions}, ops::Range, path::{Path, PathBuf}};
+
+use memmap2::MmapMut;
+use ndarray::{Array2, ArrayView2, ArrayViewMut2};
+use tracing::{event, Level};
+
+use crate::cancel;
+
+/// ticks whose features are made and written to the file at a time
+static BUILD_BLOCK: usize = 4096;
+
+#[derive(thiserror::Error, Debug)]
+pub enum MappedError {
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("there are no chunks to map")]
+    Empty,
+    #[error("tick {tick} has {found} features instead of {expected}")]
+    Features { tick: usize, found: usize, expected: usize }
+}
+
+/// a chunk matrix in a memory-mapped file instead of in memory, for inputs
+/// whose chunks alone don't fit in it. ticks are laid out one after the
+/// other like `matrix_from_vecs(..).reversed_axes()` lays them out, so the
+/// blocks of ticks the solve works on are blocks of the file, paged in
+/// when they're read. the file is removed when this is dropped, or when
+/// ctrl-c exits before it is
+pub struct MappedChunks {
+    map: MmapMut,
+    path: PathBuf,
+    features: usize,
+    ticks: usize
+}
+
+impl MappedChunks {
+    /// builds the chunks of `ticks` ticks in a file at `path`, with the
+    /// features of `BUILD_BLOCK` ticks at a time from `features`, so no
+    /// more than a block of them is ever in memory
+    pub fn build(path: &Path, ticks: usize, mut features: impl FnMut(Range<usize>) -> Vec<Vec<f32>>) -> Result<MappedChunks, MappedError> {
+        let first = features(0..BUILD_BLOCK.min(ticks));
+        let Some(m) = first.first().map(Vec::len).filter(|m| *m > 0) else {
+            return Err(MappedError::Empty);
+        };
+
+        let file = OpenOptions::new().read(true).write(true).create(true).truncate(true).open(path)?;
+        cancel::register_temporary(path);
+        file.set_len((ticks * m * size_of::<f32>()) as u64)?;
+
+        // SAFETY: the file was just made for this map, nothing else writes to it
+        let map = unsafe { MmapMut::map_mut(&file)? };
+        let mut mapped = MappedChunks { map, path: path.to_path_buf(), features: m, ticks };
+
+        mapped.write(0, &first)?;
+        drop(first);
+        for start in (BUILD_BLOCK..ticks).step_by(BUILD_BLOCK) {
+            mapped.write(start, &features(start..(start + BUILD_BLOCK).min(ticks)))?;
+        }
+
+        return Ok(mapped);
+    }
+
+    fn write(&mut self, start: usize, block: &[Vec<f32>]) -> Result<(), MappedError> {
+        let m = self.features;
+        let values = self.values_mut();
+
+        for (tick, features) in (start..).zip(block) {
+            if features.len() != m {
+                return Err(MappedError::Features { tick, found: features.len(), expected: m });
+            }
+            values[tick * m..(tick + 1) * m].copy_from_slice(features);
+        }
+
+        return Ok(());
+    }
+
+    fn values(&self) -> &[f32] {
+        // SAFETY: maps are page aligned and any bits are an f32
+        let (head, values, _) = unsafe { self.map.align_to::<f32>() };
+        assert!(head.is_empty());
+        return values;
+    }
+
+    fn values_mut(&mut self) -> &mut [f32] {
+        // SAFETY: see `values`
+        let (head, values, _) = unsafe { self.map.align_to_mut::<f32>() };
+        assert!(head.is_empty());
+        return values;
+    }
+
+    /// the chunks, dimensioned (features, ticks) like the ones in memory
+    pub fn view(&self) -> ArrayView2<'_, f32> {
+        return ArrayView2::from_shape((self.ticks, self.features), self.values()).unwrap().reversed_axes();
+    }
+
+    pub fn view_mut(&mut self) -> ArrayViewMut2<'_, f32> {
+        return ArrayViewMut2::from_shape((self.ticks, self.features), self.values_mut()).unwrap().reversed_axes();
+    }
+}
+
+impl Drop for MappedChunks {
+    fn drop(&mut self) {
+        cancel::forget_temporary(&self.path);
+        if let Err(e) = fs::remove_file(&self.path) {
+            event!(Level::WARN, "could not remove `{}`: '{}'", self.path.to_string_lossy(), e);
+        }
+    }
+}
+
+/// a part's chunks, in memory or mapped with `--map-chunks`
+pub enum Chunks {
+    Memory(Array2<f32>),
+    Mapped(MappedChunks)
+}
+
+impl Chunks {
+    pub fn view(&self) -> ArrayView2<'_, f32> {
+        return match self {
+            Chunks::Memory(chunks) => chunks.view(),
+            Chunks::Mapped(chunks) => chunks.view(),
+        };
+    }
+
+    pub fn view_mut(&mut self) -> ArrayViewMut2<'_, f32> {
+        return match self {
+            Chunks::Memory(chunks) => chunks.view_mut(),
+            Chunks::Mapped(chunks) => chunks.view_mut(),
+        };
+    }
+
+    /// the chunks in memory, read back from the file when they're mapped
+    pub fn into_owned(self) -> Array2<f32> {
+        return match self {
+            Chunks::Memory(chunks) => chunks,
+            Chunks::Mapped(chunks) => chunks.view().to_owned(),
+        };
+    }
+}
+
+impl From<Array2<f32>> for Chunks {
+    fn from(chunks: Array2<f32>) -> Self {
+        return Chunks::Memory(chunks);
+    }
+}
@@ -0,0 +1,239 @@
+use std::{fs, io::{BufReader, BufWriter, Read, Write}, path::{Path, PathBuf}};
+
+use ndarray::Array2;
+use serde::{Deserialize, Serialize};
+
+use crate::versions::Availability;
+
+static MAGIC: &[u8; 4] = b"MCPM";
+static FORMAT_VERSION: u32 = 1;
+
+static METADATA_FILE: &str = "matrices.json";
+
+#[derive(thiserror::Error, Debug)]
+pub enum MatricesError {
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("`{0}` holds no finished export of the matrices")]
+    NotExported(PathBuf),
+    #[error("`{0}` is not an exported matrix")]
+    NotAMatrix(PathBuf),
+    #[error("matrices were exported by an incompatible version")]
+    Incompatible,
+    #[error("`{0}` is truncated")]
+    Truncated(PathBuf),
+    #[error("the {0} part's matrices don't match each other")]
+    Mismatch(String),
+    #[error("matrix has a bad shape: {0}")]
+    Shape(#[from] ndarray::ShapeError),
+    #[error("invalid json: {0}")]
+    Json(#[from] serde_json::Error)
+}
+
+/// a part of the problem, as it's solved: its chunks and its columns of the
+/// basis, both normalized
+pub struct PartMatrices {
+    pub name: String,
+    /// rows of the schedule its basis columns are
+    pub sounds: Vec<usize>,
+    pub budget: usize,
+    /// rms of its input, the parts are merged by it
+    pub loudness: f32,
+    /// `normalize_columns` of the chunks, with per-tick normalization
+    pub tick_scales: Option<Vec<f32>>,
+    pub chunks: Array2<f32>,
+    pub basis: Array2<f32>
+}
+
+/// everything the solve and the export need that takes the assets or the
+/// input to make, so they can run on another machine
+pub struct Matrices {
+    pub sound_ids: Vec<(String, f32)>,
+    pub n_ticks: usize,
+    /// frames per tick
+    pub frames: usize,
+    /// frames per atom
+    pub span: usize,
+    pub tick_budgets: Vec<usize>,
+    /// `subtick::decay_compensation` of every sound, when there's more than
+    /// one frame per tick
+    pub compensation: Option<Vec<Vec<f32>>>,
+    /// which versions have which events, when the basis is made of several
+    pub versions: usize,
+    pub availability: Availability,
+    pub parts: Vec<PartMatrices>
+}
+
+/// `Matrices` without the matrices, as json
+#[derive(Serialize, Deserialize)]
+struct Metadata {
+    format_version: u32,
+    sound_ids: Vec<(String, f32)>,
+    n_ticks: usize,
+    frames: usize,
+    span: usize,
+    tick_budgets: Vec<usize>,
+    compensation: Option<Vec<Vec<f32>>>,
+    versions: usize,
+    availability: Availability,
+    parts: Vec<PartMetadata>
+}
+
+#[derive(Serialize, Deserialize)]
+struct PartMetadata {
+    name: String,
+    sounds: Vec<usize>,
+    budget: usize,
+    loudness: f32,
+    tick_scales: Option<Vec<f32>>
+}
+
+fn chunks_path(directory: &Path, part: usize) -> PathBuf {
+    directory.join(format!("part-{}-chunks.bin", part))
+}
+
+fn basis_path(directory: &Path, part: usize) -> PathBuf {
+    directory.join(format!("part-{}-basis.bin", part))
+}
+
+fn read_u64(reader: &mut impl Read, path: &Path) -> Result<u64, MatricesError> {
+    let mut buf = [0u8; 8];
+    reader.read_exact(&mut buf).map_err(|_| MatricesError::Truncated(path.to_path_buf()))?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+/// same layout as a checkpoint, without the iterations
+fn write_matrix(path: &Path, matrix: &Array2<f32>) -> Result<(), MatricesError> {
+    let (rows, cols) = matrix.dim();
+    let mut writer = BufWriter::new(fs::File::create(path)?);
+    writer.write_all(MAGIC)?;
+    writer.write_all(&FORMAT_VERSION.to_le_bytes())?;
+    writer.write_all(&(rows as u64).to_le_bytes())?;
+    writer.write_all(&(cols as u64).to_le_bytes())?;
+
+    for value in matrix.iter() {
+        writer.write_all(&value.to_le_bytes())?;
+    }
+
+    writer.flush()?;
+    Ok(())
+}
+
+fn read_matrix(path: &Path) -> Result<Array2<f32>, MatricesError> {
+    let mut reader = BufReader::new(fs::File::open(path)?);
+
+    let mut magic = [0u8; 4];
+    reader.read_exact(&mut magic).map_err(|_| MatricesError::Truncated(path.to_path_buf()))?;
+    if &magic != MAGIC {
+        return Err(MatricesError::NotAMatrix(path.to_path_buf()));
+    }
+
+    let mut version = [0u8; 4];
+    reader.read_exact(&mut version).map_err(|_| MatricesError::Truncated(path.to_path_buf()))?;
+    if u32::from_le_bytes(version) != FORMAT_VERSION {
+        return Err(MatricesError::Incompatible);
+    }
+
+    let rows = read_u64(&mut reader, path)? as usize;
+    let cols = read_u64(&mut reader, path)? as usize;
+
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+    if bytes.len() != rows * cols * 4 {
+        return Err(MatricesError::Truncated(path.to_path_buf()));
+    }
+
+    let values = bytes
+        .chunks_exact(4)
+        .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .collect::<Vec<f32>>();
+
+    Ok(Array2::from_shape_vec((rows, cols), values)?)
+}
+
+impl Matrices {
+    /// a `matrices.json` with everything but the matrices, which are each
+    /// written raw next to it. the json is written last, so a directory
+    /// without one was never finished
+    pub fn save(&self, directory: &Path) -> Result<(), MatricesError> {
+        fs::create_dir_all(directory)?;
+        let metadata_path = directory.join(METADATA_FILE);
+        if metadata_path.exists() {
+            fs::remove_file(&metadata_path)?;
+        }
+
+        let mut parts = Vec::with_capacity(self.parts.len());
+        for (index, part) in self.parts.iter().enumerate() {
+            write_matrix(&chunks_path(directory, index), &part.chunks)?;
+            write_matrix(&basis_path(directory, index), &part.basis)?;
+            parts.push(PartMetadata {
+                name: part.name.clone(),
+                sounds: part.sounds.clone(),
+                budget: part.budget,
+                loudness: part.loudness,
+                tick_scales: part.tick_scales.clone()
+            });
+        }
+
+        let metadata = Metadata {
+            format_version: FORMAT_VERSION,
+            sound_ids: self.sound_ids.clone(),
+            n_ticks: self.n_ticks,
+            frames: self.frames,
+            span: self.span,
+            tick_budgets: self.tick_budgets.clone(),
+            compensation: self.compensation.clone(),
+            versions: self.versions,
+            availability: self.availability.clone(),
+            parts
+        };
+        fs::write(metadata_path, serde_json::to_string(&metadata)?)?;
+
+        Ok(())
+    }
+
+    pub fn load(directory: &Path) -> Result<Self, MatricesError> {
+        let metadata_path = directory.join(METADATA_FILE);
+        if !metadata_path.exists() {
+            return Err(MatricesError::NotExported(directory.to_path_buf()));
+        }
+        let metadata: Metadata = serde_json::from_str(&fs::read_to_string(metadata_path)?)?;
+        if metadata.format_version != FORMAT_VERSION {
+            return Err(MatricesError::Incompatible);
+        }
+
+        let mut parts = Vec::with_capacity(metadata.parts.len());
+        for (index, part) in metadata.parts.into_iter().enumerate() {
+            let chunks = read_matrix(&chunks_path(directory, index))?;
+            let basis = read_matrix(&basis_path(directory, index))?;
+
+            // an atom's features are its frames' stacked
+            let n_frames = metadata.n_ticks * metadata.frames;
+            if chunks.dim().1 != n_frames || basis.dim() != (chunks.dim().0 * metadata.span, part.sounds.len()) {
+                return Err(MatricesError::Mismatch(part.name));
+            }
+
+            parts.push(PartMatrices {
+                name: part.name,
+                sounds: part.sounds,
+                budget: part.budget,
+                loudness: part.loudness,
+                tick_scales: part.tick_scales,
+                chunks,
+                basis
+            });
+        }
+
+        Ok(Matrices {
+            sound_ids: metadata.sound_ids,
+            n_ticks: metadata.n_ticks,
+            frames: metadata.frames,
+            span: metadata.span,
+            tick_budgets: metadata.tick_budgets,
+            compensation: metadata.compensation,
+            versions: metadata.versions,
+            availability: metadata.availability,
+            parts
+        })
+    }
+}
@@ -1,24 +1,134 @@
-use std::{collections::HashMap, fmt::Display, hash::Hash};
+use std::{collections::HashMap, error::Error, fmt::Display, fs, path::{Path, PathBuf}, sync::OnceLock, time::Duration};
 use bytes::Bytes;
 
-use anyhow::{Error, anyhow};
-use serde::{Deserialize, Deserializer};
+use reqwest::{header::RETRY_AFTER, Certificate, Client, NoProxy, Proxy, Response, StatusCode};
+use serde::{Deserialize, Deserializer, Serialize};
 use serde_json::Value;
 use sha1_smol::Sha1;
+use tracing::{event, Level};
 
-static VERSION_MANIFEST_URL: &str = "https://piston-meta.mojang.com/mc/game/version_manifest_v2.json";
+pub static VERSION_MANIFEST_URL: &str = "https://piston-meta.mojang.com/mc/game/version_manifest_v2.json";
 static ASSET_URL: &str = "https://resources.download.minecraft.net";
 
-#[derive(Deserialize, Clone, Debug)]
+static MAX_RETRIES: u32 = 6;
+static BASE_BACKOFF: Duration = Duration::from_millis(250);
+
+/// one client for every request so connections to the CDN are kept alive
+/// and pooled instead of doing a fresh TLS handshake per asset. set up by
+/// [`configure`], or with the defaults on the first request
+static CLIENT: OnceLock<Client> = OnceLock::new();
+
+#[derive(thiserror::Error, Debug)]
+pub enum MojangError {
+    #[error("request failed: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error("asset `{hash}` did not match its hash")]
+    HashMismatch { hash: String },
+    #[error("invalid proxy `{url}`: {source}")]
+    Proxy { url: String, source: reqwest::Error },
+    #[error("failed to load certificate `{path}`: {message}")]
+    Certificate { path: PathBuf, message: String }
+}
+
+impl MojangError {
+    /// mojang couldn't be reached at all, as opposed to answering badly
+    pub fn is_offline(&self) -> bool {
+        matches!(self, MojangError::Http(e) if (e.is_connect() || e.is_timeout()) && !self.is_untrusted())
+    }
+
+    /// the connection was made, but the certificate it presented wasn't
+    /// trusted. usually a proxy that intercepts TLS
+    pub fn is_untrusted(&self) -> bool {
+        let MojangError::Http(error) = self else {
+            return false;
+        };
+
+        // reqwest doesn't tell these apart, the tls backend's error does
+        let mut source = error.source();
+        while let Some(error) = source {
+            if error.to_string().to_lowercase().contains("certificate") {
+                return true;
+            }
+            source = error.source();
+        }
+        return false;
+    }
+}
+
+/// how requests leave this machine. without a proxy the usual `HTTPS_PROXY`,
+/// `HTTP_PROXY`, `ALL_PROXY` and `NO_PROXY` variables are honored
+#[derive(Clone, Debug, Default)]
+pub struct Network {
+    pub proxy: Option<String>,
+    /// trusted on top of the system's root certificates
+    pub ca_certificates: Vec<PathBuf>
+}
+
+/// a PEM bundle of one or more certificates, or a single DER one
+fn load_certificates(path: &Path) -> Result<Vec<Certificate>, MojangError> {
+    let error = |message: String| MojangError::Certificate { path: path.to_path_buf(), message };
+    let bytes = fs::read(path).map_err(|e| error(e.to_string()))?;
+
+    let certificates = match bytes.starts_with(b"-----BEGIN") {
+        true => Certificate::from_pem_bundle(&bytes),
+        false => Certificate::from_der(&bytes).map(|certificate| vec![certificate]),
+    }.map_err(|e| error(e.source().map(ToString::to_string).unwrap_or(e.to_string())))?;
+
+    if certificates.is_empty() {
+        return Err(error("no certificates in the file".to_string()));
+    }
+    return Ok(certificates);
+}
+
+fn build_client(network: &Network) -> Result<Client, MojangError> {
+    let mut builder = Client::builder()
+        .tcp_keepalive(Duration::from_secs(30))
+        .pool_idle_timeout(Duration::from_secs(90));
+
+    if let Some(url) = &network.proxy {
+        let proxy = Proxy::all(url).map_err(|source| MojangError::Proxy { url: url.clone(), source })?;
+        builder = builder.proxy(proxy.no_proxy(NoProxy::from_env()));
+    }
+    for path in &network.ca_certificates {
+        for certificate in load_certificates(path)? {
+            builder = builder.add_root_certificate(certificate);
+        }
+    }
+
+    Ok(builder.build()?)
+}
+
+/// sets up the client every request goes through. has to happen before
+/// the first request, later calls are ignored
+pub fn configure(network: &Network) -> Result<(), MojangError> {
+    let client = build_client(network)?;
+    if CLIENT.set(client).is_err() {
+        event!(Level::WARN, "http client was already set up, ignoring the network settings");
+    }
+    Ok(())
+}
+
+pub(crate) fn client() -> &'static Client {
+    CLIENT.get_or_init(|| build_client(&Network::default()).expect("failed to build http client"))
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct LatestVersion {
     pub release: String,
     pub snapshot: String
 }
 
-#[derive(Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Version {
     pub id: String,
-    pub url: String
+    /// `release`, `snapshot`, `old_beta` or `old_alpha`
+    #[serde(rename = "type", default)]
+    pub kind: String,
+    pub url: String,
+    /// e.g. `2024-06-13T08:24:03+00:00`. missing from manifests cached
+    /// before it was kept
+    #[serde(rename = "releaseTime", default)]
+    pub release_time: Option<String>
 }
 
 impl Display for Version {
@@ -27,14 +137,15 @@ impl Display for Version {
     }
 }
 
-#[derive(Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct VersionManifest {
     pub latest: LatestVersion,
     pub versions: Vec<Version>
 }
 
-pub async fn fetch_version_manifest() -> Result<VersionManifest, Error> {
-    Ok(reqwest::get(VERSION_MANIFEST_URL)
+/// `url` is [`VERSION_MANIFEST_URL`] unless a mirror is used
+pub async fn fetch_version_manifest(url: &str) -> Result<VersionManifest, MojangError> {
+    Ok(get_with_backoff(url)
         .await?
         .json::<VersionManifest>()
         .await?
@@ -75,22 +186,61 @@ pub struct AssetIndex {
     pub objects: HashMap<String, Object>
 }
 
-pub async fn fetch_asset_index(version: &Version) -> Result<AssetIndex, Error> {
-    let package = reqwest::get(&version.url)
+pub async fn fetch_asset_index(version: &Version) -> Result<AssetIndex, MojangError> {
+    let package = client().get(&version.url)
+        .send()
         .await?
         .json::<VersionPackage>()
         .await?;
 
-    Ok(reqwest::get(&package.asset_index_url)
+    Ok(client().get(&package.asset_index_url)
+        .send()
         .await?
         .json::<AssetIndex>()
         .await?
     )
 }
 
-pub async fn fetch_asset(hash: &str) -> Result<Bytes, Error> {
+fn retry_after(response: &Response) -> Option<Duration> {
+    response.headers()
+        .get(RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .parse::<u64>()
+        .ok()
+        .map(Duration::from_secs)
+}
+
+/// retries with exponential backoff when the CDN throttles (429) or
+/// falls over (5xx), honoring `Retry-After` when it is given
+async fn get_with_backoff(url: &str) -> Result<Response, MojangError> {
+    let mut attempt = 0;
+
+    loop {
+        let response = client().get(url).send().await?;
+        let status = response.status();
+
+        if (status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()) && attempt < MAX_RETRIES {
+            let delay = retry_after(&response).unwrap_or(BASE_BACKOFF * 2u32.pow(attempt));
+            event!(Level::DEBUG, "got {} for `{}`, retrying in {}ms", status, url, delay.as_millis());
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+            continue;
+        }
+
+        return Ok(response.error_for_status()?);
+    }
+}
+
+/// anything else, like a server's resource pack. it's up to the caller to
+/// check what it got
+pub async fn fetch_url(url: &str) -> Result<Bytes, MojangError> {
+    Ok(get_with_backoff(url).await?.bytes().await?)
+}
+
+pub async fn fetch_asset(hash: &str) -> Result<Bytes, MojangError> {
     let mut hasher = Sha1::new();
-    let response_bytes = reqwest::get(format!("{}/{}/{}", ASSET_URL, &hash[0..2], hash))
+    let response_bytes = get_with_backoff(&format!("{}/{}/{}", ASSET_URL, &hash[0..2], hash))
         .await?
         .bytes()
         .await?;
@@ -98,7 +248,7 @@ pub async fn fetch_asset(hash: &str) -> Result<Bytes, Error> {
     hasher.update(&response_bytes);
 
     if hasher.digest().to_string() != hash {
-        return Err(anyhow!("repsonse hash did not match asset hash"));
+        return Err(MojangError::HashMismatch { hash: hash.to_string() });
     }
 
     return Ok(response_bytes);
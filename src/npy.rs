@@ -0,0 +1,62 @@
+use std::{fs, io::{BufWriter, Write}, path::Path};
+
+use ndarray::ArrayView2;
+use tracing::{event, Level};
+
+static MAGIC: &[u8; 6] = b"\x93NUMPY";
+
+/// the header and data are aligned to this, as numpy expects
+static ALIGNMENT: usize = 64;
+
+/// writes `array` as a version 1.0 `.npy` of little-endian f32 in C order,
+/// for `numpy.load`
+pub fn write(path: &Path, array: ArrayView2<f32>) -> Result<(), std::io::Error> {
+    let (rows, cols) = array.dim();
+    let mut header = format!("{{'descr': '<f4', 'fortran_order': False, 'shape': ({}, {}), }}", rows, cols);
+    // padded with spaces and ended by a newline, after the magic, version and length
+    let unpadded = MAGIC.len() + 2 + 2 + header.len() + 1;
+    header.push_str(&" ".repeat(unpadded.next_multiple_of(ALIGNMENT) - unpadded));
+    header.push('\n');
+
+    let mut writer = BufWriter::new(fs::File::create(path)?);
+    writer.write_all(MAGIC)?;
+    writer.write_all(&[1, 0])?;
+    writer.write_all(&(header.len() as u16).to_le_bytes())?;
+    writer.write_all(header.as_bytes())?;
+
+    for value in array.iter() {
+        writer.write_all(&value.to_le_bytes())?;
+    }
+
+    writer.flush()?;
+    return Ok(());
+}
+
+/// the sound of every column of a part's basis (and row of its solution),
+/// as a table pandas reads with `read_csv`
+pub fn write_sounds(path: &Path, sound_ids: &[(String, f32)], sounds: &[usize]) -> Result<(), std::io::Error> {
+    let mut writer = BufWriter::new(fs::File::create(path)?);
+    writeln!(writer, "column,id,pitch")?;
+    for (column, sound) in sounds.iter().enumerate() {
+        let (id, pitch) = &sound_ids[*sound];
+        writeln!(writer, "{},{},{}", column, id, pitch)?;
+    }
+
+    writer.flush()?;
+    return Ok(());
+}
+
+/// a part's solve as `{name}-W.npy` (the basis, features by sounds),
+/// `{name}-V.npy` (the chunks, features by frames), `{name}-H.npy` (the
+/// solution, sounds by frames) and `{name}-sounds.csv`. all of them as the
+/// solve saw them, normalized and before the frames are merged into ticks
+pub fn dump(directory: &Path, name: &str, basis: ArrayView2<f32>, chunks: ArrayView2<f32>, h: ArrayView2<f32>, sound_ids: &[(String, f32)], sounds: &[usize]) -> Result<(), std::io::Error> {
+    fs::create_dir_all(directory)?;
+    write(&directory.join(format!("{}-W.npy", name)), basis)?;
+    write(&directory.join(format!("{}-V.npy", name)), chunks)?;
+    write(&directory.join(format!("{}-H.npy", name)), h)?;
+    write_sounds(&directory.join(format!("{}-sounds.csv", name)), sound_ids, sounds)?;
+
+    event!(Level::INFO, "wrote the {} matrices to `{}`", name, directory.to_string_lossy());
+    return Ok(());
+}
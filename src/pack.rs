@@ -0,0 +1,132 @@
+use std::{collections::HashMap, io::{Cursor, Read}, path::{Path, PathBuf}};
+
+use bytes::Bytes;
+use sha1_smol::Sha1;
+use tracing::{event, span, Level};
+use zip::ZipArchive;
+
+use crate::{assets::{self, AssetKey, FetchBehavior, FetchOptions, SoundDefinition}, mojang::{self, MojangError}};
+
+/// where packs are kept in the assets directory, by SHA-1
+static PACK_DIRECTORY: &str = "packs";
+
+#[derive(thiserror::Error, Debug)]
+pub enum PackError {
+    #[error(transparent)]
+    Mojang(#[from] MojangError),
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("not a resource pack: {0}")]
+    Zip(#[from] zip::result::ZipError),
+    #[error("invalid `{path}`: {source}")]
+    Json { path: String, source: serde_json::Error },
+    #[error("the pack's SHA-1 is {actual}, not {expected}")]
+    HashMismatch { expected: String, actual: String },
+    #[error("cache-only mode without a cached pack (`{0}`)")]
+    NotCached(PathBuf)
+}
+
+/// the sounds of a resource pack, which go over a version's like they do in
+/// game: its sound definitions replace or add to the version's, and its
+/// `.ogg`s replace the ones at the same path
+pub struct Pack {
+    /// of the zip, what servers check packs by
+    pub sha1: String,
+    definitions: HashMap<String, SoundDefinition>,
+    sounds: HashMap<AssetKey, Bytes>
+}
+
+/// the resource pack at `url`, like a server's `resource-pack`. it's checked
+/// against `sha1` (the server's `resource-pack-sha1`) when given, and only
+/// then cached, a pack that isn't pinned may change behind the same url
+pub async fn fetch(url: &str, sha1: Option<&str>, assets: &Path, options: &FetchOptions) -> Result<Pack, PackError> {
+    let _span = span!(Level::INFO, "fetch_pack", tag = "assets").entered();
+
+    let path = sha1.map(|sha1| assets.join(PACK_DIRECTORY).join(sha1.to_lowercase()).with_extension("zip"));
+    let cached = match (&path, options.behavior()) {
+        (Some(path), FetchBehavior::FetchIfMissing | FetchBehavior::CacheOnly) => tokio::fs::read(path).await.ok(),
+        _ => None,
+    };
+
+    let bytes = match cached {
+        Some(bytes) => Bytes::from(bytes),
+        None if options.behavior() == FetchBehavior::CacheOnly => {
+            return Err(PackError::NotCached(path.unwrap_or_else(|| assets.join(PACK_DIRECTORY))));
+        },
+        None => {
+            event!(Level::INFO, "downloading the server resource pack");
+            mojang::fetch_url(url).await?
+        },
+    };
+
+    let actual = Sha1::from(&bytes).digest().to_string();
+    match (sha1, &path) {
+        (Some(expected), Some(path)) => {
+            if !actual.eq_ignore_ascii_case(expected) {
+                return Err(PackError::HashMismatch { expected: expected.to_lowercase(), actual });
+            }
+
+            tokio::fs::create_dir_all(assets.join(PACK_DIRECTORY)).await?;
+            assets::write_atomic(path, &bytes).await?;
+        },
+        _ => event!(Level::WARN, "the resource pack isn't checked against a SHA-1, so it's downloaded again every run"),
+    }
+
+    let pack = read(bytes)?;
+    event!(Level::INFO, "the resource pack has {} sound definitions and {} sounds", pack.definitions.len(), pack.sounds.len());
+    return Ok(pack);
+}
+
+/// the sound definitions and sounds of a resource pack's zip. events of
+/// namespaces other than `minecraft` are named `namespace:event`
+pub fn read(bytes: Bytes) -> Result<Pack, PackError> {
+    let sha1 = Sha1::from(&bytes).digest().to_string();
+    let mut archive = ZipArchive::new(Cursor::new(bytes))?;
+
+    let mut definitions = HashMap::new();
+    let mut sounds = HashMap::new();
+    for index in 0..archive.len() {
+        let mut file = archive.by_index(index)?;
+        let name = file.name().to_string();
+        let Some((namespace, path)) = name.strip_prefix("assets/").and_then(|name| name.split_once('/')) else {
+            continue;
+        };
+
+        if path == "sounds.json" {
+            let mut contents = String::new();
+            file.read_to_string(&mut contents)?;
+            let pack_definitions = assets::parse_sound_definitions(&contents)
+                .map_err(|source| PackError::Json { path: name.clone(), source })?;
+
+            for (event, definition) in pack_definitions {
+                let event = match namespace {
+                    "minecraft" => event,
+                    namespace => format!("{}:{}", namespace, event),
+                };
+                definitions.insert(event, definition);
+            }
+        } else if path.starts_with("sounds/") && path.ends_with(".ogg") {
+            let mut bytes = Vec::with_capacity(file.size() as usize);
+            file.read_to_end(&mut bytes)?;
+            sounds.insert(AssetKey::new(&format!("{}/{}", namespace, path)), Bytes::from(bytes));
+        }
+    }
+
+    return Ok(Pack { sha1, definitions, sounds });
+}
+
+impl Pack {
+    /// puts the pack over a version's sound definitions and sounds
+    pub fn apply(&self, definitions: &mut HashMap<String, SoundDefinition>, sounds: &mut HashMap<AssetKey, Bytes>) {
+        for (event, definition) in &self.definitions {
+            match definitions.get_mut(event) {
+                Some(existing) if !definition.replace => existing.sounds.extend(definition.sounds.iter().cloned()),
+                _ => {
+                    definitions.insert(event.clone(), definition.clone());
+                },
+            }
+        }
+
+        sounds.extend(self.sounds.iter().map(|(key, bytes)| (key.clone(), bytes.clone())));
+    }
+}
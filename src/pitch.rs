@@ -0,0 +1,121 @@
+use serde::{Deserialize, Serialize};
+
+use crate::audio::{Processor, Sound};
+
+/// samples looked at when estimating a fundamental
+static WINDOW: usize = 2048;
+static MIN_FREQ: f32 = 50.0;
+static MAX_FREQ: f32 = 2000.0;
+
+/// how periodic a sound has to be (0..1) to count as having a fundamental
+static CLARITY: f32 = 0.5;
+
+/// spectral flatness below which a sound with a fundamental counts as tonal.
+/// a pure tone is close to 0, white noise is around 0.56
+static TONAL_FLATNESS: f32 = 0.25;
+
+/// pitches the game accepts for `playsound`
+static MIN_PITCH: f32 = 0.5;
+static MAX_PITCH: f32 = 2.0;
+
+/// fundamental frequency in Hz from the normalized autocorrelation (McLeod's
+/// NSDF) of the start of the sound. `None` for noisy or silent sounds
+pub fn fundamental(sound: &Sound) -> Option<f32> {
+    let window = &sound.samples[..sound.samples.len().min(WINDOW)];
+    let rate = sound.sample_rate as f32;
+
+    let min_lag = (rate / MAX_FREQ) as usize;
+    let max_lag = ((rate / MIN_FREQ) as usize).min(window.len() / 2);
+    if min_lag < 1 || min_lag >= max_lag {
+        return None;
+    }
+
+    let nsdf = (0..=max_lag)
+        .map(|lag| {
+            let (mut correlation, mut energy) = (0.0, 0.0);
+            for i in 0..window.len() - lag {
+                correlation += window[i] * window[i + lag];
+                energy += window[i] * window[i] + window[i + lag] * window[i + lag];
+            }
+            if energy > 0.0 { 2.0 * correlation / energy } else { 0.0 }
+        })
+        .collect::<Vec<f32>>();
+
+    let peaks = (min_lag.max(1)..max_lag)
+        .filter(|&lag| nsdf[lag] > nsdf[lag - 1] && nsdf[lag] >= nsdf[lag + 1])
+        .collect::<Vec<usize>>();
+
+    let best = peaks.iter().map(|&lag| nsdf[lag]).fold(0.0, f32::max);
+    if best < CLARITY {
+        return None;
+    }
+
+    // the first peak close to the best one, so an octave below isn't picked
+    let lag = *peaks.iter().find(|&&lag| nsdf[lag] >= 0.9 * best)?;
+
+    // parabolic interpolation between neighbouring lags
+    let (a, b, c) = (nsdf[lag - 1], nsdf[lag], nsdf[lag + 1]);
+    let shift = if a - 2.0 * b + c != 0.0 { 0.5 * (a - c) / (a - 2.0 * b + c) } else { 0.0 };
+
+    return Some(rate / (lag as f32 + shift));
+}
+
+/// pitches that land the sound on the semitones of 12-TET (A4 = 440Hz). a
+/// sound without a fundamental gets semitone steps relative to itself
+pub fn musical_pitches(fundamental: Option<f32>) -> Vec<f32> {
+    // how far off the scale the sound already is, in semitones
+    let offset = match fundamental {
+        Some(freq) => 12.0 * (freq / 440.0).log2(),
+        None => 0.0,
+    };
+
+    let lowest = (12.0 * MIN_PITCH.log2() + offset).ceil() as i32;
+    let highest = (12.0 * MAX_PITCH.log2() + offset).floor() as i32;
+
+    return (lowest..=highest)
+        .map(|semitone| 2f32.powf((semitone as f32 - offset) / 12.0))
+        .collect();
+}
+
+/// what kind of sound a basis sound is, kept alongside it for filtering
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Character {
+    pub fundamental: Option<f32>,
+    pub flatness: f32
+}
+
+impl Character {
+    pub fn is_tonal(&self) -> bool {
+        self.fundamental.is_some() && self.flatness < TONAL_FLATNESS
+    }
+}
+
+pub fn analyze(sound: &Sound, processor: &Processor) -> Character {
+    Character {
+        fundamental: fundamental(sound),
+        flatness: spectral_flatness(sound, processor)
+    }
+}
+
+/// geometric over arithmetic mean of the power spectrum of the start of the
+/// sound. 0 for a pure tone, towards 1 for noise (and silence)
+pub fn spectral_flatness(sound: &Sound, processor: &Processor) -> f32 {
+    let window = Sound {
+        samples: sound.samples[..sound.samples.len().min(WINDOW)].to_vec(),
+        sample_rate: sound.sample_rate
+    };
+
+    let bins = processor.fft(window);
+    let power = bins.iter().skip(1).take(bins.len() / 2)
+        .map(|bin| bin.complex.norm_sqr() + 1e-12)
+        .collect::<Vec<f32>>();
+
+    if power.is_empty() {
+        return 1.0;
+    }
+
+    let log_mean = power.iter().map(|p| p.ln()).sum::<f32>() / power.len() as f32;
+    let mean = power.iter().sum::<f32>() / power.len() as f32;
+
+    return (log_mean.exp() / mean).min(1.0);
+}
@@ -0,0 +1,339 @@
+use std::{collections::VecDeque, io::{Seek, Write}, sync::Arc};
+
+use anyhow::Error;
+use hound::{SampleFormat, WavSpec, WavWriter};
+use ndarray_rand::rand::{rngs::StdRng, Rng, SeedableRng};
+use tracing::{event, Level};
+
+use crate::{export::PlaySound, exporter::Exporter};
+
+/// what happens to a tick whose summed sounds go past ±1.0
+#[derive(clap::ValueEnum, Clone, Copy, Default, Debug)]
+pub enum Clipping {
+    /// turn the whole tick down until its peak is 1.0, like the game's mixer does
+    #[default]
+    Limit,
+    /// bend samples into ±1.0 with tanh, keeping loud ticks loud
+    Soft,
+}
+
+/// how `playsound` volumes turn into gain in the reconstruction
+#[derive(clap::ValueEnum, Clone, Copy, Default, Debug)]
+pub enum Model {
+    /// volume is gain, with no attenuation
+    #[default]
+    Naive,
+    /// the game's model: gain is capped at 1.0 and volumes above that only
+    /// widen the audible radius (16 blocks per unit of volume), with linear
+    /// falloff to silence at its edge
+    InGame,
+}
+
+/// blocks a volume 1.0 sound can be heard from
+pub static ATTENUATION_DISTANCE: f32 = 16.0;
+
+impl Model {
+    /// the gain a listener `distance` blocks from the sound hears it at
+    pub fn gain(&self, volume: f32, distance: f32) -> f32 {
+        match self {
+            Model::Naive => volume,
+            Model::InGame => {
+                let radius = ATTENUATION_DISTANCE * volume.max(1.0);
+                let falloff = (1.0 - distance / radius).clamp(0.0, 1.0);
+                volume.min(1.0) * falloff
+            }
+        }
+    }
+}
+
+/// the start of a looping preview, held back until the end is known so the
+/// end can be crossfaded into it. the end is delayed by as many samples
+struct Seam {
+    length: usize,
+    head: Vec<f32>,
+    tail: VecDeque<f32>,
+}
+
+/// reconstruction `.wav` writer that keeps samples within ±1.0 and counts the
+/// ticks where it had to. optionally writes 16-bit with noise shaped dither
+/// instead of 32-bit float
+pub struct Preview<W: Write + Seek> {
+    writer: WavWriter<W>,
+    clipping: Clipping,
+    dither: Option<StdRng>,
+    /// the quantization error of the last sample of every channel
+    error: [f32; 2],
+    /// the input, to play in the left channel, see `ab`
+    original: Option<Vec<f32>>,
+    /// the reconstruction's samples, held back until `finalize` knows how
+    /// loud it is, when there's an original to match it to
+    pending: Vec<f32>,
+    /// samples of every channel written to the file
+    written: usize,
+    seam: Option<Seam>,
+    ticks: usize,
+    clipped: usize,
+    markers: Option<Box<dyn Write>>,
+    /// samples written to the preview so far, before the seam moves them
+    samples: usize,
+    /// the first sample and length of every tick, for `markers`
+    tick_samples: Vec<(usize, usize)>,
+}
+
+impl<W: Write + Seek> Preview<W> {
+    pub fn new(writer: W, sample_rate: u32, clipping: Clipping, dither: bool) -> Result<Self, Error> {
+        return Self::create(writer, sample_rate, clipping, dither, None);
+    }
+
+    /// a stereo preview with `original` in the left channel and the
+    /// reconstruction in the right, sample for sample, to compare the two on
+    /// headphones. the original is padded with silence to the end of the
+    /// last tick and turned to the reconstruction's rms, as far as its peak
+    /// stays within ±1.0, so the two are compared at the same level. that
+    /// takes the whole reconstruction, it's written when finalized
+    pub fn ab(writer: W, sample_rate: u32, clipping: Clipping, dither: bool, original: Vec<f32>) -> Result<Self, Error> {
+        return Self::create(writer, sample_rate, clipping, dither, Some(original));
+    }
+
+    fn create(writer: W, sample_rate: u32, clipping: Clipping, dither: bool, original: Option<Vec<f32>>) -> Result<Self, Error> {
+        let channels = if original.is_some() { 2 } else { 1 };
+        let spec = match dither {
+            true => WavSpec { channels, sample_rate, bits_per_sample: 16, sample_format: SampleFormat::Int },
+            false => WavSpec { channels, sample_rate, bits_per_sample: 32, sample_format: SampleFormat::Float },
+        };
+
+        return Ok(Preview {
+            writer: WavWriter::new(writer, spec)?,
+            clipping,
+            // seeded so the same solve always renders the same file, see `with_seed`
+            dither: dither.then(|| StdRng::seed_from_u64(0)),
+            error: [0.0; 2],
+            original,
+            pending: Vec::new(),
+            written: 0,
+            seam: None,
+            ticks: 0,
+            clipped: 0,
+            markers: None,
+            samples: 0,
+            tick_samples: Vec::new(),
+        });
+    }
+
+    /// dither noise from `seed` instead of 0. the same seed always renders
+    /// the same file
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.dither = self.dither.map(|_| StdRng::seed_from_u64(seed));
+        return self;
+    }
+
+    /// for a song that loops: its first `length` samples are crossfaded into
+    /// its last, so the file plays on seamlessly when repeated. it's that
+    /// much shorter
+    pub fn with_seam(mut self, length: usize) -> Self {
+        self.seam = Some(Seam { length, head: Vec::with_capacity(length), tail: VecDeque::with_capacity(length + 1) });
+        return self;
+    }
+
+    /// writes where every tick is in the preview to `markers` when it's
+    /// finalized, as labels audacity imports (`File > Import > Labels...`)
+    /// from a tab separated `start end label` line in seconds per tick
+    pub fn with_markers(mut self, markers: Box<dyn Write>) -> Self {
+        self.markers = Some(markers);
+        return self;
+    }
+
+    pub fn write_tick(&mut self, mut samples: Vec<f32>) -> Result<(), Error> {
+        let peak = samples.iter().cloned().fold(0.0, |a: f32, b| a.max(b.abs()));
+        self.ticks += 1;
+        if self.markers.is_some() {
+            self.tick_samples.push((self.samples, samples.len()));
+        }
+        self.samples += samples.len();
+
+        if peak > 1.0 {
+            self.clipped += 1;
+            match self.clipping {
+                Clipping::Limit => samples.iter_mut().for_each(|sample| *sample /= peak),
+                Clipping::Soft => samples.iter_mut().for_each(|sample| *sample = sample.tanh()),
+            }
+        }
+
+        for sample in samples {
+            let sample = match &mut self.seam {
+                Some(seam) if seam.head.len() < seam.length => {
+                    seam.head.push(sample);
+                    None
+                },
+                Some(seam) => {
+                    seam.tail.push_back(sample);
+                    match seam.tail.len() > seam.length {
+                        true => seam.tail.pop_front(),
+                        false => None,
+                    }
+                },
+                None => Some(sample),
+            };
+
+            if let Some(sample) = sample {
+                self.write_sample(sample)?;
+            }
+        }
+
+        return Ok(());
+    }
+
+    fn write_sample(&mut self, sample: f32) -> Result<(), Error> {
+        if self.original.is_some() {
+            self.pending.push(sample);
+            return Ok(());
+        }
+
+        self.write_channel(sample, 0)?;
+        self.written += 1;
+        return Ok(());
+    }
+
+    /// the held back reconstruction, next to the original turned to its level
+    fn write_ab(&mut self) -> Result<(), Error> {
+        let Some(original) = self.original.take() else {
+            return Ok(());
+        };
+
+        let length = self.pending.len();
+        let rms = |samples: &[f32]| (samples.iter().map(|sample| sample * sample).sum::<f32>() / length.max(1) as f32).sqrt();
+        let original = &original[..original.len().min(length)];
+        let (original_rms, reconstruction_rms) = (rms(original), rms(&self.pending));
+        let peak = original.iter().fold(0.0, |a: f32, b| a.max(b.abs()));
+        let gain = match original_rms > 0.0 {
+            true => (reconstruction_rms / original_rms).min(1.0 / peak),
+            false => 1.0,
+        };
+
+        // channels are interleaved, the original's sample first
+        for (index, sample) in std::mem::take(&mut self.pending).into_iter().enumerate() {
+            self.write_channel(original.get(index).copied().unwrap_or(0.0) * gain, 0)?;
+            self.write_channel(sample, 1)?;
+            self.written += 1;
+        }
+
+        return Ok(());
+    }
+
+    fn write_channel(&mut self, sample: f32, channel: usize) -> Result<(), Error> {
+        match &mut self.dither {
+            Some(rng) => {
+                // triangular dither, with the previous quantization error
+                // fed back so the noise is pushed up out of the audible range
+                let scaled = sample * i16::MAX as f32 - self.error[channel];
+                let noise = rng.gen::<f32>() - rng.gen::<f32>();
+                let quantized = (scaled + noise).round().clamp(i16::MIN as f32, i16::MAX as f32);
+                self.error[channel] = quantized - scaled;
+                self.writer.write_sample(quantized as i16)?;
+            },
+            None => self.writer.write_sample(sample)?,
+        }
+
+        return Ok(());
+    }
+
+    /// finishes the file and reports how many ticks went past ±1.0
+    pub fn finalize(mut self) -> Result<usize, Error> {
+        // the samples the seam holds back from the start of the file
+        let mut shift = 0;
+        if let Some(seam) = self.seam.take() {
+            match seam.tail.len() < seam.length {
+                // too short to crossfade, and nothing was written yet
+                true => seam.head.iter().chain(&seam.tail).try_for_each(|sample| self.write_sample(*sample))?,
+                false => {
+                    for (index, (end, start)) in seam.tail.iter().zip(&seam.head).enumerate() {
+                        let gain = (index + 1) as f32 / (seam.length + 1) as f32;
+                        self.write_sample(end * (1.0 - gain) + start * gain)?;
+                    }
+                    shift = seam.length;
+                },
+            }
+        }
+
+        self.write_ab()?;
+        let sample_rate = self.writer.spec().sample_rate;
+        self.writer.finalize()?;
+
+        if let Some(mut markers) = self.markers.take() {
+            // the held back start is crossfaded into the end
+            let length = self.samples - shift;
+            let mut labels = self.tick_samples.iter()
+                .enumerate()
+                .map(|(tick, (start, samples))| {
+                    let start = match *start >= shift {
+                        true => start - shift,
+                        false => length - shift + start,
+                    };
+                    (start, (start + samples).min(length), tick)
+                })
+                .collect::<Vec<(usize, usize, usize)>>();
+            labels.sort();
+
+            let seconds = |sample: usize| sample as f64 / sample_rate as f64;
+            for (start, end, tick) in labels {
+                writeln!(markers, "{:.6}\t{:.6}\ttick {}", seconds(start), seconds(end), tick)?;
+            }
+            markers.flush()?;
+        }
+
+        if self.clipped > 0 {
+            event!(Level::WARN, "{} of {} preview ticks clipped", self.clipped, self.ticks);
+        }
+
+        return Ok(self.clipped);
+    }
+}
+
+/// renders the reconstruction of what the other exporters write from the
+/// basis waveforms, into a `Preview`
+pub struct Reconstruction<W: Write + Seek> {
+    preview: Preview<W>,
+    /// shared when both previews render them
+    waveforms: Arc<[Vec<f32>]>,
+    model: Model,
+    distance: f32,
+    samples_per_tick: usize,
+    ringing: Vec<f32>,
+}
+
+impl<W: Write + Seek> Reconstruction<W> {
+    pub fn new(preview: Preview<W>, waveforms: Arc<[Vec<f32>]>, model: Model, distance: f32, samples_per_tick: usize, atom_ticks: usize) -> Self {
+        return Reconstruction {
+            preview,
+            waveforms,
+            model,
+            distance,
+            samples_per_tick,
+            ringing: vec![0.0; samples_per_tick * atom_ticks],
+        };
+    }
+}
+
+impl<W: Write + Seek> Exporter for Reconstruction<W> {
+    /// the sounds are played by their index in the basis, so it's the solved
+    /// sounds that are heard even when they're aliased
+    fn write_tick(&mut self, _: usize, sounds: &[PlaySound]) -> Result<(), Error> {
+        let length = self.ringing.len();
+        for play in sounds {
+            let gain = self.model.gain(play.volume, self.distance);
+            for (ring, sample) in self.ringing.iter_mut().zip(&self.waveforms[play.sound]) {
+                *ring += sample * gain;
+            }
+        }
+
+        // atoms spanning ticks carry over into the next ones
+        let current = self.ringing.drain(..self.samples_per_tick).collect::<Vec<f32>>();
+        self.ringing.resize(length, 0.0);
+        return self.preview.write_tick(current);
+    }
+
+    fn finish(self: Box<Self>) -> Result<(), Error> {
+        self.preview.finalize()?;
+        return Ok(());
+    }
+}
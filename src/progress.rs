@@ -0,0 +1,53 @@
+use std::sync::LazyLock;
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::{oneshot, watch};
+use tracing::Span;
+
+/// how far the running solve is, in iterations of the part being solved
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct Progress {
+    pub iteration: usize,
+    pub iterations: usize
+}
+
+/// the latest progress of the solve, `None` between solves. a watch channel
+/// only keeps the latest value, so the solve's thread never waits on the
+/// async side following it
+static PROGRESS: LazyLock<watch::Sender<Option<Progress>>> = LazyLock::new(|| watch::channel(None).0);
+
+/// called by the solve as it finishes an iteration, from whichever thread
+/// it runs on
+pub fn report(iteration: usize, iterations: usize) {
+    PROGRESS.send_replace(Some(Progress { iteration, iterations }));
+}
+
+/// runs `solve` on a thread of its own and waits for it there, so the
+/// runtime (the webhook, `serve`'s api) never waits on the device and can
+/// follow its progress. a panicking solve is passed on
+pub async fn solving<T: Send + 'static>(solve: impl FnOnce() -> T + Send + 'static) -> T {
+    let (sender, receiver) = oneshot::channel();
+    // the solve's events belong to whatever the caller was doing
+    let span = Span::current();
+    let solver = std::thread::spawn(move || {
+        let _ = sender.send(span.in_scope(solve));
+    });
+
+    let solved = receiver.await;
+    PROGRESS.send_replace(None);
+    return match solved {
+        Ok(solved) => solved,
+        // the result is only dropped unsent when the solve panicked
+        Err(_) => std::panic::resume_unwind(solver.join().unwrap_err()),
+    };
+}
+
+/// the progress of the running solve, if there is one
+pub fn latest() -> Option<Progress> {
+    return *PROGRESS.borrow();
+}
+
+/// a receiver that's told whenever the progress changes
+pub fn subscribe() -> watch::Receiver<Option<Progress>> {
+    return PROGRESS.subscribe();
+}
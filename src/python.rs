@@ -0,0 +1,203 @@
+use std::{fmt::Display, path::PathBuf};
+
+use clap::ValueEnum;
+use ndarray::{Array2, Axis};
+use numpy::{IntoPyArray, PyArray2, PyReadonlyArray2};
+use pyo3::{exceptions::{PyRuntimeError, PyValueError}, prelude::*};
+
+use crate::{algebra::{self, Basis}, assets::{self, FetchOptions}, audio::{self, Processor}, decode, export::{self, Anchor, Category, Format}, exporter::{self, Song}, features::{self, FeatureKind}, mojang, timing::Timing, versions};
+
+/// commands a tick runs by default, the same as the cli's
+static COMMANDS_PER_TICK: usize = 80;
+
+/// the `(event, pitch)` of every sound in a basis
+type SoundIds = Vec<(String, f32)>;
+
+fn runtime_error(error: impl Display) -> PyErr {
+    return PyRuntimeError::new_err(error.to_string());
+}
+
+fn value_error(error: impl Display) -> PyErr {
+    return PyValueError::new_err(error.to_string());
+}
+
+fn feature_kind(features: &str) -> PyResult<FeatureKind> {
+    return FeatureKind::from_str(features, true).map_err(value_error);
+}
+
+/// the features of every predictable sound of `version` at every pitch, as
+/// `(sound_ids, basis)`: the `(event, pitch)` of every column and the basis,
+/// features by sounds. atoms spanning `atom_ticks` stack their ticks'
+/// features. the assets are fetched into `assets` like the cli's
+#[pyfunction]
+#[pyo3(signature = (version, assets = "assets", atom_ticks = 1, analysis_rate = 48000, features = "mel-weighted", manifest_url = mojang::VERSION_MANIFEST_URL))]
+fn build_basis<'py>(
+    py: Python<'py>,
+    version: &str,
+    assets: &str,
+    atom_ticks: usize,
+    analysis_rate: usize,
+    features: &str,
+    manifest_url: &str
+) -> PyResult<(SoundIds, Bound<'py, PyArray2<f32>>)> {
+    let extractor = feature_kind(features)?.extractor();
+    let assets = PathBuf::from(assets);
+
+    let basis = py.detach(|| {
+        let runtime = tokio::runtime::Runtime::new()?;
+        let sounds = runtime.block_on(async {
+            let options = FetchOptions::default();
+            let manifest = assets::fetch_version_manifest(&assets, &options, manifest_url).await?;
+            let version = versions::resolve(&manifest, version)?;
+            let version_assets = assets::fetch_version_assets(&version, &assets, &options).await?;
+            let (sounds, _) = assets::fetch_predictable_sounds(version, version_assets, None, &assets, &options, analysis_rate, &mut Timing::new()).await?;
+            Ok::<_, anyhow::Error>(sounds)
+        })?;
+
+        let sounds = audio::permute_with_pitch(audio::dedup(sounds), 32, atom_ticks);
+        let (sound_ids, sounds): (SoundIds, Vec<audio::Sound>) = sounds.into_iter().unzip();
+
+        let processor = Processor::new();
+        let samples_per_tick = audio::time_as_samples!(analysis_rate, 50);
+        let columns = features::atom_features(&sounds, atom_ticks, samples_per_tick, |batch| extractor.extract_batch(batch, &processor));
+        Ok::<_, anyhow::Error>((sound_ids, algebra::matrix_from_vecs(columns)?.reversed_axes()))
+    });
+
+    let (sound_ids, basis) = basis.map_err(runtime_error)?;
+    return Ok((sound_ids, basis.as_standard_layout().into_owned().into_pyarray(py)));
+}
+
+/// the features of every tick of a mono `.wav`, features by ticks, to solve
+/// for with a basis of the same `features` and `analysis_rate`
+#[pyfunction]
+#[pyo3(signature = (path, analysis_rate = 48000, features = "mel-weighted"))]
+fn chunk_input<'py>(py: Python<'py>, path: PathBuf, analysis_rate: usize, features: &str) -> PyResult<Bound<'py, PyArray2<f32>>> {
+    let extractor = feature_kind(features)?.extractor();
+
+    let chunks = py.detach(|| {
+        let reader = hound::WavReader::open(&path)?;
+        if reader.spec().channels > 1 {
+            return Err(anyhow::anyhow!("`{}` is stereo, only mono is supported", path.to_string_lossy()));
+        }
+
+        let mut audio = decode::read_wav(reader)?;
+        audio.resample(analysis_rate);
+
+        let samples_per_tick = audio::time_as_samples!(analysis_rate, 50);
+        let chunks = audio::chunk_ticks(&audio, samples_per_tick, audio.samples.len().div_ceil(samples_per_tick));
+        Ok(algebra::matrix_from_vecs(extractor.extract_batch(&chunks, &Processor::new()))?.reversed_axes())
+    });
+
+    return Ok(chunks.map_err(runtime_error)?.as_standard_layout().into_owned().into_pyarray(py));
+}
+
+/// the non-negative amplitudes, sounds by ticks, that best make `chunks`
+/// out of `basis`, from `chunk_input` and `build_basis`. they're solved as
+/// given, scale them like `--normalization` beforehand. `step` is worked
+/// out from the basis when it's left out
+#[pyfunction]
+#[pyo3(signature = (chunks, basis, atom_ticks = 1, iters = 128, step = None, looping = false, devices = Vec::new()))]
+#[allow(clippy::too_many_arguments)]
+fn solve<'py>(
+    py: Python<'py>,
+    chunks: PyReadonlyArray2<'py, f32>,
+    basis: PyReadonlyArray2<'py, f32>,
+    atom_ticks: usize,
+    iters: usize,
+    step: Option<f32>,
+    looping: bool,
+    devices: Vec<usize>
+) -> PyResult<Bound<'py, PyArray2<f32>>> {
+    let (chunks, basis) = (chunks.as_array().to_owned(), basis.as_array().to_owned());
+    if basis.dim().0 != chunks.dim().0 * atom_ticks {
+        return Err(value_error(format!("a basis of {} features doesn't fit chunks of {} with atoms of {} ticks", basis.dim().0, chunks.dim().0, atom_ticks)));
+    }
+
+    let solved = py.detach(|| {
+        let initial = Array2::zeros((basis.dim().1, chunks.dim().1));
+        let devices = algebra::select_devices(&devices)?;
+        let basis = Basis::from_array(basis, &devices)?;
+
+        let step = match step {
+            Some(step) => step,
+            None => basis.auto_step(atom_ticks)?.ok_or_else(|| anyhow::anyhow!("basis is all zeros, cannot pick a step size"))?,
+        };
+
+        let (h, _) = algebra::conv_pgd_nnls(chunks.view(), &basis, atom_ticks, initial, iters, step, looping)?;
+        Ok::<_, anyhow::Error>(h)
+    });
+
+    return Ok(solved.map_err(runtime_error)?.into_pyarray(py));
+}
+
+/// exports `amplitudes`, sounds by ticks, to `output` as the cli would,
+/// playing the loudest `budget` sounds of every tick at their amplitude.
+/// `sound_ids` are the ones `build_basis` gave. `pack_format` is the data
+/// pack format of the version it's played on, 1.21's by default
+#[pyfunction]
+#[pyo3(signature = (sound_ids, amplitudes, output, format = "datapack", budget = COMMANDS_PER_TICK, force = false, atom_ticks = 1, looping = false, pack_format = export::PACK_FORMAT))]
+#[allow(clippy::too_many_arguments)]
+fn export_schedule(
+    py: Python<'_>,
+    sound_ids: SoundIds,
+    amplitudes: PyReadonlyArray2<'_, f32>,
+    output: PathBuf,
+    format: &str,
+    budget: usize,
+    force: bool,
+    atom_ticks: usize,
+    looping: bool,
+    pack_format: u32
+) -> PyResult<()> {
+    let format = Format::from_str(format, true).map_err(value_error)?;
+    let amplitudes = amplitudes.as_array().to_owned();
+    if amplitudes.dim().0 != sound_ids.len() {
+        return Err(value_error(format!("{} rows of amplitudes for {} sounds", amplitudes.dim().0, sound_ids.len())));
+    }
+
+    let exported = py.detach(|| {
+        let runtime = tokio::runtime::Runtime::new()?;
+        runtime.block_on(async {
+            match format {
+                Format::Datapack => export::prepare_output(&output, force).await,
+                #[cfg(feature = "structure")]
+                Format::Structure => crate::structure::prepare_output(&output, force).await,
+            }
+        })?;
+
+        let song = Song {
+            output,
+            n_ticks: amplitudes.dim().1,
+            atom_ticks,
+            category: Category::default(),
+            anchor: Anchor::default(),
+            directions: None,
+            looping,
+            on_finish: None,
+            duck: Vec::new(),
+            ticks_per_function: 1,
+            pack_format,
+            open_format: false,
+            annotate: false,
+            residuals: None
+        };
+        let mut exporter = exporter::create(format, &song)?;
+        let mut selector = export::Selector::default();
+        for (index, amplitudes) in amplitudes.axis_iter(Axis(1)).enumerate() {
+            exporter.write_tick(index, &selector.select(amplitudes, &sound_ids, budget))?;
+        }
+        exporter.finish()
+    });
+
+    return exported.map_err(runtime_error);
+}
+
+/// the `minecraft_player` module, built with `maturin build --features python`
+#[pymodule]
+fn minecraft_player(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_function(wrap_pyfunction!(build_basis, module)?)?;
+    module.add_function(wrap_pyfunction!(chunk_input, module)?)?;
+    module.add_function(wrap_pyfunction!(solve, module)?)?;
+    module.add_function(wrap_pyfunction!(export_schedule, module)?)?;
+    return Ok(());
+}
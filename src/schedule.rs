@@ -0,0 +1,232 @@
+use std::{collections::HashMap, fs, hash::{DefaultHasher, Hash, Hasher}, io::{BufReader, BufWriter, Read, Write}, path::{Path, PathBuf}};
+
+use ndarray::Array2;
+use serde::{Deserialize, Serialize};
+
+use crate::{audio::Sound, pitch::Character};
+
+static MAGIC: &[u8; 4] = b"MCPS";
+static FORMAT_VERSION: u32 = 1;
+
+static SCHEDULE_DIRECTORY: &str = "schedules";
+
+/// longer sound names mean the file isn't a schedule, rather than a reason
+/// to allocate whatever the length says
+static MAX_NAME: usize = 1024;
+
+#[derive(thiserror::Error, Debug)]
+pub enum ScheduleError {
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("`{0}` is not a schedule")]
+    NotASchedule(PathBuf),
+    #[error("schedule was written by an incompatible version")]
+    Incompatible,
+    #[error("schedule is truncated")]
+    Truncated,
+    #[error("schedule has a bad shape: {0}")]
+    Shape(#[from] ndarray::ShapeError),
+    #[error("invalid json: {0}")]
+    Json(#[from] serde_json::Error)
+}
+
+/// a finished solve: the amplitude of every sound in every tick, before
+/// anything export-only (epsilon, budgets) is applied
+pub struct Schedule {
+    pub sound_ids: Vec<(String, f32)>,
+    pub amplitudes: Array2<f32>
+}
+
+/// identifies a solve by the decoded audio of its inputs and everything in
+/// `settings` that changes the result. std's hasher is only stable within a
+/// toolchain, a new one just misses the cache once
+pub fn fingerprint(inputs: &[Sound], settings: &str) -> String {
+    let mut hasher = DefaultHasher::new();
+    settings.hash(&mut hasher);
+
+    return format!("{:016x}{:016x}", audio_hash(inputs), hasher.finish());
+}
+
+fn audio_hash(inputs: &[Sound]) -> u64 {
+    let mut audio = DefaultHasher::new();
+    for input in inputs {
+        input.sample_rate.hash(&mut audio);
+        input.samples.len().hash(&mut audio);
+        for sample in &input.samples {
+            sample.to_bits().hash(&mut audio);
+        }
+    }
+
+    return audio.finish();
+}
+
+/// the settings of every stage before the export, which `fingerprint` tells
+/// solves apart by. the last ones of every input are kept, so a rerun that
+/// can't reuse a schedule can say which stage its settings changed
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Stages {
+    /// which sounds are in the basis, at which pitches and lengths
+    pub basis: String,
+    /// how the input is solved with them
+    pub solve: String,
+}
+
+impl Stages {
+    /// every stage, as the settings of `fingerprint`
+    pub fn settings(&self) -> String {
+        format!("{} {}", self.basis, self.solve)
+    }
+
+    /// the first stage whose settings aren't the same as in `previous`
+    pub fn changed_since(&self, previous: &Stages) -> Option<&'static str> {
+        if self.basis != previous.basis {
+            return Some("basis");
+        }
+        if self.solve != previous.solve {
+            return Some("solve");
+        }
+
+        None
+    }
+
+    pub fn load(path: &Path) -> Option<Self> {
+        serde_json::from_str(&fs::read_to_string(path).ok()?).ok()
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), ScheduleError> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        fs::write(path, serde_json::to_string(self)?)?;
+        Ok(())
+    }
+}
+
+/// where the last `Stages` solved for `inputs` are kept in the assets directory
+pub fn stages_path(assets: &Path, inputs: &[Sound]) -> PathBuf {
+    assets.join(SCHEDULE_DIRECTORY).join(format!("{:016x}", audio_hash(inputs))).with_extension("json")
+}
+
+/// where the `pitch::Character` of every sound of a basis is kept in the
+/// assets directory, by the `Stages::basis` it's built with. `basis` should
+/// also have the rate the sounds are analyzed at
+pub fn characters_path(assets: &Path, basis: &str) -> PathBuf {
+    let mut hasher = DefaultHasher::new();
+    basis.hash(&mut hasher);
+
+    return assets.join(SCHEDULE_DIRECTORY).join(format!("characters-{:016x}", hasher.finish())).with_extension("json");
+}
+
+/// the characters `save_characters` kept at `path`, by event
+pub fn load_characters(path: &Path) -> Option<HashMap<String, Character>> {
+    return serde_json::from_str(&fs::read_to_string(path).ok()?).ok();
+}
+
+pub fn save_characters(path: &Path, characters: &HashMap<String, Character>) -> Result<(), ScheduleError> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    fs::write(path, serde_json::to_string(characters)?)?;
+    return Ok(());
+}
+
+/// where the schedule of `fingerprint` is kept in the assets directory
+pub fn path(assets: &Path, fingerprint: &str) -> PathBuf {
+    assets.join(SCHEDULE_DIRECTORY).join(fingerprint).with_extension("bin")
+}
+
+fn read_u64(reader: &mut impl Read) -> Result<u64, ScheduleError> {
+    let mut buf = [0u8; 8];
+    reader.read_exact(&mut buf).map_err(|_| ScheduleError::Truncated)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+impl Schedule {
+    /// same layout as a checkpoint, with the sound ids between the header
+    /// and the amplitudes
+    pub fn save(&self, path: &Path) -> Result<(), ScheduleError> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let mut temp_path = path.as_os_str().to_owned();
+        temp_path.push(".part");
+        let temp_path = PathBuf::from(temp_path);
+
+        let (rows, cols) = self.amplitudes.dim();
+        let mut writer = BufWriter::new(fs::File::create(&temp_path)?);
+        writer.write_all(MAGIC)?;
+        writer.write_all(&FORMAT_VERSION.to_le_bytes())?;
+        writer.write_all(&(rows as u64).to_le_bytes())?;
+        writer.write_all(&(cols as u64).to_le_bytes())?;
+
+        for (name, pitch) in &self.sound_ids {
+            writer.write_all(&(name.len() as u64).to_le_bytes())?;
+            writer.write_all(name.as_bytes())?;
+            writer.write_all(&pitch.to_le_bytes())?;
+        }
+
+        for value in self.amplitudes.iter() {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+
+        writer.flush()?;
+        drop(writer);
+        fs::rename(&temp_path, path)?;
+
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> Result<Self, ScheduleError> {
+        let mut reader = BufReader::new(fs::File::open(path)?);
+
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic).map_err(|_| ScheduleError::Truncated)?;
+        if &magic != MAGIC {
+            return Err(ScheduleError::NotASchedule(path.to_path_buf()));
+        }
+
+        let mut version = [0u8; 4];
+        reader.read_exact(&mut version).map_err(|_| ScheduleError::Truncated)?;
+        if u32::from_le_bytes(version) != FORMAT_VERSION {
+            return Err(ScheduleError::Incompatible);
+        }
+
+        let rows = read_u64(&mut reader)? as usize;
+        let cols = read_u64(&mut reader)? as usize;
+
+        let mut sound_ids = Vec::new();
+        for _ in 0..rows {
+            let length = read_u64(&mut reader)? as usize;
+            if length > MAX_NAME {
+                return Err(ScheduleError::NotASchedule(path.to_path_buf()));
+            }
+
+            let mut name = vec![0u8; length];
+            let mut pitch = [0u8; 4];
+            reader.read_exact(&mut name).map_err(|_| ScheduleError::Truncated)?;
+            reader.read_exact(&mut pitch).map_err(|_| ScheduleError::Truncated)?;
+
+            let name = String::from_utf8(name).map_err(|_| ScheduleError::NotASchedule(path.to_path_buf()))?;
+            sound_ids.push((name, f32::from_le_bytes(pitch)));
+        }
+
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        if bytes.len() != rows * cols * 4 {
+            return Err(ScheduleError::Truncated);
+        }
+
+        let values = bytes
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect::<Vec<f32>>();
+
+        Ok(Self {
+            sound_ids,
+            amplitudes: Array2::from_shape_vec((rows, cols), values)?
+        })
+    }
+}
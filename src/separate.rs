@@ -0,0 +1,103 @@
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use rustfft::num_complex::Complex32;
+use tracing::{span, Level};
+
+use crate::audio::{Processor, Sound};
+
+static FRAME: usize = 2048;
+static HOP: usize = FRAME / 4;
+
+/// frames (for harmonic) or bins (for percussive) the median filters span
+static KERNEL: usize = 17;
+
+fn median(values: &mut [f32]) -> f32 {
+    values.sort_by(|a, b| a.total_cmp(b));
+    values[values.len() / 2]
+}
+
+/// splits a sound into its harmonic and percussive parts by median filtering
+/// the spectrogram (Fitzgerald, 2010). sustained tones are smooth over time
+/// and drum hits are smooth over frequency, so the two filtered spectrograms
+/// become soft masks. the parts add back up to the original
+pub fn hpss(sound: &Sound, processor: &Processor) -> (Sound, Sound) {
+    let _span = span!(Level::DEBUG, "hpss", tag = "audio").entered();
+
+    let window = apodize::hanning_iter(FRAME).map(|w| w as f32).collect::<Vec<f32>>();
+    let length = sound.samples.len();
+
+    // padded by a frame on both sides so the edges are covered by full overlap
+    let mut padded = vec![0.0; FRAME];
+    padded.extend_from_slice(&sound.samples);
+    padded.resize(padded.len() + 2 * FRAME, 0.0);
+
+    let frames = (padded.len() - FRAME) / HOP + 1;
+    let fft = processor.plan(FRAME, false);
+    let spectrogram = (0..frames)
+        .into_par_iter()
+        .map(|frame| {
+            let mut buffer = padded[frame * HOP..frame * HOP + FRAME].iter()
+                .zip(&window)
+                .map(|(sample, w)| Complex32::new(sample * w, 0.0))
+                .collect::<Vec<Complex32>>();
+            fft.process(&mut buffer);
+            buffer
+        })
+        .collect::<Vec<Vec<Complex32>>>();
+
+    // the input is real, so only the bins up to nyquist are needed and the
+    // masks are mirrored for the rest
+    let bins = FRAME / 2 + 1;
+    let magnitude = spectrogram.iter()
+        .map(|frame| frame[..bins].iter().map(|bin| bin.norm()).collect::<Vec<f32>>())
+        .collect::<Vec<Vec<f32>>>();
+
+    let half = KERNEL / 2;
+    let masks = (0..frames)
+        .into_par_iter()
+        .map(|frame| {
+            (0..bins).map(|bin| {
+                let mut across_time = (frame.saturating_sub(half)..(frame + half + 1).min(frames))
+                    .map(|f| magnitude[f][bin])
+                    .collect::<Vec<f32>>();
+                let mut across_bins = magnitude[frame][bin.saturating_sub(half)..(bin + half + 1).min(bins)].to_vec();
+
+                let harmonic = median(&mut across_time).powi(2);
+                let percussive = median(&mut across_bins).powi(2);
+
+                if harmonic + percussive > 0.0 { harmonic / (harmonic + percussive) } else { 0.5 }
+            })
+            .collect::<Vec<f32>>()
+        })
+        .collect::<Vec<Vec<f32>>>();
+
+    let ifft = processor.plan(FRAME, true);
+    let mut harmonic = vec![0.0; padded.len()];
+    let mut percussive = vec![0.0; padded.len()];
+    let mut norm = vec![0.0; padded.len()];
+
+    for (frame, (spectrum, mask)) in spectrogram.into_iter().zip(masks).enumerate() {
+        let mask = |bin: usize| mask[bin.min(FRAME - bin)];
+        let mut harmonic_frame = spectrum.iter().enumerate().map(|(bin, value)| value * mask(bin)).collect::<Vec<Complex32>>();
+        let mut percussive_frame = spectrum.iter().enumerate().map(|(bin, value)| value * (1.0 - mask(bin))).collect::<Vec<Complex32>>();
+        ifft.process(&mut harmonic_frame);
+        ifft.process(&mut percussive_frame);
+
+        // rustfft doesn't scale the inverse
+        for i in 0..FRAME {
+            let at = frame * HOP + i;
+            harmonic[at] += harmonic_frame[i].re * window[i] / FRAME as f32;
+            percussive[at] += percussive_frame[i].re * window[i] / FRAME as f32;
+            norm[at] += window[i] * window[i];
+        }
+    }
+
+    let unpad = |samples: Vec<f32>| samples[FRAME..FRAME + length].iter()
+        .zip(&norm[FRAME..FRAME + length])
+        .map(|(sample, norm)| if *norm > 1e-6 { sample / norm } else { 0.0 })
+        .collect::<Vec<f32>>();
+
+    return (
+        Sound { samples: unpad(harmonic), sample_rate: sound.sample_rate },
+        Sound { samples: unpad(percussive), sample_rate: sound.sample_rate }
+    );
+}
@@ -0,0 +1,424 @@
+use std::{collections::{HashMap, VecDeque}, fmt::Display, fs, future::Future, io, net::SocketAddr, path::{Path, PathBuf}, sync::{Arc, Mutex}, time::{Duration, SystemTime, UNIX_EPOCH}};
+
+use axum::{body::Bytes, extract::{DefaultBodyLimit, Path as UrlPath, State}, http::{header, StatusCode}, response::{IntoResponse, Response}, routing::{get, post}, Json, Router};
+use ndarray_rand::rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Notify;
+use tracing::{event, Level};
+
+use crate::{export, logging, progress::{self, Progress}};
+
+static INPUT_FILE: &str = "input.wav";
+static OUTPUT_DIRECTORY: &str = "datapack";
+static ZIP_FILE: &str = "datapack.zip";
+static CHECKPOINT_FILE: &str = "checkpoint.bin";
+static JOB_FILE: &str = "job.json";
+static LOCK_FILE: &str = "serve.lock";
+
+/// log lines of the running job a poll gets back
+static PROGRESS_LINES: usize = 20;
+
+/// times a job is run before it's failed, when the server keeps stopping
+/// while it runs. a job that crashes the server would otherwise crash
+/// every one after it
+static MAX_ATTEMPTS: u32 = 3;
+
+#[derive(thiserror::Error, Debug)]
+pub enum ServeError {
+    #[error("io error: {0}")]
+    Io(#[from] io::Error),
+    #[error("invalid json: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("another server is using `{0}`")]
+    Busy(PathBuf)
+}
+
+pub struct ServeOptions {
+    pub listen: SocketAddr,
+    /// every job gets a directory of its own in here
+    pub directory: PathBuf,
+    /// jobs waiting beyond this are turned away
+    pub max_queued: usize,
+    pub max_upload: usize,
+    /// how long a finished job's datapack can be downloaded for
+    pub keep: Duration
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "state", rename_all = "lowercase")]
+pub enum JobState {
+    Queued { position: usize },
+    Running {
+        log: Vec<String>,
+        /// how far its solve is, while it's solving
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        progress: Option<Progress>
+    },
+    Done,
+    Failed { error: String }
+}
+
+/// what a job is converted from and into, all in its own directory
+pub struct JobFiles {
+    pub input: PathBuf,
+    /// the datapack, which is zipped once it's done
+    pub output: PathBuf,
+    /// where the conversion saves its progress when the server stops, a
+    /// restart resumes from it when it's there
+    pub checkpoint: PathBuf
+}
+
+/// a job as it's kept in its directory's `job.json`
+#[derive(Serialize, Deserialize, Clone)]
+struct Job {
+    state: JobState,
+    /// milliseconds since the epoch, to queue jobs again in order
+    submitted: u64,
+    finished: Option<u64>,
+    /// times it was started
+    #[serde(default)]
+    attempts: u32
+}
+
+#[derive(Default)]
+struct Queue {
+    /// ids of the jobs waiting, first to run first
+    waiting: VecDeque<String>,
+    jobs: HashMap<String, Job>
+}
+
+fn now() -> u64 {
+    return SystemTime::now().duration_since(UNIX_EPOCH).map(|since| since.as_millis() as u64).unwrap_or(0);
+}
+
+/// the jobs of a server, each with a directory of its own, queued to run
+/// one at a time. their state is kept on disk, so a restarted server
+/// picks up where it left off. only one server can use a directory, so
+/// there's never more than one job on the device
+pub struct JobManager {
+    directory: PathBuf,
+    queue: Mutex<Queue>,
+    added: Notify,
+    rng: Mutex<StdRng>,
+    /// locked for as long as the manager lives
+    _lock: fs::File
+}
+
+impl JobManager {
+    /// locks `directory` and loads the jobs in it. ones that were running
+    /// are queued again before the rest, unless they've been started
+    /// `MAX_ATTEMPTS` times already
+    pub fn open(directory: &Path) -> Result<Self, ServeError> {
+        fs::create_dir_all(directory)?;
+        let lock = fs::File::create(directory.join(LOCK_FILE))?;
+        if lock.try_lock().is_err() {
+            return Err(ServeError::Busy(directory.to_path_buf()));
+        }
+
+        let mut queue = Queue::default();
+        for entry in fs::read_dir(directory)? {
+            let entry = entry?;
+            let path = entry.path().join(JOB_FILE);
+            if !entry.file_type()?.is_dir() || !path.exists() {
+                continue;
+            }
+
+            let id = entry.file_name().to_string_lossy().to_string();
+            match serde_json::from_str::<Job>(&fs::read_to_string(&path)?) {
+                Ok(job) => queue.jobs.insert(id, job),
+                Err(error) => {
+                    event!(Level::WARN, "skipping job {}, its state is broken: {}", id, error);
+                    continue;
+                },
+            };
+        }
+
+        let given_up = queue.jobs.iter()
+            .filter(|(_, job)| job.finished.is_none() && matches!(job.state, JobState::Running { .. }) && job.attempts >= MAX_ATTEMPTS)
+            .map(|(id, job)| (id.clone(), job.attempts))
+            .collect::<Vec<_>>();
+
+        let mut unfinished = queue.jobs.iter()
+            .filter(|(id, job)| job.finished.is_none() && !given_up.iter().any(|(given_up, _)| given_up == *id))
+            .map(|(id, job)| (!matches!(job.state, JobState::Running { .. }), job.submitted, id.clone()))
+            .collect::<Vec<_>>();
+        unfinished.sort();
+        if !unfinished.is_empty() {
+            event!(Level::INFO, "queued {} unfinished jobs again", unfinished.len());
+        }
+        queue.waiting = unfinished.into_iter().map(|(_, _, id)| id).collect();
+
+        let jobs = JobManager {
+            directory: directory.to_path_buf(),
+            queue: Mutex::new(queue),
+            added: Notify::new(),
+            rng: Mutex::new(StdRng::from_entropy()),
+            _lock: lock
+        };
+
+        for (id, attempts) in given_up {
+            event!(Level::WARN, "job {} failed, the server stopped during all {} of its attempts", id, attempts);
+            let error = format!("the server stopped during all {} attempts to run it", attempts);
+            if let Err(error) = jobs.update(&id, JobState::Failed { error }) {
+                event!(Level::ERROR, "could not save that job {} failed: {}", id, error);
+            }
+        }
+
+        return Ok(jobs);
+    }
+
+    fn job_directory(&self, id: &str) -> PathBuf {
+        self.directory.join(id)
+    }
+
+    pub fn files(&self, id: &str) -> JobFiles {
+        let directory = self.job_directory(id);
+        return JobFiles {
+            input: directory.join(INPUT_FILE),
+            output: directory.join(OUTPUT_DIRECTORY),
+            checkpoint: directory.join(CHECKPOINT_FILE)
+        };
+    }
+
+    /// sets the state of a job, in memory and on disk. written to a
+    /// temporary file first so a crash never leaves a broken one
+    fn update(&self, id: &str, state: JobState) -> Result<(), ServeError> {
+        let mut queue = self.queue.lock().unwrap();
+        let Some(job) = queue.jobs.get_mut(id) else {
+            return Ok(());
+        };
+
+        if matches!(state, JobState::Done | JobState::Failed { .. }) {
+            job.finished = Some(now());
+        }
+        job.state = state;
+
+        let path = self.job_directory(id).join(JOB_FILE);
+        let temporary = path.with_extension("json.tmp");
+        fs::write(&temporary, serde_json::to_string(job)?)?;
+        fs::rename(temporary, path)?;
+        return Ok(());
+    }
+
+    pub fn waiting(&self) -> usize {
+        self.queue.lock().unwrap().waiting.len()
+    }
+
+    /// stores `input` in a new job's directory and queues it, returning
+    /// its id
+    pub fn submit(&self, input: &[u8]) -> Result<String, ServeError> {
+        // unguessable, the id is all it takes to download a job's datapack
+        let id = format!("{:016x}", self.rng.lock().unwrap().gen::<u64>());
+        fs::create_dir_all(self.job_directory(&id))?;
+        fs::write(self.files(&id).input, input)?;
+
+        let job = Job { state: JobState::Queued { position: 0 }, submitted: now(), finished: None, attempts: 0 };
+        self.queue.lock().unwrap().jobs.insert(id.clone(), job);
+        self.update(&id, JobState::Queued { position: 0 })?;
+
+        self.queue.lock().unwrap().waiting.push_back(id.clone());
+        self.added.notify_one();
+        return Ok(id);
+    }
+
+    /// the state of a job, with the position of queued ones worked out
+    pub fn state(&self, id: &str) -> Option<JobState> {
+        let queue = self.queue.lock().unwrap();
+        return match &queue.jobs.get(id)?.state {
+            JobState::Queued { .. } => {
+                let position = queue.waiting.iter().position(|waiting| waiting == id).unwrap_or(0);
+                Some(JobState::Queued { position })
+            },
+            JobState::Running { .. } => {
+                let log = logging::recent_lines();
+                Some(JobState::Running { log: log[log.len().saturating_sub(PROGRESS_LINES)..].to_vec(), progress: progress::latest() })
+            },
+            state => Some(state.clone()),
+        };
+    }
+
+    /// waits for the next job
+    pub async fn next(&self) -> String {
+        loop {
+            let next = self.queue.lock().unwrap().waiting.pop_front();
+            match next {
+                Some(id) => return id,
+                None => self.added.notified().await,
+            }
+        }
+    }
+
+    /// marks a job running, counting the attempt before it's saved so one
+    /// that stops the server is counted too
+    pub fn start(&self, id: &str) -> Result<(), ServeError> {
+        if let Some(job) = self.queue.lock().unwrap().jobs.get_mut(id) {
+            job.attempts += 1;
+        }
+        return self.update(id, JobState::Running { log: Vec::new(), progress: None });
+    }
+
+    /// marks a job failed when its state couldn't be saved, in memory at
+    /// least, so the server can go on with the others. returns when it
+    /// failed
+    fn fail(&self, id: &str, error: ServeError) -> u64 {
+        event!(Level::ERROR, "job {} failed, its state couldn't be saved: {}", id, error);
+        let failed = JobState::Failed { error: format!("its state couldn't be saved: {}", error) };
+        if let Err(error) = self.update(id, failed) {
+            event!(Level::ERROR, "could not save that job {} failed either: {}", id, error);
+        }
+        return now();
+    }
+
+    /// zips a job's datapack once it's converted, or records why it wasn't.
+    /// returns when it finished
+    pub fn finish(&self, id: &str, converted: Result<(), String>) -> Result<u64, ServeError> {
+        let files = self.files(id);
+        let converted = converted.and_then(|()| {
+            let zip = fs::File::create(self.job_directory(id).join(ZIP_FILE)).map_err(|error| error.to_string())?;
+            export::zip_directory(&files.output, zip).map(|_| ()).map_err(|error| error.to_string())
+        });
+
+        let state = match converted {
+            Ok(()) => JobState::Done,
+            Err(error) => {
+                event!(Level::WARN, "job {} failed: {}", id, error);
+                JobState::Failed { error }
+            },
+        };
+        self.update(id, state)?;
+        return Ok(now());
+    }
+
+    /// when each finished job was finished, to forget them after a while
+    pub fn finished(&self) -> Vec<(String, u64)> {
+        return self.queue.lock().unwrap().jobs.iter()
+            .filter_map(|(id, job)| Some((id.clone(), job.finished?)))
+            .collect();
+    }
+
+    /// forgets a job and removes its files
+    pub fn remove(&self, id: &str) -> Result<(), ServeError> {
+        self.queue.lock().unwrap().jobs.remove(id);
+        fs::remove_dir_all(self.job_directory(id))?;
+        return Ok(());
+    }
+
+    /// forgets a finished job `keep` after it finished, so the server
+    /// doesn't fill its disk
+    fn expire(self: &Arc<Self>, id: String, finished: u64, keep: Duration) {
+        let remaining = (finished + keep.as_millis() as u64).saturating_sub(now());
+        let jobs = self.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(remaining)).await;
+            if let Err(removed) = jobs.remove(&id) {
+                event!(Level::WARN, "couldn't remove job {}: {}", id, removed);
+            }
+        });
+    }
+}
+
+struct Server {
+    jobs: Arc<JobManager>,
+    max_queued: usize
+}
+
+fn error(status: StatusCode, message: impl Display) -> Response {
+    return (status, Json(serde_json::json!({ "error": message.to_string() }))).into_response();
+}
+
+/// `POST /jobs` with a mono `.wav` as the body queues it, answering with
+/// the job's `id`
+async fn submit(State(server): State<Arc<Server>>, body: Bytes) -> Response {
+    if server.jobs.waiting() >= server.max_queued {
+        return error(StatusCode::SERVICE_UNAVAILABLE, "the queue is full, try again later");
+    }
+    if hound::WavReader::new(io::Cursor::new(&body)).is_err() {
+        return error(StatusCode::UNSUPPORTED_MEDIA_TYPE, "the body isn't a .wav");
+    }
+
+    return match server.jobs.submit(&body) {
+        Ok(id) => {
+            event!(Level::INFO, "queued job {}", id);
+            (StatusCode::ACCEPTED, Json(serde_json::json!({ "id": id }))).into_response()
+        },
+        Err(submitted) => {
+            event!(Level::ERROR, "couldn't queue a job: {}", submitted);
+            error(StatusCode::INTERNAL_SERVER_ERROR, "couldn't store the input")
+        },
+    };
+}
+
+/// `GET /jobs/{id}`: whether it's queued (and how many jobs are ahead of
+/// it), running (with the last lines it logged), done or failed
+async fn poll(State(server): State<Arc<Server>>, UrlPath(id): UrlPath<String>) -> Response {
+    return match server.jobs.state(&id) {
+        Some(state) => Json(state).into_response(),
+        None => error(StatusCode::NOT_FOUND, "no such job"),
+    };
+}
+
+/// `GET /jobs/{id}/datapack.zip`, once it's done
+async fn download(State(server): State<Arc<Server>>, UrlPath(id): UrlPath<String>) -> Response {
+    match server.jobs.state(&id) {
+        Some(JobState::Done) => {},
+        Some(_) => return error(StatusCode::CONFLICT, "the job isn't done"),
+        None => return error(StatusCode::NOT_FOUND, "no such job"),
+    }
+
+    return match tokio::fs::read(server.jobs.job_directory(&id).join(ZIP_FILE)).await {
+        Ok(zip) => ([(header::CONTENT_TYPE, "application/zip")], zip).into_response(),
+        Err(read) => error(StatusCode::INTERNAL_SERVER_ERROR, read),
+    };
+}
+
+/// serves the api on `options.listen` and runs the queued jobs one at a
+/// time with `convert`, which writes a datapack to `JobFiles::output`.
+/// jobs run on the calling task, so `convert` needn't be `Send`, while
+/// requests are answered on others. returns once `stop` does, leaving the
+/// job it stopped in to be resumed by the next server
+pub async fn serve<C, F, E, S>(options: ServeOptions, mut convert: C, stop: S) -> Result<(), ServeError>
+where
+    C: FnMut(JobFiles) -> F,
+    F: Future<Output = Result<(), E>>,
+    E: Display,
+    S: Fn() -> bool
+{
+    let jobs = Arc::new(JobManager::open(&options.directory)?);
+    for (id, finished) in jobs.finished() {
+        jobs.expire(id, finished, options.keep);
+    }
+
+    let listener = tokio::net::TcpListener::bind(options.listen).await?;
+    event!(Level::INFO, "listening on http://{}", listener.local_addr()?);
+
+    let server = Arc::new(Server { jobs: jobs.clone(), max_queued: options.max_queued });
+    let router = Router::new()
+        .route("/jobs", post(submit))
+        .route("/jobs/{id}", get(poll))
+        .route("/jobs/{id}/datapack.zip", get(download))
+        .layer(DefaultBodyLimit::max(options.max_upload))
+        .with_state(server);
+    let http = tokio::spawn(async move { axum::serve(listener, router).await });
+
+    while !stop() {
+        let id = jobs.next().await;
+        if let Err(error) = jobs.start(&id) {
+            let finished = jobs.fail(&id, error);
+            jobs.expire(id, finished, options.keep);
+            continue;
+        }
+        event!(Level::INFO, "running job {}", id);
+
+        let converted = convert(jobs.files(&id)).await.map_err(|error| format!("{:#}", error));
+        if stop() {
+            // still running as far as the next server knows
+            break;
+        }
+
+        let finished = jobs.finish(&id, converted).unwrap_or_else(|error| jobs.fail(&id, error));
+        jobs.expire(id, finished, options.keep);
+    }
+
+    http.abort();
+    return Ok(());
+}
@@ -0,0 +1,68 @@
+use std::{path::PathBuf, str::FromStr};
+
+use crate::pitch::Character;
+
+/// which basis sounds an input may be solved with
+#[derive(Clone, Copy, Default, Debug, PartialEq)]
+pub enum SoundFilter {
+    #[default]
+    All,
+    Tonal,
+    Percussive,
+}
+
+impl SoundFilter {
+    pub fn allows(&self, character: Option<&Character>) -> bool {
+        match (self, character) {
+            (SoundFilter::All, _) => true,
+            (SoundFilter::Tonal, Some(character)) => character.is_tonal(),
+            (SoundFilter::Percussive, Some(character)) => !character.is_tonal(),
+            (_, None) => false,
+        }
+    }
+}
+
+/// a pre-separated stem (e.g. `vocals.wav` from demucs), given on the command
+/// line as `path[,budget=N][,sounds=all|tonal|percussive]`
+#[derive(Clone, Debug)]
+pub struct Stem {
+    pub path: PathBuf,
+    /// commands per tick this stem may use, split evenly when `None`
+    pub budget: Option<usize>,
+    pub filter: SoundFilter,
+}
+
+impl Stem {
+    pub fn name(&self) -> String {
+        self.path.file_stem().map(|stem| stem.to_string_lossy().to_string()).unwrap_or_default()
+    }
+}
+
+impl FromStr for Stem {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut fields = s.split(',');
+        let path = PathBuf::from(fields.next().unwrap_or_default());
+        if path.as_os_str().is_empty() {
+            return Err("missing the stem's path".to_string());
+        }
+
+        let mut stem = Stem { path, budget: None, filter: SoundFilter::All };
+        for field in fields {
+            match field.split_once('=') {
+                Some(("budget", budget)) => match budget.parse::<usize>() {
+                    Ok(budget) if budget > 0 => stem.budget = Some(budget),
+                    _ => return Err(format!("`{}` is not a positive budget", budget)),
+                },
+                Some(("sounds", "all")) => stem.filter = SoundFilter::All,
+                Some(("sounds", "tonal")) => stem.filter = SoundFilter::Tonal,
+                Some(("sounds", "percussive")) => stem.filter = SoundFilter::Percussive,
+                Some(("sounds", other)) => return Err(format!("`{}` is not one of `all`, `tonal` or `percussive`", other)),
+                _ => return Err(format!("unknown stem option `{}`", field)),
+            }
+        }
+
+        Ok(stem)
+    }
+}
@@ -0,0 +1,225 @@
+use std::{io::Write, path::Path};
+
+use flate2::{write::GzEncoder, Compression};
+use tokio::fs;
+use tracing::{span, Level};
+
+use crate::{cancel, export::{self, ExportError, PlaySound, Stops}, exporter::{Exporter, Song}};
+
+/// the data version of 1.21, which `export::PACK_FORMAT` targets too
+pub static DATA_VERSION: i32 = 3953;
+
+/// ticks laid out next to each other before starting a new layer on top
+static LAYER_ROWS: usize = 64;
+
+/// the little of NBT that a structure needs
+enum Tag {
+    Byte(i8),
+    Int(i32),
+    String(String),
+    List(Vec<Tag>),
+    Compound(Vec<(&'static str, Tag)>)
+}
+
+impl Tag {
+    fn id(&self) -> u8 {
+        match self {
+            Tag::Byte(_) => 1,
+            Tag::Int(_) => 3,
+            Tag::String(_) => 8,
+            Tag::List(_) => 9,
+            Tag::Compound(_) => 10,
+        }
+    }
+
+    fn write_string(out: &mut Vec<u8>, s: &str) {
+        out.extend_from_slice(&(s.len() as u16).to_be_bytes());
+        out.extend_from_slice(s.as_bytes());
+    }
+
+    fn write_payload(&self, out: &mut Vec<u8>) {
+        match self {
+            Tag::Byte(value) => out.push(*value as u8),
+            Tag::Int(value) => out.extend_from_slice(&value.to_be_bytes()),
+            Tag::String(value) => Self::write_string(out, value),
+            Tag::List(values) => {
+                // an empty list is a list of end tags
+                out.push(values.first().map(Tag::id).unwrap_or(0));
+                out.extend_from_slice(&(values.len() as i32).to_be_bytes());
+                for value in values {
+                    value.write_payload(out);
+                }
+            },
+            Tag::Compound(fields) => {
+                for (name, value) in fields {
+                    out.push(value.id());
+                    Self::write_string(out, name);
+                    value.write_payload(out);
+                }
+                out.push(0);
+            },
+        }
+    }
+}
+
+fn position(x: usize, y: usize, z: usize) -> Tag {
+    Tag::List(vec![Tag::Int(x as i32), Tag::Int(y as i32), Tag::Int(z as i32)])
+}
+
+fn command_block(command: &str, auto: bool) -> Tag {
+    Tag::Compound(vec![
+        ("id", Tag::String(if auto { "minecraft:chain_command_block" } else { "minecraft:command_block" }.to_string())),
+        ("Command", Tag::String(command.to_string())),
+        ("auto", Tag::Byte(auto as i8)),
+        ("TrackOutput", Tag::Byte(0)),
+        ("UpdateLastExecution", Tag::Byte(1)),
+    ])
+}
+
+/// where the row of `tick` starts. ticks run along z and stack up in layers
+fn row(tick: usize) -> (usize, usize) {
+    (tick / LAYER_ROWS, tick % LAYER_ROWS)
+}
+
+/// a structure of command blocks that plays `ticks`, the commands of every
+/// tick in order. each tick is a row along x: a trigger, an impulse command
+/// block that clears it and chain command blocks for the commands, the last
+/// of which puts a redstone block on the next tick's trigger. command blocks
+/// run the tick after they're powered, so rows play one tick apart. playback
+/// starts with a redstone block on the trigger at the origin. the last row
+/// triggers the first when `looping`
+pub fn command_blocks(ticks: &[Vec<String>], looping: bool) -> Vec<u8> {
+    let _span = span!(Level::INFO, "command_blocks", tag = "export").entered();
+
+    // air, impulse and chain, all facing along the row
+    let block_state = |name: &str| Tag::Compound(vec![
+        ("Name", Tag::String(name.to_string())),
+        ("Properties", Tag::Compound(vec![
+            ("facing", Tag::String("east".to_string())),
+            ("conditional", Tag::String("false".to_string())),
+        ])),
+    ]);
+    let palette = vec![
+        Tag::Compound(vec![("Name", Tag::String("minecraft:air".to_string()))]),
+        block_state("minecraft:command_block"),
+        block_state("minecraft:chain_command_block"),
+    ];
+
+    let mut blocks = Vec::new();
+    let mut length = 0;
+    for (tick, commands) in ticks.iter().enumerate() {
+        let (y, z) = row(tick);
+        blocks.push(Tag::Compound(vec![("pos", position(0, y, z)), ("state", Tag::Int(0))]));
+
+        let mut row_commands = vec!["setblock ~-1 ~ ~ air".to_string()];
+        row_commands.extend(commands.iter().cloned());
+        let next = match tick + 1 < ticks.len() {
+            true => Some(tick + 1),
+            false => looping.then_some(0),
+        };
+        if let Some(next) = next {
+            let (next_y, next_z) = row(next);
+            let x = row_commands.len() + 1;
+            row_commands.push(format!(
+                "setblock ~-{} ~{} ~{} redstone_block",
+                x, next_y as i64 - y as i64, next_z as i64 - z as i64
+            ));
+        }
+
+        for (index, command) in row_commands.iter().enumerate() {
+            let auto = index > 0;
+            blocks.push(Tag::Compound(vec![
+                ("pos", position(index + 1, y, z)),
+                ("state", Tag::Int(if auto { 2 } else { 1 })),
+                ("nbt", command_block(command, auto)),
+            ]));
+        }
+        length = length.max(row_commands.len() + 1);
+    }
+
+    let (layers, rows) = match ticks.len() {
+        0 => (0, 0),
+        n => (n.div_ceil(LAYER_ROWS), n.min(LAYER_ROWS)),
+    };
+
+    let root = Tag::Compound(vec![
+        ("DataVersion", Tag::Int(DATA_VERSION)),
+        ("size", position(length, layers, rows)),
+        ("palette", Tag::List(palette)),
+        ("blocks", Tag::List(blocks)),
+        ("entities", Tag::List(Vec::new())),
+    ]);
+
+    // the root is a named compound, with an empty name
+    let mut out = vec![root.id()];
+    Tag::write_string(&mut out, "");
+    root.write_payload(&mut out);
+
+    return out;
+}
+
+/// refuses to overwrite an existing file unless `force` is set
+pub async fn prepare_output(output: &Path, force: bool) -> Result<(), ExportError> {
+    if fs::try_exists(output).await? && !force {
+        return Err(ExportError::Exists(output.to_path_buf()));
+    }
+
+    if let Some(parent) = output.parent() {
+        fs::create_dir_all(parent).await?;
+    }
+
+    Ok(())
+}
+
+/// writes the gzipped structure, ready for `/place template`
+pub fn write(output: &Path, ticks: &[Vec<String>], looping: bool) -> Result<(), ExportError> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(&command_blocks(ticks, looping))?;
+
+    std::fs::write(output, encoder.finish()?)?;
+    Ok(())
+}
+
+/// collects the commands of every tick and writes them as `command_blocks`
+/// at the end, whole or not at all
+pub struct Structure {
+    song: Song,
+    ticks: Vec<Vec<String>>,
+    stops: Stops
+}
+
+impl Structure {
+    pub fn new(song: Song) -> Self {
+        Structure { song, ticks: Vec::new(), stops: Stops::default() }
+    }
+}
+
+impl Exporter for Structure {
+    /// rows of the structure trigger the next one themselves
+    fn write_tick(&mut self, index: usize, sounds: &[PlaySound]) -> Result<(), anyhow::Error> {
+        let output = export::song_tick_sounds(&self.song, index, sounds, &mut self.stops);
+        self.ticks.push(output.lines().map(|line| line.trim().to_string()).collect::<Vec<String>>());
+        Ok(())
+    }
+
+    /// the first row ducks in place of `audio:start`, and what `audio:finish`
+    /// does runs in a row of its own after the last atoms ring out. a loop
+    /// starts over instead
+    fn finish(mut self: Box<Self>) -> Result<(), anyhow::Error> {
+        if cancel::requested() {
+            return Ok(());
+        }
+
+        let song = &self.song;
+        if let Some(first) = self.ticks.first_mut() {
+            first.splice(0..0, song.duck.iter().map(|category| export::stop_sounds(*category).trim().to_string()));
+        }
+
+        if !song.looping {
+            self.ticks.resize(self.ticks.len() + song.atom_ticks - 1, Vec::new());
+            self.ticks.push(export::finish_function(song.on_finish.as_deref(), song.category).lines().map(String::from).collect());
+        }
+
+        Ok(write(&song.output, &self.ticks, song.looping)?)
+    }
+}
@@ -0,0 +1,52 @@
+use ndarray::{Array2, ArrayView2};
+
+/// ticks per second, the rate commands are run at whatever the analysis
+pub static TICK_RATE: usize = 20;
+
+/// rms of each of the first `frames` frames of `samples`, silence past the end
+fn envelope(samples: &[f32], samples_per_frame: usize, frames: usize) -> Vec<f32> {
+    return (0..frames)
+        .map(|frame| {
+            let frame = samples.iter().skip(frame * samples_per_frame).take(samples_per_frame);
+            (frame.map(|sample| sample * sample).sum::<f32>() / samples_per_frame.max(1) as f32).sqrt()
+        })
+        .collect();
+}
+
+/// what an activation `offset` frames into a tick (for every offset below
+/// `frames`) is worth at the start of the tick, for a sound spanning `span`
+/// frames. started that much early, the sound has decayed by as much by the
+/// time it was wanted: the least squares stand-in is its envelope's overlap
+/// with itself shifted by the offset, relative to its energy. a held sound
+/// keeps nearly all of it, a short hit only what still overlaps
+pub fn decay_compensation(samples: &[f32], samples_per_frame: usize, frames: usize, span: usize) -> Vec<f32> {
+    let envelope = envelope(samples, samples_per_frame, span.max(frames));
+    let energy = envelope.iter().map(|rms| rms * rms).sum::<f32>();
+    if energy <= 0.0 {
+        return vec![1.0; frames];
+    }
+
+    return (0..frames)
+        .map(|offset| {
+            let overlap = envelope.iter().zip(&envelope[offset..]).map(|(early, late)| early * late).sum::<f32>();
+            (overlap / energy).clamp(0.0, 1.0)
+        })
+        .collect();
+}
+
+/// merges every `frames` columns of a solve at sub-tick resolution into the
+/// tick they're in, summing them weighted by their row's `compensation` at
+/// their offset into the tick. `column_scales` undoes `normalize_columns`
+/// of the frames first, as they don't carry over to ticks
+pub fn quantize(h: ArrayView2<f32>, frames: usize, column_scales: Option<&[f32]>, compensation: &[&[f32]]) -> Array2<f32> {
+    let (rows, n_frames) = h.dim();
+    assert_eq!(rows, compensation.len());
+
+    let scale = |column: usize| column_scales.map(|scales| scales[column]).unwrap_or(1.0);
+    let mut ticks = Array2::<f32>::zeros((rows, n_frames.div_ceil(frames)));
+    for ((row, frame), val) in h.indexed_iter() {
+        ticks[[row, frame / frames]] += val * scale(frame) * compensation[row][frame % frames];
+    }
+
+    return ticks;
+}
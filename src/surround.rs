@@ -0,0 +1,73 @@
+use std::f32::consts::{FRAC_PI_2, TAU};
+
+use crate::{audio::Sound, export::{self, Category, PlaySound}};
+
+/// the emitters around every player, counterclockwise from the front, as
+/// `^left ^up ^forward` offsets from where they stand and look
+pub static EMITTERS: [&str; 4] = ["^ ^ ^2", "^2 ^ ^", "^ ^ ^-2", "^-2 ^ ^"];
+
+/// a sound isn't played from an emitter it gets less of than this
+static MIN_GAIN: f32 = 0.05;
+
+/// where every tick of a stereo input comes from, in radians counterclockwise
+/// from the front. left and right come from how loud each channel is, and
+/// the rear from how much of it is out of phase (the side, `left - right`),
+/// like matrix surround decoders do. a silent tick is in front
+pub fn directions(left: &Sound, right: &Sound, samples_per_tick: usize) -> Vec<f32> {
+    let n_ticks = left.samples.len().max(right.samples.len()).div_ceil(samples_per_tick);
+
+    return (0..n_ticks)
+        .map(|tick| {
+            let range = tick * samples_per_tick..(tick + 1) * samples_per_tick;
+            let (mut left_energy, mut right_energy, mut side_energy) = (0.0, 0.0, 0.0);
+            for index in range {
+                let (l, r) = (left.samples.get(index).copied().unwrap_or(0.0), right.samples.get(index).copied().unwrap_or(0.0));
+                left_energy += l * l;
+                right_energy += r * r;
+                side_energy += (l - r) * (l - r) / 4.0;
+            }
+
+            let energy = left_energy + right_energy;
+            if energy <= 0.0 {
+                return 0.0;
+            }
+
+            // the mid and side energies add up to half of the channels'
+            let leftness = (left_energy - right_energy) / energy;
+            let rear = side_energy / (energy / 2.0);
+            leftness.atan2(1.0 - 2.0 * rear).rem_euclid(TAU)
+        })
+        .collect();
+}
+
+/// the two `EMITTERS` either side of `direction` and how much of a sound
+/// each plays, keeping its power
+pub fn gains(direction: f32) -> [(usize, f32); 2] {
+    let sector = direction.rem_euclid(TAU) / FRAC_PI_2;
+    let (emitter, between) = (sector.floor() as usize % EMITTERS.len(), sector.fract());
+
+    return [(emitter, (between * FRAC_PI_2).cos()), ((emitter + 1) % EMITTERS.len(), (between * FRAC_PI_2).sin())];
+}
+
+/// `export::tick_sounds` played around every player from `direction`, each
+/// sound from the emitters either side of it
+pub fn tick_sounds(sounds: &[PlaySound], stop_previous: bool, category: Category, direction: f32) -> String {
+    let mut output = String::new();
+    if stop_previous {
+        output.push_str(&export::stop_sounds(category));
+    }
+
+    for sound in sounds {
+        for (emitter, gain) in gains(direction) {
+            if gain < MIN_GAIN {
+                continue;
+            }
+            output.push_str(&format!(
+                "execute as @a at @s positioned {} run playsound {} {} @s ~ ~ ~ {:.5} {:.5} \n",
+                EMITTERS[emitter], sound.name, category.as_str(), sound.volume * gain, sound.pitch
+            ));
+        }
+    }
+
+    return output;
+}
@@ -0,0 +1,184 @@
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use rustfft::num_complex::Complex32;
+use tracing::{event, span, Level};
+
+use crate::audio::{Processor, Sound};
+
+static FRAME: usize = 1024;
+static HOP: usize = FRAME / 4;
+
+/// tempos the estimate is searched in, anything outside is most likely an
+/// octave error of one inside
+static MIN_BPM: f32 = 60.0;
+static MAX_BPM: f32 = 200.0;
+
+/// the most the input is sped up or slowed down. beyond that the stretch
+/// is audible and the song should just be left as is
+pub static MAX_STRETCH: f32 = 0.06;
+
+/// wsola frames, how far a frame may move to line up with the previous one
+/// and how densely that is checked
+static STRETCH_FRAME: usize = 2048;
+static TOLERANCE: usize = 256;
+static STRIDE: usize = 4;
+
+/// how strongly something starts at every hop, the rectified increase of
+/// the log magnitude spectrum (spectral flux)
+pub fn onset_envelope(sound: &Sound, processor: &Processor) -> Vec<f32> {
+    let _span = span!(Level::DEBUG, "onset_envelope", tag = "audio").entered();
+
+    if sound.samples.len() < FRAME {
+        return Vec::new();
+    }
+
+    let window = apodize::hanning_iter(FRAME).map(|w| w as f32).collect::<Vec<f32>>();
+    let frames = (sound.samples.len() - FRAME) / HOP + 1;
+    let fft = processor.plan(FRAME, false);
+
+    let spectrogram = (0..frames)
+        .into_par_iter()
+        .map(|frame| {
+            let mut buffer = sound.samples[frame * HOP..frame * HOP + FRAME].iter()
+                .zip(&window)
+                .map(|(sample, w)| Complex32::new(sample * w, 0.0))
+                .collect::<Vec<Complex32>>();
+            fft.process(&mut buffer);
+            buffer[..FRAME / 2 + 1].iter().map(|bin| (1.0 + bin.norm()).ln()).collect::<Vec<f32>>()
+        })
+        .collect::<Vec<Vec<f32>>>();
+
+    let mut envelope = vec![0.0];
+    for pair in spectrogram.windows(2) {
+        envelope.push(pair[1].iter().zip(&pair[0]).map(|(now, before)| (now - before).max(0.0)).sum());
+    }
+
+    return envelope;
+}
+
+/// beats per minute, from the autocorrelation of the onset envelope. lags
+/// are weighted towards 120bpm so the tempo isn't mistaken for half or
+/// double of itself. `None` when nothing repeats
+pub fn estimate(sound: &Sound, processor: &Processor) -> Option<f32> {
+    let envelope = onset_envelope(sound, processor);
+    let mean = envelope.iter().sum::<f32>() / envelope.len().max(1) as f32;
+    let envelope = envelope.iter().map(|value| value - mean).collect::<Vec<f32>>();
+
+    let hops_per_minute = 60.0 * sound.sample_rate as f32 / HOP as f32;
+    let shortest = (hops_per_minute / MAX_BPM).floor() as usize;
+    let longest = (hops_per_minute / MIN_BPM).ceil() as usize;
+    if envelope.len() <= longest {
+        return None;
+    }
+
+    let (lag, strength) = (shortest.max(1)..=longest)
+        .into_par_iter()
+        .map(|lag| {
+            let correlation = envelope.iter().zip(&envelope[lag..]).map(|(a, b)| a * b).sum::<f32>();
+            let octaves = (hops_per_minute / lag as f32 / 120.0).log2();
+            (lag, correlation * (-0.5 * octaves * octaves).exp())
+        })
+        .reduce(|| (0, f32::NEG_INFINITY), |a, b| if b.1 > a.1 { b } else { a });
+
+    if strength <= 0.0 {
+        return None;
+    }
+
+    // the peak is refined between its neighbouring lags
+    let correlation = |lag: usize| envelope.iter().zip(&envelope[lag..]).map(|(a, b)| a * b).sum::<f32>();
+    let (before, peak, after) = (correlation(lag - 1), correlation(lag), correlation(lag + 1));
+    let curvature = before - 2.0 * peak + after;
+    let offset = if curvature < 0.0 { (0.5 * (before - after) / curvature).clamp(-0.5, 0.5) } else { 0.0 };
+
+    return Some(hops_per_minute / (lag as f32 + offset));
+}
+
+/// how much to stretch a song at `bpm` so every `subdivision`th of a beat
+/// lasts a whole number of ticks, e.g. 1.02 makes it 2% longer. `None`
+/// when that takes more than `MAX_STRETCH`
+pub fn grid_stretch(bpm: f32, subdivision: u32) -> Option<f32> {
+    let step_ticks = 20.0 * 60.0 / (bpm * subdivision as f32);
+    let stretch = step_ticks.round().max(1.0) / step_ticks;
+
+    return ((stretch - 1.0).abs() <= MAX_STRETCH).then_some(stretch);
+}
+
+/// where the beats fall relative to the start of the envelope, in hops
+fn beat_phase(envelope: &[f32], period: f32) -> f32 {
+    let steps = period.ceil() as usize;
+    return (0..steps)
+        .map(|phase| {
+            let strength = (0..)
+                .map(|beat| (phase as f32 + beat as f32 * period).round() as usize)
+                .take_while(|hop| *hop < envelope.len())
+                .map(|hop| envelope[hop])
+                .sum::<f32>();
+            (phase, strength)
+        })
+        .fold((0, f32::NEG_INFINITY), |a, b| if b.1 > a.1 { b } else { a })
+        .0 as f32;
+}
+
+/// silence to put before the song so its beats start on a tick boundary
+pub fn beat_offset(sound: &Sound, bpm: f32, processor: &Processor) -> usize {
+    let envelope = onset_envelope(sound, processor);
+    let period = 60.0 * sound.sample_rate as f32 / (HOP as f32 * bpm);
+    let samples_per_tick = sound.sample_rate / 20;
+
+    // onsets are detected at the frame they end up in, roughly half a frame late
+    let first_beat = (beat_phase(&envelope, period) * HOP as f32) as usize + FRAME / 2;
+    return (samples_per_tick - first_beat % samples_per_tick) % samples_per_tick;
+}
+
+/// changes the length of a sound by `stretch` without changing its pitch
+/// (wsola). every frame is taken from around where it would be in the
+/// original, moved so it lines up with how the previous one continues
+pub fn time_stretch(sound: &Sound, stretch: f32) -> Sound {
+    let _span = span!(Level::DEBUG, "time_stretch", tag = "audio").entered();
+
+    let synthesis_hop = STRETCH_FRAME / 2;
+    let analysis_hop = synthesis_hop as f32 / stretch;
+    let length = (sound.samples.len() as f32 * stretch).round() as usize;
+
+    // zero padded so every frame and every shift of it is in bounds
+    let mut input = vec![0.0; TOLERANCE];
+    input.extend_from_slice(&sound.samples);
+    input.resize(input.len() + STRETCH_FRAME + 2 * TOLERANCE + synthesis_hop, 0.0);
+
+    let window = apodize::hanning_iter(STRETCH_FRAME + 1).take(STRETCH_FRAME).map(|w| w as f32).collect::<Vec<f32>>();
+    let mut output = vec![0.0; length + STRETCH_FRAME];
+
+    let mut previous = TOLERANCE;
+    for frame in 0..length.div_ceil(synthesis_hop) + 1 {
+        let nominal = TOLERANCE + (frame as f32 * analysis_hop).round() as usize;
+
+        let start = match frame {
+            0 => nominal,
+            _ => {
+                // what the previous frame would have continued with
+                let natural = &input[previous + synthesis_hop..previous + synthesis_hop + synthesis_hop];
+                (nominal - TOLERANCE..=nominal + TOLERANCE)
+                    .step_by(STRIDE)
+                    .map(|start| {
+                        let candidate = &input[start..start + synthesis_hop];
+                        (start, natural.iter().zip(candidate).step_by(STRIDE).map(|(a, b)| a * b).sum::<f32>())
+                    })
+                    .fold((nominal, f32::NEG_INFINITY), |a, b| if b.1 > a.1 { b } else { a })
+                    .0
+            }
+        };
+
+        let at = frame * synthesis_hop;
+        if at >= output.len() {
+            break;
+        }
+        for ((out, sample), w) in output[at..].iter_mut().zip(&input[start..start + STRETCH_FRAME]).zip(&window) {
+            *out += sample * w;
+        }
+        previous = start;
+    }
+
+    output.truncate(length);
+    event!(Level::DEBUG, "stretched {} samples to {}", sound.samples.len(), output.len());
+
+    return Sound { samples: output, sample_rate: sound.sample_rate };
+}
@@ -42,6 +42,54 @@ fn test_firsttick() {
     assert_eq!(first_tick.sample_rate, tone.sample_rate, "first_tick changed sample rate");
 }
 
+#[test]
+fn test_trim_leading_silence() {
+    use crate::audio::Sound;
+
+    let tone = gen_frequency(300.0, 48000, 50);
+    let mut delayed = Sound { samples: vec![0.0; 240], sample_rate: 48000 };
+    delayed.samples.extend([0.0001; 10]);
+    delayed.samples.extend(&tone.samples);
+
+    // the hiss below the threshold goes too, but never more than `most`
+    let trimmed = delayed.clone().trim_leading_silence(-60.0, 2400).samples.clone();
+    assert_eq!(trimmed.len(), delayed.samples.len() - 251);
+    assert_eq!(delayed.clone().trim_leading_silence(-60.0, 100).samples.len(), delayed.samples.len() - 100);
+    assert_eq!(delayed.clone().trim_leading_silence(-90.0, 2400).samples.len(), delayed.samples.len() - 240);
+
+    let mut silence = Sound { samples: vec![0.0; 2400], sample_rate: 48000 };
+    assert_eq!(silence.trim_leading_silence(-60.0, 2400).samples.len(), 2400);
+}
+
+#[test]
+fn test_section() {
+    use crate::audio::Sound;
+
+    let ramp = Sound { samples: (0..1000).map(|sample| sample as f32).collect(), sample_rate: 100 };
+    assert_eq!(ramp.clone().section(2.0, Some(0.5)).samples, (200..250).map(|sample| sample as f32).collect::<Vec<f32>>());
+    assert_eq!(ramp.clone().section(9.5, None).samples.len(), 50);
+
+    // a duration past the end stops at the end, a start past it is empty
+    assert_eq!(ramp.clone().section(9.0, Some(5.0)).samples.len(), 100);
+    assert!(ramp.clone().section(12.0, Some(1.0)).samples.is_empty());
+}
+
+#[test]
+fn test_parse_time() {
+    use crate::audio::parse_time;
+
+    assert_eq!(parse_time("1m23s"), Ok(83.0));
+    assert_eq!(parse_time("1m23"), Ok(83.0));
+    assert_eq!(parse_time("45s"), Ok(45.0));
+    assert_eq!(parse_time("2m"), Ok(120.0));
+    assert_eq!(parse_time("1.5"), Ok(1.5));
+    assert_eq!(parse_time("0s"), Ok(0.0));
+
+    for time in ["", "m", "s", "1h", "-5s", "1m-5s", "inf"] {
+        assert!(parse_time(time).is_err(), "`{}` parsed", time);
+    }
+}
+
 #[test]
 fn test_pitch() {
     let mut tone = gen_frequency(300.0, 48000, 50);
@@ -63,6 +111,27 @@ fn test_layout() {
     assert!(flattened.iter().partial_cmp(&ndarray_vec).expect("failed to compare").is_eq());
 }
 
+#[test]
+fn test_atom_features() {
+    use crate::{audio::{self, Sound}, features};
+
+    // a tick's features are its first sample and its length
+    let extract = |sounds: &[Sound]| sounds.iter().map(|sound| vec![sound.samples[0], sound.samples.len() as f32]).collect::<Vec<Vec<f32>>>();
+    let ramp = Sound { samples: (0..25).map(|sample| sample as f32).collect(), sample_rate: 200 };
+
+    // the last tick is padded, ticks past the end are silence
+    let chunks = audio::chunk_range(&ramp, 10, 2..4);
+    assert_eq!(chunks.iter().map(|chunk| chunk.samples.len()).collect::<Vec<usize>>(), vec![10, 10]);
+    assert_eq!((chunks[0].samples[0], chunks[0].samples[5], chunks[1].samples[0]), (20.0, 0.0, 0.0));
+
+    let sounds = vec![ramp.clone(), Sound { samples: vec![-1.0; 5], sample_rate: 200 }];
+    assert_eq!(features::atom_features(&sounds, 1, 10, extract), vec![vec![0.0, 25.0], vec![-1.0, 5.0]]);
+    assert_eq!(features::atom_features(&sounds, 3, 10, extract), vec![
+        vec![0.0, 10.0, 10.0, 10.0, 20.0, 10.0],
+        vec![-1.0, 10.0, 0.0, 10.0, 0.0, 10.0]
+    ]);
+}
+
 #[cfg(test)]
 fn nnls_test<T: Fn(Array2<f32>, Array2<f32>) -> Array2<f32>>(f: T, target: &Array2<f32>, chunks: &Array2<f32>) -> Result<Vec<f32>, Error> {
     let mut chunks = chunks.clone();
@@ -84,7 +153,7 @@ fn shape_test(sample_size: usize, chunks: usize, targets: usize) -> bool {
     let chunks = Array2::random((sample_size, chunks), Uniform::new(-1.0, 1.0));
     let target = Array2::random((sample_size, targets), Uniform::new(-1.0, 1.0));
 
-    let cpu = nnls_test(|target, chunks| algebra::cpu_pgd_nnls(target, chunks, 400, 1e-6), &target, &chunks).unwrap();
+    let cpu = nnls_test(|target, chunks| algebra::cpu_pgd_nnls(target.view(), chunks.view(), 400, 1e-6), &target, &chunks).unwrap();
     let gpu = nnls_test(|target, chunks| algebra::pgd_nnls(target, chunks, 400, 1e-6), &target, &chunks).unwrap();
 
     let err = cpu.iter()
@@ -102,3 +171,2341 @@ fn test_nnls() {
     assert!(shape_test(15, 92, 3), "NNLS failed at non-mutiple");
     assert!(shape_test(2400, 5, 9), "NNLS failed at real sample size");
 }
+
+#[test]
+fn test_timing_accumulates() {
+    use crate::timing::{Stage, Timing};
+    use std::time::Duration;
+
+    let mut timing = Timing::new();
+    timing.record(Stage::Features, Duration::from_millis(10));
+    timing.record(Stage::Solve, Duration::from_millis(5));
+    timing.record(Stage::Features, Duration::from_millis(20));
+
+    assert_eq!(timing.get(Stage::Features), Some(Duration::from_millis(30)));
+    assert_eq!(timing.get(Stage::Export), None);
+    assert_eq!(timing.total(), Duration::from_millis(35));
+}
+
+#[test]
+fn test_checkpoint_roundtrip() {
+    use crate::checkpoint::Checkpoint;
+
+    let h = Array2::random((7, 5), Uniform::new(0.0, 1.0));
+    let path = std::env::temp_dir().join(format!("minecraft-player-checkpoint-{}.bin", std::process::id()));
+
+    Checkpoint { iterations: 42, h: h.clone() }.save(&path).unwrap();
+    let loaded = Checkpoint::load(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(loaded.iterations, 42);
+    assert_eq!(loaded.h, h);
+}
+
+#[cfg(test)]
+fn wav_roundtrip<S: hound::Sample + Copy>(spec: hound::WavSpec, samples: &[S]) -> Vec<f32> {
+    let mut cursor = std::io::Cursor::new(Vec::new());
+    let mut writer = hound::WavWriter::new(&mut cursor, spec).unwrap();
+    for sample in samples {
+        writer.write_sample(*sample).unwrap();
+    }
+    writer.finalize().unwrap();
+
+    cursor.set_position(0);
+    crate::decode::read_wav(hound::WavReader::new(cursor).unwrap()).unwrap().samples
+}
+
+#[test]
+fn test_wav_formats() {
+    let spec = |bits_per_sample, sample_format| hound::WavSpec { channels: 1, sample_rate: 48000, bits_per_sample, sample_format };
+
+    let int16 = wav_roundtrip(spec(16, hound::SampleFormat::Int), &[i16::MIN, 0, 16384]);
+    assert_eq!(int16, vec![-1.0, 0.0, 0.5]);
+
+    let int24 = wav_roundtrip(spec(24, hound::SampleFormat::Int), &[-(1 << 23), 1 << 22]);
+    assert_eq!(int24, vec![-1.0, 0.5]);
+
+    let float = wav_roundtrip(spec(32, hound::SampleFormat::Float), &[0.25f32, -0.75]);
+    assert_eq!(float, vec![0.25, -0.75]);
+}
+
+#[test]
+fn test_downsample_antialias() {
+    // 30kHz is above the 24kHz nyquist of the target rate and would alias to 18kHz
+    let mut tone = gen_frequency(30000.0, 96000, 50);
+    tone.resample(48000);
+
+    assert_eq!(tone.sample_rate, 48000);
+    let interior = &tone.samples[100..tone.samples.len() - 100];
+    let peak = interior.iter().cloned().fold(0.0f32, |a, b| a.max(b.abs()));
+    assert!(peak < 0.05, "aliased peak of {}", peak);
+}
+
+#[test]
+fn test_chroma_peak() {
+    use crate::features::{Chroma, FeatureExtractor};
+
+    let tone = gen_frequency(440.0, 48000, 50);
+    let chroma = Chroma.extract(&tone, &crate::audio::Processor::new());
+
+    let peak = chroma.iter().enumerate().max_by(|a, b| a.1.total_cmp(b.1)).unwrap().0;
+    assert_eq!(peak, 9, "440Hz should land on A");
+}
+
+#[test]
+fn test_mel_operator() {
+    use crate::features::{FeatureExtractor, MelWeighted};
+
+    let tone = gen_frequency(1234.0, 48000, 50);
+    let n = tone.samples.len();
+    let expected = MelWeighted.extract(&tone, &crate::audio::Processor::new());
+
+    let applied = MelWeighted::operator(n, tone.sample_rate).apply(&tone.samples);
+
+    let peak = expected.iter().cloned().fold(0.0f32, |a, b| a.max(b.abs()));
+    let err = expected.iter().zip(&applied).map(|(a, b)| (a - b).abs()).fold(0.0f32, f32::max);
+    assert!(err < peak * 1e-3, "operator deviates by {} (peak {})", err, peak);
+}
+
+#[test]
+fn test_fft_cache_populates_once() {
+    use rayon::prelude::*;
+
+    let processor = crate::audio::Processor::new();
+    let tone = gen_frequency(300.0, 22050, 50);
+
+    (0..32).into_par_iter().for_each(|_| {
+        processor.fft(tone.clone());
+    });
+
+    assert_eq!(processor.planned_lengths(), vec![tone.samples.len()]);
+}
+
+#[test]
+fn test_lipschitz() {
+    use ndarray::array;
+
+    let basis = array![[3.0f32, 0.0], [0.0, 1.0], [0.0, 0.0]];
+    let lipschitz = algebra::cpu_lipschitz(basis.view(), 64);
+    assert!((lipschitz - 9.0).abs() < 1e-3, "expected 9, got {}", lipschitz);
+
+    assert!(matches!("auto".parse(), Ok(algebra::StepSize::Auto)));
+    assert!("0".parse::<algebra::StepSize>().is_err());
+    assert!("-1e-6".parse::<algebra::StepSize>().is_err());
+}
+
+#[test]
+fn test_per_tick_normalization() {
+    use ndarray::array;
+
+    let original = array![[0.5f32, -4.0, 0.0], [0.25, 2.0, 0.0]];
+    let mut normalized = original.clone();
+    let peaks = algebra::normalize_columns(&mut normalized);
+
+    assert_eq!(peaks, vec![0.5, 4.0, 0.0]);
+    assert_eq!(normalized, array![[1.0, -1.0, 0.0], [0.5, 0.5, 0.0]]);
+
+    algebra::scale_columns(&mut normalized, &peaks);
+    assert_eq!(normalized, original);
+}
+
+#[test]
+fn test_preview_clipping() {
+    use crate::preview::{Clipping, Preview};
+
+    let render = |clipping, dither| {
+        let mut cursor = std::io::Cursor::new(Vec::new());
+        let mut preview = Preview::new(&mut cursor, 48000, clipping, dither).unwrap();
+        preview.write_tick(vec![0.5, -0.25]).unwrap();
+        preview.write_tick(vec![2.0, -1.0]).unwrap();
+        let clipped = preview.finalize().unwrap();
+
+        cursor.set_position(0);
+        let samples = crate::decode::read_wav(hound::WavReader::new(cursor).unwrap()).unwrap().samples;
+        (clipped, samples)
+    };
+
+    let (clipped, limited) = render(Clipping::Limit, false);
+    assert_eq!(clipped, 1);
+    assert_eq!(limited, vec![0.5, -0.25, 1.0, -0.5]);
+
+    let (_, soft) = render(Clipping::Soft, false);
+    assert!(soft[2] < 1.0 && soft[2] > 0.95);
+
+    let (_, dithered) = render(Clipping::Limit, true);
+    assert!(dithered.iter().zip(&limited).all(|(a, b)| (a - b).abs() < 1e-3));
+}
+
+#[test]
+fn test_in_game_gain() {
+    use crate::preview::Model;
+
+    assert_eq!(Model::Naive.gain(2.0, 100.0), 2.0);
+
+    // louder than 1.0 only reaches further
+    assert_eq!(Model::InGame.gain(2.0, 0.0), 1.0);
+    assert_eq!(Model::InGame.gain(2.0, 16.0), 0.5);
+    assert_eq!(Model::InGame.gain(0.5, 8.0), 0.25);
+    assert_eq!(Model::InGame.gain(1.0, 20.0), 0.0);
+}
+
+#[test]
+fn test_fundamental() {
+    use crate::pitch;
+
+    let tone = gen_frequency(440.0, 48000, 50);
+    let estimate = pitch::fundamental(&tone).unwrap();
+    assert!((estimate - 440.0).abs() < 2.0, "expected 440Hz, got {}", estimate);
+
+    let silence = crate::audio::Sound { samples: vec![0.0; 2400], sample_rate: 48000 };
+    assert_eq!(pitch::fundamental(&silence), None);
+
+    // A4 is already on the scale, so it's pitched by whole semitones
+    let pitches = pitch::musical_pitches(Some(440.0));
+    assert_eq!(pitches.len(), 25);
+    assert!(pitches.iter().any(|p| (p - 1.0).abs() < 1e-6));
+    assert!(pitches.iter().all(|p| (0.5..=2.0).contains(p)));
+
+    // a quarter tone sharp lands every pitch a quarter tone down
+    let sharp = pitch::musical_pitches(Some(440.0 * 2f32.powf(0.5 / 12.0)));
+    assert!(sharp.iter().any(|p| (p - 2f32.powf(-0.5 / 12.0)).abs() < 1e-4));
+    assert!(sharp.iter().all(|p| (p - 1.0).abs() > 0.02));
+}
+
+#[test]
+fn test_spectral_flatness() {
+    use crate::pitch;
+    use ndarray_rand::rand::{rngs::StdRng, Rng, SeedableRng};
+
+    let processor = crate::audio::Processor::new();
+
+    let tone = pitch::analyze(&gen_frequency(440.0, 48000, 50), &processor);
+    assert!(tone.is_tonal(), "{:?}", tone);
+
+    let mut rng = StdRng::seed_from_u64(0);
+    let noise = crate::audio::Sound { samples: (0..2400).map(|_| rng.gen_range(-1.0..1.0)).collect(), sample_rate: 48000 };
+    let noise = pitch::analyze(&noise, &processor);
+    assert!(!noise.is_tonal(), "{:?}", noise);
+}
+
+#[test]
+fn test_hpss() {
+    let processor = crate::audio::Processor::new();
+
+    // a steady tone with a click every 100ms
+    let mut mix = gen_frequency(440.0, 48000, 1000);
+    for i in (0..mix.samples.len()).step_by(4800) {
+        mix.samples[i] += 1.0;
+    }
+
+    let (harmonic, percussive) = crate::separate::hpss(&mix, &processor);
+    assert_eq!(harmonic.samples.len(), mix.samples.len());
+
+    let err = mix.samples.iter().zip(harmonic.samples.iter().zip(&percussive.samples))
+        .map(|(m, (h, p))| (m - h - p).abs())
+        .fold(0.0f32, f32::max);
+    assert!(err < 1e-3, "parts don't add back up, off by {}", err);
+
+    // the clicks should mostly end up percussive, the tone in between harmonic
+    let energy = |samples: &[f32], at: usize| samples[at - 50..at + 50].iter().map(|s| s * s).sum::<f32>();
+    let (click, between) = (4800 * 5, 4800 * 5 + 2400);
+    assert!(energy(&percussive.samples, click) > 4.0 * energy(&percussive.samples, between));
+    assert!(energy(&harmonic.samples, between) > 4.0 * energy(&percussive.samples, between));
+}
+
+#[test]
+fn test_accumulate_rows() {
+    use ndarray::array;
+
+    let mut into = Array2::<f32>::zeros((3, 2));
+    let from = array![[1.0f32, 2.0], [4.0, 0.0]];
+
+    algebra::accumulate_rows(&mut into, from.view(), &[2, 0], None, 1.0, usize::MAX);
+    assert_eq!(into, array![[1.0, 0.0], [0.0, 0.0], [0.25, 0.5]]);
+
+    // column scales are undone before normalizing
+    let mut into = Array2::<f32>::zeros((2, 2));
+    algebra::accumulate_rows(&mut into, from.view(), &[0, 1], Some(&[1.0, 4.0]), 0.5, usize::MAX);
+    assert_eq!(into, array![[0.0625, 0.5], [0.25, 0.0]]);
+
+    // only the loudest sound of every tick fits a budget of one
+    let mut into = Array2::<f32>::zeros((2, 2));
+    algebra::accumulate_rows(&mut into, from.view(), &[0, 1], None, 1.0, 1);
+    assert_eq!(into, array![[0.0, 0.5], [1.0, 0.0]]);
+}
+
+#[test]
+fn test_stem_parsing() {
+    use crate::stems::{SoundFilter, Stem};
+
+    let stem = "stems/vocals.wav,budget=40,sounds=tonal".parse::<Stem>().unwrap();
+    assert_eq!(stem.name(), "vocals");
+    assert_eq!(stem.budget, Some(40));
+    assert_eq!(stem.filter, SoundFilter::Tonal);
+
+    let stem = "drums.wav".parse::<Stem>().unwrap();
+    assert_eq!((stem.budget, stem.filter), (None, SoundFilter::All));
+
+    assert!("drums.wav,budget=0".parse::<Stem>().is_err());
+    assert!("drums.wav,sounds=loud".parse::<Stem>().is_err());
+    assert!("drums.wav,volume=2".parse::<Stem>().is_err());
+}
+
+#[cfg(test)]
+fn export_schedule(schedule: &Array2<f32>, sound_ids: &[(String, f32)], budget: usize) -> Vec<String> {
+    use crate::export;
+
+    let n_ticks = schedule.ncols();
+    schedule.columns().into_iter()
+        .enumerate()
+        .map(|(index, amplitudes)| {
+            let sounds = export::select(amplitudes, sound_ids, budget);
+            export::tick_function(&sounds, (index + 1 < n_ticks).then_some(index + 1), true, export::Category::Record, &export::Anchor::default())
+        })
+        .collect()
+}
+
+#[test]
+fn test_export_golden() {
+    use ndarray::array;
+
+    let sound_ids = vec![
+        ("minecraft:block.note_block.harp".to_string(), 0.5),
+        ("minecraft:block.note_block.bass".to_string(), 1.0),
+        ("minecraft:entity.cat.purr".to_string(), 2.0),
+    ];
+    let schedule = array![[0.25f32, 0.0], [1.0, 0.0], [0.125, 0.0]];
+
+    let ticks = export_schedule(&schedule, &sound_ids, 2);
+    assert_eq!(ticks, vec![
+        "stopsound @a[tag=!nomusic] record\n\
+         playsound minecraft:block.note_block.bass record @a 0 -60 0 1.00000 1.00000 \n\
+         playsound minecraft:block.note_block.harp record @a 0 -60 0 0.25000 0.50000 \n\
+         schedule function audio:_/1 1t append\n",
+        "stopsound @a[tag=!nomusic] record\n",
+    ]);
+}
+
+#[test]
+fn test_export_properties() {
+    let sound_ids = algebra::interpolated_range(0.5, 2.0, 32).into_iter()
+        .enumerate()
+        .map(|(i, pitch)| (format!("minecraft:sound_{}", i % 7), pitch))
+        .collect::<Vec<(String, f32)>>();
+
+    for budget in [1, 5, 32, 80] {
+        // sparse like a real solve, with plenty of exact zeros
+        let mut schedule = Array2::random((sound_ids.len(), 24), Uniform::new(-1.0f32, 1.0));
+        schedule.mapv_inplace(|val| val.max(0.0));
+        algebra::normalize_to_global(&mut schedule);
+
+        let ticks = export_schedule(&schedule, &sound_ids, budget);
+
+        for (index, tick) in ticks.iter().enumerate() {
+            let lines = tick.lines().collect::<Vec<&str>>();
+            assert_eq!(lines[0], "stopsound @a[tag=!nomusic] record");
+
+            let plays = lines.iter().filter(|line| line.starts_with("playsound")).collect::<Vec<_>>();
+            assert!(plays.len() <= budget, "tick {} has {} commands over a budget of {}", index, plays.len(), budget);
+
+            for play in plays {
+                let fields = play.split_whitespace().collect::<Vec<&str>>();
+                let (volume, pitch) = (fields[fields.len() - 2].parse::<f32>().unwrap(), fields[fields.len() - 1].parse::<f32>().unwrap());
+                assert!(volume > 0.0 && volume <= 2.0, "volume {} out of range", volume);
+                assert!((0.5..=2.0).contains(&pitch), "pitch {} out of range", pitch);
+            }
+
+            // every function schedules the one after it, and the last one nothing
+            let schedules = lines.iter().filter(|line| line.starts_with("schedule")).collect::<Vec<_>>();
+            match index + 1 < ticks.len() {
+                true => assert_eq!(schedules, vec![&format!("schedule function audio:_/{} 1t append", index + 1).as_str()]),
+                false => assert!(schedules.is_empty()),
+            }
+        }
+    }
+}
+
+#[test]
+fn test_prepare_output_errors() {
+    use crate::export::{self, ExportError};
+
+    let output = std::env::temp_dir().join(format!("minecraft-player-output-{}", std::process::id()));
+    std::fs::create_dir_all(&output).unwrap();
+    std::fs::write(output.join("unrelated.txt"), "").unwrap();
+
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let not_empty = runtime.block_on(export::prepare_output(&output, false));
+    let not_a_directory = runtime.block_on(export::prepare_output(&output.join("unrelated.txt"), false));
+    let forced = runtime.block_on(export::prepare_output(&output, true));
+    std::fs::remove_dir_all(&output).unwrap();
+
+    assert!(matches!(not_empty, Err(ExportError::NotEmpty(_))));
+    assert!(matches!(not_a_directory, Err(ExportError::NotADirectory(_))));
+    assert!(forced.is_ok());
+}
+
+#[test]
+fn test_version_manifest_cache() {
+    use crate::{assets::{self, FetchBehavior, FetchOptions}, mojang::{LatestVersion, VersionManifest}};
+
+    let assets_path = std::env::temp_dir().join(format!("minecraft-player-assets-{}", std::process::id()));
+    std::fs::create_dir_all(&assets_path).unwrap();
+    let cached = VersionManifest {
+        latest: LatestVersion { release: "1.21".to_string(), snapshot: "24w01a".to_string() },
+        versions: vec![]
+    };
+    std::fs::write(assets_path.join("version_manifest.json"), serde_json::to_string(&cached).unwrap()).unwrap();
+
+    // nothing listens on the discard port, so every fetch fails
+    let unreachable = "http://127.0.0.1:9/version_manifest_v2.json";
+    let (fetch, refetch) = (FetchOptions::default(), FetchOptions { behavior: FetchBehavior::Refetch, ..FetchOptions::default() });
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let fresh = runtime.block_on(assets::fetch_version_manifest(&assets_path, &fetch, unreachable));
+    let refetched = runtime.block_on(assets::fetch_version_manifest(&assets_path, &refetch, unreachable));
+
+    std::fs::remove_file(assets_path.join("version_manifest.json")).unwrap();
+    let missing = runtime.block_on(assets::fetch_version_manifest(&assets_path, &fetch, unreachable));
+    std::fs::remove_dir_all(&assets_path).unwrap();
+
+    assert_eq!(fresh.unwrap().latest.release, "1.21");
+    assert_eq!(refetched.unwrap().latest.release, "1.21", "falls back to the cache when offline");
+    assert!(missing.is_err());
+}
+
+#[test]
+fn test_resolve_version() {
+    use crate::{mojang::{LatestVersion, Version, VersionManifest}, versions::{self, VersionError}};
+
+    let version = |id: &str| Version { id: id.to_string(), kind: "release".to_string(), url: String::new(), release_time: None };
+    let manifest = VersionManifest {
+        latest: LatestVersion { release: "1.21".to_string(), snapshot: "24w33a".to_string() },
+        versions: vec![version("24w33a"), version("1.21"), version("1.20.6"), version("1.20.5")]
+    };
+
+    assert_eq!(versions::resolve(&manifest, versions::LATEST_RELEASE).unwrap().id, "1.21");
+    assert_eq!(versions::resolve(&manifest, versions::LATEST_SNAPSHOT).unwrap().id, "24w33a");
+    assert_eq!(versions::resolve(&manifest, "1.21").unwrap().id, "1.21");
+    assert_eq!(versions::resolve(&manifest, "20.6").unwrap().id, "1.20.6");
+    assert!(matches!(versions::resolve(&manifest, "1.20"), Err(VersionError::Ambiguous { candidates, .. }) if candidates.len() == 2));
+    assert!(matches!(versions::resolve(&manifest, "b1.7"), Err(VersionError::NotFound(_))));
+}
+
+#[test]
+fn test_version_union() {
+    use crate::versions;
+
+    let (sounds, availability) = versions::union(vec![
+        ("1.20.4".to_string(), vec![("entity.cow.ambient".to_string(), 0), ("block.note_block.harp".to_string(), 1)]),
+        ("1.21.4".to_string(), vec![("entity.cow.ambient".to_string(), 2), ("block.trial_spawner.open".to_string(), 3)]),
+    ]);
+
+    // the target version's recording wins
+    assert_eq!(sounds.iter().map(|(_, sound)| *sound).collect::<Vec<_>>(), vec![0, 1, 3]);
+    assert_eq!(availability["entity.cow.ambient"], vec!["1.20.4", "1.21.4"]);
+    assert_eq!(availability["block.note_block.harp"], vec!["1.20.4"]);
+    assert_eq!(availability["block.trial_spawner.open"], vec!["1.21.4"]);
+}
+
+#[test]
+fn test_version_compatibility() {
+    use std::collections::HashMap;
+
+    use crate::versions::{self, Compatibility};
+
+    let events = |pairs: &[(&str, &str)]| pairs.iter().map(|(event, sound)| (event.to_string(), sound.to_string())).collect::<HashMap<String, String>>();
+    let basis = events(&[("a", "1 1 1"), ("b", "2 1 1"), ("c", "3 1 1"), ("d", "4 1 1")]);
+    let output = events(&[("a", "1 1 1"), ("b", "2 0.5 1"), ("e", "3 1 1")]);
+
+    let compatibility = versions::compatibility(&basis, &output);
+    assert_eq!(compatibility["a"], Compatibility::Same);
+    assert_eq!(compatibility["b"], Compatibility::Changed);
+    assert_eq!(compatibility["c"], Compatibility::Renamed("e".to_string()));
+    assert_eq!(compatibility["d"], Compatibility::Missing);
+
+    let sounds = ["a", "b", "c", "d"].iter().enumerate().map(|(index, event)| (event.to_string(), index)).collect::<Vec<_>>();
+    let names = |sounds: Vec<(String, usize)>| sounds.into_iter().map(|(event, _)| event).collect::<Vec<String>>();
+    assert_eq!(names(versions::adapt(sounds.clone(), &compatibility, false)), vec!["a", "b", "e"]);
+    assert_eq!(names(versions::adapt(sounds, &compatibility, true)), vec!["a", "e"]);
+
+    // `c` and `f` are both renamed to `e`, which another version of the basis has
+    let basis = events(&[("c", "3 1 1"), ("e", "3 1 1"), ("f", "3 1 1"), ("g", "5 1 1")]);
+    let output = events(&[("e", "3 1 1"), ("h", "5 1 1")]);
+    let compatibility = versions::compatibility(&basis, &output);
+    let sounds = ["c", "e", "f", "g"].iter().enumerate().map(|(index, event)| (event.to_string(), index)).collect::<Vec<_>>();
+    assert_eq!(versions::adapt(sounds.clone(), &compatibility, false), vec![("e".to_string(), 1), ("h".to_string(), 3)]);
+    assert_eq!(names(versions::adapt(sounds.into_iter().filter(|(event, _)| event != "e").collect(), &compatibility, false)), vec!["e", "h"]);
+}
+
+#[test]
+fn test_predictable_sounds() {
+    use std::collections::HashMap;
+    use crate::assets::{self, SoundDefinition};
+
+    let definitions: HashMap<String, SoundDefinition> = serde_json::from_str(r#"{
+        "block.note_block.harp": { "sounds": ["note/harp"] },
+        "entity.cat.purr": { "sounds": [{ "name": "mob/cat/purr1", "pitch": 1.5, "volume": 0.5 }] },
+        "entity.cow.ambient": { "sounds": ["mob/cow/say1", "mob/cow/say2"] },
+        "music.game": { "sounds": [{ "name": "music.menu", "resource_type": "event" }] }
+    }"#).unwrap();
+
+    let mut predictable = assets::predictable_sounds(&definitions);
+    predictable.sort_by(|a, b| a.event.cmp(&b.event));
+
+    let events = predictable.iter().map(|sound| sound.event.as_str()).collect::<Vec<&str>>();
+    assert_eq!(events, vec!["block.note_block.harp", "entity.cat.purr"]);
+    assert_eq!(predictable[0].path, assets::AssetKey::new("minecraft/sounds/note/harp.ogg"));
+    assert_eq!((predictable[1].pitch, predictable[1].volume), (1.5, 0.5));
+}
+
+#[test]
+fn test_find_predictable_sound() {
+    use std::collections::HashMap;
+    use crate::{assets::{self, AssetsError, SoundDefinition}, audio::Sound};
+
+    let definitions: HashMap<String, SoundDefinition> = serde_json::from_str(r#"{
+        "entity.cat.purr": { "sounds": [{ "name": "mob/cat/purr1", "pitch": 2.0, "volume": 0.5 }] },
+        "entity.cow.ambient": { "sounds": ["mob/cow/say1", "mob/cow/say2"] },
+        "music.game": { "sounds": [{ "name": "entity.cat.purr", "type": "event", "volume": 0.5 }] }
+    }"#).unwrap();
+
+    let purr = assets::find_predictable_sound(&definitions, "minecraft:entity.cat.purr").unwrap();
+    assert_eq!(purr.event, "entity.cat.purr");
+    assert_eq!(purr.path, assets::AssetKey::new("minecraft/sounds/mob/cat/purr1.ogg"));
+
+    let game = assets::find_predictable_sound(&definitions, "music.game").unwrap();
+    assert_eq!((game.path, game.volume, game.via), (purr.path.clone(), 0.25, vec!["entity.cat.purr".to_string()]));
+
+    assert!(matches!(assets::find_predictable_sound(&definitions, "entity.cow.ambient"), Err(AssetsError::UnpredictableEvent(_))));
+    assert!(matches!(assets::find_predictable_sound(&definitions, "entity.cow.hurt"), Err(AssetsError::UnknownEvent(_))));
+
+    // the way the basis plays it: twice as fast, at half the volume
+    let mut sound = Sound { samples: vec![1.0; 100], sample_rate: 48000 };
+    purr.play(&mut sound);
+    assert_eq!(sound.samples, vec![0.5; 50]);
+}
+
+#[test]
+fn test_sound_definition_corpus() {
+    use crate::assets::{self, AssetKey};
+
+    // the same kinds of definitions as sounds.json has had over the versions,
+    // and resource packs have in theirs
+    let corpus = [
+        // 1.7: categories, streamed records
+        r#"{
+            "ambient.cave.cave": { "category": "ambient", "sounds": ["ambient/cave/cave1", "ambient/cave/cave2"] },
+            "records.13": { "category": "record", "sounds": [{ "name": "records/13", "stream": true }] },
+            "note.harp": { "category": "record", "sounds": ["note/harp"] }
+        }"#,
+        // 1.9 to 1.12: subtitles
+        r#"{ "block.note.harp": { "sounds": ["note/harp"], "subtitle": "subtitles.block.note.harp" } }"#,
+        // 1.13 on: weights, preloading, attenuation
+        r#"{
+            "block.lever.click": { "sounds": [{ "name": "random/click", "pitch": 0.5, "volume": 0.3, "preload": true }] },
+            "entity.warden.heartbeat": { "sounds": [{ "name": "mob/warden/heartbeat_1", "attenuation_distance": 24, "weight": 2 }] }
+        }"#,
+        // 1.19 on: events playing other events
+        r#"{
+            "entity.skeleton.ambient": { "sounds": [{ "name": "mob/skeleton/say1", "volume": 0.5 }] },
+            "block.note_block.imitate.skeleton": { "sounds": [{ "name": "entity.skeleton.ambient", "type": "event", "pitch": 1.5 }] },
+            "music.overworld.forest": { "sounds": [{ "name": "music.game", "type": "event" }] }
+        }"#,
+        // resource packs: a byte order mark, namespaces, definitions without
+        // sounds and ones that don't parse
+        "\u{feff}{ \"block.note_block.harp\": { \"replace\": true, \"sounds\": [\"custompack:harp\"] } }",
+        r#"{
+            "custom.ping": { "sounds": [{ "name": "minecraft:block.note_block.harp", "type": "event", "volume": 0.5 }] },
+            "block.note_block.harp": { "sounds": ["custompack:harp"] },
+            "entity.cat.ambient": { "subtitle": "subtitles.entity.cat.ambient", "replace": true },
+            "broken.nameless": { "sounds": [{ "volume": 0.5 }] },
+            "broken.string": "note/harp"
+        }"#,
+    ];
+
+    let mut predictable = Vec::new();
+    for json in corpus {
+        let definitions = assets::parse_sound_definitions(json).unwrap();
+        assert!(!definitions.contains_key("broken.nameless") && !definitions.contains_key("broken.string"));
+        predictable.extend(assets::predictable_sounds(&definitions).into_iter().map(|sound| (sound.event, sound.path, sound.pitch, sound.volume)));
+    }
+
+    let key = AssetKey::new;
+    assert_eq!(predictable, vec![
+        ("note.harp".to_string(), key("minecraft/sounds/note/harp.ogg"), 1.0, 1.0),
+        ("records.13".to_string(), key("minecraft/sounds/records/13.ogg"), 1.0, 1.0),
+        ("block.note.harp".to_string(), key("minecraft/sounds/note/harp.ogg"), 1.0, 1.0),
+        ("block.lever.click".to_string(), key("minecraft/sounds/random/click.ogg"), 0.5, 0.3),
+        ("entity.warden.heartbeat".to_string(), key("minecraft/sounds/mob/warden/heartbeat_1.ogg"), 1.0, 1.0),
+        ("block.note_block.imitate.skeleton".to_string(), key("minecraft/sounds/mob/skeleton/say1.ogg"), 1.5, 0.5),
+        ("entity.skeleton.ambient".to_string(), key("minecraft/sounds/mob/skeleton/say1.ogg"), 1.0, 0.5),
+        ("block.note_block.harp".to_string(), key("custompack/sounds/harp.ogg"), 1.0, 1.0),
+        ("block.note_block.harp".to_string(), key("custompack/sounds/harp.ogg"), 1.0, 1.0),
+        ("custom.ping".to_string(), key("custompack/sounds/harp.ogg"), 1.0, 0.5),
+    ]);
+}
+
+#[test]
+fn test_event_references() {
+    use crate::assets;
+
+    // a disc playing an alias of a song, cycles, a missing event and one
+    // with a choice of sounds
+    let definitions = assets::parse_sound_definitions(r#"{
+        "music.song": { "sounds": [{ "name": "music/song", "volume": 0.5 }] },
+        "music.alias": { "sounds": [{ "name": "minecraft:music.song", "type": "event", "pitch": 0.5 }] },
+        "music_disc.song": { "sounds": [{ "name": "music.alias", "type": "event", "volume": 0.5 }] },
+        "loop.a": { "sounds": [{ "name": "loop.b", "type": "event" }] },
+        "loop.b": { "sounds": [{ "name": "loop.a", "type": "event" }] },
+        "loop.self": { "sounds": [{ "name": "loop.self", "type": "event" }] },
+        "dangling": { "sounds": [{ "name": "nowhere", "type": "event" }] },
+        "choice": { "sounds": ["one", "two"] },
+        "choosing": { "sounds": [{ "name": "choice", "type": "event" }] }
+    }"#).unwrap();
+
+    let predictable = assets::predictable_sounds(&definitions);
+    let events = predictable.iter().map(|sound| sound.event.as_str()).collect::<Vec<&str>>();
+    assert_eq!(events, vec!["music.alias", "music.song", "music_disc.song"]);
+
+    let disc = &predictable[2];
+    assert_eq!(disc.path, assets::AssetKey::new("minecraft/sounds/music/song.ogg"));
+    assert_eq!((disc.pitch, disc.volume), (0.5, 0.25));
+    assert_eq!(disc.via, vec!["music.alias", "music.song"]);
+    assert!(predictable[1].via.is_empty());
+
+    // an event playing another at the same pitch is the same column, the
+    // one that plays the file itself is kept
+    let definitions = assets::parse_sound_definitions(r#"{
+        "note.harp": { "sounds": ["note/harp"] },
+        "note.quiet_harp": { "sounds": [{ "name": "note.harp", "type": "event", "volume": 0.5 }] },
+        "note.low_harp": { "sounds": [{ "name": "note.harp", "type": "event", "pitch": 0.5 }] },
+        "a.harp": { "sounds": [{ "name": "note.quiet_harp", "type": "event" }] }
+    }"#).unwrap();
+    let distinct = assets::distinct_sounds(assets::predictable_sounds(&definitions));
+    let events = distinct.iter().map(|sound| sound.event.as_str()).collect::<Vec<&str>>();
+    assert_eq!(events, vec!["note.harp", "note.low_harp"]);
+}
+
+#[test]
+fn test_exclude_long() {
+    use std::{collections::HashMap, time::Duration};
+    use crate::assets;
+
+    let sounds = vec![("note".to_string(), 0), ("disc".to_string(), 1), ("loop".to_string(), 2), ("unknown".to_string(), 3), ("cave".to_string(), 4)];
+    let durations = HashMap::from([
+        ("note".to_string(), Duration::from_secs(1)),
+        ("disc".to_string(), Duration::from_secs(180)),
+        ("loop".to_string(), Duration::from_secs(30)),
+        ("cave".to_string(), Duration::from_secs(10)),
+    ]);
+
+    let (kept, excluded) = assets::exclude_long(sounds, &durations, Duration::from_secs(10));
+    assert_eq!(kept, vec![("note".to_string(), 0), ("unknown".to_string(), 3), ("cave".to_string(), 4)]);
+    assert_eq!(excluded, vec![("disc".to_string(), Duration::from_secs(180)), ("loop".to_string(), Duration::from_secs(30))]);
+}
+
+#[test]
+fn test_sound_definition_fuzz() {
+    use std::collections::HashSet;
+    use crate::assets;
+    use ndarray_rand::rand::{rngs::StdRng, Rng, SeedableRng};
+    use serde_json::{json, Map, Value};
+
+    let mut rng = StdRng::seed_from_u64(0);
+    let events = ["a", "b", "c", "minecraft:a", "missing"];
+    let files = ["x/one", "two", "ns:three"];
+    let number = |rng: &mut StdRng| match rng.gen_range(0..4) {
+        0 => json!(rng.gen_range(0..3)),
+        1 => json!(rng.gen_range(0.0..2.0)),
+        2 => json!("1.0"),
+        _ => json!(null),
+    };
+
+    for _ in 0..500 {
+        let mut definitions = Map::new();
+        for event in &events[..3] {
+            let sounds = (0..rng.gen_range(0..3))
+                .map(|_| {
+                    let reference = rng.gen_bool(0.5);
+                    let name = match reference {
+                        true => events[rng.gen_range(0..events.len())],
+                        false => files[rng.gen_range(0..files.len())],
+                    };
+                    if !reference && rng.gen_bool(0.3) {
+                        return json!(name);
+                    }
+
+                    let mut sound = Map::new();
+                    if rng.gen_bool(0.95) {
+                        sound.insert("name".to_string(), json!(name));
+                    }
+                    match (reference, rng.gen_range(0..4)) {
+                        (true, _) => { sound.insert("type".to_string(), json!("event")); },
+                        (false, 0) => { sound.insert("type".to_string(), json!("file")); },
+                        (false, 1) => { sound.insert("type".to_string(), json!("bogus")); },
+                        (false, _) => {},
+                    }
+                    for field in ["volume", "pitch", "weight", "attenuation_distance"] {
+                        if rng.gen_bool(0.3) {
+                            sound.insert(field.to_string(), number(&mut rng));
+                        }
+                    }
+                    Value::Object(sound)
+                })
+                .collect::<Vec<Value>>();
+
+            let definition = match rng.gen_range(0..10) {
+                0 => json!(sounds),
+                1 => json!({ "replace": true }),
+                _ => json!({ "sounds": sounds, "subtitle": "subtitle", "replace": rng.gen_bool(0.5) }),
+            };
+            definitions.insert(event.to_string(), definition);
+        }
+
+        // no definition is lost to another that doesn't parse
+        let json = Value::Object(definitions.clone()).to_string();
+        let parsed = assets::parse_sound_definitions(&json).unwrap();
+        for (event, definition) in &definitions {
+            let alone = json!({ event: definition }).to_string();
+            assert_eq!(parsed.contains_key(event), !assets::parse_sound_definitions(&alone).unwrap().is_empty(), "{}", json);
+        }
+
+        // references end, cycles or not, on a file of the definitions
+        let file_keys = files.iter().map(|file| assets::sound_key(file)).collect::<HashSet<_>>();
+        for sound in assets::predictable_sounds(&parsed) {
+            assert!(file_keys.contains(&sound.path), "{} played {} in {}", sound.event, sound.path, json);
+            assert_eq!(parsed[&sound.event].sounds.len(), 1, "{}", json);
+        }
+    }
+}
+
+#[cfg(test)]
+fn ogg_page(granule: i64, body: &[u8]) -> Vec<u8> {
+    let mut page = b"OggS".to_vec();
+    page.extend([0, 0]);
+    page.extend(granule.to_le_bytes());
+    page.extend([0; 12]);
+    page.push(1);
+    page.push(body.len() as u8);
+    page.extend(body);
+    page
+}
+
+#[test]
+fn test_sound_info() {
+    use crate::assets::{self, AssetKey};
+
+    let mut ident = b"\x01vorbis".to_vec();
+    ident.extend(0u32.to_le_bytes());
+    ident.push(2);
+    ident.extend(44100u32.to_le_bytes());
+    ident.extend([0; 14]);
+
+    let mut ogg = ogg_page(0, &ident);
+    ogg.extend(ogg_page(-1, &[0; 40]));
+    ogg.extend(ogg_page(22050, &[0; 40]));
+
+    let info = assets::sound_info(&AssetKey::new("test.ogg"), &ogg).unwrap();
+    assert_eq!((info.channels, info.sample_rate), (2, 44100));
+    assert_eq!(info.duration.as_millis(), 500);
+
+    assert!(assets::sound_info(&AssetKey::new("test.ogg"), b"RIFF").is_err());
+}
+
+#[test]
+fn test_conv_nnls() {
+    use ndarray::s;
+
+    let (m, r, n, span) = (12, 6, 20, 3);
+
+    // with atoms one tick long it's the plain solve
+    let data = Array2::random((m, n), Uniform::new(0.0f32, 1.0));
+    let basis = Array2::random((m, r), Uniform::new(0.0f32, 1.0));
+    let plain = algebra::cpu_pgd_nnls(data.view(), basis.view(), 50, 1e-2);
+    let conv = algebra::cpu_conv_pgd_nnls(data.view(), basis.view(), 1, 50, 1e-2);
+    assert!(plain.iter().zip(&conv).all(|(a, b)| (a - b).abs() < 1e-5));
+
+    // and solved in blocks of ticks, which don't change the result either
+    let long = Array2::random((m, 300), Uniform::new(0.0f32, 1.0));
+    let plain = algebra::cpu_pgd_nnls(long.view(), basis.view(), 50, 1e-2);
+    let blocked = algebra::cpu_conv_pgd_nnls(long.view(), basis.view(), 1, 50, 1e-2);
+    assert!(plain.iter().zip(&blocked).all(|(a, b)| (a - b).abs() < 1e-5));
+
+    // solved through the gram matrix when the basis has fewer sounds than
+    // twice its features, and directly otherwise
+    assert!(algebra::use_gram(m, r, 1));
+    assert!(!algebra::use_gram(m, r, span));
+    let wide = Array2::random((m, 3 * m), Uniform::new(0.0f32, 1.0));
+    assert!(!algebra::use_gram(m, 3 * m, 1));
+    let plain = algebra::cpu_pgd_nnls(data.view(), wide.view(), 50, 1e-3);
+    let direct = algebra::cpu_conv_pgd_nnls(data.view(), wide.view(), 1, 50, 1e-3);
+    assert!(plain.iter().zip(&direct).all(|(a, b)| (a - b).abs() < 1e-5));
+
+    // a planted schedule of atoms three ticks long, each heard in the two
+    // ticks after the one it starts in as well
+    let atoms = Array2::random((span * m, r), Uniform::new(0.0f32, 1.0));
+    let mut planted = Array2::<f32>::zeros((r, n));
+    for t in (0..n).step_by(4) {
+        planted[[t % r, t]] = 1.0;
+    }
+
+    let mut data = Array2::<f32>::zeros((m, n));
+    for d in 0..span {
+        let heard = atoms.slice(s![d * m..(d + 1) * m, ..]).dot(&planted);
+        let mut target = data.slice_mut(s![.., d..]);
+        target += &heard.slice(s![.., ..n - d]);
+    }
+
+    let step = 1.0 / (algebra::cpu_lipschitz(atoms.view(), 64) * span as f32);
+    let h = algebra::cpu_conv_pgd_nnls(data.view(), atoms.view(), span, 2000, step);
+
+    let residual = |h: &Array2<f32>| {
+        let mut heard = Array2::<f32>::zeros((m, n));
+        for d in 0..span {
+            let part = atoms.slice(s![d * m..(d + 1) * m, ..]).dot(h);
+            let mut target = heard.slice_mut(s![.., d..]);
+            target += &part.slice(s![.., ..n - d]);
+        }
+        (heard - &data).mapv(|x| x * x).sum().sqrt()
+    };
+    let norm = data.mapv(|x| x * x).sum().sqrt();
+    assert!(residual(&h) < 0.05 * norm, "residual {} of {}", residual(&h), norm);
+}
+
+#[test]
+fn test_tuning_cache_roundtrip() {
+    use crate::tuning::{Tiles, TuningCache};
+
+    let assets_path = std::env::temp_dir().join(format!("minecraft-player-tuning-{}", std::process::id()));
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+
+    let mut cache = runtime.block_on(TuningCache::load(&assets_path));
+    assert!(cache.devices.is_empty());
+
+    cache.devices.insert("gfx1100".to_string(), Tiles { row: 4, col: 32 });
+    runtime.block_on(cache.save(&assets_path)).unwrap();
+    let loaded = runtime.block_on(TuningCache::load(&assets_path));
+    std::fs::remove_dir_all(&assets_path).unwrap();
+
+    assert_eq!(loaded.devices.get("gfx1100"), Some(&Tiles { row: 4, col: 32 }));
+}
+
+#[test]
+fn test_host_basis_solve() {
+    let data = Array2::random((12, 10), Uniform::new(0.0f32, 1.0));
+    let atoms = Array2::random((24, 5), Uniform::new(0.0f32, 1.0));
+
+    let whole = algebra::cpu_conv_pgd_nnls(data.view(), atoms.view(), 2, 80, 1e-3);
+
+    // resuming from a checkpoint continues the same descent
+    let (halfway, completed) = algebra::cpu_conv_pgd_nnls_from(data.view(), atoms.view(), 2, Array2::zeros((5, 10)), 40, 1e-3, false);
+    assert_eq!(completed, 40);
+
+    let basis = algebra::Basis::Host(atoms);
+    let (resumed, completed) = algebra::conv_pgd_nnls(data.view(), &basis, 2, halfway, 40, 1e-3, false).unwrap();
+    assert_eq!(completed, 40);
+    assert!(whole.iter().zip(&resumed).all(|(a, b)| (a - b).abs() < 1e-5));
+}
+
+#[test]
+fn test_dedup() {
+    use crate::audio;
+
+    let tone = gen_frequency(440.0, 48000, 50);
+    let mut quieter = tone.clone();
+    quieter.adjust_volume(0.5);
+    let mut nudged = tone.clone();
+    nudged.samples[10] += 1e-7;
+
+    let sounds = vec![
+        ("block.note_block.harp".to_string(), tone.clone()),
+        ("block.note_block.bell".to_string(), quieter),
+        ("item.harp".to_string(), nudged),
+        ("a.harp".to_string(), tone),
+    ];
+
+    let mut kept = audio::dedup(sounds).into_iter().map(|(name, _)| name).collect::<Vec<String>>();
+    kept.sort();
+    assert_eq!(kept, vec!["a.harp", "block.note_block.bell"]);
+}
+
+#[test]
+fn test_emphasis_budgets() {
+    use crate::emphasis;
+
+    let file = "# chorus\n1.0 1.5 4\n\n0.0 0.1 0 # silent intro\n";
+    let weights = emphasis::parse(file, 40).unwrap();
+    assert_eq!(weights[0], 0.0);
+    assert_eq!(weights[5], 1.0);
+    assert_eq!(weights[20], 4.0);
+    assert_eq!(weights[30], 1.0);
+
+    let budgets = emphasis::budgets(&weights, 80);
+    assert_eq!(budgets[0], 1);
+    assert_eq!(budgets[5], 20);
+    assert_eq!(budgets[25], 80);
+
+    assert!(matches!(emphasis::parse("1.0 2.0", 40), Err(emphasis::EmphasisError::Parse { line: 1, .. })));
+    assert!(matches!(emphasis::parse("\n2.0 1.0 1", 40), Err(emphasis::EmphasisError::Parse { line: 2, .. })));
+}
+
+#[test]
+fn test_fft_round_trip() {
+    use crate::features::{FeatureExtractor, MelWeighted};
+
+    let processor = crate::audio::Processor::new();
+    let tone = gen_frequency(440.0, 22050, 50);
+
+    // the window stays on, the inverse only undoes the scaling
+    let round_trip = processor.ifft(processor.fft(tone.clone()));
+    let windowed = tone.samples.iter().zip(apodize::hamming_iter(tone.samples.len())).map(|(s, w)| s * w as f32);
+    let err = windowed.zip(&round_trip).map(|(a, b)| (a - b).abs()).fold(0.0f32, f32::max);
+    assert!(err < 1e-4, "round trip deviates by {}", err);
+
+    // filtering with no weights gives the sound back, edges too
+    let filtered = processor.filter(&tone, |_| 1.0);
+    let err = tone.samples.iter().zip(&filtered).map(|(a, b)| (a - b).abs()).fold(0.0f32, f32::max);
+    assert!(err < 1e-4, "filter deviates by {}", err);
+
+    // the same tone for twice as long comes out just as loud
+    let rms = |samples: &[f32]| (samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32).sqrt();
+    let short = rms(&MelWeighted.extract(&gen_frequency(1234.0, 48000, 50), &processor));
+    let long = rms(&MelWeighted.extract(&gen_frequency(1234.0, 48000, 100), &processor));
+    assert!((short / long - 1.0).abs() < 0.05, "mel scales with the length: {} vs {}", short, long);
+}
+
+#[test]
+fn test_schedule_cache() {
+    use crate::schedule::{self, Schedule};
+
+    let tone = gen_frequency(440.0, 22050, 50);
+    let key = schedule::fingerprint(std::slice::from_ref(&tone), "settings");
+    assert_eq!(key, schedule::fingerprint(std::slice::from_ref(&tone), "settings"));
+    assert_ne!(key, schedule::fingerprint(std::slice::from_ref(&tone), "other settings"));
+    assert_ne!(key, schedule::fingerprint(&[gen_frequency(441.0, 22050, 50)], "settings"));
+
+    let assets = std::env::temp_dir().join(format!("minecraft-player-schedules-{}", std::process::id()));
+    let path = schedule::path(&assets, &key);
+    let saved = Schedule {
+        sound_ids: vec![("minecraft:block.note_block.harp".to_string(), 0.5), ("minecraft:entity.cat.ambient".to_string(), 2.0)],
+        amplitudes: Array2::random((2, 7), Uniform::new(0.0f32, 1.0))
+    };
+    saved.save(&path).unwrap();
+
+    let loaded = Schedule::load(&path).unwrap();
+    assert_eq!(loaded.sound_ids, saved.sound_ids);
+    assert_eq!(loaded.amplitudes, saved.amplitudes);
+
+    std::fs::write(&path, b"MCPH").unwrap();
+    assert!(Schedule::load(&path).is_err());
+    std::fs::remove_dir_all(&assets).unwrap();
+}
+
+#[test]
+#[cfg(feature = "structure")]
+fn test_structure_command_blocks() {
+    use crate::structure;
+
+    let contains = |haystack: &[u8], needle: &str| haystack.windows(needle.len()).filter(|window| *window == needle.as_bytes()).count();
+
+    let ticks = vec![vec!["playsound minecraft:block.note_block.harp record @a 0 -60 0 1.00000 1.00000".to_string()]; 65];
+    let nbt = structure::command_blocks(&ticks, false);
+
+    // a root compound with an empty name
+    assert_eq!(&nbt[..3], &[10, 0, 0]);
+    assert_eq!(contains(&nbt, "playsound"), 65);
+
+    // every row but the last triggers the next one, wrapping onto a new layer
+    assert_eq!(contains(&nbt, "redstone_block"), 64);
+    assert_eq!(contains(&nbt, "setblock ~-3 ~0 ~1 redstone_block"), 63);
+    assert_eq!(contains(&nbt, "setblock ~-3 ~1 ~-63 redstone_block"), 1);
+    assert_eq!(contains(&nbt, "setblock ~-1 ~ ~ air"), 65);
+
+    // a loop's last row triggers the first again
+    let looping = structure::command_blocks(&ticks, true);
+    assert_eq!(contains(&looping, "redstone_block"), 65);
+    assert_eq!(contains(&looping, "setblock ~-3 ~-1 ~0 redstone_block"), 1);
+}
+
+#[test]
+fn test_smooth_ticks() {
+    use ndarray::array;
+
+    let mut schedule = array![[1.0f32, 0.0, 1.0, 1.0], [0.0, 0.0, 0.0, 0.0]];
+    algebra::smooth_ticks(&mut schedule, 0.5);
+
+    // the dropout is filled in halfway and the edges blend with their one neighbour
+    assert_eq!(schedule.row(0).to_vec(), vec![0.5, 0.5, 0.75, 1.0]);
+    assert!(schedule.row(1).iter().all(|value| *value == 0.0));
+
+    let mut unchanged = array![[1.0f32, 0.0, 1.0]];
+    algebra::smooth_ticks(&mut unchanged, 0.0);
+    assert_eq!(unchanged, array![[1.0f32, 0.0, 1.0]]);
+}
+
+#[test]
+fn test_tempo() {
+    use crate::{audio::{Processor, Sound}, tempo};
+
+    // a click every beat at 125bpm, which is 9.6 ticks per beat
+    let sample_rate = 22050;
+    let beat = sample_rate * 60 / 125;
+    let mut samples = vec![0.0f32; sample_rate * 10];
+    for start in (0..samples.len()).step_by(beat) {
+        for (i, sample) in samples[start..].iter_mut().take(200).enumerate() {
+            *sample = (i as f32 * 0.7).sin() * (1.0 - i as f32 / 200.0);
+        }
+    }
+    let clicks = Sound { samples, sample_rate };
+
+    let bpm = tempo::estimate(&clicks, &Processor::new()).unwrap();
+    assert!((bpm - 125.0).abs() < 2.0, "estimated {}bpm", bpm);
+
+    let stretch = tempo::grid_stretch(125.0, 1).unwrap();
+    assert!((stretch - 10.0 / 9.6).abs() < 1e-4);
+    assert!(tempo::grid_stretch(125.0 * 1.5 / 1.1, 3).is_none());
+
+    // stretching keeps the pitch
+    let tone = gen_frequency(440.0, sample_rate, 1000);
+    let stretched = tempo::time_stretch(&tone, stretch);
+    assert_eq!(stretched.samples.len(), (tone.samples.len() as f32 * stretch).round() as usize);
+
+    let crossings = |samples: &[f32]| samples.windows(2).filter(|pair| pair[0] < 0.0 && pair[1] >= 0.0).count() as f32;
+    let ratio = crossings(&stretched.samples) / crossings(&tone.samples);
+    assert!((ratio - stretch).abs() < 0.02, "{} crossings per original one", ratio);
+}
+
+#[test]
+fn test_chapter_functions() {
+    use crate::export;
+
+    assert_eq!(export::chapter_function(24000), "function audio:_/24000\n");
+
+    let index = export::index_function(&[0, 24000, 96000]);
+    let lines = index.lines().collect::<Vec<&str>>();
+    assert_eq!(lines.len(), 3);
+    assert!(lines[1].starts_with("tellraw @s "));
+
+    let line = serde_json::from_str::<serde_json::Value>(lines[2].trim_start_matches("tellraw @s ")).unwrap();
+    assert_eq!(line["text"], "chapter 3 (1:20:00)");
+    assert_eq!(line["clickEvent"]["value"], "/function audio:chapter/2");
+}
+
+#[test]
+fn test_read_cached_sounds() {
+    use std::collections::HashMap;
+
+    use crate::{assets::{self, AssetKey, Concurrency, FetchBehavior, FetchOptions}, mojang::{AssetIndex, Version}};
+
+    let assets_path = std::env::temp_dir().join(format!("minecraft-player-reads-{}", std::process::id()));
+    let version = Version { id: "1.21".to_string(), kind: "release".to_string(), url: String::new(), release_time: None };
+    let sounds = assets_path.join("1.21").join("minecraft/sounds");
+    std::fs::create_dir_all(&sounds).unwrap();
+    for (name, size) in [("small.ogg", 10), ("large.ogg", 200_000), ("notes.txt", 5)] {
+        std::fs::write(sounds.join(name), vec![size as u8; size]).unwrap();
+    }
+
+    // one file open at a time still reads all of them
+    let index = AssetIndex { objects: HashMap::new() };
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let options = FetchOptions { behavior: FetchBehavior::CacheOnly, concurrency: Concurrency { downloads: 1, files: 1 }, ..FetchOptions::default() };
+    let read = runtime.block_on(assets::fetch_sounds(&assets_path, &version, &options, &index)).unwrap();
+
+    // offline never refetches, whatever the behavior
+    let offline = FetchOptions { behavior: FetchBehavior::Refetch, offline: true, ..FetchOptions::default() };
+    let offline_read = runtime.block_on(assets::fetch_sounds(&assets_path, &version, &offline, &index)).unwrap();
+    std::fs::remove_dir_all(&assets_path).unwrap();
+
+    assert_eq!(offline_read.len(), 2);
+    assert_eq!(read.len(), 2);
+    assert_eq!(read[&AssetKey::new("minecraft/sounds/large.ogg")].len(), 200_000);
+    assert_eq!(read[&AssetKey::new("minecraft/sounds/small.ogg")].as_ref(), &[10u8; 10]);
+}
+
+#[test]
+fn test_network_errors() {
+    use crate::mojang::{self, MojangError, Network};
+
+    let certificate = std::env::temp_dir().join(format!("minecraft-player-ca-{}.pem", std::process::id()));
+    std::fs::write(&certificate, "-----BEGIN CERTIFICATE-----\nnot base64\n-----END CERTIFICATE-----\n").unwrap();
+    let bad_certificate = mojang::configure(&Network { proxy: None, ca_certificates: vec![certificate.clone()] });
+    let missing_certificate = mojang::configure(&Network { proxy: None, ca_certificates: vec![certificate.with_extension("missing")] });
+    std::fs::remove_file(&certificate).unwrap();
+
+    // failing to configure leaves the client alone
+    assert!(matches!(bad_certificate, Err(MojangError::Certificate { .. })));
+    assert!(matches!(missing_certificate, Err(MojangError::Certificate { .. })));
+    assert!(matches!(mojang::configure(&Network { proxy: Some("not a url".to_string()), ca_certificates: Vec::new() }), Err(MojangError::Proxy { .. })));
+}
+
+#[test]
+fn test_alias_map() {
+    use crate::export::{self, ExportError};
+
+    let aliases = export::parse_aliases(r#"{ "entity.cow.ambient": "mypack:cow/moo", "block.bell.use": "block.note_block.bell" }"#).unwrap();
+    assert_eq!(aliases["entity.cow.ambient"], "mypack:cow/moo");
+    assert_eq!(aliases["block.bell.use"], "block.note_block.bell");
+
+    assert!(matches!(export::parse_aliases(r#"{ "block.bell.use": "Block Bell" }"#), Err(ExportError::InvalidAlias { .. })));
+    assert!(matches!(export::parse_aliases(r#"{ "block.bell.use": "a:b:c" }"#), Err(ExportError::InvalidAlias { .. })));
+    assert!(matches!(export::parse_aliases("[]"), Err(ExportError::Json(_))));
+
+    // keyed like the basis names its events
+    let aliases = export::parse_aliases(r#"{ "minecraft:entity.cow.ambient": "mypack:cow/moo" }"#).unwrap();
+    assert_eq!(aliases["entity.cow.ambient"], "mypack:cow/moo");
+    let twice = r#"{ "minecraft:block.bell.use": "a", "block.bell.use": "b" }"#;
+    assert!(matches!(export::parse_aliases(twice), Err(ExportError::DuplicateAlias(event)) if event == "block.bell.use"));
+}
+
+#[test]
+fn test_heaviest_window() {
+    use crate::emphasis;
+
+    let weights = [0.1, 0.2, 0.9, 1.0, 0.8, 0.1, 0.3];
+    assert_eq!(emphasis::heaviest_window(&weights, 3), 2);
+    assert_eq!(emphasis::heaviest_window(&weights, 1), 3);
+    assert_eq!(emphasis::heaviest_window(&weights, 7), 0);
+    assert_eq!(emphasis::heaviest_window(&weights, 20), 0);
+}
+
+#[test]
+fn test_focus_weights() {
+    use crate::features::Focus;
+
+    let weights = Focus::Voice.weights(&[1000.0, 300.0, 150.0, 212.0, 6800.0, 20000.0]);
+    assert_eq!(weights[0], 4.0);
+    assert_eq!(weights[1], 4.0);
+    assert!((weights[2] - 1.0).abs() < 1e-5, "an octave below is left as is");
+    assert!(weights[3] > 1.0 && weights[3] < 4.0);
+    assert!((weights[4] - 1.0).abs() < 1e-5, "an octave above is left as is");
+    assert_eq!(weights[5], 1.0);
+}
+
+#[test]
+fn test_post_process() {
+    use crate::algebra::PostProcess;
+
+    let mut schedule = Array2::from_shape_vec((1, 4), vec![0.25, 0.5, 0.0001, 0.81]).unwrap();
+    PostProcess { gamma: 0.5, renormalize: true, epsilon: 0.05, round: 0.1, ..PostProcess::default() }.apply(&mut schedule);
+
+    // 0.81^0.5 = 0.9 is scaled up to 1, and 0.0001^0.5 = 0.01 is dropped
+    let expected = [0.6, 0.8, 0.0, 1.0];
+    for (value, expected) in schedule.iter().zip(expected) {
+        assert!((value - expected).abs() < 1e-5, "{} != {}", value, expected);
+    }
+
+    // a chapter fades by where it is in the whole schedule
+    let mut chapter = Array2::from_elem((2, 3), 1.0f32);
+    algebra::fade(&mut chapter, 7, 10, 1, 3);
+    assert_eq!(chapter.row(0).to_vec(), vec![0.75, 0.5, 0.25]);
+
+    let mut faded = Array2::from_elem((1, 6), 1.0f32);
+    PostProcess { fade_in: 3, ..PostProcess::default() }.apply(&mut faded);
+    assert_eq!(faded.row(0).to_vec(), vec![0.25, 0.5, 0.75, 1.0, 1.0, 1.0]);
+
+    let mut unchanged = Array2::from_shape_vec((1, 2), vec![0.25, 0.5]).unwrap();
+    PostProcess::default().apply(&mut unchanged);
+    assert_eq!(unchanged.as_slice().unwrap(), &[0.25, 0.5]);
+}
+
+#[test]
+fn test_json_log_lines() {
+    use std::{io::Write, sync::{Arc, Mutex}};
+    use tracing::{event, span, Level};
+    use tracing_subscriber::layer::SubscriberExt;
+    use crate::logging::{json_layer, CustomLayer};
+
+    #[derive(Clone, Default)]
+    struct Capture(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Capture {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let capture = Capture::default();
+    let writer = capture.clone();
+    let subscriber = tracing_subscriber::registry()
+        .with(CustomLayer)
+        .with(json_layer(move || writer.clone()));
+
+    tracing::subscriber::with_default(subscriber, || {
+        let _span = span!(Level::INFO, "solve", tag = "gpu").entered();
+        event!(Level::INFO, progress = true, iteration = 3, iterations = 128, "solving");
+        event!(Level::ERROR, help = true, "try `--local`");
+    });
+
+    let output = String::from_utf8(capture.0.lock().unwrap().clone()).unwrap();
+    let lines = output.lines().map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap()).collect::<Vec<_>>();
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[0]["level"], "info");
+    assert_eq!(lines[0]["tag"], "gpu");
+    assert_eq!(lines[0]["message"], "solving");
+    assert_eq!(lines[0]["iteration"], 3);
+    assert_eq!(lines[0]["iterations"], 128);
+    assert_eq!(lines[1]["level"], "error");
+    assert_eq!(lines[1]["help"], true);
+}
+
+#[test]
+fn test_log_rotation() {
+    use crate::logging;
+
+    let directory = std::env::temp_dir().join(format!("minecraft-player-logs-{}", std::process::id()));
+    std::fs::create_dir_all(&directory).unwrap();
+    let path = directory.join("run.log");
+
+    for run in 0..7 {
+        logging::rotate(&path).unwrap();
+        std::fs::write(&path, run.to_string()).unwrap();
+    }
+
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "6");
+    assert_eq!(std::fs::read_to_string(directory.join("run.log.1")).unwrap(), "5");
+    assert_eq!(std::fs::read_to_string(directory.join("run.log.5")).unwrap(), "1");
+    assert!(!directory.join("run.log.6").exists());
+
+    std::fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+fn test_asset_keys() {
+    use std::path::Path;
+
+    use crate::assets::AssetKey;
+
+    let key = AssetKey::new("minecraft/sounds/note/harp.ogg");
+    assert_eq!(AssetKey::new("minecraft\\sounds\\note\\harp.ogg"), key);
+    assert_eq!(AssetKey::from_relative(&Path::new("minecraft").join("sounds").join("note").join("harp.ogg")), key);
+
+    let cache = Path::new("assets").join("1.21");
+    assert_eq!(key.to_path(&cache), cache.join("minecraft").join("sounds").join("note").join("harp.ogg"));
+    assert_eq!(AssetKey::from_relative(key.to_path(&cache).strip_prefix(&cache).unwrap()), key);
+}
+
+#[test]
+fn test_repeat_function() {
+    use crate::export;
+
+    assert_eq!(export::repeat_function(10, 3, Some(13)), concat!(
+        "function audio:_/play/10\n",
+        "schedule function audio:_/play/10 1t append\n",
+        "schedule function audio:_/play/10 2t append\n",
+        "schedule function audio:_/13 3t append\n",
+    ));
+
+    // the last run has nothing to schedule after it
+    assert_eq!(export::repeat_function(10, 3, None).lines().count(), 3);
+    assert_eq!(export::schedule_next(Some(4), 1), "schedule function audio:_/4 1t append\n");
+}
+
+#[test]
+fn test_finish_function() {
+    use crate::export::{self, Category};
+
+    assert_eq!(export::finish_function(None, Category::Record), "stopsound @a[tag=!nomusic] record\n");
+    assert_eq!(export::finish_function(Some("mypack:next_song"), Category::Record).lines().last(), Some("function mypack:next_song"));
+    assert_eq!(export::schedule_finish(2), "schedule function audio:finish 2t append\n");
+
+    assert!(export::is_resource_location("mypack:songs/next"));
+    assert!(export::is_resource_location("next_song"));
+    assert!(!export::is_resource_location("MyPack:Next Song"));
+    assert!(!export::is_resource_location("mypack:"));
+}
+
+#[test]
+fn test_loop() {
+    use ndarray::s;
+    use crate::{export, preview::{Clipping, Preview}};
+
+    // atoms started in the last ticks ring on into the first ones
+    let (m, r, n, span) = (12, 6, 20, 3);
+    let atoms = Array2::random((span * m, r), Uniform::new(0.0f32, 1.0));
+    let mut planted = Array2::<f32>::zeros((r, n));
+    for t in (1..n).step_by(3) {
+        planted[[t % r, t]] = 1.0;
+    }
+
+    let heard = |h: &Array2<f32>| {
+        let mut heard = Array2::<f32>::zeros((m, n));
+        for d in 0..span {
+            let part = atoms.slice(s![d * m..(d + 1) * m, ..]).dot(h);
+            for t in 0..n {
+                let mut column = heard.column_mut((t + d) % n);
+                column += &part.column(t);
+            }
+        }
+        heard
+    };
+    let data = heard(&planted);
+
+    let step = 1.0 / (algebra::cpu_lipschitz(atoms.view(), 64) * span as f32);
+    let (h, _) = algebra::cpu_conv_pgd_nnls_from(data.view(), atoms.view(), span, Array2::zeros((r, n)), 2000, step, true);
+    let residual = (heard(&h) - &data).mapv(|x| x * x).sum().sqrt();
+    let norm = data.mapv(|x| x * x).sum().sqrt();
+    assert!(residual < 0.05 * norm, "residual {} of {}", residual, norm);
+
+    // the preview's end is crossfaded into its start, which is left out
+    let mut cursor = std::io::Cursor::new(Vec::new());
+    let mut preview = Preview::new(&mut cursor, 48000, Clipping::Limit, false).unwrap().with_seam(2);
+    for tick in [[1.0, 1.0], [0.5, 0.5], [0.0, 0.0]] {
+        preview.write_tick(tick.to_vec()).unwrap();
+    }
+    preview.finalize().unwrap();
+    cursor.set_position(0);
+    let samples = crate::decode::read_wav(hound::WavReader::new(cursor).unwrap()).unwrap().samples;
+    assert_eq!(samples.len(), 4);
+    assert_eq!(samples[..2], [0.5, 0.5]);
+    assert!((samples[2] - 1.0 / 3.0).abs() < 1e-6 && (samples[3] - 2.0 / 3.0).abs() < 1e-6);
+
+    assert_eq!(export::stop_function(&[0, 4], &[1], export::Category::Record), concat!(
+        "schedule clear audio:_/0\n",
+        "schedule clear audio:_/4\n",
+        "schedule clear audio:_/play/1\n",
+        "stopsound @a[tag=!nomusic] record\n",
+    ));
+}
+
+#[test]
+fn test_sound_categories() {
+    use crate::export::{self, Anchor, Category, PlaySound};
+
+    let sounds = vec![PlaySound { sound: 0, name: "minecraft:block.note_block.harp", volume: 1.0, pitch: 1.0 }];
+    assert_eq!(export::tick_sounds(&sounds, true, Category::Music, &Anchor::default()), concat!(
+        "stopsound @a[tag=!nomusic] music\n",
+        "playsound minecraft:block.note_block.harp music @a 0 -60 0 1.00000 1.00000 \n",
+    ));
+    assert_eq!(export::finish_function(None, Category::Ambient), "stopsound @a[tag=!nomusic] ambient\n");
+
+    // ducking stops the other categories before the first tick
+    assert_eq!(export::start_function(&[Category::Music, Category::Weather]), concat!(
+        "stopsound @a[tag=!nomusic] music\n",
+        "stopsound @a[tag=!nomusic] weather\n",
+        "function audio:_/0\n",
+    ));
+    assert_eq!(export::start_function(&[]), "function audio:_/0\n");
+}
+
+#[test]
+fn test_datapack_exporter() {
+    use crate::{export::{self, Anchor, Category, Format, PlaySound}, exporter::{self, Song}};
+
+    let output = std::env::temp_dir().join(format!("minecraft-player-exporter-{}", std::process::id()));
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    runtime.block_on(export::prepare_output(&output, true)).unwrap();
+
+    let song = Song { output: output.clone(), n_ticks: 5, atom_ticks: 1, category: Category::Record, anchor: Anchor::default(), directions: None, looping: false, on_finish: None, duck: Vec::new(), ticks_per_function: 1, pack_format: export::PACK_FORMAT, open_format: false, annotate: false, residuals: None };
+    let harp = vec![PlaySound { sound: 0, name: "minecraft:block.note_block.harp", volume: 1.0, pitch: 1.0 }];
+    let mut exporter = exporter::create(Format::Datapack, &song).unwrap();
+    for (index, sounds) in [&harp[..], &[], &[], &harp, &harp].into_iter().enumerate() {
+        exporter.write_tick(index, sounds).unwrap();
+    }
+    exporter.finish().unwrap();
+
+    // the silence in between is skipped over by the tick before it
+    let tick_directory = export::tick_directory(&output, export::PACK_FORMAT);
+    let read = |tick: usize| std::fs::read_to_string(tick_directory.join(format!("{}.mcfunction", tick))).ok();
+    assert!(read(0).unwrap().ends_with("schedule function audio:_/1 1t append\n"));
+    assert!(read(1).unwrap().ends_with("schedule function audio:_/3 2t append\n"));
+    assert_eq!(read(2), None);
+    assert!(read(4).unwrap().ends_with("schedule function audio:finish 1t append\n"));
+    assert!(export::finish_path(&output, export::PACK_FORMAT).exists());
+
+    std::fs::remove_dir_all(&output).unwrap();
+}
+
+#[test]
+fn test_atom_stops() {
+    use crate::{export::{self, Anchor, Category, Format, PlaySound}, exporter::{self, Song}};
+
+    let output = std::env::temp_dir().join(format!("minecraft-player-stops-{}", std::process::id()));
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    runtime.block_on(export::prepare_output(&output, true)).unwrap();
+
+    let song = Song { output: output.clone(), n_ticks: 6, atom_ticks: 2, category: Category::Record, anchor: Anchor::default(), directions: None, looping: false, on_finish: None, duck: Vec::new(), ticks_per_function: 1, pack_format: export::PACK_FORMAT, open_format: false, annotate: false, residuals: None };
+    let harp = PlaySound { sound: 0, name: "minecraft:block.note_block.harp", volume: 1.0, pitch: 1.0 };
+    let bass = PlaySound { sound: 1, name: "minecraft:block.note_block.bass", volume: 1.0, pitch: 1.0 };
+    let mut exporter = exporter::create(Format::Datapack, &song).unwrap();
+    for (index, sounds) in [vec![harp.clone()], vec![bass.clone()], vec![bass.clone()], vec![], vec![], vec![]].iter().enumerate() {
+        exporter.write_tick(index, sounds).unwrap();
+    }
+    exporter.finish().unwrap();
+
+    // a sound is stopped two ticks after it played, unless it played since
+    let tick_directory = export::tick_directory(&output, export::PACK_FORMAT);
+    let read = |tick: usize| std::fs::read_to_string(tick_directory.join(format!("{}.mcfunction", tick))).unwrap_or_default();
+    let harp_stop = export::stop_sound(Category::Record, harp.name);
+    let bass_stop = export::stop_sound(Category::Record, bass.name);
+    assert!(!read(1).contains("stopsound"));
+    assert!(read(2).starts_with(&harp_stop));
+    assert!(!read(3).contains("stopsound"));
+    assert_eq!(read(4).lines().next(), bass_stop.lines().next());
+    assert!(!read(0).contains("stopsound"));
+
+    std::fs::remove_dir_all(&output).unwrap();
+}
+
+#[test]
+fn test_pack_format() {
+    use crate::{export, versions};
+    use std::path::Path;
+
+    assert_eq!(versions::pack_format("2024-06-13T08:24:03+00:00"), Some(48));
+    // the snapshot that renamed `functions`, and one before it
+    assert_eq!(versions::pack_format("2024-05-22T12:00:00+00:00"), Some(45));
+    assert_eq!(versions::pack_format("2024-05-15T12:00:00+00:00"), Some(41));
+    assert_eq!(versions::pack_format("2017-09-18T08:39:46+00:00"), None);
+    // 1.21.9 and 1.21.10, then a version the table doesn't cover yet
+    assert_eq!(versions::pack_format("2025-09-30T09:24:59+00:00"), Some(88));
+    assert_eq!(versions::pack_format("2025-10-07T09:10:04+00:00"), Some(88));
+    assert_eq!(versions::pack_format("2025-12-09T12:00:00+00:00"), None);
+
+    let output = Path::new("song");
+    assert!(export::tick_directory(output, 41).ends_with("data/audio/functions/_"));
+    assert!(export::tick_directory(output, 48).ends_with("data/audio/function/_"));
+    assert!(export::load_tag_path(output, 15).ends_with("data/minecraft/tags/functions/load.json"));
+
+    assert_eq!(versions::newest_pack_format(), 88);
+    assert_eq!(export::mcmeta(48, false)["pack"].get("min_format"), None);
+    assert_eq!(export::mcmeta(88, false)["pack"]["max_format"], 88);
+    assert_eq!(export::mcmeta(88, false)["pack"].get("supported_formats"), None);
+    // a version newer than the table loads it whatever its format
+    let open = export::mcmeta(88, true);
+    assert_eq!(open["pack"]["min_format"], 88);
+    assert!(open["pack"]["max_format"].as_u64().unwrap() > 88);
+    assert_eq!(open["pack"]["supported_formats"]["min_inclusive"], 88);
+}
+
+#[test]
+fn test_grouped_datapack() {
+    use crate::{export::{self, Anchor, Category, Format, PlaySound}, exporter::{self, Song}};
+
+    let output = std::env::temp_dir().join(format!("minecraft-player-grouped-{}", std::process::id()));
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    runtime.block_on(export::prepare_output(&output, true)).unwrap();
+
+    let song = Song { output: output.clone(), n_ticks: 5, atom_ticks: 1, category: Category::Record, anchor: Anchor::default(), directions: None, looping: false, on_finish: None, duck: Vec::new(), ticks_per_function: 2, pack_format: export::PACK_FORMAT, open_format: false, annotate: false, residuals: None };
+    let harp = vec![PlaySound { sound: 0, name: "minecraft:block.note_block.harp", volume: 1.0, pitch: 1.0 }];
+    let mut exporter = exporter::create(Format::Datapack, &song).unwrap();
+    for (index, sounds) in [&harp[..], &harp, &[], &[], &harp].into_iter().enumerate() {
+        exporter.write_tick(index, sounds).unwrap();
+    }
+    exporter.finish().unwrap();
+
+    let tick_directory = export::tick_directory(&output, export::PACK_FORMAT);
+    let read = |tick: usize| std::fs::read_to_string(tick_directory.join(format!("{}.mcfunction", tick))).ok();
+    let first = read(0).unwrap();
+    // started from outside, it starts over whatever the score was left at
+    assert!(first.starts_with("execute unless score #next audio matches 0 run scoreboard players set #tick audio 0\nscoreboard players reset #next audio\n"));
+    assert!(first.contains("execute if score #tick audio matches 0..0 run scoreboard players set #next audio 0\n"));
+    assert!(first.contains("execute if score #tick audio matches 1 run playsound minecraft:block.note_block.harp"));
+    assert!(first.contains("execute if score #tick audio matches 0..0 run schedule function audio:_/0 1t append\n"));
+    assert!(first.ends_with("execute if score #tick audio matches 1 run schedule function audio:_/2 1t append\nscoreboard players add #tick audio 1\n"));
+    assert_eq!(read(1), None);
+
+    // a silent block is skipped over, like a silent run
+    assert_eq!(read(2).unwrap(), "stopsound @a[tag=!nomusic] record\nschedule function audio:_/4 2t append\n");
+    assert!(read(4).unwrap().contains("execute if score #tick audio matches 4 run schedule function audio:finish 1t append\n"));
+    assert_eq!(std::fs::read_to_string(export::load_path(&output, export::PACK_FORMAT)).unwrap(), "scoreboard objectives add audio dummy\n");
+    assert!(export::load_tag_path(&output, export::PACK_FORMAT).exists());
+
+    std::fs::remove_dir_all(&output).unwrap();
+}
+
+#[test]
+fn test_anchor() {
+    use crate::export::{Anchor, Category, PlaySound};
+
+    let harp = PlaySound { sound: 0, name: "minecraft:block.note_block.harp", volume: 1.0, pitch: 0.5 };
+    assert_eq!("player".parse::<Anchor>().unwrap().playsound(&harp, Category::Record), "execute as @a at @s run playsound minecraft:block.note_block.harp record @s ~ ~ ~ 1.00000 0.50000 ");
+    assert_eq!("world 12 64.5 -3".parse::<Anchor>().unwrap().playsound(&harp, Category::Record), "playsound minecraft:block.note_block.harp record @a 12 64.5 -3 1.00000 0.50000 ");
+    assert_eq!("entity @e[tag=speaker]".parse::<Anchor>().unwrap(), Anchor::Entity("@e[tag=speaker]".to_string()));
+    assert!(Anchor::Entity("@e[tag=speaker]".to_string()).playsound(&harp, Category::Music).starts_with("execute at @e[tag=speaker] run playsound"));
+
+    assert_eq!("world 0 -60 0".parse::<Anchor>().unwrap(), Anchor::default());
+    assert!("world 1 2".parse::<Anchor>().is_err());
+    assert!("entity speaker".parse::<Anchor>().is_err());
+    assert!("nowhere".parse::<Anchor>().is_err());
+}
+
+#[test]
+fn test_surround() {
+    use std::f32::consts::{FRAC_PI_2, PI};
+    use crate::{audio::Sound, decode, export::{Category, PlaySound}, surround};
+
+    let stereo = Sound { samples: vec![0.5, -0.5, 0.25, 0.0, 0.5, 0.5, 0.0, 0.0], sample_rate: 20 };
+    let channels = decode::split_channels(&stereo, 2);
+    let (left, right) = (&channels[0], &channels[1]);
+    assert_eq!(left.samples, vec![0.5, 0.25, 0.5, 0.0]);
+
+    // out of phase, only the left, the same on both and silence
+    let directions = surround::directions(left, right, 1);
+    assert!((directions[0] - PI).abs() < 1e-5);
+    assert!((directions[1] - FRAC_PI_2).abs() < 1e-5);
+    assert_eq!(directions[2..], [0.0, 0.0]);
+
+    let [(first, a), (second, b)] = surround::gains(FRAC_PI_2 / 2.0);
+    assert_eq!((first, second), (0, 1));
+    assert!((a * a + b * b - 1.0).abs() < 1e-5);
+    assert_eq!(surround::gains(3.0 * FRAC_PI_2 + 1e-7)[1].0, 0);
+
+    let harp = [PlaySound { sound: 0, name: "minecraft:block.note_block.harp", volume: 1.0, pitch: 1.0 }];
+    assert_eq!(surround::tick_sounds(&harp, false, Category::Record, FRAC_PI_2), "execute as @a at @s positioned ^2 ^ ^ run playsound minecraft:block.note_block.harp record @s ~ ~ ~ 1.00000 1.00000 \n");
+    assert_eq!(surround::tick_sounds(&harp, false, Category::Record, FRAC_PI_2 / 2.0).lines().count(), 2);
+}
+
+#[test]
+fn test_loudness() {
+    use crate::{algebra::PostProcess, loudness};
+    use ndarray::array;
+
+    let levels = loudness::sound_levels([[0.5f32, -0.5].as_slice(), &[]]);
+    assert_eq!(levels, vec![0.5, 0.0]);
+
+    // the silent tick and the one 20dB under the rest are gated out
+    let schedule = array![[1.0f32, 0.0, 0.1, 1.0], [1.0, 1.0, 0.0, 0.0]];
+    let powers = loudness::tick_powers(schedule.view(), &levels);
+    assert_eq!([powers[0], powers[1], powers[3]], [0.25, 0.0, 0.25]);
+    assert!((powers[2] - 0.0025).abs() < 1e-7);
+    let loudness = loudness::loudness(&powers).unwrap();
+    assert!((loudness + 6.0206).abs() < 1e-3);
+    assert_eq!(loudness::loudness(&[0.0, 1e-9]), None);
+
+    assert!((loudness::gain(-6.0, -26.0) - 0.1).abs() < 1e-6);
+
+    // the gain can't push a volume past 1
+    let mut loud = array![[0.25f32, 0.75]];
+    PostProcess { gain: 2.0, epsilon: 0.0, ..PostProcess::default() }.apply(&mut loud);
+    assert_eq!(loud, array![[0.5, 1.0]]);
+}
+
+#[test]
+fn test_annotated_datapack() {
+    use crate::{export::{self, Anchor, Category, Format, PlaySound}, exporter::{self, Song}};
+
+    let output = std::env::temp_dir().join(format!("minecraft-player-annotated-{}", std::process::id()));
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    runtime.block_on(export::prepare_output(&output, true)).unwrap();
+
+    let song = Song { output: output.clone(), n_ticks: 25, atom_ticks: 1, category: Category::Record, anchor: Anchor::default(), directions: None, looping: false, on_finish: None, duck: Vec::new(), ticks_per_function: 1, pack_format: export::PACK_FORMAT, open_format: false, annotate: true, residuals: Some(vec![0.25; 25]) };
+    let harp = [PlaySound { sound: 0, name: "minecraft:block.note_block.harp", volume: 0.5, pitch: 1.0 }];
+    let mut exporter = exporter::create(Format::Datapack, &song).unwrap();
+    for index in 0..25 {
+        let sounds = if index < 21 { &harp[..] } else { &[] };
+        exporter.write_tick(index, sounds).unwrap();
+    }
+    exporter.finish().unwrap();
+
+    assert_eq!(export::tick_time(1234), "1:01.70");
+    let tick_directory = export::tick_directory(&output, export::PACK_FORMAT);
+    let read = |path: std::path::PathBuf| std::fs::read_to_string(path).unwrap();
+    let play = read(export::play_directory(&tick_directory).join("0.mcfunction"));
+    assert!(play.starts_with("# tick 0 at 0:00.00, residual 0.250\n# loudest: minecraft:block.note_block.harp at 0.50, pitch 1.00\n# the same through tick 20\n"));
+    assert!(read(tick_directory.join("21.mcfunction")).starts_with("# tick 21 at 0:01.05, residual 0.250\n# silent\n"));
+
+    let manifest: serde_json::Value = serde_json::from_str(&read(export::manifest_path(&output))).unwrap();
+    assert_eq!(manifest.as_array().unwrap().len(), 25);
+    assert_eq!(manifest[20]["function"], "audio:_/play/0");
+    assert_eq!(manifest[24]["function"], "audio:_/21");
+    assert_eq!(manifest[24]["path"], "data/audio/function/_/21.mcfunction");
+    assert_eq!(manifest[3]["loudest"][0]["sound"], "minecraft:block.note_block.harp");
+
+    // comments go into a grouped function as they are
+    let block = export::block_function(0, &["# tick 0\nplaysound a\n".to_string()], "");
+    assert!(block.contains("\n# tick 0\nexecute if score #tick audio matches 0 run playsound a\n"));
+
+    std::fs::remove_dir_all(&output).unwrap();
+}
+
+#[test]
+fn test_diagnostics_bundle() {
+    use std::io::Read;
+    use crate::diagnostics;
+
+    let path = std::env::temp_dir().join(format!("minecraft-player-diagnostics-{}.zip", std::process::id()));
+    diagnostics::note("ticks", 10);
+    diagnostics::note("ticks", 20);
+    diagnostics::write_bundle(&path, "out of memory", "Args { .. }").unwrap();
+
+    let mut archive = zip::ZipArchive::new(std::fs::File::open(&path).unwrap()).unwrap();
+    let mut read = |name: &str| {
+        let mut contents = String::new();
+        archive.by_name(name).unwrap().read_to_string(&mut contents).unwrap();
+        contents
+    };
+    assert_eq!(read("error.txt"), "out of memory");
+    assert_eq!(read("settings.txt"), "Args { .. }");
+    assert!(read("environment.txt").starts_with(&format!("minecraft-player {}", env!("CARGO_PKG_VERSION"))));
+    assert!(read("notes.txt").contains("ticks: 20\n"));
+    assert!(!read("notes.txt").contains("ticks: 10"));
+    assert!(!read("devices.txt").is_empty());
+    read("log.txt");
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_group_sparsity() {
+    use crate::algebra;
+    use ndarray::{array, Array2};
+
+    // rows 0 and 1 are pitches of the same event
+    let h = array![[1.0, 1.0], [2.0, 0.0], [0.5, 0.0], [0.0, 0.25], [0.0, 0.0]];
+    let mut weights = vec![0.0; 4];
+    algebra::group_weights(h.view(), &[0, 0, 1, 2, 3], &mut weights);
+    assert_eq!(weights, vec![4.0, 0.5, 0.25, 0.0]);
+
+    // half of the 3 used, rounded up, then no fewer than the max
+    assert_eq!(algebra::prune_groups(&weights, 1), Some(vec![true, true, false, false]));
+    assert_eq!(algebra::prune_groups(&weights, 3), None);
+    assert_eq!(algebra::prune_groups(&[4.0, 0.5, 0.0, 0.0], 1), Some(vec![true, false, false, false]));
+
+    // dropped sounds can't come back in the next round
+    let data = array![[1.0, 0.0], [0.0, 1.0]];
+    let mut basis = algebra::Basis::Host(array![[1.0, 0.0, 1.0], [0.0, 1.0, 1.0]]);
+    basis.zero_columns(&[2]).unwrap();
+    let (h, _) = algebra::conv_pgd_nnls(data.view(), &basis, 1, Array2::zeros((3, 2)), 100, 0.3, false).unwrap();
+    assert!(h.row(2).iter().all(|x| *x == 0.0));
+    assert!((h[[0, 0]] - 1.0).abs() < 1e-3 && (h[[1, 1]] - 1.0).abs() < 1e-3);
+}
+
+#[test]
+fn test_audibility() {
+    use crate::audibility::Audibility;
+
+    // -40 dB is a gain of 0.01
+    let near = Audibility::new(-40.0, 0.0, 1.0);
+    assert!(near.is_audible(0.01));
+    assert!(!near.is_audible(0.009));
+    // louder than 1.0 is only heard further away
+    assert_eq!(near.heard(4.0), 1.0);
+
+    // half of a volume 1.0 sound's 16 block range, at half the slider
+    let far = Audibility::new(-40.0, 8.0, 0.5);
+    assert!((far.heard(1.0) - 0.25).abs() < 1e-6);
+    assert!(!far.is_audible(0.02));
+    assert!(far.is_audible(0.05));
+
+    // out of range, only minVolume is heard
+    let out_of_range = Audibility::new(-40.0, 100.0, 1.0);
+    assert_eq!(out_of_range.heard(1.0), 0.0);
+    let min_volume = Audibility { min_volume: 0.1, ..out_of_range };
+    assert!((min_volume.heard(1.0) - 0.1).abs() < 1e-6);
+}
+
+#[test]
+fn test_seeded_preview() {
+    use crate::preview::{Clipping, Preview};
+
+    let render = |seed| {
+        let mut cursor = std::io::Cursor::new(Vec::new());
+        let mut preview = Preview::new(&mut cursor, 48000, Clipping::Limit, true).unwrap().with_seed(seed);
+        preview.write_tick(vec![0.1; 256]).unwrap();
+        preview.finalize().unwrap();
+        cursor.into_inner()
+    };
+
+    assert_eq!(render(7), render(7));
+    assert_ne!(render(7), render(8));
+}
+
+#[test]
+fn test_deterministic_schedule() {
+    use std::collections::HashMap;
+    use crate::{algebra, assets::{self, SoundDefinition}, audio, export};
+    use ndarray::Array2;
+    use ndarray_rand::rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+
+    // definitions hashed in any order give the basis in the same order
+    let names = (0..32).map(|index| format!("event.{:02}", index)).collect::<Vec<String>>();
+    let orders = (0..4)
+        .map(|_| {
+            let definitions = names.iter()
+                .map(|name| (name.clone(), serde_json::from_str::<SoundDefinition>(&format!(r#"{{"sounds": ["{}"]}}"#, name)).unwrap()))
+                .collect::<HashMap<String, SoundDefinition>>();
+            assets::predictable_sounds(&definitions).into_iter().map(|predictable| predictable.event).collect::<Vec<String>>()
+        })
+        .collect::<Vec<Vec<String>>>();
+    assert!(orders.iter().all(|order| *order == names));
+
+    // however the sounds arrive, the same solve comes out bit for bit, and
+    // ties between equally loud sounds are broken the same way
+    let sounds = (0..6)
+        .map(|index| (format!("sound.{}", index), gen_frequency(220.0 * (1 + index % 3) as f32, 4000, 50)))
+        .collect::<Vec<(String, audio::Sound)>>();
+    let solve = |seed| {
+        let mut shuffled = sounds.clone();
+        shuffled.shuffle(&mut StdRng::seed_from_u64(seed));
+        let basis = audio::dedup(shuffled);
+
+        let columns = basis.iter().map(|(_, sound)| sound.samples.clone()).collect::<Vec<Vec<f32>>>();
+        let basis_matrix = algebra::matrix_from_vecs(columns).unwrap().reversed_axes();
+        let data = Array2::from_shape_fn((basis_matrix.nrows(), 3), |(row, column)| basis_matrix[[row, column % basis_matrix.ncols()]]);
+        let h = algebra::cpu_conv_pgd_nnls(data.view(), basis_matrix.view(), 1, 50, 1e-3);
+
+        let sound_ids = basis.iter().map(|(name, _)| (name.clone(), 1.0)).collect::<Vec<(String, f32)>>();
+        let selected = h.columns().into_iter()
+            .map(|amplitudes| export::select(amplitudes, &sound_ids, 2).into_iter().map(|play| play.name.to_string()).collect::<Vec<String>>())
+            .collect::<Vec<Vec<String>>>();
+        (sound_ids, h, selected)
+    };
+
+    let first = solve(0);
+    for seed in 1..8 {
+        let (sound_ids, h, selected) = solve(seed);
+        assert_eq!(sound_ids, first.0);
+        assert!(h.iter().zip(&first.1).all(|(a, b)| a.to_bits() == b.to_bits()));
+        assert_eq!(selected, first.2);
+    }
+}
+
+#[test]
+fn test_stage_changes() {
+    use crate::schedule::{self, Stages};
+
+    let stages = Stages { basis: "1.21 tonal".to_string(), solve: "400 iterations".to_string() };
+    assert_eq!(stages.changed_since(&stages), None);
+    assert_eq!(stages.changed_since(&Stages { solve: "800 iterations".to_string(), ..stages.clone() }), Some("solve"));
+    // the basis comes first, it's solved with again too
+    assert_eq!(stages.changed_since(&Stages { basis: "1.20".to_string(), solve: String::new() }), Some("basis"));
+
+    let tone = gen_frequency(440.0, 22050, 50);
+    let assets = std::env::temp_dir().join(format!("minecraft-player-stages-{}", std::process::id()));
+    let path = schedule::stages_path(&assets, std::slice::from_ref(&tone));
+    assert_ne!(path, schedule::stages_path(&assets, &[gen_frequency(441.0, 22050, 50)]));
+    assert_eq!(Stages::load(&path), None);
+
+    stages.save(&path).unwrap();
+    assert_eq!(Stages::load(&path), Some(stages));
+    std::fs::remove_dir_all(&assets).unwrap();
+}
+
+#[test]
+fn test_kept_characters() {
+    use std::collections::HashMap;
+    use crate::{audio::Processor, pitch, schedule};
+
+    // kept by the basis they were analyzed for
+    let assets = std::env::temp_dir().join(format!("minecraft-player-characters-{}", std::process::id()));
+    let path = schedule::characters_path(&assets, "1.21 tonal 48000");
+    assert_ne!(path, schedule::characters_path(&assets, "1.21 tonal 22050"));
+    assert_eq!(schedule::load_characters(&path), None);
+
+    let characters = HashMap::from([
+        ("block.note_block.harp".to_string(), pitch::analyze(&gen_frequency(440.0, 22050, 100), &Processor::new())),
+        ("entity.generic.explode".to_string(), pitch::Character { fundamental: None, flatness: 0.9 }),
+    ]);
+    schedule::save_characters(&path, &characters).unwrap();
+    assert_eq!(schedule::load_characters(&path), Some(characters));
+    std::fs::remove_dir_all(&assets).unwrap();
+}
+
+#[test]
+fn test_server_pack() {
+    use std::{collections::HashMap, io::Write};
+    use crate::{assets::{self, AssetKey, SoundDefinition}, pack};
+    use bytes::Bytes;
+    use zip::{write::SimpleFileOptions, ZipWriter};
+
+    let mut zip = ZipWriter::new(std::io::Cursor::new(Vec::new()));
+    for (name, contents) in [
+        ("pack.mcmeta", r#"{"pack": {"pack_format": 34, "description": "server"}}"#),
+        ("assets/minecraft/sounds.json", r#"{
+            "block.note_block.harp": {"replace": true, "sounds": ["custom/harp"]},
+            "entity.cat.ambient": {"sounds": ["custom/meow"]}
+        }"#),
+        ("assets/mypack/sounds.json", r#"{"song.intro": {"sounds": [{"name": "mypack:intro", "pitch": 0.5}]}}"#),
+        ("assets/minecraft/sounds/custom/harp.ogg", "harp"),
+        ("assets/mypack/sounds/intro.ogg", "intro"),
+    ] {
+        zip.start_file(name, SimpleFileOptions::default()).unwrap();
+        zip.write_all(contents.as_bytes()).unwrap();
+    }
+    let bytes = Bytes::from(zip.finish().unwrap().into_inner());
+    let pack = pack::read(bytes.clone()).unwrap();
+    assert_eq!(pack.sha1, sha1_smol::Sha1::from(&bytes).digest().to_string());
+
+    let mut definitions = serde_json::from_str::<HashMap<String, SoundDefinition>>(r#"{
+        "block.note_block.harp": {"sounds": ["note/harp"]},
+        "entity.cat.ambient": {"sounds": ["mob/cat/meow1"]}
+    }"#).unwrap();
+    let mut sounds = HashMap::from([(AssetKey::new("minecraft/sounds/note/harp.ogg"), Bytes::from_static(b"vanilla"))]);
+    pack.apply(&mut definitions, &mut sounds);
+
+    // replaced, added to (so it plays one of two at random), and new
+    let predictable = assets::predictable_sounds(&definitions);
+    let events = predictable.iter().map(|predictable| predictable.event.as_str()).collect::<Vec<&str>>();
+    assert_eq!(events, vec!["block.note_block.harp", "mypack:song.intro"]);
+    assert_eq!(predictable[0].path, AssetKey::new("minecraft/sounds/custom/harp.ogg"));
+    assert_eq!((predictable[1].path.as_str(), predictable[1].pitch), ("mypack/sounds/intro.ogg", 0.5));
+    assert_eq!(sounds.len(), 3);
+    assert_eq!(sounds[&AssetKey::new("mypack/sounds/intro.ogg")], Bytes::from_static(b"intro"));
+
+    assert!(pack::read(Bytes::from_static(b"not a zip")).is_err());
+}
+
+#[test]
+fn test_subtick_quantize() {
+    use ndarray::array;
+    use crate::subtick::{decay_compensation, quantize};
+
+    // a held sound overlaps itself for 3 of its 4 frames a frame later
+    let held = decay_compensation(&[1.0; 40], 10, 2, 4);
+    assert_eq!(held, vec![1.0, 0.75]);
+    // a hit over by the next frame doesn't
+    let mut hit = vec![0.0; 40];
+    hit[..10].fill(1.0);
+    assert_eq!(decay_compensation(&hit, 10, 2, 4), vec![1.0, 0.0]);
+    assert_eq!(decay_compensation(&[0.0; 40], 10, 2, 4), vec![1.0, 1.0]);
+
+    let h = array![[1.0, 2.0, 0.0, 4.0], [1.0, 1.0, 1.0, 1.0]];
+    let ticks = quantize(h.view(), 2, None, &[&held, &[1.0, 0.0]]);
+    assert_eq!(ticks, array![[2.5, 3.0], [1.0, 1.0]]);
+
+    // frames are scaled back before they're merged
+    let ticks = quantize(h.view(), 2, Some(&[2.0, 1.0, 1.0, 1.0]), &[&held, &held]);
+    assert_eq!(ticks, array![[3.5, 3.0], [2.75, 1.75]]);
+}
+
+#[test]
+fn test_convergence_record() {
+    use crate::convergence::{self, Series};
+    use ndarray::s;
+
+    let data = Array2::random((6, 8), Uniform::new(0.0f32, 1.0));
+    let objective = |basis: &Array2<f32>, span: usize, h: &Array2<f32>| {
+        let mut heard = Array2::<f32>::zeros(data.dim());
+        for d in 0..span {
+            let part = basis.slice(s![d * 6..(d + 1) * 6, ..]).dot(h);
+            let mut target = heard.slice_mut(s![.., d..]);
+            target += &part.slice(s![.., ..8 - d]);
+        }
+        0.5 * (heard - &data).mapv(|x| x * x).sum()
+    };
+
+    // through the gram matrix, and directly for atoms spanning ticks
+    for (span, r) in [(1, 3), (2, 4)] {
+        let basis = Array2::random((span * 6, r), Uniform::new(0.0f32, 1.0));
+        let series = Series::new("input");
+        convergence::record(&series, || algebra::cpu_conv_pgd_nnls(data.view(), basis.view(), span, 20, 1e-2));
+
+        // every iteration records the objective before its update
+        let objectives = series.objectives();
+        assert_eq!(objectives.len(), 20);
+        for iters in [0, 5, 19] {
+            let h = algebra::cpu_conv_pgd_nnls(data.view(), basis.view(), span, iters, 1e-2);
+            let expected = objective(&basis, span, &h);
+            assert!((objectives[iters] - expected).abs() < 1e-3 * expected, "{} != {}", objectives[iters], expected);
+        }
+        assert!(objectives.windows(2).all(|pair| pair[1] <= pair[0] * (1.0 + 1e-5)));
+
+        // a later solve continues the same series
+        convergence::record(&series, || algebra::cpu_conv_pgd_nnls(data.view(), basis.view(), span, 5, 1e-2));
+        assert_eq!(series.objectives().len(), 25);
+        assert!(series.recent_fall().unwrap() >= 0.0);
+    }
+
+    // and nothing is recorded outside of `record`
+    assert!(convergence::recorder().is_none());
+    assert!(convergence::render(&[Series::new("empty")]).is_err());
+}
+
+#[test]
+fn test_device_residual_norm() {
+    // long enough that every work item of the reduction strides more than once
+    let data = Array2::random((2400, 9), Uniform::new(-1.0f32, 1.0));
+    let basis = Array2::random((2400, 5), Uniform::new(-1.0f32, 1.0));
+    let h = Array2::random((5, 9), Uniform::new(0.0f32, 1.0));
+
+    let device_basis = algebra::DeviceBasis::from_array(basis.view(), &algebra::select_devices(&[]).unwrap()).unwrap();
+    let device_norm = device_basis.residual_norm(data.view(), h.view(), 1, false).unwrap();
+    let norm = algebra::residual_norm_f64(data.view(), basis.view(), h.view(), 1, false) as f32;
+    assert!((device_norm - norm).abs() < 1e-4 * norm, "{} != {}", device_norm, norm);
+}
+
+#[test]
+fn test_residual_f64() {
+    use ndarray::s;
+
+    let (m, r, n, span) = (5, 4, 7, 3);
+    let data = Array2::random((m, n), Uniform::new(-1.0f32, 1.0));
+    let atoms = Array2::random((span * m, r), Uniform::new(-1.0f32, 1.0));
+    let mut h = Array2::random((r, n), Uniform::new(0.0f32, 1.0));
+    h.slice_mut(s![1, ..]).fill(0.0);
+
+    for circular in [false, true] {
+        let mut heard = Array2::<f32>::zeros((m, n));
+        for d in 0..span {
+            let part = atoms.slice(s![d * m..(d + 1) * m, ..]).dot(&h);
+            for t in 0..n {
+                match (d <= t, circular) {
+                    (true, _) => heard.column_mut(t).scaled_add(1.0, &part.column(t - d)),
+                    (false, true) => heard.column_mut(t).scaled_add(1.0, &part.column(t + n - d)),
+                    (false, false) => {},
+                }
+            }
+        }
+
+        let expected = (heard - &data).mapv(|x| x * x).sum().sqrt() as f64;
+        let residual = algebra::residual_norm_f64(data.view(), atoms.view(), h.view(), span, circular);
+        assert!((residual - expected).abs() < 1e-5 * expected, "{} != {}", residual, expected);
+    }
+}
+
+#[test]
+fn test_matrices_roundtrip() {
+    use crate::matrices::{Matrices, MatricesError, PartMatrices};
+
+    let directory = std::env::temp_dir().join(format!("minecraft-player-matrices-{}", std::process::id()));
+    // 3 ticks of 2 frames, atoms of 2 frames over 4 features
+    let part = |name: &str, sounds: Vec<usize>, tick_scales| PartMatrices {
+        name: name.to_string(),
+        basis: Array2::random((8, sounds.len()), Uniform::new(0.0f32, 1.0)),
+        sounds,
+        budget: 100,
+        loudness: 0.25,
+        tick_scales,
+        chunks: Array2::random((4, 6), Uniform::new(-1.0f32, 1.0)),
+    };
+    let saved = Matrices {
+        sound_ids: vec![("minecraft:block.note_block.harp".to_string(), 0.5), ("minecraft:entity.cat.ambient".to_string(), 2.0)],
+        n_ticks: 3,
+        frames: 2,
+        span: 2,
+        tick_budgets: vec![255, 128, 255],
+        compensation: Some(vec![vec![1.0, 0.5], vec![1.0, 0.9]]),
+        versions: 2,
+        availability: [("minecraft:entity.cat.ambient".to_string(), vec!["1.20".to_string()])].into_iter().collect(),
+        parts: vec![part("harmonic", vec![0], Some(vec![0.5; 6])), part("percussive", vec![0, 1], None)]
+    };
+    saved.save(&directory).unwrap();
+
+    let loaded = Matrices::load(&directory).unwrap();
+    assert_eq!(loaded.sound_ids, saved.sound_ids);
+    assert_eq!((loaded.n_ticks, loaded.frames, loaded.span), (3, 2, 2));
+    assert_eq!(loaded.tick_budgets, saved.tick_budgets);
+    assert_eq!(loaded.compensation, saved.compensation);
+    assert_eq!(loaded.availability, saved.availability);
+    for (loaded, saved) in loaded.parts.iter().zip(&saved.parts) {
+        assert_eq!(loaded.name, saved.name);
+        assert_eq!(loaded.sounds, saved.sounds);
+        assert_eq!(loaded.tick_scales, saved.tick_scales);
+        assert_eq!(loaded.chunks, saved.chunks);
+        assert_eq!(loaded.basis, saved.basis);
+    }
+
+    // a basis that doesn't fit the chunks is caught on import
+    std::fs::copy(directory.join("part-0-basis.bin"), directory.join("part-1-basis.bin")).unwrap();
+    assert!(matches!(Matrices::load(&directory), Err(MatricesError::Mismatch(name)) if name == "percussive"));
+    std::fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+#[cfg(feature = "npy")]
+fn test_npy_layout() {
+    use crate::npy;
+
+    let path = std::env::temp_dir().join(format!("minecraft-player-npy-{}.npy", std::process::id()));
+    let array = Array2::from_shape_vec((2, 3), vec![1.0f32, 2.0, 3.0, 4.0, 5.0, 6.0]).unwrap();
+    // transposed, so C order has to be written rather than the memory's
+    npy::write(&path, array.t()).unwrap();
+    let bytes = std::fs::read(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(&bytes[..8], b"\x93NUMPY\x01\x00");
+    let header_length = u16::from_le_bytes([bytes[8], bytes[9]]) as usize;
+    let header = std::str::from_utf8(&bytes[10..10 + header_length]).unwrap();
+    assert_eq!((10 + header_length) % 64, 0);
+    assert!(header.starts_with("{'descr': '<f4', 'fortran_order': False, 'shape': (3, 2), }"));
+    assert!(header.ends_with('\n'));
+
+    let values = bytes[10 + header_length..].chunks_exact(4).map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])).collect::<Vec<f32>>();
+    assert_eq!(values, vec![1.0, 4.0, 2.0, 5.0, 3.0, 6.0]);
+}
+
+#[test]
+#[cfg(feature = "capi")]
+fn test_capi_invalid_argument() {
+    use crate::capi::{self, McpStatus};
+    use std::ffi::CStr;
+
+    let mut basis = std::ptr::null_mut();
+    let status = unsafe { capi::mcp_basis_new(std::ptr::null(), std::ptr::null(), 0, 48000, 1, 0, &mut basis) };
+    assert_eq!(status, McpStatus::InvalidArgument);
+    assert!(basis.is_null());
+
+    let error = unsafe { CStr::from_ptr(capi::mcp_last_error()) };
+    assert!(error.to_str().unwrap().contains("null"));
+
+    // the features are the header's, not whatever order `FeatureKind` has
+    let samples = [0.0f32; 960];
+    let (sounds, lengths) = ([samples.as_ptr()], [samples.len()]);
+    let status = unsafe { capi::mcp_basis_new(sounds.as_ptr(), lengths.as_ptr(), 1, 48000, 1, 6, &mut basis) };
+    assert_eq!(status, McpStatus::InvalidArgument);
+    let error = unsafe { CStr::from_ptr(capi::mcp_last_error()) };
+    assert!(error.to_str().unwrap().contains("features"));
+
+    let mut schedule = std::ptr::null_mut();
+    let status = unsafe { capi::mcp_solve(std::ptr::null(), std::ptr::null(), 0, std::ptr::null(), &mut schedule) };
+    assert_eq!(status, McpStatus::InvalidArgument);
+    assert_eq!(unsafe { capi::mcp_schedule_ticks(schedule) }, 0);
+}
+
+#[test]
+fn test_zip_directory() {
+    use crate::export;
+    use std::io::Read;
+
+    let directory = std::env::temp_dir().join(format!("minecraft-player-zip-{}", std::process::id()));
+    std::fs::create_dir_all(directory.join("data/audio/function/_")).unwrap();
+    std::fs::write(directory.join("pack.mcmeta"), "{}").unwrap();
+    std::fs::write(directory.join("data/audio/function/_/0.mcfunction"), "say hi").unwrap();
+
+    let path = directory.with_extension("zip");
+    export::zip_directory(&directory, std::fs::File::create(&path).unwrap()).unwrap();
+    let mut zip = zip::ZipArchive::new(std::fs::File::open(&path).unwrap()).unwrap();
+
+    let mut contents = String::new();
+    zip.by_name("data/audio/function/_/0.mcfunction").unwrap().read_to_string(&mut contents).unwrap();
+    assert_eq!(contents, "say hi");
+    assert_eq!(zip.len(), 2);
+
+    std::fs::remove_dir_all(&directory).unwrap();
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+#[cfg(feature = "serve")]
+fn test_jobs_survive_restart() {
+    use crate::serve::{JobManager, JobState, ServeError};
+
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let directory = std::env::temp_dir().join(format!("minecraft-player-jobs-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&directory);
+
+    let mut jobs = JobManager::open(&directory).unwrap();
+    assert!(matches!(JobManager::open(&directory), Err(ServeError::Busy(_))));
+
+    let (first, second) = (jobs.submit(b"first").unwrap(), jobs.submit(b"second").unwrap());
+    assert_eq!(runtime.block_on(jobs.next()), first);
+    jobs.start(&first).unwrap();
+    assert_eq!(jobs.state(&second), Some(JobState::Queued { position: 0 }));
+    drop(jobs);
+
+    // the running job was interrupted, so it goes first again
+    jobs = JobManager::open(&directory).unwrap();
+    assert_eq!(jobs.state(&second), Some(JobState::Queued { position: 1 }));
+    assert_eq!(runtime.block_on(jobs.next()), first);
+    jobs.start(&first).unwrap();
+    assert_eq!(std::fs::read(jobs.files(&first).input).unwrap(), b"first");
+
+    jobs.finish(&first, Err("broken".to_string())).unwrap();
+    drop(jobs);
+    jobs = JobManager::open(&directory).unwrap();
+    assert_eq!(jobs.state(&first), Some(JobState::Failed { error: "broken".to_string() }));
+    assert_eq!(jobs.waiting(), 1);
+
+    // a job the server keeps stopping during is given up on
+    assert_eq!(runtime.block_on(jobs.next()), second);
+    jobs.start(&second).unwrap();
+    for _ in 1..3 {
+        drop(jobs);
+        jobs = JobManager::open(&directory).unwrap();
+        assert_eq!(runtime.block_on(jobs.next()), second);
+        jobs.start(&second).unwrap();
+    }
+    drop(jobs);
+    let jobs = JobManager::open(&directory).unwrap();
+    assert!(matches!(jobs.state(&second), Some(JobState::Failed { .. })));
+    assert_eq!(jobs.waiting(), 0);
+
+    drop(jobs);
+    std::fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+fn test_format_duration() {
+    use crate::webhook;
+    use std::time::Duration;
+
+    assert_eq!(webhook::format_duration(Duration::from_millis(3400)), "3.4s");
+    assert_eq!(webhook::format_duration(Duration::from_secs(123)), "2m 03s");
+    assert_eq!(webhook::format_duration(Duration::from_secs(3723)), "1h 02m 03s");
+}
+
+#[test]
+fn test_read_raw() {
+    use crate::decode::{self, RawFormat};
+
+    let s16 = [0x00, 0x40, 0x00, 0xc0, 0xff];
+    let sound = decode::read_raw(&s16[..], RawFormat::S16le, 8000).unwrap();
+    // the dangling byte isn't a sample
+    assert_eq!(sound.samples, vec![0.5, -0.5]);
+    assert_eq!(sound.sample_rate, 8000);
+
+    let s24 = [0x00, 0x00, 0xc0, 0xff, 0xff, 0x7f];
+    let sound = decode::read_raw(&s24[..], RawFormat::S24le, 48000).unwrap();
+    assert_eq!(sound.samples[0], -0.5);
+    assert!((sound.samples[1] - 1.0).abs() < 1e-6);
+
+    let f32s = [0.25f32, -1.0].iter().flat_map(|value| value.to_le_bytes()).collect::<Vec<u8>>();
+    assert_eq!(decode::read_raw(&f32s[..], RawFormat::F32le, 48000).unwrap().samples, vec![0.25, -1.0]);
+}
+
+#[test]
+fn test_buffer_wav() {
+    use crate::decode;
+
+    // how ffmpeg writes a wav into a pipe, sizes it couldn't go back to fill in
+    let mut stream = b"RIFF\xff\xff\xff\xffWAVEfmt \x10\x00\x00\x00".to_vec();
+    stream.extend_from_slice(&[1, 0, 1, 0]);
+    stream.extend_from_slice(&8000u32.to_le_bytes());
+    stream.extend_from_slice(&16000u32.to_le_bytes());
+    stream.extend_from_slice(&[2, 0, 16, 0]);
+    stream.extend_from_slice(b"LIST\x04\x00\x00\x00INFO");
+    stream.extend_from_slice(b"data\xff\xff\xff\xff");
+    // the stream was cut off a byte into the last sample
+    stream.extend_from_slice(&[0x00, 0x40, 0x00, 0xc0, 0x00]);
+
+    let reader = decode::buffer_wav(&stream[..]).unwrap();
+    assert_eq!(reader.spec().sample_rate, 8000);
+    assert_eq!(decode::read_wav(reader).unwrap().samples, vec![0.5, -0.5]);
+
+    assert!(decode::buffer_wav(&b"not a wav"[..]).is_err());
+}
+
+#[test]
+#[cfg(feature = "opus")]
+fn test_read_opus() {
+    use crate::decode;
+    use audiopus::{coder::Encoder, Application, Channels, SampleRate};
+    use ogg::{PacketWriteEndInfo, PacketWriter};
+
+    let (frame, frames, pre_skip) = (960, 25, 312);
+    let samples = gen_frequency(440.0, 48000, 500).samples;
+    let encoder = Encoder::new(SampleRate::Hz48000, Channels::Mono, Application::Audio).unwrap();
+
+    let mut file = Vec::new();
+    let mut writer = PacketWriter::new(&mut file);
+    let mut head = b"OpusHead\x01\x01".to_vec();
+    head.extend_from_slice(&(pre_skip as u16).to_le_bytes());
+    head.extend_from_slice(&48000u32.to_le_bytes());
+    head.extend_from_slice(&[0, 0, 0]);
+    writer.write_packet(head, 1, PacketWriteEndInfo::EndPage, 0).unwrap();
+    writer.write_packet(b"OpusTags\x00\x00\x00\x00\x00\x00\x00\x00".to_vec(), 1, PacketWriteEndInfo::EndPage, 0).unwrap();
+
+    // the stream ends 100 samples into the last frame
+    let length = frame * (frames - 1) + 100;
+    for (index, chunk) in samples.chunks(frame).enumerate() {
+        let mut packet = vec![0u8; 4000];
+        let size = encoder.encode_float(chunk, &mut packet).unwrap();
+        packet.truncate(size);
+
+        let (end, position) = match index + 1 == frames {
+            true => (PacketWriteEndInfo::EndStream, pre_skip + length),
+            false => (PacketWriteEndInfo::NormalPacket, frame * (index + 1)),
+        };
+        writer.write_packet(packet, 1, end, position as u64).unwrap();
+    }
+    drop(writer);
+
+    let (sound, channels) = decode::read_opus(std::io::Cursor::new(file.clone()), 1).unwrap();
+    assert_eq!(channels, 1);
+    assert_eq!(sound.sample_rate, 48000);
+    assert_eq!(sound.samples.len(), length);
+    let rms = (sound.samples.iter().map(|sample| sample * sample).sum::<f32>() / sound.samples.len() as f32).sqrt();
+    assert!(rms > 0.5, "decoded a {} rms sine", rms);
+
+    // decoded to stereo for `--surround`, every frame of it
+    let (stereo, channels) = decode::read_opus(std::io::Cursor::new(file), 2).unwrap();
+    assert_eq!(channels, 1);
+    assert_eq!(stereo.samples.len(), 2 * length);
+    assert!(stereo.samples.chunks_exact(2).zip(&sound.samples).all(|(frame, mono)| (frame[0] - mono).abs() < 0.05));
+}
+
+#[test]
+fn test_progress() {
+    use std::time::Duration;
+    use crate::progress;
+
+    // other tests' solves report into the same channel, this one's are
+    // told apart by their number of iterations
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let seen = runtime.block_on(async {
+        let mut receiver = progress::subscribe();
+        let follower = tokio::spawn(async move {
+            let mut seen = 0;
+            while seen < 10 && receiver.changed().await.is_ok() {
+                seen += receiver.borrow_and_update().is_some_and(|progress| progress.iterations == 7919) as usize;
+            }
+            seen
+        });
+
+        // the solve blocks its thread, which mustn't hold up the follower
+        progress::solving(|| {
+            for iteration in 1..=50 {
+                progress::report(iteration, 7919);
+                std::thread::sleep(Duration::from_millis(5));
+            }
+        }).await;
+
+        tokio::time::timeout(Duration::from_secs(10), follower).await.unwrap().unwrap()
+    });
+    assert_eq!(seen, 10);
+
+    // a panicking solve is passed on
+    let panicked = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| runtime.block_on(progress::solving(|| panic!("solve")))));
+    assert!(panicked.is_err());
+}
+
+#[test]
+fn test_mapped_chunks() {
+    use crate::mapped::{Chunks, MappedChunks};
+
+    // more ticks than are built at once, so the blocks have to line up
+    let n_ticks = 5000;
+    let columns = (0..n_ticks).map(|tick| (0..3).map(|feature| ((tick * 3 + feature) % 17) as f32 - 8.0).collect()).collect::<Vec<Vec<f32>>>();
+    let in_memory = algebra::matrix_from_vecs(columns.clone()).unwrap().reversed_axes();
+
+    let path = std::env::temp_dir().join(format!("minecraft-player-mapped-{}.chunks", std::process::id()));
+    let mut mapped = Chunks::Mapped(MappedChunks::build(&path, n_ticks, |ticks| columns[ticks].to_vec()).unwrap());
+    assert_eq!(mapped.view(), in_memory.view());
+
+    // normalized and solved in place like chunks in memory
+    let mut normalized = in_memory.clone();
+    algebra::normalize_to_minus_plus(&mut normalized);
+    algebra::normalize_to_minus_plus(&mut mapped.view_mut());
+    assert_eq!(mapped.view(), normalized.view());
+
+    let basis = algebra::Basis::Host(Array2::random((3, 4), Uniform::new(0.0f32, 1.0)));
+    let (from_memory, _) = algebra::conv_pgd_nnls(normalized.view(), &basis, 1, Array2::zeros((4, n_ticks)), 20, 1e-2, false).unwrap();
+    let (from_map, _) = algebra::conv_pgd_nnls(mapped.view(), &basis, 1, Array2::zeros((4, n_ticks)), 20, 1e-2, false).unwrap();
+    assert_eq!(from_map, from_memory);
+
+    // the file only lives as long as the chunks
+    assert!(path.exists());
+    drop(mapped);
+    assert!(!path.exists());
+}
+
+#[test]
+fn test_selector() {
+    use ndarray::Array1;
+    use ndarray_rand::rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use crate::export::Selector;
+
+    let sound_ids = (0..500).map(|sound| (format!("minecraft:sound_{}", sound), 1.0)).collect::<Vec<(String, f32)>>();
+    let mut rng = StdRng::seed_from_u64(0);
+    let mut selector = Selector::default();
+
+    // one buffer for every tick, against sorting each one whole
+    for _ in 0..200 {
+        let amplitudes = (0..sound_ids.len())
+            .map(|_| match rng.gen_range(0..10) {
+                0 => f32::NAN,
+                1 => 0.0,
+                2 => -0.5,
+                // ties at the budget's edge
+                3 => 0.25,
+                _ => rng.gen_range(0.0..1.0),
+            })
+            .collect::<Array1<f32>>();
+
+        for budget in [0, 1, 64, 499, 600] {
+            let mut expected = amplitudes.iter().copied().enumerate().filter(|(_, volume)| *volume > 0.0).collect::<Vec<(usize, f32)>>();
+            expected.sort_by(|a, b| b.1.total_cmp(&a.1));
+            expected.truncate(budget);
+
+            let selected = selector.select(amplitudes.view(), &sound_ids, budget).iter().map(|sound| (sound.sound, sound.volume)).collect::<Vec<(usize, f32)>>();
+            assert_eq!(selected, expected);
+        }
+    }
+
+    // NaN never makes it past accumulating the solution either
+    let mut into = Array2::zeros((3, 1));
+    algebra::accumulate_rows_scaled(&mut into, ndarray::array![[f32::NAN], [0.5], [0.25]].view(), &[0, 1, 2], None, 1.0, 1);
+    assert_eq!(into, ndarray::array![[0.0], [0.5], [0.0]]);
+}
+
+#[test]
+fn test_sanitize() {
+    use ndarray::array;
+
+    let mut h = array![[0.5, f32::NAN, 0.0, 1.0], [0.25, 0.75, f32::INFINITY, f32::NEG_INFINITY]];
+    assert_eq!(algebra::sanitize(&mut h), vec![1, 2, 3]);
+    assert_eq!(h, array![[0.5, 0.0, 0.0, 1.0], [0.25, 0.75, 0.0, 0.0]]);
+
+    // a finite solution is left as it is
+    assert!(algebra::sanitize(&mut h).is_empty());
+}
+
+#[test]
+fn test_preview_markers() {
+    use crate::preview::{Clipping, Preview};
+
+    let path = std::env::temp_dir().join(format!("minecraft-player-markers-{}.txt", std::process::id()));
+    let markers = |seam: Option<usize>| {
+        let mut cursor = std::io::Cursor::new(Vec::new());
+        let preview = Preview::new(&mut cursor, 4, Clipping::Limit, false).unwrap()
+            .with_markers(Box::new(std::fs::File::create(&path).unwrap()));
+        let mut preview = match seam {
+            Some(length) => preview.with_seam(length),
+            None => preview,
+        };
+        for tick in [[1.0, 1.0], [0.5, 0.5], [0.0, 0.0]] {
+            preview.write_tick(tick.to_vec()).unwrap();
+        }
+        preview.finalize().unwrap();
+        std::fs::read_to_string(&path).unwrap()
+    };
+
+    assert_eq!(markers(None), concat!(
+        "0.000000\t0.500000\ttick 0\n",
+        "0.500000\t1.000000\ttick 1\n",
+        "1.000000\t1.500000\ttick 2\n",
+    ));
+
+    // the first tick is held back and crossfaded into the last
+    assert_eq!(markers(Some(2)), concat!(
+        "0.000000\t0.500000\ttick 1\n",
+        "0.500000\t1.000000\ttick 0\n",
+        "0.500000\t1.000000\ttick 2\n",
+    ));
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_ab_preview() {
+    use crate::preview::{Clipping, Preview};
+
+    let mut cursor = std::io::Cursor::new(Vec::new());
+    let mut preview = Preview::ab(&mut cursor, 48000, Clipping::Limit, false, vec![0.75, 1.5, -0.5]).unwrap();
+    preview.write_tick(vec![0.5, -0.25]).unwrap();
+    preview.write_tick(vec![2.0, -1.0]).unwrap();
+    assert_eq!(preview.finalize().unwrap(), 1);
+
+    // the original on the left, padded to the last tick and turned down to
+    // the level of the clipped reconstruction on the right, as far as its
+    // peak allows
+    cursor.set_position(0);
+    let mut reader = hound::WavReader::new(cursor).unwrap();
+    assert_eq!(reader.spec().channels, 2);
+    let samples = reader.samples::<f32>().collect::<Result<Vec<f32>, _>>().unwrap();
+    let expected = [0.5, 0.5, 1.0, -0.25, -1.0 / 3.0, 1.0, 0.0, -0.5];
+    assert!(samples.iter().zip(expected).all(|(sample, expected)| (sample - expected).abs() < 1e-6), "{:?}", samples);
+
+    // a quieter original is turned up to the reconstruction's rms
+    let mut cursor = std::io::Cursor::new(Vec::new());
+    let mut preview = Preview::ab(&mut cursor, 48000, Clipping::Limit, false, vec![0.1, -0.1]).unwrap();
+    preview.write_tick(vec![0.4, -0.4]).unwrap();
+    preview.finalize().unwrap();
+    cursor.set_position(0);
+    let samples = hound::WavReader::new(cursor).unwrap().samples::<f32>().collect::<Result<Vec<f32>, _>>().unwrap();
+    assert!((samples[0] - 0.4).abs() < 1e-6 && (samples[2] + 0.4).abs() < 1e-6, "{:?}", samples);
+}
@@ -0,0 +1,113 @@
+use std::{fmt::Display, time::{Duration, Instant}};
+
+use serde::Serialize;
+use tracing::{event, Level};
+
+use crate::webhook;
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Stage {
+    Fetch,
+    Decode,
+    Permute,
+    Features,
+    Chunking,
+    Solve,
+    Export
+}
+
+impl Display for Stage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Stage::Fetch => "fetch",
+            Stage::Decode => "decode",
+            Stage::Permute => "permute",
+            Stage::Features => "features",
+            Stage::Chunking => "chunking",
+            Stage::Solve => "solve",
+            Stage::Export => "export",
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Serialize, Debug)]
+struct StageTiming {
+    stage: Stage,
+    millis: u128
+}
+
+/// collects how long each pipeline stage took. a stage that is entered
+/// more than once (e.g. features for basis and input) accumulates
+#[derive(Default)]
+pub struct Timing {
+    stages: Vec<(Stage, Duration)>,
+    current: Option<(Stage, Instant)>
+}
+
+impl Timing {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// starts timing `stage`, finishing whatever stage was running
+    pub fn start(&mut self, stage: Stage) {
+        self.finish();
+        event!(Level::INFO, progress = true, stage = %stage, "started {}", stage);
+        self.current = Some((stage, Instant::now()));
+    }
+
+    /// the webhook only hears about a stage the first time it finishes,
+    /// stages are entered again for every version and part
+    pub fn finish(&mut self) {
+        if let Some((stage, start)) = self.current.take() {
+            let first = self.get(stage).is_none();
+            let elapsed = start.elapsed();
+            self.record(stage, elapsed);
+            if first {
+                webhook::send(format!("finished {} in {}", stage, webhook::format_duration(elapsed)));
+            }
+        }
+    }
+
+    pub fn record(&mut self, stage: Stage, duration: Duration) {
+        match self.stages.iter_mut().find(|(s, _)| *s == stage) {
+            Some((_, total)) => *total += duration,
+            None => self.stages.push((stage, duration)),
+        }
+    }
+
+    pub fn get(&self, stage: Stage) -> Option<Duration> {
+        self.stages.iter().find(|(s, _)| *s == stage).map(|(_, d)| *d)
+    }
+
+    /// every stage with its time, in the order they first started
+    pub fn stages(&self) -> impl Iterator<Item = (Stage, Duration)> + '_ {
+        self.stages.iter().copied()
+    }
+
+    pub fn total(&self) -> Duration {
+        self.stages.iter().map(|(_, d)| *d).sum()
+    }
+
+    pub fn log_summary(&self) {
+        let total = self.total().as_secs_f64().max(f64::EPSILON);
+
+        event!(Level::INFO, "{:<10}{:>12}{:>8}", "stage", "time", "share");
+        for (stage, duration) in &self.stages {
+            event!(Level::INFO, stage = %stage, millis = duration.as_millis() as u64, "{:<10}{:>10}ms{:>7.1}%", stage.to_string(), duration.as_millis(), 100.0 * duration.as_secs_f64() / total);
+        }
+        event!(Level::INFO, millis = self.total().as_millis() as u64, "{:<10}{:>10}ms", "total", self.total().as_millis());
+    }
+
+    pub fn to_json(&self) -> Result<String, serde_json::Error> {
+        let stages = self.stages
+            .iter()
+            .map(|(stage, duration)| StageTiming { stage: *stage, millis: duration.as_millis() })
+            .collect::<Vec<StageTiming>>();
+
+        serde_json::to_string_pretty(&stages)
+    }
+}
@@ -0,0 +1,157 @@
+use std::{collections::HashMap, path::Path, time::{Duration, Instant}};
+
+use ndarray::Array2;
+use ndarray_rand::{rand_distr::Uniform, RandomExt};
+use ocl::{enums::{DeviceInfo, DeviceInfoResult}, Buffer, Device};
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+use tracing::{event, span, Level};
+
+use crate::{algebra::{self, SolverError}, assets};
+
+static TUNING_FILE: &str = "tuning.json";
+
+/// (m, r, n) of the product timed for every candidate. big enough that
+/// launch overhead doesn't decide, small enough to probe in about a second
+static PROBE_SHAPE: (usize, usize, usize) = (512, 2048, 128);
+static REPEATS: usize = 4;
+
+/// work-group shape of the GEMM kernels, `row` x `col` work-items. both
+/// kernels keep a `col` x `col` tile in local memory
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Tiles {
+    pub row: usize,
+    pub col: usize
+}
+
+impl Default for Tiles {
+    fn default() -> Self {
+        Tiles { row: 2, col: 64 }
+    }
+}
+
+impl Tiles {
+    fn local_bytes(&self) -> u64 {
+        ((self.col * self.col + self.row * self.col) * size_of::<f32>()) as u64
+    }
+}
+
+pub static CANDIDATES: &[Tiles] = &[
+    Tiles { row: 1, col: 16 }, Tiles { row: 2, col: 16 }, Tiles { row: 4, col: 16 }, Tiles { row: 8, col: 16 },
+    Tiles { row: 1, col: 32 }, Tiles { row: 2, col: 32 }, Tiles { row: 4, col: 32 }, Tiles { row: 8, col: 32 },
+    Tiles { row: 1, col: 64 }, Tiles { row: 2, col: 64 }, Tiles { row: 4, col: 64 },
+];
+
+/// the best tiles found for every device so far, by device name
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct TuningCache {
+    pub devices: HashMap<String, Tiles>
+}
+
+impl TuningCache {
+    pub async fn load(assets: &Path) -> Self {
+        let Ok(contents) = fs::read_to_string(assets.join(TUNING_FILE)).await else {
+            return Self::default();
+        };
+
+        serde_json::from_str(&contents).unwrap_or_else(|e| {
+            event!(Level::WARN, "tuning cache is unreadable, ignoring it: '{}'", e);
+            Self::default()
+        })
+    }
+
+    pub async fn save(&self, assets: &Path) -> Result<(), std::io::Error> {
+        fs::create_dir_all(assets).await?;
+        assets::write_atomic(&assets.join(TUNING_FILE), serde_json::to_string_pretty(self)?.as_bytes()).await
+    }
+}
+
+/// whether the device can run `tiles` at all
+fn fits(device: Device, tiles: Tiles) -> Result<bool, SolverError> {
+    let local_memory = match device.info(DeviceInfo::LocalMemSize)? {
+        DeviceInfoResult::LocalMemSize(bytes) => bytes,
+        _ => 0,
+    };
+
+    Ok(tiles.row * tiles.col <= device.max_wg_size()? && tiles.local_bytes() <= local_memory)
+}
+
+/// time of `REPEATS` W h products with `tiles`, or `None` if the result was
+/// wrong, which some drivers manage for shapes they claim to support
+fn time(device: Device, tiles: Tiles, w: &Array2<f32>, h: &Array2<f32>, expected: &Array2<f32>) -> Result<Option<Duration>, SolverError> {
+    let ((m, r), n) = (w.dim(), h.dim().1);
+    let pq = algebra::build_pro_que(device, tiles)?;
+
+    let buffer = |values: &[f32]| Buffer::<f32>::builder()
+        .queue(pq.queue().clone())
+        .len(values.len())
+        .copy_host_slice(values)
+        .build();
+
+    let buffer_w = buffer(w.as_slice().unwrap())?;
+    let buffer_h = buffer(h.as_slice().unwrap())?;
+    let buffer_zero = buffer(&vec![0.0; m * n])?;
+    let buffer_whv = buffer(&vec![0.0; m * n])?;
+
+    let k_whv = pq.kernel_builder("gemm_whv")
+        .global_work_size((m.div_ceil(tiles.row) * tiles.row, n.div_ceil(tiles.col) * tiles.col))
+        .local_work_size((tiles.row, tiles.col))
+        .arg(&buffer_w)
+        .arg(&buffer_h)
+        .arg(&buffer_zero)
+        .arg(&buffer_whv)
+        .arg(m as u32)
+        .arg(n as u32)
+        .arg(r as u32)
+        .build()?;
+
+    // the first run includes whatever the driver does lazily
+    unsafe { k_whv.enq()?; }
+    pq.finish()?;
+
+    let start = Instant::now();
+    for _ in 0..REPEATS {
+        unsafe { k_whv.enq()?; }
+    }
+    pq.finish()?;
+    let elapsed = start.elapsed();
+
+    let mut whv = vec![0.0; m * n];
+    buffer_whv.read(&mut whv).enq()?;
+
+    let peak = expected.iter().fold(0.0f32, |a, b| a.max(b.abs()));
+    let correct = whv.iter().zip(expected).all(|(a, b)| (a - b).abs() <= 1e-3 * peak.max(1.0));
+
+    Ok(correct.then_some(elapsed))
+}
+
+/// tries every candidate that fits the device and returns the fastest one
+/// that gives the right result, or the default when none does
+pub fn probe(device: Device) -> Result<Tiles, SolverError> {
+    let _span = span!(Level::INFO, "probe", tag = "gpu").entered();
+
+    let (m, r, n) = PROBE_SHAPE;
+    let w = Array2::random((m, r), Uniform::new(-1.0f32, 1.0));
+    let h = Array2::random((r, n), Uniform::new(0.0f32, 1.0));
+    let expected = w.dot(&h);
+
+    let mut best: Option<(Tiles, Duration)> = None;
+    for tiles in CANDIDATES {
+        if !fits(device, *tiles)? {
+            continue;
+        }
+
+        match time(device, *tiles, &w, &h, &expected) {
+            Ok(Some(elapsed)) => {
+                event!(Level::DEBUG, "{}x{}: {}us", tiles.row, tiles.col, elapsed.as_micros());
+                if best.is_none_or(|(_, fastest)| elapsed < fastest) {
+                    best = Some((*tiles, elapsed));
+                }
+            },
+            Ok(None) => event!(Level::DEBUG, "{}x{}: wrong results, skipping", tiles.row, tiles.col),
+            Err(e) => event!(Level::DEBUG, "{}x{}: failed, skipping: {}", tiles.row, tiles.col, e),
+        }
+    }
+
+    return Ok(best.map(|(tiles, _)| tiles).unwrap_or_default());
+}
@@ -0,0 +1,77 @@
+use std::time::Instant;
+
+use ndarray::Array2;
+use ndarray_rand::{rand::{rngs::StdRng, SeedableRng}, rand_distr::Uniform, RandomExt};
+use ocl::Device;
+
+use crate::algebra::{self, SolverError};
+
+/// (m, r, n) of V = W h. the odd ones catch tiling bugs, the last is close
+/// to a real tick's worth of samples
+pub static SHAPES: &[(usize, usize, usize)] = &[(32, 64, 16), (15, 92, 3), (2400, 5, 9), (2400, 512, 64)];
+
+/// shapes solved with atoms spanning the given ticks. small, the CPU side of
+/// the convolution is slow
+pub static CONV_SHAPES: &[((usize, usize, usize), usize)] = &[((32, 64, 16), 3), ((15, 92, 9), 4)];
+
+/// largest difference from the CPU, relative to the solution's peak, that
+/// still counts as the same result. float sums run in a different order
+pub static TOLERANCE: f32 = 1e-4;
+
+static ITERS: usize = 400;
+static STEP: f32 = 1e-6;
+
+pub struct Parity {
+    pub shape: (usize, usize, usize),
+    pub span: usize,
+    /// of the solution, or of the residual's norm worked out on the device
+    /// if that's further off
+    pub deviation: f32,
+    pub cpu_millis: u128,
+    pub device_millis: u128
+}
+
+impl Parity {
+    pub fn passed(&self) -> bool {
+        self.deviation.is_finite() && self.deviation < TOLERANCE
+    }
+}
+
+/// solves the same random problem on the CPU and on `device`, the same way
+/// the parity test does. `span` is how many ticks the basis' atoms span, and
+/// the problem is made from `seed`, so a failing one can be solved again
+pub fn parity(device: Device, shape: (usize, usize, usize), span: usize, seed: u64) -> Result<Parity, SolverError> {
+    let (m, r, n) = shape;
+
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut data = Array2::random_using((m, n), Uniform::new(-1.0, 1.0), &mut rng);
+    let mut basis = Array2::random_using((span * m, r), Uniform::new(-1.0, 1.0), &mut rng);
+    algebra::normalize_to_minus_plus(&mut data);
+    algebra::normalize_to_minus_plus(&mut basis);
+
+    let start = Instant::now();
+    let mut cpu = algebra::cpu_conv_pgd_nnls(data.view(), basis.view(), span, ITERS, STEP);
+    let cpu_millis = start.elapsed().as_millis();
+
+    let start = Instant::now();
+    let device_basis = algebra::DeviceBasis::from_array(basis.view(), &[device])?;
+    let (mut gpu, _) = algebra::conv_pgd_nnls_device(data.view(), &device_basis, span, Array2::zeros((r, n)).view(), ITERS, STEP, false)?;
+    let device_millis = start.elapsed().as_millis();
+
+    // the reduction the objective is recorded with, on the device's own solution
+    let device_norm = device_basis.residual_norm(data.view(), gpu.view(), span, false)? as f64;
+    let norm = algebra::residual_norm_f64(data.view(), basis.view(), gpu.view(), span, false);
+    let residual = ((device_norm - norm).abs() / norm.max(f64::MIN_POSITIVE)) as f32;
+
+    algebra::normalize_to_global(&mut cpu);
+    algebra::normalize_to_global(&mut gpu);
+
+    // NaN from a broken driver has to fail, so it isn't folded away by max
+    let deviation = cpu.iter()
+        .zip(&gpu)
+        .map(|(a, b)| (a - b).abs())
+        .chain([residual])
+        .fold(0.0, |a: f32, b| if a.is_nan() || b.is_nan() { f32::NAN } else { a.max(b) });
+
+    Ok(Parity { shape, span, deviation, cpu_millis, device_millis })
+}
@@ -0,0 +1,187 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use crate::mojang::{Version, VersionManifest};
+
+/// keywords that resolve to whatever mojang currently lists as latest
+pub static LATEST_RELEASE: &str = "latest-release";
+pub static LATEST_SNAPSHOT: &str = "latest-snapshot";
+
+/// data pack formats, by the day the version that brought each out was
+/// released (1.13, 1.15, 1.16.2 and so on). snapshots get the format of the
+/// release before them, except 24w21a, which renamed `functions`
+static PACK_FORMATS: &[(&str, u32)] = &[
+    ("2018-07-18", 4),
+    ("2019-12-10", 5),
+    ("2020-08-11", 6),
+    ("2021-06-08", 7),
+    ("2021-11-30", 8),
+    ("2022-02-28", 9),
+    ("2022-06-07", 10),
+    ("2023-03-14", 12),
+    ("2023-06-07", 15),
+    ("2023-09-21", 18),
+    ("2023-12-05", 26),
+    ("2024-04-23", 41),
+    ("2024-05-22", 45),
+    ("2024-06-13", 48),
+    ("2024-10-22", 57),
+    ("2024-12-03", 61),
+    ("2025-03-25", 71),
+    ("2025-06-17", 80),
+    ("2025-06-30", 81),
+    ("2025-09-30", 88)
+];
+
+/// the day after the newest release `PACK_FORMATS` is known to be right
+/// for (1.21.10, still 88). versions from then on may have a newer format
+static COVERED_UNTIL: &str = "2025-10-08";
+
+/// the ids of the versions every sound event is in
+pub type Availability = BTreeMap<String, Vec<String>>;
+
+/// how an event of the basis plays on the version the output is for
+#[derive(Clone, Debug, PartialEq)]
+pub enum Compatibility {
+    Same,
+    /// plays something else there, re-recorded or with another pitch or volume
+    Changed,
+    /// plays the same there under another name
+    Renamed(String),
+    /// doesn't exist there, its playsounds would silently do nothing
+    Missing
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum VersionError {
+    #[error("could not find a matching version to `{0}`")]
+    NotFound(String),
+    #[error("{} versions match `{query}`", candidates.len())]
+    Ambiguous { query: String, candidates: Vec<Version> }
+}
+
+fn find_id(manifest: &VersionManifest, id: &str) -> Result<Version, VersionError> {
+    manifest.versions.iter()
+        .find(|v| v.id == id)
+        .cloned()
+        .ok_or_else(|| VersionError::NotFound(id.to_string()))
+}
+
+/// resolves `query` against the manifest: a keyword, an exact id, or a
+/// substring of exactly one id
+pub fn resolve(manifest: &VersionManifest, query: &str) -> Result<Version, VersionError> {
+    if query == LATEST_RELEASE {
+        return find_id(manifest, &manifest.latest.release);
+    }
+    if query == LATEST_SNAPSHOT {
+        return find_id(manifest, &manifest.latest.snapshot);
+    }
+
+    if let Some(exact) = manifest.versions.iter().find(|v| v.id == query) {
+        return Ok(exact.clone());
+    }
+
+    let mut candidates = manifest.versions.iter()
+        .filter(|v| v.id.contains(query))
+        .cloned()
+        .collect::<Vec<Version>>();
+
+    return match candidates.len() {
+        0 => Err(VersionError::NotFound(query.to_string())),
+        1 => Ok(candidates.remove(0)),
+        _ => Err(VersionError::Ambiguous { query: query.to_string(), candidates }),
+    };
+}
+
+/// the data pack format of a version, by its release time (as in the
+/// manifest). `None` before 1.13, which had no datapacks, and for versions
+/// newer than the table covers
+pub fn pack_format(release_time: &str) -> Option<u32> {
+    if release_time >= COVERED_UNTIL {
+        return None;
+    }
+
+    return PACK_FORMATS.iter()
+        .rev()
+        .find(|(since, _)| release_time >= *since)
+        .map(|(_, format)| *format);
+}
+
+/// the newest format `PACK_FORMATS` knows, the closest there is to the one
+/// of a version newer than it covers
+pub fn newest_pack_format() -> u32 {
+    return PACK_FORMATS.last().map(|(_, format)| *format).unwrap();
+}
+
+/// the sounds of every version in `sets` (version id, sounds by event) as
+/// one basis. an event several versions have is taken from the first of
+/// them, which is the target version
+pub fn union<T>(sets: Vec<(String, Vec<(String, T)>)>) -> (Vec<(String, T)>, Availability) {
+    let mut availability = Availability::new();
+    let mut seen = HashSet::new();
+    let mut sounds = Vec::new();
+
+    for (id, set) in sets {
+        for (event, sound) in set {
+            let versions = availability.entry(event.clone()).or_default();
+            if !versions.contains(&id) {
+                versions.push(id.clone());
+            }
+            if seen.insert(event.clone()) {
+                sounds.push((event, sound));
+            }
+        }
+    }
+
+    return (sounds, availability);
+}
+
+/// compares what the events of the basis play (see `assets::event_sounds`)
+/// with what they play on the output version
+pub fn compatibility(basis: &HashMap<String, String>, output: &HashMap<String, String>) -> HashMap<String, Compatibility> {
+    // the first name wins when several events play the same, so it's stable
+    let mut names = output.keys().collect::<Vec<&String>>();
+    names.sort();
+    let mut by_sound = HashMap::new();
+    for name in names {
+        by_sound.entry(&output[name]).or_insert(name);
+    }
+
+    return basis.iter()
+        .map(|(event, sound)| {
+            let compatibility = match output.get(event) {
+                Some(output_sound) if output_sound == sound => Compatibility::Same,
+                Some(_) => Compatibility::Changed,
+                None => match by_sound.get(sound) {
+                    Some(name) => Compatibility::Renamed(name.to_string()),
+                    None => Compatibility::Missing,
+                },
+            };
+            (event.clone(), compatibility)
+        })
+        .collect();
+}
+
+/// the basis as the output version has it: renamed events go by their new
+/// name, missing ones are left out and so are changed ones with `exclude_changed`.
+/// an event renamed to one the basis already has, or that another event was
+/// renamed to, plays the same sound there, so it's only kept once
+pub fn adapt<T>(sounds: Vec<(String, T)>, compatibility: &HashMap<String, Compatibility>, exclude_changed: bool) -> Vec<(String, T)> {
+    let adapted = sounds.into_iter()
+        .filter_map(|(event, sound)| match compatibility.get(&event) {
+            Some(Compatibility::Renamed(name)) => Some((name.clone(), sound, true)),
+            Some(Compatibility::Missing) => None,
+            Some(Compatibility::Changed) if exclude_changed => None,
+            _ => Some((event, sound, false)),
+        })
+        .collect::<Vec<(String, T, bool)>>();
+
+    let own = adapted.iter()
+        .filter(|(_, _, renamed)| !renamed)
+        .map(|(event, _, _)| event.clone())
+        .collect::<HashSet<String>>();
+    let mut renamed_to = HashSet::new();
+    return adapted.into_iter()
+        .filter(|(event, _, renamed)| !renamed || (!own.contains(event) && renamed_to.insert(event.clone())))
+        .map(|(event, sound, _)| (event, sound))
+        .collect();
+}
@@ -0,0 +1,152 @@
+use std::{fs, io, path::Path, sync::Mutex, time::Duration};
+
+use reqwest::{header::RETRY_AFTER, Response, StatusCode};
+use tokio::{sync::mpsc::{self, UnboundedSender}, task::JoinHandle};
+use tracing::{event, Level};
+
+use crate::{mojang, progress};
+
+/// discord cuts messages off beyond this
+static MAX_MESSAGE: usize = 2000;
+
+/// how long `flush` waits for the messages still being posted
+static FLUSH_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// times a rate limited message is posted again, each after the wait the
+/// webhook asks for, up to `MAX_RETRY_WAIT`
+static MAX_RETRIES: usize = 3;
+static MAX_RETRY_WAIT: Duration = Duration::from_secs(10);
+
+/// messages are posted one after another by a task of their own, in order
+static POSTER: Mutex<Option<(UnboundedSender<String>, JoinHandle<()>)>> = Mutex::new(None);
+
+/// posts every message `send` is given to `url`, as discord's `content`.
+/// has to be called in a tokio runtime
+pub fn configure(url: String) {
+    let (sender, mut messages) = mpsc::unbounded_channel::<String>();
+    let poster = tokio::spawn(async move {
+        let mut warned = false;
+        while let Some(message) = messages.recv().await {
+            let mut retries = 0;
+            let posted = loop {
+                let response = mojang::client()
+                    .post(&url)
+                    .json(&serde_json::json!({ "content": message }))
+                    .send()
+                    .await;
+
+                match response {
+                    Ok(response) if response.status() == StatusCode::TOO_MANY_REQUESTS && retries < MAX_RETRIES => {
+                        retries += 1;
+                        tokio::time::sleep(retry_after(response).await).await;
+                    },
+                    response => break response.and_then(|response| response.error_for_status()),
+                }
+            };
+
+            // one warning is enough, a broken webhook mustn't get in the way
+            if let Err(error) = posted {
+                if !warned {
+                    event!(Level::WARN, "couldn't post to the webhook: {}", error);
+                    warned = true;
+                }
+            }
+        }
+    });
+
+    *POSTER.lock().unwrap() = Some((sender, poster));
+    follow_progress();
+}
+
+/// how long a rate limited webhook asks to wait, by its `Retry-After`
+/// header or discord's `retry_after` in the body, in seconds
+async fn retry_after(response: Response) -> Duration {
+    let header = response.headers()
+        .get(RETRY_AFTER)
+        .and_then(|value| value.to_str().ok()?.parse::<f64>().ok());
+    let seconds = match header {
+        Some(seconds) => Some(seconds),
+        None => response.json::<serde_json::Value>().await.ok().and_then(|body| body["retry_after"].as_f64()),
+    };
+
+    return seconds
+        .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+        .unwrap_or(Duration::from_secs(1))
+        .min(MAX_RETRY_WAIT);
+}
+
+/// posts how far the solve is at every quarter of it, as its thread reports
+/// it. a part starting over is solved from the first quarter again
+fn follow_progress() {
+    let mut progress = progress::subscribe();
+    tokio::spawn(async move {
+        let (mut posted, mut last) = (0, 0);
+        while progress.changed().await.is_ok() {
+            let Some(current) = *progress.borrow_and_update() else {
+                (posted, last) = (0, 0);
+                continue;
+            };
+
+            if current.iteration < last {
+                posted = 0;
+            }
+            last = current.iteration;
+
+            let quarter = 4 * current.iteration / current.iterations.max(1);
+            if (1..4).contains(&quarter) && quarter > posted {
+                posted = quarter;
+                send(format!("solving, {}% done", quarter * 25));
+            }
+        }
+    });
+}
+
+pub fn enabled() -> bool {
+    POSTER.lock().unwrap().is_some()
+}
+
+/// queues `message` for the webhook, when there is one
+pub fn send(message: impl Into<String>) {
+    if let Some((sender, _)) = POSTER.lock().unwrap().as_ref() {
+        let mut message = message.into();
+        if message.chars().count() > MAX_MESSAGE {
+            message = message.chars().take(MAX_MESSAGE - 1).chain(['…']).collect();
+        }
+        let _ = sender.send(message);
+    }
+}
+
+/// waits for the queued messages to be posted, before the program exits
+pub async fn flush() {
+    let poster = POSTER.lock().unwrap().take();
+    if let Some((sender, poster)) = poster {
+        drop(sender);
+        if tokio::time::timeout(FLUSH_TIMEOUT, poster).await.is_err() {
+            event!(Level::WARN, "gave up on posting to the webhook");
+        }
+    }
+}
+
+/// `duration` as `1h 02m 03s`, `2m 03s` or `3.4s`
+pub fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    return match seconds {
+        0..60 => format!("{:.1}s", duration.as_secs_f64()),
+        60..3600 => format!("{}m {:02}s", seconds / 60, seconds % 60),
+        _ => format!("{}h {:02}m {:02}s", seconds / 3600, seconds / 60 % 60, seconds % 60),
+    };
+}
+
+/// bytes taken by the file at `path`, or by every file under it
+pub fn size_on_disk(path: &Path) -> Result<u64, io::Error> {
+    let metadata = fs::metadata(path)?;
+    if !metadata.is_dir() {
+        return Ok(metadata.len());
+    }
+
+    let mut size = 0;
+    for entry in fs::read_dir(path)? {
+        size += size_on_disk(&entry?.path())?;
+    }
+    return Ok(size);
+}